@@ -1,26 +1,108 @@
 // databasetool/src/backup/archive.rs
 use anyhow::{Context, Result};
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use tar::Builder;
 use walkdir::WalkDir;
 
-/// Creates a GZipped TAR archive from a source directory.
+use crate::backup::compression::{self, CompressionWriter};
+use crate::backup::encryption::{self, ArchiveWriter};
+use crate::config::{CompressionConfig, EncryptionConfig};
+
+/// Separator between a file name and its segment number in streamed archives,
+/// e.g. `app.dump.part-00003`. Streaming writes large dumps as several tar entries
+/// because a tar header needs the entry size before the data.
+pub const SEGMENT_SEPARATOR: &str = ".part-";
+
+/// Name of the `index`-th segment of `file_name` inside a streamed archive.
+pub fn segment_entry_name(file_name: &str, index: usize) -> String {
+    format!("{}{}{:05}", file_name, SEGMENT_SEPARATOR, index)
+}
+
+/// Splits a segment entry name into the original file name and the segment number.
+pub fn parse_segment_entry_name(entry_name: &str) -> Option<(&str, usize)> {
+    let (file_name, index) = entry_name.rsplit_once(SEGMENT_SEPARATOR)?;
+    if file_name.is_empty() || index.len() != 5 || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((file_name, index.parse().ok()?))
+}
+
+/// Reassembles `<file>.part-NNNNN` segments of a streamed archive into `<file>`.
+///
+/// Archives without segments are left untouched. Segments are removed once joined.
+///
+/// # Returns
+/// Number of files that were reassembled.
+pub fn join_segmented_files(extracted_dir: &Path) -> Result<usize> {
+    let mut groups: BTreeMap<PathBuf, Vec<(usize, PathBuf)>> = BTreeMap::new();
+    for entry in WalkDir::new(extracted_dir) {
+        let entry = entry.with_context(|| format!("Failed to walk directory: {}", extracted_dir.display()))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let Some(entry_name) = entry.file_name().to_str() else { continue };
+        if let Some((file_name, index)) = parse_segment_entry_name(entry_name) {
+            let target = entry.path().with_file_name(file_name);
+            groups.entry(target).or_default().push((index, entry.path().to_path_buf()));
+        }
+    }
+
+    for (target, segments) in groups.iter_mut() {
+        segments.sort();
+        for (expected, (index, path)) in segments.iter().enumerate() {
+            if *index != expected {
+                anyhow::bail!(
+                    "Segment {} of {} is missing from the archive (found {} instead)",
+                    expected,
+                    target.display(),
+                    path.display()
+                );
+            }
+        }
+        let mut output = File::create(target.as_path())
+            .with_context(|| format!("Failed to create reassembled file: {}", target.display()))?;
+        for (_, path) in segments.iter() {
+            let mut segment = File::open(path)
+                .with_context(|| format!("Failed to open segment: {}", path.display()))?;
+            io::copy(&mut segment, &mut output)
+                .with_context(|| format!("Failed to append segment {} to {}", path.display(), target.display()))?;
+            fs::remove_file(path).with_context(|| format!("Failed to remove joined segment: {}", path.display()))?;
+        }
+        println!("✓ Reassembled {} from {} segment(s)", target.display(), segments.len());
+    }
+    Ok(groups.len())
+}
+
+/// File name of the archive for a backup taken at `timestamp`: `<timestamp>.tar.gz`
+/// (or `.tar.zst`, `.tar.lz4`, `.tar` for the other codecs), with `.age` appended when
+/// the archive is encrypted.
+pub fn archive_file_name(timestamp: &str, compression: &CompressionConfig, encrypted: bool) -> String {
+    let suffix = if encrypted { encryption::ENCRYPTED_ARCHIVE_SUFFIX } else { "" };
+    format!("{}.{}{}", timestamp, compression.codec.extension(), suffix)
+}
+
+/// Creates a compressed TAR archive from a source directory.
 ///
 /// The archive will contain all files and directories within `source_dir`.
 /// The paths inside the archive will be relative to `source_dir`.
+/// With `encryption` set, the compressed stream is encrypted with age before it reaches the disk.
 ///
 /// # Arguments
 /// * `source_dir` - The directory whose contents will be archived.
-/// * `archive_dest_path` - The full path where the `.tar.gz` archive will be created.
+/// * `archive_dest_path` - The full path where the archive will be created.
+/// * `compression` - Codec, level and threads for the tar stream.
+/// * `encryption` - Keys to encrypt the archive with, if any.
 ///
 /// # Returns
 /// Path to the created archive file.
-pub fn create_tar_gz_archive(
+pub fn create_archive(
     source_dir: &Path,
     archive_dest_path: &Path,
+    compression: &CompressionConfig,
+    encryption: Option<&EncryptionConfig>,
 ) -> Result<PathBuf> {
     if !source_dir.is_dir() {
         return Err(anyhow::anyhow!(
@@ -28,20 +110,21 @@ pub fn create_tar_gz_archive(
             source_dir.display()
         ));
     }
-    if let Some(parent) = archive_dest_path.parent() {
-        if !parent.exists() {
-            std::fs::create_dir_all(parent).with_context(|| {
-                format!(
-                    "Failed to create parent directory for archive: {}",
-                    parent.display()
-                )
-            })?;
-        }
+    if let Some(parent) = archive_dest_path.parent()
+        && !parent.exists()
+    {
+        std::fs::create_dir_all(parent).with_context(|| {
+            format!(
+                "Failed to create parent directory for archive: {}",
+                parent.display()
+            )
+        })?;
     }
 
 
     println!(
-        "Creating tar.gz archive from {} to {}",
+        "Creating {} archive from {} to {}",
+        compression.codec.extension(),
         source_dir.display(),
         archive_dest_path.display()
     );
@@ -52,8 +135,24 @@ pub fn create_tar_gz_archive(
             archive_dest_path.display()
         )
     })?;
-    let enc = GzEncoder::new(archive_file, Compression::default());
-    let mut tar_builder = Builder::new(enc);
+    let writer = ArchiveWriter::new(archive_file, encryption)?;
+    let writer = write_tar(source_dir, CompressionWriter::new(writer, compression)?).with_context(|| {
+        format!("Failed to write archive: {}", archive_dest_path.display())
+    })?;
+    writer.finish().and_then(ArchiveWriter::finish).with_context(|| {
+        format!("Failed to finish archive: {}", archive_dest_path.display())
+    })?;
+
+    println!(
+        "✓ Archive created successfully at {}",
+        archive_dest_path.display()
+    );
+    Ok(archive_dest_path.to_path_buf())
+}
+
+/// Writes every file and directory below `source_dir` as a tar stream into `output`.
+fn write_tar<W: Write>(source_dir: &Path, output: W) -> Result<W> {
+    let mut tar_builder = Builder::new(output);
 
     // Add files from the source directory recursively.
     // The paths in the archive will be relative to source_dir.
@@ -75,7 +174,8 @@ pub fn create_tar_gz_archive(
         }
 
         if path.is_dir() {
-            tar_builder.append_dir_all(name, path).with_context(|| {
+            // Only the directory entry itself; WalkDir visits its contents next.
+            tar_builder.append_dir(name, path).with_context(|| {
                 format!("Failed to append directory {} to archive", path.display())
             })?;
         } else if path.is_file() {
@@ -85,38 +185,25 @@ pub fn create_tar_gz_archive(
         }
     }
 
-    let encoder = tar_builder.into_inner().with_context(|| {
-        format!(
-            "Failed to get inner encoder from tar builder for archive: {}",
-            archive_dest_path.display()
-        )
-    })?;
-    
-    encoder.finish().with_context(|| {
-        format!(
-            "Failed to finish Gzip encoding for archive: {}",
-            archive_dest_path.display()
-        )
-    })?;
-
-    println!(
-        "✓ Tar.gz archive created successfully at {}",
-        archive_dest_path.display()
-    );
-    Ok(archive_dest_path.to_path_buf())
+    tar_builder.into_inner().context("Failed to get inner encoder from tar builder")
 }
 
-/// Extracts a GZipped TAR archive to a destination directory.
+/// Extracts a compressed TAR archive to a destination directory.
+///
+/// Encrypted archives are recognised by their age header and decrypted on the fly; the
+/// compression codec is detected from the magic bytes of the (decrypted) stream.
 ///
 /// # Arguments
-/// * `archive_path` - Path to the `.tar.gz` archive file.
+/// * `archive_path` - Path to the archive file, e.g. `.tar.gz`, `.tar.zst` or `.tar.gz.age`.
 /// * `extract_to_dir` - The directory where the contents will be extracted.
+/// * `encryption` - Keys to decrypt the archive with, if it is encrypted.
 ///
 /// # Returns
 /// Path to the directory where files were extracted.
-pub fn extract_tar_gz_archive(
+pub fn extract_archive(
     archive_path: &Path,
     extract_to_dir: &Path,
+    encryption: Option<&EncryptionConfig>,
 ) -> Result<PathBuf> {
     if !archive_path.is_file() {
         return Err(anyhow::anyhow!(
@@ -140,7 +227,7 @@ pub fn extract_tar_gz_archive(
     }
 
     println!(
-        "Extracting tar.gz archive from {} to {}",
+        "Extracting archive from {} to {}",
         archive_path.display(),
         extract_to_dir.display()
     );
@@ -148,8 +235,16 @@ pub fn extract_tar_gz_archive(
     let archive_file = File::open(archive_path).with_context(|| {
         format!("Failed to open archive file: {}", archive_path.display())
     })?;
-    let gz_decoder = flate2::read::GzDecoder::new(archive_file);
-    let mut archive = tar::Archive::new(gz_decoder);
+    let archive_reader: Box<dyn Read> = if encryption::is_encrypted_file(archive_path)? {
+        println!("🔐 Archive is encrypted; decrypting while extracting.");
+        Box::new(encryption::decrypting_reader(archive_file, encryption)?)
+    } else {
+        Box::new(archive_file)
+    };
+    let (codec, tar_reader) = compression::decompressing_reader(io::BufReader::new(archive_reader))
+        .with_context(|| format!("Failed to read archive {}", archive_path.display()))?;
+    println!("Detected archive compression: {}", codec.as_str());
+    let mut archive = tar::Archive::new(tar_reader);
 
     archive.unpack(extract_to_dir).with_context(|| {
         format!(
@@ -160,8 +255,38 @@ pub fn extract_tar_gz_archive(
     })?;
 
     println!(
-        "✓ Tar.gz archive extracted successfully to {}",
+        "✓ Archive extracted successfully to {}",
         extract_to_dir.display()
     );
     Ok(extract_to_dir.to_path_buf())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_segment_entry_name() {
+        assert_eq!(segment_entry_name("app.dump", 3), "app.dump.part-00003");
+        assert_eq!(parse_segment_entry_name("app.dump.part-00003"), Some(("app.dump", 3)));
+        assert_eq!(parse_segment_entry_name("app.dump"), None);
+        assert_eq!(parse_segment_entry_name("app.dump.part-3"), None);
+    }
+
+    #[test]
+    fn test_join_segmented_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join(segment_entry_name("app_data.sql", 1)), "world")?;
+        fs::write(dir.path().join(segment_entry_name("app_data.sql", 0)), "hello ")?;
+        fs::write(dir.path().join("app_schema.sql"), "schema")?;
+
+        assert_eq!(join_segmented_files(dir.path())?, 1);
+        assert_eq!(fs::read_to_string(dir.path().join("app_data.sql"))?, "hello world");
+        assert!(!dir.path().join(segment_entry_name("app_data.sql", 0)).exists());
+        assert_eq!(fs::read_to_string(dir.path().join("app_schema.sql"))?, "schema");
+
+        fs::write(dir.path().join(segment_entry_name("crm.dump", 1)), "orphan")?;
+        assert!(join_segmented_files(dir.path()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+// databasetool/src/backup/compression.rs
+use anyhow::{Context, Result};
+use flate2::Compression;
+use std::io::{self, BufRead, Read, Write};
+
+use crate::config::{CompressionCodec, CompressionConfig};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const LZ4_FRAME_MAGIC: &[u8] = &[0x04, 0x22, 0x4d, 0x18];
+/// Uncompressed tar archives carry `ustar` at this offset of their first header.
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+/// Default zstd level; the zstd CLI uses the same.
+const ZSTD_DEFAULT_LEVEL: i32 = 3;
+
+/// Writer that compresses everything written to it with the configured codec.
+///
+/// `finish` must be called once all data is written to flush the final frame.
+pub enum CompressionWriter<W: Write> {
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    Lz4(lz4::Encoder<W>),
+    None(W),
+}
+
+impl<W: Write> CompressionWriter<W> {
+    pub fn new(output: W, config: &CompressionConfig) -> Result<Self> {
+        let writer = match config.codec {
+            CompressionCodec::Gzip => {
+                let level = config.level.map(|level| Compression::new(level as u32)).unwrap_or_default();
+                CompressionWriter::Gzip(flate2::write::GzEncoder::new(output, level))
+            }
+            CompressionCodec::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(output, config.level.unwrap_or(ZSTD_DEFAULT_LEVEL))
+                    .context("Failed to set up zstd compression")?;
+                if config.threads > 1 {
+                    encoder
+                        .multithread(config.threads)
+                        .context("Failed to enable multithreaded zstd compression")?;
+                }
+                CompressionWriter::Zstd(encoder)
+            }
+            CompressionCodec::Lz4 => {
+                let encoder = lz4::EncoderBuilder::new()
+                    .level(config.level.unwrap_or(0) as u32)
+                    .build(output)
+                    .context("Failed to set up lz4 compression")?;
+                CompressionWriter::Lz4(encoder)
+            }
+            CompressionCodec::None => CompressionWriter::None(output),
+        };
+        Ok(writer)
+    }
+
+    /// Writes the end of the compressed stream and returns the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        match self {
+            CompressionWriter::Gzip(encoder) => encoder.finish().context("Failed to finish gzip compression"),
+            CompressionWriter::Zstd(encoder) => encoder.finish().context("Failed to finish zstd compression"),
+            CompressionWriter::Lz4(encoder) => {
+                let (output, result) = encoder.finish();
+                result.context("Failed to finish lz4 compression")?;
+                Ok(output)
+            }
+            CompressionWriter::None(output) => Ok(output),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressionWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressionWriter::Gzip(encoder) => encoder.write(data),
+            CompressionWriter::Zstd(encoder) => encoder.write(data),
+            CompressionWriter::Lz4(encoder) => encoder.write(data),
+            CompressionWriter::None(output) => output.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressionWriter::Gzip(encoder) => encoder.flush(),
+            CompressionWriter::Zstd(encoder) => encoder.flush(),
+            CompressionWriter::Lz4(encoder) => encoder.flush(),
+            CompressionWriter::None(output) => output.flush(),
+        }
+    }
+}
+
+/// Identifies the codec of an archive from its first bytes.
+///
+/// # Returns
+/// `None` when the bytes are neither a known compressed stream nor a tar header.
+pub fn detect_codec(header: &[u8]) -> Option<CompressionCodec> {
+    if header.starts_with(GZIP_MAGIC) {
+        Some(CompressionCodec::Gzip)
+    } else if header.starts_with(ZSTD_MAGIC) {
+        Some(CompressionCodec::Zstd)
+    } else if header.starts_with(LZ4_FRAME_MAGIC) {
+        Some(CompressionCodec::Lz4)
+    } else if header.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC) {
+        Some(CompressionCodec::None)
+    } else {
+        None
+    }
+}
+
+/// Wraps an archive stream in the decoder its magic bytes call for.
+///
+/// The codec is never taken from the file name, so renamed archives still extract.
+///
+/// # Returns
+/// The detected codec and a reader producing the uncompressed tar stream.
+pub fn decompressing_reader<'a, R: BufRead + 'a>(mut input: R) -> Result<(CompressionCodec, Box<dyn Read + 'a>)> {
+    // Peek without consuming; the decoder needs to see the magic bytes too.
+    let header = input.fill_buf().context("Failed to read archive header")?;
+    let codec = detect_codec(header).context(
+        "Archive is not a recognized backup archive (expected a gzip, zstd, lz4 or uncompressed tar stream)",
+    )?;
+    let reader: Box<dyn Read + 'a> = match codec {
+        CompressionCodec::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(input)),
+        CompressionCodec::Zstd => {
+            Box::new(zstd::stream::read::Decoder::with_buffer(input).context("Failed to set up zstd decompression")?)
+        }
+        CompressionCodec::Lz4 => Box::new(lz4::Decoder::new(input).context("Failed to set up lz4 decompression")?),
+        CompressionCodec::None => Box::new(input),
+    };
+    Ok((codec, reader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(config: CompressionConfig) -> Result<CompressionCodec> {
+        let data: Vec<u8> = (0..200_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        let mut tar_builder = tar::Builder::new(CompressionWriter::new(Vec::new(), &config)?);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar_builder.append_data(&mut header, "app_data.sql", data.as_slice())?;
+        let compressed = tar_builder.into_inner()?.finish()?;
+
+        let (codec, reader) = decompressing_reader(compressed.as_slice())?;
+        let mut archive = tar::Archive::new(reader);
+        let mut entry = archive.entries()?.next().context("archive is empty")??;
+        let mut extracted = Vec::new();
+        entry.read_to_end(&mut extracted)?;
+        assert_eq!(extracted, data);
+        Ok(codec)
+    }
+
+    #[test]
+    fn test_every_codec_round_trips_and_is_detected() -> Result<()> {
+        for codec in [CompressionCodec::Gzip, CompressionCodec::Zstd, CompressionCodec::Lz4, CompressionCodec::None] {
+            let config = CompressionConfig { codec, level: None, threads: 1 };
+            assert_eq!(round_trip(config)?, codec);
+        }
+        let config = CompressionConfig { codec: CompressionCodec::Zstd, level: Some(19), threads: 2 };
+        assert_eq!(round_trip(config)?, CompressionCodec::Zstd);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_format_is_rejected() {
+        assert!(detect_codec(b"PK\x03\x04 not a tarball").is_none());
+        assert!(decompressing_reader(&b"plain text file"[..]).is_err());
+    }
+}
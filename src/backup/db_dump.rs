@@ -1,24 +1,96 @@
 // databasetool/src/backup/db_dump.rs
 use anyhow::{Context, Result};
 use sqlx::{Connection, PgConnection, Row};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::Arc;
+use tokio::process::Command as TokioCommand;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use url::Url;
 use which::which;
 
-use crate::config::BackupConfig;
+use crate::backup::manifest::{self, DatabaseStats};
+use crate::config::{BackupConfig, DumpFormat};
+use crate::utils::find_psql_executable;
+use crate::utils::sql_script::quote_literal;
+use crate::utils::table_filter::{self, TableSelection};
 
 // Helper function to find pg_dump executable
-fn find_pg_dump_executable() -> Result<PathBuf> {
+pub(crate) fn find_pg_dump_executable() -> Result<PathBuf> {
     which("pg_dump")
         .context("pg_dump executable not found in PATH. Please ensure PostgreSQL client tools are installed and in your PATH.")
 }
 
+/// Outcome of dumping several databases. One failing database does not stop the others.
+#[derive(Debug, Default)]
+pub struct DumpSummary {
+    pub successfully_dumped_dbs: Vec<String>,
+    pub failed: Vec<(String, anyhow::Error)>,
+    pub stats: BTreeMap<String, DatabaseStats>, // Of the dumped databases, for the manifest
+}
+
+/// A transaction on the source whose snapshot is exported for pg_dump (`--snapshot`).
+///
+/// Held open until every dump step of the database has run; the statistics for the manifest
+/// are then read in the same snapshot, so they describe exactly what was dumped even while
+/// the source keeps taking writes.
+pub(crate) struct DumpSnapshot {
+    conn: PgConnection,
+    pub id: String,
+}
+
+impl DumpSnapshot {
+    pub async fn export(db_url: &str) -> Result<Self> {
+        let mut conn = PgConnection::connect(db_url).await.context("Failed to connect to export a snapshot")?;
+        sqlx::query("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .execute(&mut conn)
+            .await
+            .context("Failed to start the snapshot transaction")?;
+        let id: String = sqlx::query_scalar("SELECT pg_export_snapshot()")
+            .fetch_one(&mut conn)
+            .await
+            .context("Failed to export a snapshot")?;
+        Ok(DumpSnapshot { conn, id })
+    }
+
+    /// Starts a read-only transaction on `conn` that sees the exported snapshot `id`.
+    pub async fn import(conn: &mut PgConnection, id: &str) -> Result<()> {
+        sqlx::query("BEGIN").execute(&mut *conn).await.context("Failed to start a transaction")?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .execute(&mut *conn)
+            .await
+            .context("Failed to set the transaction isolation level")?;
+        sqlx::query(&format!("SET TRANSACTION SNAPSHOT {}", quote_literal(id)))
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("Failed to import snapshot {}", id))?;
+        Ok(())
+    }
+
+    /// Ends the transaction once nothing reads the snapshot any more.
+    pub async fn close(mut self) {
+        sqlx::query("COMMIT").execute(&mut self.conn).await.ok();
+        self.conn.close().await.ok();
+    }
+
+    /// Reads the manifest statistics in the snapshot, then ends the transaction.
+    pub async fn collect_stats(mut self, selection: &TableSelection, table_checksums: bool) -> Result<DatabaseStats> {
+        let stats = manifest::collect_database_stats(&mut self.conn, selection, table_checksums).await;
+        self.close().await;
+        stats
+    }
+}
+
 /// Dumps all specified databases or all non-template databases from the source using pg_dump.
+///
+/// Up to `max_parallel_databases` databases are dumped concurrently. A failure of one database
+/// is recorded in the returned summary (and its partial files removed) while the others continue;
+/// only setup problems such as a missing pg_dump or an unreachable server return an error.
 pub async fn dump_databases(
     backup_config: &BackupConfig,
     target_dump_dir: &Path,
-) -> Result<Vec<String>> {
+) -> Result<DumpSummary> {
     println!(
         "Starting pg_dump based database dump process. Target directory: {}",
         target_dump_dir.display()
@@ -26,7 +98,65 @@ pub async fn dump_databases(
 
     let pg_dump_path = find_pg_dump_executable()?;
     println!("Found pg_dump executable at: {}", pg_dump_path.display());
+    if !backup_config.filters.is_empty() {
+        println!("Filters: {}", backup_config.filters.describe());
+    }
+
+    let base_url_str = get_base_url_without_db(&backup_config.source_db_url)?;
+    let databases_to_backup = resolve_databases_to_backup(backup_config).await?;
+    println!(
+        "Databases to be backed up: {:?} (up to {} in parallel)",
+        databases_to_backup, backup_config.max_parallel_databases
+    );
+
+    let mut summary = DumpSummary::default();
+    let semaphore = Arc::new(Semaphore::new(backup_config.max_parallel_databases));
+    let mut dump_tasks = JoinSet::new();
+
+    for db_name in &databases_to_backup {
+        let semaphore = Arc::clone(&semaphore);
+        let pg_dump_path = pg_dump_path.clone();
+        let backup_config = backup_config.clone();
+        let db_url = format!("{}/{}", base_url_str, db_name);
+        let dump_dir = target_dump_dir.to_path_buf();
+        let db_name = db_name.clone();
+        dump_tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.expect("dump semaphore is never closed");
+            let result = dump_single_database(&pg_dump_path, &db_url, &db_name, &dump_dir, &backup_config).await;
+            (db_name, result)
+        });
+    }
+
+    while let Some(joined) = dump_tasks.join_next().await {
+        let (db_name, result) = joined.context("pg_dump task panicked")?;
+        match result {
+            Ok(stats) => {
+                println!("✓ Successfully dumped {} using pg_dump ({} format)", db_name, backup_config.dump_format.as_str());
+                summary.stats.insert(db_name.clone(), stats);
+                summary.successfully_dumped_dbs.push(db_name);
+            }
+            Err(e) => {
+                eprintln!("❌ Dump of database {} failed: {:#}", db_name, e);
+                // Leave nothing half-written behind to end up in the archive.
+                for (_, name) in dump_entry_names(&db_name, backup_config.dump_format) {
+                    let path = target_dump_dir.join(name);
+                    let _ = if path.is_dir() { std::fs::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+                }
+                summary.failed.push((db_name, e));
+            }
+        }
+    }
 
+    // Tasks finish in arbitrary order; keep the archive contents and logs deterministic.
+    summary.successfully_dumped_dbs.sort();
+    summary.failed.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(summary)
+}
+
+/// Determines which databases a backup covers: the configured list, or every
+/// non-template database on the source server. Invalid names, templates and the
+/// `postgres` maintenance database (unless listed explicitly) are skipped.
+pub(crate) async fn resolve_databases_to_backup(backup_config: &BackupConfig) -> Result<Vec<String>> {
     let base_url_str = get_base_url_without_db(&backup_config.source_db_url)?;
     // Admin connection is still needed if the list of databases isn't explicitly provided.
     let mut admin_conn_opt = if backup_config.databases_to_backup.is_none() {
@@ -61,88 +191,178 @@ pub async fn dump_databases(
         }
     };
 
+    let databases_to_backup: Vec<String> = databases_to_backup
+        .into_iter()
+        .filter(|db_name| {
+            if db_name.trim().is_empty() || db_name.contains(|c: char| !c.is_alphanumeric() && c != '_' && c != '-') {
+                eprintln!("Skipping invalid database name: {}", db_name);
+                return false;
+            }
+            if db_name.starts_with("template")
+                || (db_name == "postgres"
+                    && backup_config
+                        .databases_to_backup
+                        .as_ref()
+                        .is_none_or(|dbs| !dbs.contains(db_name)))
+            {
+                println!("Skipping system/template database: {}", db_name);
+                return false;
+            }
+            true
+        })
+        .collect();
+
     if databases_to_backup.is_empty() {
         anyhow::bail!("No databases found or specified to back up.");
     }
+    Ok(databases_to_backup)
+}
 
-    println!("Databases to be backed up: {:?}", databases_to_backup);
-    let mut successfully_dumped_dbs = Vec::new();
+/// Name of the file holding the rows kept by `filters.where`, next to the dump of `db_name`.
+///
+/// A psql script of `COPY` blocks, loaded after the dump itself in every format.
+pub fn row_filter_entry_name(db_name: &str) -> String {
+    format!("{}.rows.sql", db_name)
+}
 
-    for db_name in &databases_to_backup {
-        if db_name.trim().is_empty() || db_name.contains(|c: char| !c.is_alphanumeric() && c != '_' && c != '-') {
-            eprintln!("Skipping invalid database name: {}", db_name);
-            continue;
-        }
+/// Names of the files (or, for directory format, the directory) a database is dumped to,
+/// relative to the dump directory, each paired with its kind as recorded in the manifest.
+/// The row-filtered data file only exists when `filters.where` matched a table.
+pub fn dump_entry_names(db_name: &str, format: DumpFormat) -> Vec<(&'static str, String)> {
+    let mut names = match format {
+        DumpFormat::Plain => vec![
+            ("schema", format!("{}_schema.sql", db_name)),
+            ("data", format!("{}_data.sql", db_name)),
+        ],
+        DumpFormat::Custom => vec![("dump", format!("{}.dump", db_name))],
+        DumpFormat::Directory => vec![("dump", format!("{}.dir", db_name))],
+    };
+    names.push(("rows", row_filter_entry_name(db_name)));
+    names
+}
 
-        if db_name.starts_with("template")
-            || (db_name == "postgres"
-                && backup_config
-                    .databases_to_backup
-                    .as_ref()
-                    .map_or(true, |dbs| !dbs.contains(db_name)))
-        {
-            println!("Skipping system/template database: {}", db_name);
-            continue;
-        }
+/// Client program that runs a dump step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DumpProgram {
+    PgDump,
+    Psql, // Prints the rows kept by `filters.where`
+}
 
-        println!("Processing database with pg_dump: {}", db_name);
-        let db_specific_url_for_pg_dump = format!("{}/{}", base_url_str, db_name);
-
-        let schema_file_path = target_dump_dir.join(format!("{}_schema.sql", db_name));
-        let data_file_path = target_dump_dir.join(format!("{}_data.sql", db_name));
-
-        // Dump schema using pg_dump
-        println!("Dumping schema for {} to {} using pg_dump...", db_name, schema_file_path.display());
-        let schema_dump_cmd_output = Command::new(&pg_dump_path)
-            .arg("--schema-only")
-            .arg("-f")
-            .arg(&schema_file_path)
-            .arg(&db_specific_url_for_pg_dump) // pg_dump accepts the full URL
-            .output()
-            .with_context(|| format!("Failed to execute pg_dump for schema of database: {}", db_name))?;
-
-        if !schema_dump_cmd_output.status.success() {
-            return Err(anyhow::anyhow!(
-                "pg_dump (schema) for database {} failed with status: {}\nStdout: {}\nStderr: {}",
-                db_name,
-                schema_dump_cmd_output.status,
-                String::from_utf8_lossy(&schema_dump_cmd_output.stdout),
-                String::from_utf8_lossy(&schema_dump_cmd_output.stderr)
-            ));
-        }
-        println!("✓ Schema for {} dumped successfully via pg_dump.", db_name);
-
-        // Dump data using pg_dump
-        println!("Dumping data for {} to {} using pg_dump...", db_name, data_file_path.display());
-        let data_dump_cmd_output = Command::new(&pg_dump_path)
-            .arg("--data-only")
-            .arg("--column-inserts") // Produces INSERT statements; good for compatibility if restore uses psql or similar
-            // .arg("--inserts") // Alternative: might be faster, one large INSERT per table
-            .arg("-f")
-            .arg(&data_file_path)
-            .arg(&db_specific_url_for_pg_dump)
-            .output()
-            .with_context(|| format!("Failed to execute pg_dump for data of database: {}", db_name))?;
-
-        if !data_dump_cmd_output.status.success() {
-            return Err(anyhow::anyhow!(
-                "pg_dump (data) for database {} failed with status: {}\nStdout: {}\nStderr: {}",
-                db_name,
-                schema_dump_cmd_output.status,
-                String::from_utf8_lossy(&data_dump_cmd_output.stdout),
-                String::from_utf8_lossy(&data_dump_cmd_output.stderr)
-            ));
+/// One dump invocation: the manifest kind, the entry it writes, the program and its arguments.
+pub(crate) struct DumpStep {
+    pub kind: &'static str,
+    pub entry_name: String,
+    pub program: DumpProgram,
+    pub args: Vec<String>,
+}
+
+/// The pg_dump invocations needed to dump one database in the given format.
+///
+/// Plain format runs a schema-only and a data-only pg_dump; custom and directory
+/// format produce a single archive with both, restored later with pg_restore.
+/// With filters, excluded objects are left out, and the rows of tables with a `where`
+/// condition are written by an extra psql step instead of pg_dump. Every step reads the
+/// exported `snapshot`, when there is one.
+pub(crate) fn dump_steps(
+    db_name: &str,
+    dump_format: DumpFormat,
+    jobs: usize,
+    selection: &TableSelection,
+    snapshot: Option<&str>,
+) -> Vec<DumpStep> {
+    dump_entry_names(db_name, dump_format)
+        .into_iter()
+        .filter_map(|(kind, entry_name)| {
+            let mut args: Vec<String> = match (dump_format, kind) {
+                (_, "rows") => {
+                    let args = selection.row_dump_args(snapshot);
+                    return (!args.is_empty()).then_some(DumpStep { kind, entry_name, program: DumpProgram::Psql, args });
+                }
+                (DumpFormat::Plain, "schema") => vec!["--schema-only".to_string()],
+                // --column-inserts produces INSERT statements; good for compatibility if restore uses psql or similar.
+                (DumpFormat::Plain, _) => vec!["--data-only".to_string(), "--column-inserts".to_string()],
+                (DumpFormat::Directory, _) if jobs > 1 => {
+                    vec![format!("--format={}", dump_format.as_str()), format!("--jobs={}", jobs)]
+                }
+                _ => vec![format!("--format={}", dump_format.as_str())],
+            };
+            args.extend(selection.pg_dump_args());
+            args.extend(snapshot.map(|snapshot| format!("--snapshot={}", snapshot)));
+            Some(DumpStep { kind, entry_name, program: DumpProgram::PgDump, args })
+        })
+        .collect()
+}
+
+/// Dumps one database in the configured format and returns the statistics for the manifest,
+/// read in the snapshot the dump was taken from.
+async fn dump_single_database(
+    pg_dump_path: &Path,
+    db_url: &str,
+    db_name: &str,
+    dump_dir: &Path,
+    backup_config: &BackupConfig,
+) -> Result<DatabaseStats> {
+    println!("Processing database with pg_dump: {}", db_name);
+    let selection = table_filter::resolve_database_filters(db_url, &backup_config.filters)
+        .await
+        .with_context(|| format!("Failed to apply filters to database {}", db_name))?;
+    let snapshot = DumpSnapshot::export(db_url)
+        .await
+        .with_context(|| format!("Failed to export a snapshot of database {}", db_name))?;
+
+    let steps = dump_steps(db_name, backup_config.dump_format, backup_config.jobs, &selection, Some(&snapshot.id));
+    for step in steps {
+        let output_path = dump_dir.join(&step.entry_name);
+        let args: Vec<&str> = step.args.iter().map(String::as_str).collect();
+        match step.program {
+            DumpProgram::Psql => {
+                println!("Dumping filtered rows of {} table(s) of {} to {} using psql...", selection.row_filters.len(), db_name, output_path.display());
+                run_dump_program(&find_psql_executable()?, "psql", &args, "-o", &output_path, db_url).await
+            }
+            DumpProgram::PgDump => {
+                println!("Dumping {} of {} to {} using pg_dump...", step.kind, db_name, output_path.display());
+                run_dump_program(pg_dump_path, "pg_dump", &args, "-f", &output_path, db_url).await
+            }
         }
-        println!("✓ Data for {} dumped successfully via pg_dump.", db_name);
-        
-        successfully_dumped_dbs.push(db_name.clone());
-        println!("✓ Successfully dumped schema and data for {} using pg_dump", db_name);
+        .with_context(|| format!("Dump ({}) for database {} failed", step.kind, db_name))?;
+        println!("✓ {} of {} dumped successfully.", step.kind, db_name);
     }
-
-    Ok(successfully_dumped_dbs)
+    snapshot
+        .collect_stats(&selection, backup_config.table_checksums)
+        .await
+        .with_context(|| format!("Failed to collect row counts of database {}", db_name))
 }
 
+/// Runs pg_dump (`-f`) or psql (`-o`) with its output written to `output_file`.
+async fn run_dump_program(
+    program_path: &Path,
+    program: &str,
+    args: &[&str],
+    output_flag: &str,
+    output_file: &Path,
+    db_url: &str,
+) -> Result<()> {
+    let output = TokioCommand::new(program_path)
+        .args(args)
+        .arg(output_flag)
+        .arg(output_file)
+        .arg(db_url) // pg_dump and psql accept the full URL
+        .kill_on_drop(true)
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {}", program))?;
 
+    if !output.status.success() {
+        anyhow::bail!(
+            "{} exited with status: {}\nStdout: {}\nStderr: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
 
 async fn get_database_list(conn: &mut PgConnection) -> Result<Vec<String>> {
     println!("Fetching list of databases...");
@@ -163,7 +383,7 @@ async fn get_database_list(conn: &mut PgConnection) -> Result<Vec<String>> {
     Ok(db_names)
 }
 
-fn get_base_url_without_db(full_url: &str) -> Result<String> {
+pub(crate) fn get_base_url_without_db(full_url: &str) -> Result<String> {
     let mut parsed_url = Url::parse(full_url)
         .with_context(|| format!("Invalid database URL format: {}", full_url))?;
     parsed_url.set_path("");
@@ -0,0 +1,214 @@
+// databasetool/src/backup/encryption.rs
+use age::secrecy::SecretString;
+use age::stream::{StreamReader, StreamWriter};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::config::EncryptionConfig;
+
+/// Suffix added to the names of encrypted archives, e.g. `2024-05-01_02-00-00.tar.gz.age`.
+pub const ENCRYPTED_ARCHIVE_SUFFIX: &str = ".age";
+
+/// Every age file starts with this header line, whatever the key type.
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1";
+
+/// Builds the age encryptor for the configured passphrase, recipients or key file.
+///
+/// With a key file, the archive is encrypted to the public keys of its identities, so
+/// the same file can be used for backup and restore.
+fn build_encryptor(config: &EncryptionConfig) -> Result<age::Encryptor> {
+    if let Some(passphrase) = &config.passphrase {
+        return Ok(age::Encryptor::with_user_passphrase(SecretString::from(passphrase.clone())));
+    }
+
+    let mut recipients: Vec<Box<dyn age::Recipient + Send>> = Vec::new();
+    for recipient in &config.recipients {
+        let parsed = age::x25519::Recipient::from_str(recipient.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid encryption recipient '{}': {}", recipient, e))?;
+        recipients.push(Box::new(parsed));
+    }
+    if let Some(key_file) = &config.key_file {
+        let identity_file = read_identity_file(key_file)?;
+        recipients.extend(
+            identity_file
+                .to_recipients()
+                .with_context(|| format!("Failed to derive public keys from key file {}", key_file.display()))?,
+        );
+    }
+
+    age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient.as_ref() as &dyn age::Recipient))
+        .context("Failed to set up archive encryption")
+}
+
+fn read_identity_file(key_file: &Path) -> Result<age::IdentityFile<age::NoCallbacks>> {
+    age::IdentityFile::from_file(key_file.to_string_lossy().into_owned())
+        .with_context(|| format!("Failed to read encryption key file {}", key_file.display()))
+}
+
+/// Writer that encrypts everything written to it when encryption is configured.
+///
+/// `finish` must be called once all data is written; an encrypted archive that is not
+/// finished is truncated and cannot be decrypted.
+pub enum ArchiveWriter<W: Write> {
+    Plain(W),
+    Encrypted(StreamWriter<W>),
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(output: W, encryption: Option<&EncryptionConfig>) -> Result<Self> {
+        match encryption {
+            None => Ok(ArchiveWriter::Plain(output)),
+            Some(config) => {
+                let writer = build_encryptor(config)?
+                    .wrap_output(output)
+                    .context("Failed to write the encryption header")?;
+                Ok(ArchiveWriter::Encrypted(writer))
+            }
+        }
+    }
+
+    /// Writes the final encrypted chunk (if any) and returns the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        match self {
+            ArchiveWriter::Plain(output) => Ok(output),
+            ArchiveWriter::Encrypted(writer) => writer.finish().context("Failed to finish archive encryption"),
+        }
+    }
+}
+
+impl<W: Write> Write for ArchiveWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            ArchiveWriter::Plain(output) => output.write(data),
+            ArchiveWriter::Encrypted(writer) => writer.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ArchiveWriter::Plain(output) => output.flush(),
+            ArchiveWriter::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Checks the first bytes of a file for the age header.
+pub fn is_encrypted_file(path: &Path) -> Result<bool> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open archive file: {}", path.display()))?;
+    let mut header = [0u8; AGE_MAGIC.len()];
+    let mut read = 0;
+    while read < header.len() {
+        match file.read(&mut header[read..]).with_context(|| format!("Failed to read archive file: {}", path.display()))? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(&header[..read] == AGE_MAGIC)
+}
+
+/// Wraps an encrypted archive in a decrypting reader.
+///
+/// Fails with an explanation when no key is configured or the configured key does not fit,
+/// instead of letting the gzip decoder report garbage.
+pub fn decrypting_reader<R: Read>(input: R, encryption: Option<&EncryptionConfig>) -> Result<StreamReader<R>> {
+    let config = encryption.context(
+        "The archive is encrypted, but no encryption block is configured. Set encryption.passphrase or encryption.key_file in config.json.",
+    )?;
+    let decryptor = age::Decryptor::new(input).context("The archive has a malformed encryption header")?;
+
+    let identities: Vec<Box<dyn age::Identity>> = if decryptor.is_scrypt() {
+        let passphrase = config.passphrase.as_ref().context(
+            "The archive is encrypted with a passphrase, but encryption.passphrase is not set in config.json.",
+        )?;
+        vec![Box::new(age::scrypt::Identity::new(SecretString::from(passphrase.clone())))]
+    } else {
+        let key_file = config.key_file.as_ref().context(
+            "The archive is encrypted to a public key. Restoring it needs the matching private key: set encryption.key_file in config.json.",
+        )?;
+        read_identity_file(key_file)?
+            .into_identities()
+            .with_context(|| format!("Failed to load identities from key file {}", key_file.display()))?
+    };
+
+    decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))
+        .map_err(|e| match e {
+            age::DecryptError::NoMatchingKeys | age::DecryptError::DecryptionFailed | age::DecryptError::KeyDecryptionFailed => {
+                anyhow::anyhow!("Failed to decrypt the archive: wrong passphrase or key file ({})", e)
+            }
+            other => anyhow::anyhow!("Failed to decrypt the archive: {}", other),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt(data: &[u8], config: &EncryptionConfig) -> Result<Vec<u8>> {
+        let mut writer = ArchiveWriter::new(Vec::new(), Some(config))?;
+        writer.write_all(data)?;
+        writer.finish()
+    }
+
+    fn decrypt(data: &[u8], config: &EncryptionConfig) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        decrypting_reader(data, Some(config))?.read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_passphrase_round_trip_and_wrong_passphrase() -> Result<()> {
+        let config = EncryptionConfig { passphrase: Some("correct horse".to_string()), ..Default::default() };
+        let encrypted = encrypt(b"customer data", &config)?;
+        assert!(encrypted.starts_with(AGE_MAGIC));
+        assert_eq!(decrypt(&encrypted, &config)?, b"customer data");
+
+        let wrong = EncryptionConfig { passphrase: Some("battery staple".to_string()), ..Default::default() };
+        let err = decrypt(&encrypted, &wrong).unwrap_err();
+        assert!(err.to_string().contains("wrong passphrase or key file"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_file_round_trip_and_recipient_needs_key_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let identity = age::x25519::Identity::generate();
+        let key_file = dir.path().join("backup.key");
+        std::fs::write(&key_file, format!("# backup key\n{}\n", age::secrecy::ExposeSecret::expose_secret(&identity.to_string())))?;
+
+        // Encrypting to the public key only; the key file decrypts.
+        let recipient_only = EncryptionConfig { recipients: vec![identity.to_public().to_string()], ..Default::default() };
+        let encrypted = encrypt(b"customer data", &recipient_only)?;
+        assert!(decrypt(&encrypted, &recipient_only).is_err());
+
+        let with_key_file = EncryptionConfig { key_file: Some(key_file.clone()), ..Default::default() };
+        assert_eq!(decrypt(&encrypted, &with_key_file)?, b"customer data");
+        assert_eq!(decrypt(&encrypt(b"more", &with_key_file)?, &with_key_file)?, b"more");
+
+        // A different key is reported as a wrong key.
+        let other_key_file = dir.path().join("other.key");
+        let other = age::x25519::Identity::generate();
+        std::fs::write(&other_key_file, age::secrecy::ExposeSecret::expose_secret(&other.to_string()))?;
+        let wrong = EncryptionConfig { key_file: Some(other_key_file), ..Default::default() };
+        let err = decrypt(&encrypted, &wrong).unwrap_err();
+        assert!(err.to_string().contains("wrong passphrase or key file"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_encrypted_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let encrypted_path = dir.path().join("a.tar.gz.age");
+        let config = EncryptionConfig { passphrase: Some("secret".to_string()), ..Default::default() };
+        std::fs::write(&encrypted_path, encrypt(b"data", &config)?)?;
+        let plain_path = dir.path().join("a.tar.gz");
+        std::fs::write(&plain_path, [0x1f, 0x8b, 0x08])?;
+
+        assert!(is_encrypted_file(&encrypted_path)?);
+        assert!(!is_encrypted_file(&plain_path)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+// databasetool/src/backup/globals.rs
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::process::Command as TokioCommand;
+use which::which;
+
+/// Name of the `pg_dumpall --globals-only` output at the root of the archive.
+pub const GLOBALS_FILE_NAME: &str = "globals.sql";
+
+fn find_pg_dumpall_executable() -> Result<PathBuf> {
+    which("pg_dumpall")
+        .context("pg_dumpall executable not found in PATH. Please ensure PostgreSQL client tools are installed and in your PATH.")
+}
+
+/// Arguments for dumping the roles, role memberships, role settings and tablespaces of the cluster.
+///
+/// Without `include_passwords` the password hashes are left out, which also lets a role
+/// without superuser rights run the dump (pg_authid is only readable by superusers).
+pub fn pg_dumpall_args(admin_url: &str, include_passwords: bool) -> Vec<String> {
+    let mut args = vec!["--globals-only".to_string(), "--no-password".to_string()];
+    if !include_passwords {
+        args.push("--no-role-passwords".to_string());
+    }
+    args.push(format!("--dbname={}", admin_url));
+    args
+}
+
+/// Runs `pg_dumpall --globals-only` against the source server and returns the SQL it printed.
+///
+/// The output is a few kilobytes even for large clusters, so it is kept in memory; the streaming
+/// backup appends it to the archive directly.
+pub async fn dump_globals(source_base_url: &str, include_passwords: bool) -> Result<Vec<u8>> {
+    let pg_dumpall_path = find_pg_dumpall_executable()?;
+    let admin_url = format!("{}/postgres", source_base_url.trim_end_matches('/'));
+    println!(
+        "Dumping roles, role memberships and tablespaces with pg_dumpall{}...",
+        if include_passwords { "" } else { " (without role passwords)" }
+    );
+    let output = TokioCommand::new(&pg_dumpall_path)
+        .args(pg_dumpall_args(&admin_url, include_passwords))
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to execute pg_dumpall")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "pg_dumpall exited with status: {}\nStderr: {}\nSet \"globals\": {{\"dump\": false}} to back up without roles.",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Dumps the cluster globals into `GLOBALS_FILE_NAME` inside `dump_dir`.
+pub async fn write_globals(source_base_url: &str, dump_dir: &Path, include_passwords: bool) -> Result<()> {
+    let sql = dump_globals(source_base_url, include_passwords).await?;
+    let path = dump_dir.join(GLOBALS_FILE_NAME);
+    std::fs::write(&path, sql).with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("✓ Cluster globals written to {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pg_dumpall_args_leave_out_passwords_by_default() {
+        let args = pg_dumpall_args("postgres://admin@db:5432/postgres", false);
+        assert_eq!(
+            args,
+            vec!["--globals-only", "--no-password", "--no-role-passwords", "--dbname=postgres://admin@db:5432/postgres"]
+        );
+        assert!(!pg_dumpall_args("postgres://admin@db:5432/postgres", true).contains(&"--no-role-passwords".to_string()));
+    }
+}
@@ -1,20 +1,25 @@
 // databasetool/src/backup/logic.rs
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use tempfile::{Builder as TempFileBuilder, TempDir};
 
-use crate::config::{AppConfig, BackupConfig};
-use crate::backup::{archive, db_dump, s3_upload};
+use crate::config::{AppConfig, BackupConfig, PruneConfig, UploadConfig};
+use crate::backup::{archive, db_dump, encryption, globals, manifest, retention, s3_upload, stream};
+use crate::catalog;
 
 
 /// Orchestrates the entire database backup process.
 ///
 /// 1. Sets up a temporary directory for SQL dumps.
-/// 2. Dumps databases to this temporary directory.
-/// 3. Creates a tar.gz archive of the dumped files.
+/// 2. Dumps the cluster globals (roles, memberships, tablespaces) and the databases to this temporary directory.
+/// 3. Writes `manifest.json` (checksums, row counts, versions) and creates a tar.gz archive.
 /// 4. Optionally uploads the archive to S3-compatible storage.
 /// 5. Cleans up the temporary dump directory.
+/// 6. Applies the retention policy (if configured) to the local directory and S3 prefix.
+///
+/// With `streaming_upload` the dumps are instead piped straight into an S3 multipart upload.
 pub async fn perform_backup_orchestration(
     app_config: &AppConfig,
     backup_config: &BackupConfig,
@@ -23,6 +28,14 @@ pub async fn perform_backup_orchestration(
     println!("Current working directory: {:?}", std::env::current_dir().unwrap_or_default());
     println!("Backup configuration: {:?}", backup_config);
 
+    if backup_config.streaming_upload {
+        return perform_streaming_backup(app_config, backup_config).await;
+    }
+    let local_backup_path = backup_config
+        .local_backup_path
+        .as_deref()
+        .context("local_backup_dir must be set in config.json for backup")?;
+
     // 1. Prepare temporary directory for SQL dumps
     // This will be a directory like /configured_temp_root/timestamp/ or /system_temp/timestamp/
     // The `_temp_dump_dir_guard` ensures cleanup if `temp_dump_root` was None.
@@ -34,49 +47,83 @@ pub async fn perform_backup_orchestration(
     println!("Temporary dump directory for this operation: {}", current_operation_dump_dir.display());
 
 
-    // 2. Dump databases
-    let dumped_db_names = db_dump::dump_databases(backup_config, &current_operation_dump_dir)
+    // 2. Dump the cluster globals, then the databases
+    let source_base_url = db_dump::get_base_url_without_db(&backup_config.source_db_url)?;
+    if backup_config.dump_globals {
+        globals::write_globals(&source_base_url, &current_operation_dump_dir, backup_config.include_role_passwords)
+            .await
+            .context("Failed to dump cluster globals")?;
+    }
+    let dump_summary = db_dump::dump_databases(backup_config, &current_operation_dump_dir)
         .await
         .context("Failed to dump databases")?;
+    let dumped_db_names = &dump_summary.successfully_dumped_dbs;
+    let failed_db_names: Vec<&str> = dump_summary.failed.iter().map(|(db, _)| db.as_str()).collect();
 
     if dumped_db_names.is_empty() {
+        if !failed_db_names.is_empty() {
+            anyhow::bail!("All database dumps failed: {:?}", failed_db_names);
+        }
         println!("No databases were dumped. Backup process might be incomplete or no databases were targeted.");
         // Depending on requirements, this could be an error or just a warning.
         // For now, continue to allow archiving an empty directory if that's the (unlikely) outcome.
     } else {
         println!("Successfully dumped databases: {:?}", dumped_db_names);
     }
+    if !failed_db_names.is_empty() {
+        println!(
+            "⚠️  {} database(s) failed to dump and will be missing from this archive: {:?}",
+            failed_db_names.len(),
+            failed_db_names
+        );
+    }
+
+    // 3. Write the manifest next to the dumps, then create the archive
+    manifest::write_manifest(
+        &source_base_url,
+        &current_operation_dump_dir,
+        dumped_db_names,
+        &dump_summary.stats,
+        backup_config.dump_format,
+        backup_config.compression.codec,
+    )
+    .await
+    .context("Failed to write backup manifest")?;
 
-    // 3. Create tar.gz archive
     // The archive name will be based on the timestamp used for the current_operation_dump_dir name.
     let archive_file_name_stem = current_operation_dump_dir
         .file_name()
         .and_then(|name| name.to_str())
-        .unwrap_or_else(|| "backup_unknown_ts"); // Fallback, should not happen with current setup
+        .unwrap_or("backup_unknown_ts"); // Fallback, should not happen with current setup
 
-    let archive_file_name = format!("{}.tar.gz", archive_file_name_stem);
+    let archive_file_name = archive::archive_file_name(archive_file_name_stem, &backup_config.compression, backup_config.encryption.is_some());
     
     // Ensure the local_backup_path (e.g., /mnt/backups) exists
-    if !backup_config.local_backup_path.exists() {
-        fs::create_dir_all(&backup_config.local_backup_path).with_context(|| {
+    if !local_backup_path.exists() {
+        fs::create_dir_all(local_backup_path).with_context(|| {
             format!(
                 "Failed to create local backup directory: {}",
-                backup_config.local_backup_path.display()
+                local_backup_path.display()
             )
         })?;
-        println!("Created local backup directory: {}", backup_config.local_backup_path.display());
-    } else if !backup_config.local_backup_path.is_dir() {
+        println!("Created local backup directory: {}", local_backup_path.display());
+    } else if !local_backup_path.is_dir() {
         return Err(anyhow::anyhow!(
             "LOCAL_BACKUP_DIR '{}' exists but is not a directory.",
-            backup_config.local_backup_path.display()
+            local_backup_path.display()
         ));
     }
 
 
-    let final_archive_path = backup_config.local_backup_path.join(&archive_file_name);
+    let final_archive_path = local_backup_path.join(&archive_file_name);
 
-    archive::create_tar_gz_archive(&current_operation_dump_dir, &final_archive_path)
-        .context("Failed to create tar.gz archive")?;
+    archive::create_archive(
+        &current_operation_dump_dir,
+        &final_archive_path,
+        &backup_config.compression,
+        backup_config.encryption.as_ref(),
+    )
+    .context("Failed to create archive")?;
     println!("Archive created at: {}", final_archive_path.display());
 
     // 4. Upload to S3/Spaces (if configured)
@@ -86,20 +133,23 @@ pub async fn perform_backup_orchestration(
             // Optional: Perform a connection check. Could be made configurable.
             // s3_upload::check_s3_connection(spaces_conf).await.context("S3 connection check failed")?;
 
-            let s3_key = if let Some(prefix) = &spaces_conf.folder_prefix {
-                if prefix.is_empty() {
-                    archive_file_name.clone()
-                } else {
-                    format!("{}/{}", prefix.trim_end_matches('/'), archive_file_name)
-                }
-            } else {
-                format!("database_backups/{}", archive_file_name) // Default prefix if none provided
-            };
-
-            s3_upload::upload_file_to_s3(spaces_conf, &final_archive_path, &s3_key)
+            let s3_key = s3_upload::backup_object_key(spaces_conf, &archive_file_name);
+
+            let metadata = HashMap::from([(
+                catalog::DATABASES_METADATA_KEY.to_string(),
+                dumped_db_names.join(","),
+            )]);
+            s3_upload::upload_file_to_s3(spaces_conf, &final_archive_path, &s3_key, metadata, None)
                 .await
                 .context("Failed to upload archive to S3/Spaces")?;
             println!("Successfully uploaded archive to S3/Spaces bucket: {}, key: {}", spaces_conf.bucket_name, s3_key);
+
+            if !backup_config.keep_local_copy {
+                fs::remove_file(&final_archive_path).with_context(|| {
+                    format!("Failed to remove local archive after upload: {}", final_archive_path.display())
+                })?;
+                println!("Removed local archive {} (keep_local_copy is false).", final_archive_path.display());
+            }
         } else {
             println!("Upload to Spaces requested, but Spaces is not configured. Skipping upload.");
         }
@@ -127,10 +177,170 @@ pub async fn perform_backup_orchestration(
         println!("System temporary dump directory {} will be cleaned up automatically.", current_operation_dump_dir.display());
     }
     
+    // A partial archive is kept, but the run still fails so schedulers notice.
+    // Retention is skipped so an incomplete backup never causes complete ones to be pruned.
+    if !dump_summary.failed.is_empty() {
+        let details: Vec<String> = dump_summary
+            .failed
+            .iter()
+            .map(|(db, e)| format!("{}: {:#}", db, e))
+            .collect();
+        anyhow::bail!(
+            "Backup archive {} is incomplete; {} database(s) failed to dump:\n  {}",
+            archive_file_name,
+            dump_summary.failed.len(),
+            details.join("\n  ")
+        );
+    }
+
+    // 6. Retention: only reached after the new archive is safely stored.
+    apply_retention_after_backup(app_config, backup_config).await?;
+
+    println!("✅ Backup orchestration completed.");
+    Ok(())
+}
+
+/// Streams the backup straight to S3 instead of building the archive in a temporary directory.
+///
+/// Any failure aborts the upload, so unlike the staged flow there are no partial archives.
+async fn perform_streaming_backup(
+    app_config: &AppConfig,
+    backup_config: &BackupConfig,
+) -> Result<()> {
+    let spaces_conf = app_config
+        .spaces_config
+        .as_ref()
+        .context("streaming_upload requires S3/Spaces to be configured")?;
+
+    let databases = db_dump::resolve_databases_to_backup(backup_config).await?;
+    if backup_config.max_parallel_databases > 1 {
+        println!("Note: databases are dumped one at a time when streaming; max_parallel_databases is ignored.");
+    }
+    println!("Databases to be backed up: {:?}", databases);
+
+    let timestamp = chrono::Local::now().format(retention::ARCHIVE_TIMESTAMP_FORMAT);
+    let archive_file_name = archive::archive_file_name(&timestamp.to_string(), &backup_config.compression, backup_config.encryption.is_some());
+    let streamed = stream::stream_backup_to_s3(backup_config, spaces_conf, &archive_file_name, &databases)
+        .await
+        .context("Failed to stream backup to S3/Spaces")?;
+    println!(
+        "Successfully streamed archive ({}) to S3/Spaces bucket: {}, key: {}",
+        catalog::format_size(streamed.uploaded_bytes),
+        spaces_conf.bucket_name,
+        streamed.s3_key
+    );
+    if let Some(local_copy) = &streamed.local_copy {
+        println!("Local copy written to: {}", local_copy.display());
+    }
+
+    apply_retention_after_backup(app_config, backup_config).await?;
+
     println!("✅ Backup orchestration completed.");
     Ok(())
 }
 
+/// Applies the retention policy (if configured) to every location the backup was written to.
+async fn apply_retention_after_backup(app_config: &AppConfig, backup_config: &BackupConfig) -> Result<()> {
+    let Some(policy) = &backup_config.retention else {
+        return Ok(());
+    };
+    if backup_config.keep_local_copy
+        && let Some(local_backup_path) = &backup_config.local_backup_path
+    {
+        retention::prune_local_archives(local_backup_path, policy, false)
+            .context("Failed to apply retention policy to local backup directory")?;
+    }
+    if backup_config.upload_to_spaces
+        && let Some(spaces_conf) = &app_config.spaces_config
+    {
+        retention::prune_s3_archives(spaces_conf, policy, false)
+            .await
+            .context("Failed to apply retention policy to S3/Spaces backups")?;
+    }
+    Ok(())
+}
+
+/// Uploads an existing local archive under the backup prefix.
+///
+/// Used to retry the upload of a backup whose upload failed or was interrupted. The databases
+/// in the archive are read from its entries and stored as object metadata, like a normal backup.
+pub async fn perform_upload_orchestration(
+    app_config: &AppConfig,
+    upload_config: &UploadConfig,
+) -> Result<()> {
+    let spaces_conf = app_config
+        .spaces_config
+        .as_ref()
+        .context("upload requires S3/Spaces to be configured")?;
+
+    let archive_file_name = upload_config
+        .archive_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("Archive path has no valid file name")?;
+    if retention::parse_archive_timestamp(archive_file_name).is_none() {
+        println!(
+            "⚠️  {} is not named like a backup archive (<timestamp>.tar.gz, .tar.zst, ...); list, restore selectors and retention will not see it.",
+            archive_file_name
+        );
+    }
+
+    // The database list of an encrypted archive is not readable without the key, so it is left
+    // out of the object metadata and `list --contents` shows it as unknown.
+    let mut metadata = HashMap::new();
+    if encryption::is_encrypted_file(&upload_config.archive_path)? {
+        println!("ℹ️  {} is encrypted; its database list is not recorded in the S3 metadata.", archive_file_name);
+    } else {
+        let databases = catalog::databases_in_local_archive(&upload_config.archive_path)
+            .context("Failed to read databases from archive")?;
+        metadata.insert(catalog::DATABASES_METADATA_KEY.to_string(), databases.join(","));
+    }
+    let s3_key = s3_upload::backup_object_key(spaces_conf, archive_file_name);
+    s3_upload::upload_file_to_s3(
+        spaces_conf,
+        &upload_config.archive_path,
+        &s3_key,
+        metadata,
+        upload_config.resume_upload_id.as_deref(),
+    )
+    .await
+    .context("Failed to upload archive to S3/Spaces")?;
+
+    println!("✅ Upload orchestration completed.");
+    Ok(())
+}
+
+/// Applies the retention policy outside of a backup run.
+///
+/// In dry-run mode the archives that would be kept and deleted are listed, but nothing is removed.
+pub async fn perform_prune_orchestration(
+    app_config: &AppConfig,
+    prune_config: &PruneConfig,
+) -> Result<()> {
+    println!("🧹 Starting prune orchestration...");
+    println!("Prune configuration: {:?}", prune_config);
+
+    let mut total = 0;
+    if let Some(local_path) = &prune_config.local_backup_path {
+        total += retention::prune_local_archives(local_path, &prune_config.retention, prune_config.dry_run)
+            .context("Failed to prune local backup directory")?;
+    }
+    if prune_config.prune_spaces
+        && let Some(spaces_conf) = &app_config.spaces_config
+    {
+        total += retention::prune_s3_archives(spaces_conf, &prune_config.retention, prune_config.dry_run)
+            .await
+            .context("Failed to prune S3/Spaces backups")?;
+    }
+
+    if prune_config.dry_run {
+        println!("✅ Dry run completed: {} archive(s) would be deleted.", total);
+    } else {
+        println!("✅ Prune orchestration completed: {} archive(s) deleted.", total);
+    }
+    Ok(())
+}
+
 /// Sets up the temporary directory for storing SQL dumps before archiving.
 ///
 /// If `configured_temp_root` is `Some`, a timestamped subdirectory is created within it.
@@ -144,7 +354,7 @@ pub async fn perform_backup_orchestration(
 fn setup_temporary_dump_directory(
     configured_temp_root: Option<&Path>,
 ) -> Result<(Option<TempDir>, PathBuf)> {
-    let timestamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+    let timestamp = chrono::Local::now().format(retention::ARCHIVE_TIMESTAMP_FORMAT).to_string();
 
     match configured_temp_root {
         Some(root_path) => {
@@ -0,0 +1,648 @@
+// databasetool/src/backup/manifest.rs
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, PgConnection, Row};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::backup::{db_dump, globals};
+use crate::config::{CompressionCodec, DumpFormat};
+use crate::utils::sql_script::quote_ident;
+use crate::utils::table_filter::{TableName, TableSelection};
+
+/// Name of the manifest file at the root of every backup archive.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Version of the manifest layout, bumped when fields change incompatibly.
+pub const MANIFEST_FORMAT_VERSION: u32 = 1;
+
+/// Describes the contents of a backup archive.
+///
+/// Written as `manifest.json` next to the dump files before archiving, and read back
+/// by restore before anything is applied to the target server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    pub tool_version: String,
+    pub created_at: String, // RFC 3339, UTC
+    pub source_server_version: Option<String>,
+    pub dump_format: String,
+    /// Codec of the archive the manifest was written into; absent in archives made before codecs were configurable.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// `globals.sql` from `pg_dumpall --globals-only`; absent when `globals.dump` was off or in older archives.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub globals: Option<ManifestFile>,
+    pub databases: Vec<ManifestDatabase>,
+}
+
+/// One dumped database: the files it was written to and what they contain, read in the
+/// snapshot pg_dump dumped from. Restore compares the restored database against it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestDatabase {
+    pub name: String,
+    pub files: Vec<ManifestFile>,
+    /// Exact row counts keyed by `schema.table`, as dumped (filtered rows only for `filters.where`).
+    pub row_counts: BTreeMap<String, i64>,
+    /// md5 of the sorted row texts keyed by `schema.table`; only with `table_checksums`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub table_checksums: BTreeMap<String, String>,
+    /// Number of tables, indexes, constraints, sequences, views and functions that were dumped;
+    /// absent in archives made before restores were verified against them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_counts: Option<BTreeMap<String, i64>>,
+    /// Owner, privileges and settings of the database itself, which pg_dump leaves out;
+    /// absent in archives made before they were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<DatabaseAccess>,
+}
+
+/// Database-level ownership, `GRANT ... ON DATABASE` and `ALTER DATABASE ... SET` of a dumped
+/// database, as read from pg_database and pg_db_role_setting. Restore re-applies them with `globals.restore`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseAccess {
+    pub owner: String,
+    /// `datacl` as aclitem texts such as `app_ro=c/postgres`; absent while the database has the default privileges.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acl: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub settings: Vec<DatabaseSetting>,
+}
+
+/// One `name=value` setting of a database, for every role or (`ALTER ROLE ... IN DATABASE`) for one role.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatabaseSetting {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    pub setting: String,
+}
+
+/// What a database contains, as recorded in the manifest and measured again after a restore.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DatabaseStats {
+    pub row_counts: BTreeMap<String, i64>,
+    pub table_checksums: BTreeMap<String, String>,
+    pub object_counts: BTreeMap<String, i64>,
+}
+
+/// A file inside the archive, relative to the archive root.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub path: String,
+    pub kind: String, // "schema" or "data" for plain dumps, "dump" for custom and directory format, "rows" for filters.where
+    pub size_bytes: u64,
+    pub sha256: String,
+}
+
+impl BackupManifest {
+    /// The dump format recorded at backup time.
+    pub fn dump_format(&self) -> Result<DumpFormat> {
+        DumpFormat::parse(&self.dump_format)
+    }
+
+    /// Names of the databases in the archive, in dump order.
+    pub fn database_names(&self) -> Vec<String> {
+        self.databases.iter().map(|db| db.name.clone()).collect()
+    }
+
+    /// Serializes the manifest as pretty-printed JSON, the form stored in archives.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize backup manifest")
+    }
+
+    /// Recomputes the SHA-256 of every file listed in the manifest and compares it with the recorded value.
+    ///
+    /// # Arguments
+    /// * `archive_root` - Directory the archive was extracted to (the one containing `manifest.json`).
+    ///
+    /// # Returns
+    /// An error naming every missing or mismatching file; restore must not continue in that case.
+    pub fn verify_checksums(&self, archive_root: &Path) -> Result<()> {
+        let mut problems = Vec::new();
+        for file in self.globals.iter().chain(self.databases.iter().flat_map(|db| &db.files)) {
+            let path = archive_root.join(&file.path);
+            if !path.is_file() {
+                problems.push(format!("{}: listed in manifest but missing from archive", file.path));
+                continue;
+            }
+            let actual = sha256_file(&path)?;
+            if actual != file.sha256 {
+                problems.push(format!("{}: expected sha256 {}, found {}", file.path, file.sha256, actual));
+            }
+        }
+
+        if !problems.is_empty() {
+            anyhow::bail!(
+                "Archive failed checksum verification ({} problem(s)):\n  {}",
+                problems.len(),
+                problems.join("\n  ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Builds the manifest for a finished dump directory and writes it as `manifest.json`.
+///
+/// # Arguments
+/// * `source_base_url` - Source server URL without a database path.
+/// * `dump_dir` - Directory holding the dump files of every database.
+/// * `database_names` - Databases that were successfully dumped.
+/// * `stats` - Row counts, checksums and object counts of each database, read while it was dumped.
+/// * `dump_format` - Format the databases were dumped in.
+/// * `compression` - Codec the archive will be compressed with.
+///
+/// # Returns
+/// The manifest that was written.
+pub async fn write_manifest(
+    source_base_url: &str,
+    dump_dir: &Path,
+    database_names: &[String],
+    stats: &BTreeMap<String, DatabaseStats>,
+    dump_format: DumpFormat,
+    compression: CompressionCodec,
+) -> Result<BackupManifest> {
+    println!("Writing backup manifest for {} database(s)...", database_names.len());
+
+    let mut files_by_database = Vec::with_capacity(database_names.len());
+    for db_name in database_names {
+        let mut files = Vec::new();
+        for (kind, entry_name) in db_dump::dump_entry_names(db_name, dump_format) {
+            let path = dump_dir.join(&entry_name);
+            if path.is_file() {
+                files.push(describe_file(&path, &entry_name, kind)?);
+            } else if path.is_dir() {
+                // Directory format: every file in the directory is listed on its own.
+                let mut paths: Vec<_> = WalkDir::new(&path)
+                    .into_iter()
+                    .collect::<Result<Vec<_>, _>>()
+                    .with_context(|| format!("Failed to walk dump directory: {}", path.display()))?
+                    .into_iter()
+                    .filter(|entry| entry.file_type().is_file())
+                    .map(|entry| entry.into_path())
+                    .collect();
+                paths.sort();
+                for file_path in paths {
+                    let relative = file_path.strip_prefix(dump_dir)?.to_string_lossy().replace('\\', "/");
+                    files.push(describe_file(&file_path, &relative, kind)?);
+                }
+            }
+        }
+        files_by_database.push((db_name.clone(), files));
+    }
+
+    let globals_path = dump_dir.join(globals::GLOBALS_FILE_NAME);
+    let globals = if globals_path.is_file() {
+        Some(describe_file(&globals_path, globals::GLOBALS_FILE_NAME, "globals")?)
+    } else {
+        None
+    };
+
+    let manifest = build_manifest(source_base_url, files_by_database, globals, stats, dump_format, compression).await;
+
+    let manifest_path = dump_dir.join(MANIFEST_FILE_NAME);
+    fs::write(&manifest_path, manifest.to_json()?)
+        .with_context(|| format!("Failed to write backup manifest: {}", manifest_path.display()))?;
+    println!("✓ Manifest written to {}", manifest_path.display());
+    Ok(manifest)
+}
+
+/// Assembles a manifest from already described files and the statistics taken while dumping,
+/// adding the server version and the ownership, privileges and settings of every database,
+/// read from the source server.
+///
+/// Databases without statistics are still recorded, with a warning and empty counts.
+pub async fn build_manifest(
+    source_base_url: &str,
+    files_by_database: Vec<(String, Vec<ManifestFile>)>,
+    globals: Option<ManifestFile>,
+    stats: &BTreeMap<String, DatabaseStats>,
+    dump_format: DumpFormat,
+    compression: CompressionCodec,
+) -> BackupManifest {
+    let mut source_server_version = None;
+    let mut access = BTreeMap::new();
+    if let Some((db_name, _)) = files_by_database.first() {
+        let db_url = format!("{}/{}", source_base_url.trim_end_matches('/'), db_name);
+        match PgConnection::connect(&db_url).await {
+            Ok(mut conn) => {
+                source_server_version = fetch_server_version(&mut conn).await.ok();
+                // pg_database is shared by the cluster, so one connection reads every database.
+                for (name, _) in &files_by_database {
+                    match collect_database_access(&mut conn, name).await {
+                        Ok(db_access) => {
+                            access.insert(name.clone(), db_access);
+                        }
+                        Err(e) => println!("⚠️  Could not read the owner and privileges of {}: {:#}", name, e),
+                    }
+                }
+                conn.close().await.ok();
+            }
+            Err(e) => println!("⚠️  Could not connect to {} to read the server version: {}", db_name, e),
+        }
+    }
+
+    let mut databases = Vec::with_capacity(files_by_database.len());
+    for (db_name, files) in files_by_database {
+        let database = match stats.get(&db_name) {
+            Some(stats) => ManifestDatabase {
+                row_counts: stats.row_counts.clone(),
+                table_checksums: stats.table_checksums.clone(),
+                object_counts: Some(stats.object_counts.clone()),
+                access: access.remove(&db_name),
+                name: db_name,
+                files,
+            },
+            None => {
+                println!("⚠️  No row counts were collected for {}; restore cannot verify it.", db_name);
+                ManifestDatabase {
+                    access: access.remove(&db_name),
+                    name: db_name,
+                    files,
+                    row_counts: BTreeMap::new(),
+                    table_checksums: BTreeMap::new(),
+                    object_counts: None,
+                }
+            }
+        };
+        databases.push(database);
+    }
+
+    BackupManifest {
+        format_version: MANIFEST_FORMAT_VERSION,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        source_server_version,
+        dump_format: dump_format.as_str().to_string(),
+        compression: Some(compression.as_str().to_string()),
+        globals,
+        databases,
+    }
+}
+
+/// Reads `manifest.json` from an extracted archive.
+///
+/// Returns `Ok(None)` for archives created before manifests existed.
+pub fn read_manifest(archive_root: &Path) -> Result<Option<BackupManifest>> {
+    let manifest_path = archive_root.join(MANIFEST_FILE_NAME);
+    if !manifest_path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Failed to read backup manifest: {}", manifest_path.display()))?;
+    let manifest: BackupManifest = serde_json::from_str(&content)
+        .with_context(|| format!("Backup manifest is not valid JSON: {}", manifest_path.display()))?;
+    if manifest.format_version > MANIFEST_FORMAT_VERSION {
+        anyhow::bail!(
+            "Backup manifest format version {} is newer than this tool supports ({}). Please upgrade databasetool.",
+            manifest.format_version,
+            MANIFEST_FORMAT_VERSION
+        );
+    }
+    Ok(Some(manifest))
+}
+
+/// Computes the lowercase hex SHA-256 of a file.
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open {} for hashing", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to hash {}", path.display()))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn describe_file(path: &Path, relative_path: &str, kind: &str) -> Result<ManifestFile> {
+    let size_bytes = fs::metadata(path)
+        .with_context(|| format!("Failed to read metadata of {}", path.display()))?
+        .len();
+    Ok(ManifestFile {
+        path: relative_path.to_string(),
+        kind: kind.to_string(),
+        size_bytes,
+        sha256: sha256_file(path)?,
+    })
+}
+
+async fn fetch_server_version(conn: &mut PgConnection) -> Result<String> {
+    let row = sqlx::query("SHOW server_version")
+        .fetch_one(conn)
+        .await
+        .context("Failed to query server_version")?;
+    row.try_get::<String, _>(0).context("Failed to read server_version")
+}
+
+/// Reads the owner, privileges and settings of database `db_name` from the shared catalogs.
+pub async fn collect_database_access(conn: &mut PgConnection, db_name: &str) -> Result<DatabaseAccess> {
+    let (owner, acl): (String, Option<Vec<String>>) =
+        sqlx::query_as("SELECT pg_get_userbyid(datdba)::text, datacl::text[] FROM pg_database WHERE datname = $1")
+            .bind(db_name)
+            .fetch_optional(&mut *conn)
+            .await
+            .context("Failed to read pg_database")?
+            .with_context(|| format!("Database {} not found in pg_database", db_name))?;
+    let rows: Vec<(Option<String>, Vec<String>)> = sqlx::query_as(
+        "SELECT r.rolname::text, s.setconfig FROM pg_db_role_setting s \
+         JOIN pg_database d ON d.oid = s.setdatabase LEFT JOIN pg_roles r ON r.oid = s.setrole \
+         WHERE d.datname = $1 ORDER BY 1 NULLS FIRST",
+    )
+    .bind(db_name)
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to read pg_db_role_setting")?;
+    let settings = rows
+        .into_iter()
+        .flat_map(|(role, config)| config.into_iter().map(move |setting| DatabaseSetting { role: role.clone(), setting }))
+        .collect();
+    Ok(DatabaseAccess { owner, acl, settings })
+}
+
+/// Counts the rows of every user table, keyed by `schema.table`.
+pub async fn collect_row_counts(conn: &mut PgConnection) -> Result<BTreeMap<String, i64>> {
+    let tables = sqlx::query(
+        "SELECT table_schema, table_name FROM information_schema.tables \
+         WHERE table_type = 'BASE TABLE' AND table_schema NOT IN ('pg_catalog', 'information_schema') \
+         ORDER BY table_schema, table_name",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to list tables for row counts")?;
+
+    let mut counts = BTreeMap::new();
+    for table in tables {
+        let schema: String = table.try_get("table_schema")?;
+        let name: String = table.try_get("table_name")?;
+        let query = format!(
+            "SELECT COUNT(*) FROM {}.{}",
+            quote_ident(&schema),
+            quote_ident(&name)
+        );
+        let count: i64 = sqlx::query_scalar(&query)
+            .fetch_one(&mut *conn)
+            .await
+            .with_context(|| format!("Failed to count rows of {}.{}", schema, name))?;
+        counts.insert(format!("{}.{}", schema, name), count);
+    }
+    Ok(counts)
+}
+
+/// Session settings that make `t::text` independent of the server and client configuration,
+/// so a checksum taken at backup time can be compared on another server. `SET LOCAL`: they
+/// only last until the end of the current transaction.
+const CHECKSUM_SETTINGS: &[&str] = &[
+    "SET LOCAL TimeZone = 'UTC'",
+    "SET LOCAL DateStyle = 'ISO, MDY'",
+    "SET LOCAL IntervalStyle = 'postgres'",
+    "SET LOCAL extra_float_digits = 1",
+    "SET LOCAL bytea_output = 'hex'",
+    "SET LOCAL lc_monetary = 'C'",
+];
+
+/// Applies `CHECKSUM_SETTINGS`; `conn` must be inside a transaction.
+pub async fn apply_checksum_settings(conn: &mut PgConnection) -> Result<()> {
+    for setting in CHECKSUM_SETTINGS {
+        sqlx::query(setting).execute(&mut *conn).await.with_context(|| format!("Failed to run {}", setting))?;
+    }
+    Ok(())
+}
+
+/// SQL for a checksum of the rows of `t`, each written as `row_text` (`t::text` unless
+/// columns are masked). Run it with `CHECKSUM_SETTINGS` applied.
+///
+/// Every row is hashed on its own and the first 64 bits of the hashes are summed, so the
+/// checksum depends on the contents only, not on the physical order of the rows, and is
+/// computed in one pass without sorting or holding the table in memory.
+pub fn rows_checksum(row_text: &str) -> String {
+    format!(
+        "md5(COUNT(*) || ':' || COALESCE(SUM(('x' || left(md5({}), 16))::bit(64)::bigint), 0))",
+        row_text
+    )
+}
+
+/// `SELECT COUNT(*), <checksum>` over the rows of `table` matching `condition`, with
+/// `rows_checksum` of every row as text. `NULL` without `checksum`.
+pub fn table_stats_query(table: &TableName, condition: Option<&str>, checksum: bool) -> String {
+    format!(
+        "SELECT COUNT(*), {} FROM {} t{}",
+        if checksum { rows_checksum("t::text") } else { "NULL::text".to_string() },
+        table.quoted(),
+        condition.map(|condition| format!(" WHERE ({})", condition)).unwrap_or_default()
+    )
+}
+
+/// Ordinary tables of the database, in name order. Partitioned parents hold no rows of their
+/// own; their partitions are listed instead.
+pub async fn list_tables(conn: &mut PgConnection) -> Result<Vec<TableName>> {
+    let rows = sqlx::query(
+        "SELECT n.nspname::text AS schema_name, c.relname::text AS table_name \
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+         WHERE c.relkind = 'r' AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+           AND n.nspname NOT LIKE 'pg_toast%' AND n.nspname NOT LIKE 'pg_temp_%' \
+         ORDER BY 1, 2",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to list tables")?;
+    rows.into_iter()
+        .map(|row| Ok(TableName { schema: row.try_get("schema_name")?, name: row.try_get("table_name")? }))
+        .collect()
+}
+
+/// Reads what the manifest records about one database: the rows of every table that is dumped,
+/// optionally their checksums, and the number of objects of each kind.
+///
+/// Run inside the transaction whose snapshot pg_dump used, so the numbers describe exactly
+/// what was dumped. `selection` is applied like pg_dump applies it: excluded tables are left
+/// out, schema-only tables count 0 rows and row-filtered tables only their matching rows.
+pub async fn collect_database_stats(
+    conn: &mut PgConnection,
+    selection: &TableSelection,
+    table_checksums: bool,
+) -> Result<DatabaseStats> {
+    apply_checksum_settings(conn).await?;
+    let mut stats = DatabaseStats::default();
+    for table in list_tables(conn).await? {
+        if selection.excludes(&table) {
+            continue;
+        }
+        let condition = match selection.row_filter(&table) {
+            _ if selection.schema_only_tables.contains(&table) => Some("false"),
+            Some(filter) => Some(filter.condition.as_str()),
+            None => None,
+        };
+        let (rows, checksum): (i64, Option<String>) = sqlx::query_as(&table_stats_query(&table, condition, table_checksums))
+            .fetch_one(&mut *conn)
+            .await
+            .with_context(|| format!("Failed to count rows of {}", table))?;
+        stats.row_counts.insert(table.to_string(), rows);
+        if let Some(checksum) = checksum {
+            stats.table_checksums.insert(table.to_string(), checksum);
+        }
+    }
+    stats.object_counts = collect_object_counts(conn, selection).await?;
+    Ok(stats)
+}
+
+/// Kinds of objects counted by `collect_object_counts`, in report order.
+pub const OBJECT_KINDS: [&str; 6] = ["tables", "indexes", "constraints", "sequences", "views", "functions"];
+
+/// Counts the user objects of each kind in `OBJECT_KINDS`, leaving out members of extensions
+/// (they come with `CREATE EXTENSION`) and what `selection` excludes. Indexes, constraints and
+/// owned sequences belong to their table and are left out with it.
+pub async fn collect_object_counts(conn: &mut PgConnection, selection: &TableSelection) -> Result<BTreeMap<String, i64>> {
+    let objects: Vec<(String, String, Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT o.kind, o.schema_name, o.table_schema, o.table_name FROM ( \
+           SELECT 'tables' AS kind, n.nspname::text AS schema_name, n.nspname::text AS table_schema, \
+                  c.relname::text AS table_name, 'pg_class'::regclass AS classid, c.oid AS objid \
+           FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace WHERE c.relkind IN ('r', 'p') \
+           UNION ALL \
+           SELECT 'indexes', n.nspname::text, n.nspname::text, t.relname::text, 'pg_class'::regclass, x.indexrelid \
+           FROM pg_index x JOIN pg_class t ON t.oid = x.indrelid JOIN pg_namespace n ON n.oid = t.relnamespace \
+           UNION ALL \
+           SELECT 'constraints', n.nspname::text, n.nspname::text, t.relname::text, 'pg_constraint'::regclass, k.oid \
+           FROM pg_constraint k JOIN pg_class t ON t.oid = k.conrelid JOIN pg_namespace n ON n.oid = t.relnamespace \
+           WHERE k.contype IN ('p', 'u', 'f', 'c', 'x') \
+           UNION ALL \
+           SELECT 'sequences', n.nspname::text, tn.nspname::text, t.relname::text, 'pg_class'::regclass, s.oid \
+           FROM pg_class s JOIN pg_namespace n ON n.oid = s.relnamespace \
+           LEFT JOIN pg_depend d ON d.classid = 'pg_class'::regclass AND d.objid = s.oid \
+                AND d.refclassid = 'pg_class'::regclass AND d.deptype IN ('a', 'i') \
+           LEFT JOIN pg_class t ON t.oid = d.refobjid LEFT JOIN pg_namespace tn ON tn.oid = t.relnamespace \
+           WHERE s.relkind = 'S' \
+           UNION ALL \
+           SELECT 'views', n.nspname::text, NULL, NULL, 'pg_class'::regclass, c.oid \
+           FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace WHERE c.relkind IN ('v', 'm') \
+           UNION ALL \
+           SELECT 'functions', n.nspname::text, NULL, NULL, 'pg_proc'::regclass, p.oid \
+           FROM pg_proc p JOIN pg_namespace n ON n.oid = p.pronamespace \
+         ) o \
+         WHERE o.schema_name NOT IN ('pg_catalog', 'information_schema') AND o.schema_name NOT LIKE 'pg_toast%' \
+           AND o.schema_name NOT LIKE 'pg_temp_%' \
+           AND NOT EXISTS (SELECT 1 FROM pg_depend e WHERE e.classid = o.classid AND e.objid = o.objid AND e.deptype = 'e')",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to count database objects")?;
+
+    let mut counts: BTreeMap<String, i64> = OBJECT_KINDS.iter().map(|kind| (kind.to_string(), 0)).collect();
+    for (kind, schema, table_schema, table_name) in objects {
+        let owner = table_schema.zip(table_name).map(|(schema, name)| TableName { schema, name });
+        if selection.excluded_schemas.contains(&schema) || owner.is_some_and(|owner| selection.excludes(&owner)) {
+            continue;
+        }
+        *counts.entry(kind).or_default() += 1;
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_manifest(dir: &Path) -> Result<BackupManifest> {
+        fs::write(dir.join("app_schema.sql"), "CREATE TABLE t (id int);")?;
+        fs::write(dir.join("app_data.sql"), "INSERT INTO t (id) VALUES (1);")?;
+        Ok(BackupManifest {
+            format_version: MANIFEST_FORMAT_VERSION,
+            tool_version: "0.1.0".to_string(),
+            created_at: "2024-05-01T02:00:00+00:00".to_string(),
+            source_server_version: Some("16.2".to_string()),
+            dump_format: "plain".to_string(),
+            compression: Some("zstd".to_string()),
+            databases: vec![ManifestDatabase {
+                name: "app".to_string(),
+                files: vec![
+                    describe_file(&dir.join("app_schema.sql"), "app_schema.sql", "schema")?,
+                    describe_file(&dir.join("app_data.sql"), "app_data.sql", "data")?,
+                ],
+                row_counts: BTreeMap::from([("public.t".to_string(), 1)]),
+                table_checksums: BTreeMap::from([("public.t".to_string(), "b026324c6904b2a9cb4b88d6d61c81d1".to_string())]),
+                object_counts: Some(OBJECT_KINDS.iter().map(|kind| (kind.to_string(), 1)).collect()),
+                access: Some(DatabaseAccess {
+                    owner: "app_owner".to_string(),
+                    acl: Some(vec!["=Tc/app_owner".to_string(), "app_owner=CTc/app_owner".to_string()]),
+                    settings: vec![DatabaseSetting { role: None, setting: "work_mem=64MB".to_string() }],
+                }),
+            }],
+            globals: None,
+        })
+    }
+
+    #[test]
+    fn test_sha256_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("abc.txt");
+        fs::write(&path, "abc")?;
+        assert_eq!(
+            sha256_file(&path)?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_round_trip_and_checksums() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let manifest = sample_manifest(dir.path())?;
+        fs::write(dir.path().join(MANIFEST_FILE_NAME), serde_json::to_string_pretty(&manifest)?)?;
+
+        let read_back = read_manifest(dir.path())?.expect("manifest should be present");
+        assert_eq!(read_back, manifest);
+        assert_eq!(read_back.database_names(), vec!["app".to_string()]);
+        read_back.verify_checksums(dir.path())?;
+
+        fs::write(dir.path().join("app_data.sql"), "INSERT INTO t (id) VALUES (2);")?;
+        let err = read_back.verify_checksums(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("app_data.sql"));
+
+        fs::remove_file(dir.path().join("app_schema.sql"))?;
+        let err = read_back.verify_checksums(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("2 problem(s)"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_globals_file_is_verified() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut manifest = sample_manifest(dir.path())?;
+        fs::write(dir.path().join(globals::GLOBALS_FILE_NAME), "CREATE ROLE app_owner;\n")?;
+        manifest.globals = Some(describe_file(&dir.path().join(globals::GLOBALS_FILE_NAME), globals::GLOBALS_FILE_NAME, "globals")?);
+        manifest.verify_checksums(dir.path())?;
+
+        fs::write(dir.path().join(globals::GLOBALS_FILE_NAME), "CREATE ROLE intruder SUPERUSER;\n")?;
+        let err = manifest.verify_checksums(dir.path()).unwrap_err();
+        assert!(err.to_string().contains(globals::GLOBALS_FILE_NAME));
+        Ok(())
+    }
+
+    #[test]
+    fn test_older_manifest_without_checksums_or_object_counts() -> Result<()> {
+        let database: ManifestDatabase =
+            serde_json::from_str(r#"{"name": "app", "files": [], "row_counts": {"public.t": 3}}"#)?;
+        assert!(database.table_checksums.is_empty());
+        assert_eq!(database.object_counts, None);
+        assert_eq!(database.access, None);
+        assert!(!serde_json::to_string(&database)?.contains("table_checksums"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_stats_query() {
+        let table = TableName { schema: "public".to_string(), name: "events".to_string() };
+        assert_eq!(table_stats_query(&table, None, false), "SELECT COUNT(*), NULL::text FROM \"public\".\"events\" t");
+        assert_eq!(
+            table_stats_query(&table, Some("kind <> 'debug'"), true),
+            "SELECT COUNT(*), md5(COUNT(*) || ':' || COALESCE(SUM(('x' || left(md5(t::text), 16))::bit(64)::bigint), 0)) \
+             FROM \"public\".\"events\" t WHERE (kind <> 'debug')"
+        );
+    }
+
+    #[test]
+    fn test_read_manifest_missing_is_none() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert!(read_manifest(dir.path())?.is_none());
+        Ok(())
+    }
+}
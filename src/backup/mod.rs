@@ -2,6 +2,12 @@ mod logic; // Keep existing logic, will be refactored internally
 pub(crate) mod s3_upload; // New module for S3 interactions
 pub(crate) mod archive;   // New module for tarball creation
 pub(crate) mod db_dump;    // New module for database dumping logic
+pub(crate) mod retention;  // Retention policy and pruning of old archives
+pub(crate) mod manifest;   // manifest.json with checksums and row counts
+pub(crate) mod stream;     // Streaming pg_dump -> tar -> gzip -> S3 multipart pipeline
+pub(crate) mod encryption; // Client-side age encryption of archives
+pub(crate) mod compression; // gzip/zstd/lz4 codecs and magic-byte detection
+pub(crate) mod globals;    // pg_dumpall --globals-only: roles, memberships and tablespaces
 
 use anyhow::Result;
 use crate::config::AppConfig;
@@ -17,4 +23,26 @@ pub async fn run_backup_flow(app_config: &AppConfig) -> Result<()> {
     // Delegate to the internal logic function, which will be refactored
     // to use the new modular components (s3_upload, archive, db_dump).
     logic::perform_backup_orchestration(app_config, backup_config).await
-}
\ No newline at end of file
+}
+
+/// Public entry point for the standalone prune command.
+/// Applies the configured retention policy to the local backup directory and the S3 prefix.
+pub async fn run_prune_flow(app_config: &AppConfig) -> Result<()> {
+    let prune_config = match &app_config.operation {
+        Some(crate::config::OperationConfig::Prune(cfg)) => cfg,
+        _ => anyhow::bail!("Prune operation selected but no prune configuration found."),
+    };
+
+    logic::perform_prune_orchestration(app_config, prune_config).await
+}
+
+/// Public entry point for the standalone upload command.
+/// Uploads an existing local archive, resuming an interrupted multipart upload when possible.
+pub async fn run_upload_flow(app_config: &AppConfig) -> Result<()> {
+    let upload_config = match &app_config.operation {
+        Some(crate::config::OperationConfig::Upload(cfg)) => cfg,
+        _ => anyhow::bail!("Upload operation selected but no upload configuration found."),
+    };
+
+    logic::perform_upload_orchestration(app_config, upload_config).await
+}
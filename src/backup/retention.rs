@@ -0,0 +1,265 @@
+// databasetool/src/backup/retention.rs
+use anyhow::{Context, Result};
+use chrono::{Datelike, NaiveDateTime};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::backup::s3_upload;
+use crate::catalog::{self, ArchiveLocation, CatalogEntry};
+use crate::config::{RetentionPolicy, SpacesConfig};
+
+/// Timestamp format used for archive names, e.g. `2024-05-01_02-00-00.tar.gz`.
+pub const ARCHIVE_TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// An archive found in a backup location together with the time encoded in its name.
+#[derive(Debug, Clone)]
+pub struct ArchiveCandidate {
+    pub file_name: String,
+    pub timestamp: NaiveDateTime,
+}
+
+impl From<CatalogEntry> for ArchiveCandidate {
+    fn from(entry: CatalogEntry) -> Self {
+        let file_name = match &entry.location {
+            ArchiveLocation::Local(path) => path.file_name().map(|name| name.to_string_lossy().into_owned()),
+            ArchiveLocation::S3 { key, .. } => key.rsplit('/').next().map(str::to_string),
+        };
+        ArchiveCandidate {
+            file_name: file_name.unwrap_or(entry.id),
+            timestamp: entry.timestamp,
+        }
+    }
+}
+
+/// Result of applying a retention policy: which archives stay and which go.
+/// Each kept archive carries the rules that kept it, for the dry-run listing.
+#[derive(Debug, Default)]
+pub struct RetentionPlan {
+    pub keep: Vec<(ArchiveCandidate, Vec<&'static str>)>,
+    pub prune: Vec<ArchiveCandidate>,
+}
+
+/// Extracts the backup timestamp from an archive file name.
+///
+/// Only names produced by the backup flow (`<timestamp>.tar...`) are recognised;
+/// anything else returns `None` and is never considered for pruning.
+pub fn parse_archive_timestamp(file_name: &str) -> Option<NaiveDateTime> {
+    let stamp = file_name.get(..19)?;
+    let rest = &file_name[19..];
+    if !rest.starts_with(".tar") {
+        return None;
+    }
+    NaiveDateTime::parse_from_str(stamp, ARCHIVE_TIMESTAMP_FORMAT).ok()
+}
+
+/// Decides which archives to keep under a grandfather-father-son policy.
+///
+/// Archives are walked newest first. `keep_last` keeps the N most recent archives;
+/// the daily, weekly and monthly rules each keep the newest archive of the N most
+/// recent days, ISO weeks and months that have a backup. An archive kept by any rule survives.
+pub fn plan_retention(mut archives: Vec<ArchiveCandidate>, policy: &RetentionPolicy) -> RetentionPlan {
+    archives.sort_by_key(|archive| std::cmp::Reverse(archive.timestamp));
+
+    let mut seen_days = HashSet::new();
+    let mut seen_weeks = HashSet::new();
+    let mut seen_months = HashSet::new();
+    let mut plan = RetentionPlan::default();
+
+    for (index, archive) in archives.into_iter().enumerate() {
+        let date = archive.timestamp.date();
+        let mut reasons = Vec::new();
+
+        if index < policy.keep_last {
+            reasons.push("last");
+        }
+        if seen_days.len() < policy.keep_daily && seen_days.insert(date) {
+            reasons.push("daily");
+        }
+        let week = (date.iso_week().year(), date.iso_week().week());
+        if seen_weeks.len() < policy.keep_weekly && seen_weeks.insert(week) {
+            reasons.push("weekly");
+        }
+        let month = (date.year(), date.month());
+        if seen_months.len() < policy.keep_monthly && seen_months.insert(month) {
+            reasons.push("monthly");
+        }
+
+        if reasons.is_empty() {
+            plan.prune.push(archive);
+        } else {
+            plan.keep.push((archive, reasons));
+        }
+    }
+    plan
+}
+
+/// Applies the retention policy to archives in a local backup directory.
+///
+/// Returns the number of archives deleted (or that would be deleted in dry-run mode).
+pub fn prune_local_archives(backup_dir: &Path, policy: &RetentionPolicy, dry_run: bool) -> Result<usize> {
+    println!("🧹 Applying retention policy to local backup directory: {}", backup_dir.display());
+    if !backup_dir.is_dir() {
+        println!("Local backup directory {} does not exist. Nothing to prune.", backup_dir.display());
+        return Ok(0);
+    }
+
+    let candidates = catalog::list_local_archives(backup_dir, false)?
+        .into_iter()
+        .map(ArchiveCandidate::from)
+        .collect();
+
+    let plan = plan_retention(candidates, policy);
+    print_plan("local", &plan, dry_run);
+
+    if !dry_run {
+        for archive in &plan.prune {
+            let path = backup_dir.join(&archive.file_name);
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to delete expired archive: {}", path.display()))?;
+            println!("✓ Deleted {}", path.display());
+        }
+    }
+    Ok(plan.prune.len())
+}
+
+/// Applies the retention policy to archives stored directly under the S3 backup prefix.
+///
+/// Returns the number of objects deleted (or that would be deleted in dry-run mode).
+pub async fn prune_s3_archives(spaces_config: &SpacesConfig, policy: &RetentionPolicy, dry_run: bool) -> Result<usize> {
+    println!(
+        "🧹 Applying retention policy to s3://{}/{}",
+        spaces_config.bucket_name,
+        s3_upload::backup_key_prefix(spaces_config)
+    );
+
+    let client = s3_upload::build_s3_client(spaces_config).await;
+    let candidates = catalog::list_s3_archives(&client, spaces_config, false)
+        .await?
+        .into_iter()
+        .map(ArchiveCandidate::from)
+        .collect();
+
+    let plan = plan_retention(candidates, policy);
+    print_plan("s3", &plan, dry_run);
+
+    if !dry_run {
+        for archive in &plan.prune {
+            let key = s3_upload::backup_object_key(spaces_config, &archive.file_name);
+            client
+                .delete_object()
+                .bucket(&spaces_config.bucket_name)
+                .key(&key)
+                .send()
+                .await
+                .with_context(|| format!("Failed to delete expired archive s3://{}/{}", spaces_config.bucket_name, key))?;
+            println!("✓ Deleted s3://{}/{}", spaces_config.bucket_name, key);
+        }
+    }
+    Ok(plan.prune.len())
+}
+
+fn print_plan(location: &str, plan: &RetentionPlan, dry_run: bool) {
+    println!(
+        "Retention ({}): {} archive(s) kept, {} archive(s) {}",
+        location,
+        plan.keep.len(),
+        plan.prune.len(),
+        if dry_run { "would be deleted" } else { "to delete" }
+    );
+    for (archive, reasons) in &plan.keep {
+        println!("   keep    {}  ({})", archive.file_name, reasons.join(", "));
+    }
+    for archive in &plan.prune {
+        println!("   {}  {}", if dry_run { "prune*" } else { "prune " }, archive.file_name);
+    }
+    if dry_run && !plan.prune.is_empty() {
+        println!("   * dry run: nothing was deleted");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(name: &str) -> ArchiveCandidate {
+        ArchiveCandidate {
+            file_name: name.to_string(),
+            timestamp: parse_archive_timestamp(name).unwrap(),
+        }
+    }
+
+    fn names(archives: &[ArchiveCandidate]) -> Vec<&str> {
+        archives.iter().map(|a| a.file_name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_archive_timestamp() {
+        assert!(parse_archive_timestamp("2024-05-01_02-00-00.tar.gz").is_some());
+        assert!(parse_archive_timestamp("2024-05-01_02-00-00.zip").is_none());
+        assert!(parse_archive_timestamp("notes.txt").is_none());
+        assert!(parse_archive_timestamp("2024-13-01_02-00-00.tar.gz").is_none());
+    }
+
+    #[test]
+    fn test_keep_last_only() {
+        let archives = vec![
+            candidate("2024-05-01_02-00-00.tar.gz"),
+            candidate("2024-05-03_02-00-00.tar.gz"),
+            candidate("2024-05-02_02-00-00.tar.gz"),
+        ];
+        let policy = RetentionPolicy { keep_last: 2, ..Default::default() };
+        let plan = plan_retention(archives, &policy);
+        assert_eq!(names(&plan.prune), vec!["2024-05-01_02-00-00.tar.gz"]);
+        assert_eq!(plan.keep.len(), 2);
+    }
+
+    #[test]
+    fn test_grandfather_father_son() {
+        let archives = vec![
+            candidate("2024-03-31_02-00-00.tar.gz"),
+            candidate("2024-04-30_02-00-00.tar.gz"),
+            candidate("2024-05-01_02-00-00.tar.gz"),
+            candidate("2024-05-01_14-00-00.tar.gz"),
+            candidate("2024-05-02_02-00-00.tar.gz"),
+            candidate("2024-05-08_02-00-00.tar.gz"),
+        ];
+        let policy = RetentionPolicy { keep_last: 1, keep_daily: 2, keep_weekly: 2, keep_monthly: 3 };
+        let plan = plan_retention(archives, &policy);
+
+        // 05-08 is last/daily/weekly/monthly, 05-02 is daily and newest of its week,
+        // 04-30 is newest of April, 03-31 newest of March. Both 05-01 runs are superseded.
+        assert_eq!(
+            names(&plan.prune),
+            vec!["2024-05-01_14-00-00.tar.gz", "2024-05-01_02-00-00.tar.gz"]
+        );
+        let kept: Vec<&str> = plan.keep.iter().map(|(a, _)| a.file_name.as_str()).collect();
+        assert_eq!(
+            kept,
+            vec![
+                "2024-05-08_02-00-00.tar.gz",
+                "2024-05-02_02-00-00.tar.gz",
+                "2024-04-30_02-00-00.tar.gz",
+                "2024-03-31_02-00-00.tar.gz",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prune_local_archives_dry_run_and_delete() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["2024-05-01_02-00-00.tar.gz", "2024-05-02_02-00-00.tar.gz", "unrelated.txt"] {
+            fs::write(dir.path().join(name), b"x")?;
+        }
+        let policy = RetentionPolicy { keep_last: 1, ..Default::default() };
+
+        assert_eq!(prune_local_archives(dir.path(), &policy, true)?, 1);
+        assert!(dir.path().join("2024-05-01_02-00-00.tar.gz").exists());
+
+        assert_eq!(prune_local_archives(dir.path(), &policy, false)?, 1);
+        assert!(!dir.path().join("2024-05-01_02-00-00.tar.gz").exists());
+        assert!(dir.path().join("2024-05-02_02-00-00.tar.gz").exists());
+        assert!(dir.path().join("unrelated.txt").exists());
+        Ok(())
+    }
+}
@@ -1,25 +1,30 @@
 // databasetool/src/backup/s3_upload.rs
 use anyhow::{Context, Result};
 use aws_sdk_s3 as s3;
+use bytes::Bytes;
+use md5::{Digest, Md5};
 use s3::primitives::ByteStream;
 use s3::config::Region;
-use std::path::Path;
-// Removed: use tokio::fs::File;
+use s3::error::{ProvideErrorMetadata, SdkError};
+use s3::operation::upload_part::UploadPartError;
+use s3::types::{CompletedMultipartUpload, CompletedPart};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use crate::config::SpacesConfig;
 
-/// Uploads a file to an S3-compatible object storage service (like DigitalOcean Spaces).
-pub async fn upload_file_to_s3(
-    spaces_config: &SpacesConfig,
-    file_path: &Path,
-    s3_key: &str,
-) -> Result<()> {
-    println!(
-        "Attempting to upload {} to S3 bucket {} with key {}",
-        file_path.display(),
-        spaces_config.bucket_name,
-        s3_key
-    );
+/// S3 refuses multipart uploads with more parts than this.
+const MAX_MULTIPART_PARTS: u64 = 10_000;
 
+/// Builds an S3 client for the configured S3-compatible endpoint using static credentials.
+pub async fn build_s3_client(spaces_config: &SpacesConfig) -> s3::Client {
     let sdk_config = aws_config::defaults(s3::config::BehaviorVersion::latest())
         .endpoint_url(&spaces_config.endpoint_url)
         .region(Region::new(spaces_config.region.clone()))
@@ -33,27 +38,92 @@ pub async fn upload_file_to_s3(
         .load()
         .await;
 
-    let client = s3::Client::new(&sdk_config);
+    let s3_config = s3::config::Builder::from(&sdk_config)
+        .force_path_style(spaces_config.force_path_style)
+        .build();
+    s3::Client::from_conf(s3_config)
+}
 
-    let body = ByteStream::from_path(file_path)
-        .await
-        .with_context(|| format!("Failed to create ByteStream from file: {}", file_path.display()))?;
+/// Returns the key prefix (without trailing slash) under which backup archives are stored.
+///
+/// Falls back to `database_backups` when no `folder_prefix` is configured.
+pub fn backup_key_prefix(spaces_config: &SpacesConfig) -> String {
+    match &spaces_config.folder_prefix {
+        Some(prefix) => prefix.trim_end_matches('/').to_string(),
+        None => "database_backups".to_string(), // Default prefix if none provided
+    }
+}
 
-    client
-        .put_object()
-        .bucket(&spaces_config.bucket_name)
-        .key(s3_key)
-        .body(body)
-        .send()
+/// Builds the full object key for an archive file name under the backup prefix.
+pub fn backup_object_key(spaces_config: &SpacesConfig, archive_file_name: &str) -> String {
+    let prefix = backup_key_prefix(spaces_config);
+    if prefix.is_empty() {
+        archive_file_name.to_string()
+    } else {
+        format!("{}/{}", prefix, archive_file_name)
+    }
+}
+
+/// Uploads a file to an S3-compatible object storage service (like DigitalOcean Spaces).
+///
+/// Files up to one part size are sent with a single `put_object`; larger files use a multipart
+/// upload with `transfer.concurrency` parts in flight, each retried with exponential backoff.
+/// While a multipart upload runs, its upload ID is saved next to the file (see `upload_state_path`)
+/// so an interrupted run can pick up where it stopped. If a part still fails after all retries the
+/// upload is aborted, so no abandoned parts are left in the bucket.
+///
+/// # Arguments
+/// * `spaces_config` - Destination bucket and transfer settings.
+/// * `file_path` - Local file to upload.
+/// * `s3_key` - Object key to write.
+/// * `metadata` - S3 user metadata stored on the object (e.g. the databases in the archive).
+/// * `resume_upload_id` - Upload ID of an interrupted multipart upload to continue instead of the saved one.
+pub async fn upload_file_to_s3(
+    spaces_config: &SpacesConfig,
+    file_path: &Path,
+    s3_key: &str,
+    metadata: HashMap<String, String>,
+    resume_upload_id: Option<&str>,
+) -> Result<()> {
+    println!(
+        "Attempting to upload {} to S3 bucket {} with key {}",
+        file_path.display(),
+        spaces_config.bucket_name,
+        s3_key
+    );
+
+    let file_size = tokio::fs::metadata(file_path)
         .await
-        .with_context(|| {
-            format!(
-                "Failed to upload file {} to S3 bucket {} with key {}",
-                file_path.display(),
-                spaces_config.bucket_name,
-                s3_key
-            )
-        })?;
+        .with_context(|| format!("Failed to read metadata of file: {}", file_path.display()))?
+        .len();
+    let client = build_s3_client(spaces_config).await;
+
+    if file_size <= spaces_config.transfer.part_size_bytes && resume_upload_id.is_none() {
+        with_retries(spaces_config.transfer.max_retries, &format!("Upload of {}", s3_key), || async {
+            let body = ByteStream::from_path(file_path)
+                .await
+                .with_context(|| format!("Failed to create ByteStream from file: {}", file_path.display()))?;
+            client
+                .put_object()
+                .bucket(&spaces_config.bucket_name)
+                .key(s3_key)
+                .set_metadata(Some(metadata.clone()))
+                .body(body)
+                .send()
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to upload file {} to S3 bucket {} with key {}",
+                        file_path.display(),
+                        spaces_config.bucket_name,
+                        s3_key
+                    )
+                })
+        })
+        .await?;
+    } else {
+        upload_file_multipart(&client, spaces_config, file_path, file_size, s3_key, metadata, resume_upload_id).await?;
+    }
 
     println!(
         "✅ Successfully uploaded {} to S3 bucket {} with key {}",
@@ -64,6 +134,522 @@ pub async fn upload_file_to_s3(
     Ok(())
 }
 
+/// Progress of a multipart upload, saved next to the file so an interrupted upload can resume.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct UploadState {
+    bucket: String,
+    key: String,
+    upload_id: String,
+    part_size: u64,
+    file_size: u64,
+}
+
+/// Where the state of an in-progress upload of `file_path` is saved.
+///
+/// The leading dot keeps the file out of archive listings and retention, which only
+/// consider names starting with a backup timestamp.
+pub fn upload_state_path(file_path: &Path) -> PathBuf {
+    let file_name = file_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    file_path.with_file_name(format!(".{}.upload", file_name))
+}
+
+fn read_upload_state(state_path: &Path) -> Option<UploadState> {
+    let content = std::fs::read_to_string(state_path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            println!("⚠️  Ignoring unreadable upload state {}: {}", state_path.display(), e);
+            None
+        }
+    }
+}
+
+fn write_upload_state(state_path: &Path, state: &UploadState) -> Result<()> {
+    let json = serde_json::to_string_pretty(state).context("Failed to serialize upload state")?;
+    std::fs::write(state_path, json)
+        .with_context(|| format!("Failed to write upload state: {}", state_path.display()))
+}
+
+/// One part of a multipart upload: its 1-based number and byte range in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PartRange {
+    number: i32,
+    offset: u64,
+    len: u64,
+}
+
+/// Grows `part_size` (in whole MiB) when needed so the file fits in S3's 10,000 part limit.
+fn effective_part_size(file_size: u64, part_size: u64) -> u64 {
+    const MIB: u64 = 1024 * 1024;
+    let min_part_size = file_size.div_ceil(MAX_MULTIPART_PARTS).div_ceil(MIB) * MIB;
+    part_size.max(min_part_size)
+}
+
+/// Splits a file into consecutive parts of `part_size` bytes; the last part may be shorter.
+fn plan_parts(file_size: u64, part_size: u64) -> Vec<PartRange> {
+    let mut parts = Vec::new();
+    let mut offset = 0;
+    while offset < file_size || parts.is_empty() {
+        let len = part_size.min(file_size - offset);
+        parts.push(PartRange { number: parts.len() as i32 + 1, offset, len });
+        offset += len;
+    }
+    parts
+}
+
+/// Uploads `file_path` as a multipart upload, resuming a saved or given upload ID when possible.
+async fn upload_file_multipart(
+    client: &s3::Client,
+    spaces_config: &SpacesConfig,
+    file_path: &Path,
+    file_size: u64,
+    s3_key: &str,
+    metadata: HashMap<String, String>,
+    resume_upload_id: Option<&str>,
+) -> Result<()> {
+    let transfer = spaces_config.transfer;
+    let bucket = spaces_config.bucket_name.clone();
+    let state_path = upload_state_path(file_path);
+    let saved_state = read_upload_state(&state_path);
+    let planned_part_size = effective_part_size(file_size, transfer.part_size_bytes);
+
+    let resumable = match resume_upload_id {
+        Some(upload_id) => Some(UploadState {
+            bucket: bucket.clone(),
+            key: s3_key.to_string(),
+            upload_id: upload_id.to_string(),
+            part_size: saved_state
+                .filter(|state| state.upload_id == upload_id)
+                .map_or(planned_part_size, |state| state.part_size),
+            file_size,
+        }),
+        None => match saved_state {
+            Some(state) if state.bucket == bucket && state.key == s3_key && state.file_size == file_size => Some(state),
+            Some(stale) => {
+                println!("⚠️  Saved upload {} no longer matches {}, discarding it", stale.upload_id, file_path.display());
+                if let Err(abort_err) = abort_multipart_upload(client, &stale).await {
+                    println!("⚠️  {:#}", abort_err);
+                }
+                None
+            }
+            None => None,
+        },
+    };
+
+    let mut existing_parts = HashMap::new();
+    let mut state = None;
+    if let Some(resumable) = resumable {
+        match list_uploaded_parts(client, &resumable, transfer.max_retries).await {
+            Ok(parts) => {
+                println!(
+                    "Resuming multipart upload {} of s3://{}/{} ({} part(s) already uploaded)",
+                    resumable.upload_id,
+                    bucket,
+                    s3_key,
+                    parts.len()
+                );
+                existing_parts = parts;
+                state = Some(resumable);
+            }
+            Err(e) if resume_upload_id.is_some() => {
+                return Err(e).with_context(|| format!("Cannot resume multipart upload {}", resumable.upload_id));
+            }
+            Err(e) => println!("⚠️  Saved upload {} cannot be resumed, starting a new one: {:#}", resumable.upload_id, e),
+        }
+    }
+
+    let state = match state {
+        Some(state) => state,
+        None => {
+            let upload_id = with_retries(transfer.max_retries, &format!("Starting multipart upload of {}", s3_key), || async {
+                let output = client
+                    .create_multipart_upload()
+                    .bucket(&bucket)
+                    .key(s3_key)
+                    .set_metadata(Some(metadata.clone()))
+                    .send()
+                    .await
+                    .with_context(|| format!("Failed to start multipart upload to s3://{}/{}", bucket, s3_key))?;
+                output
+                    .upload_id()
+                    .map(str::to_string)
+                    .context("S3 did not return an upload ID for the multipart upload")
+            })
+            .await?;
+            println!("Started multipart upload to s3://{}/{} (upload ID {})", bucket, s3_key, upload_id);
+            UploadState {
+                bucket: bucket.clone(),
+                key: s3_key.to_string(),
+                upload_id,
+                part_size: planned_part_size,
+                file_size,
+            }
+        }
+    };
+    write_upload_state(&state_path, &state)?;
+
+    let parts = plan_parts(file_size, state.part_size);
+    match upload_file_parts(client, &state, file_path, &parts, existing_parts, transfer.concurrency, transfer.max_retries).await {
+        Ok(completed_parts) => {
+            complete_multipart_upload(client, &state, completed_parts, transfer.max_retries).await?;
+            let _ = std::fs::remove_file(&state_path);
+            Ok(())
+        }
+        Err(e) if is_transient_failure(&e) => {
+            // Keep the upload ID and state file so the parts already stored are reused next time
+            println!(
+                "⚠️  Multipart upload {} of s3://{}/{} was interrupted; its state is kept in {}. \
+                 Upload the file again to resume, e.g. `databasetool upload {} --upload-id {}` for an archive.",
+                state.upload_id,
+                state.bucket,
+                state.key,
+                state_path.display(),
+                file_path.display(),
+                state.upload_id
+            );
+            Err(e).context("Multipart upload was interrupted and can be resumed")
+        }
+        Err(e) => {
+            if let Err(abort_err) = abort_multipart_upload(client, &state).await {
+                println!("⚠️  {:#}", abort_err);
+            }
+            let _ = std::fs::remove_file(&state_path);
+            Err(e).context("Multipart upload failed and was aborted")
+        }
+    }
+}
+
+/// Lists the parts already stored for an upload as part number -> (ETag, size).
+async fn list_uploaded_parts(
+    client: &s3::Client,
+    state: &UploadState,
+    max_retries: u32,
+) -> Result<HashMap<i32, (String, u64)>> {
+    let mut parts = HashMap::new();
+    let mut marker: Option<String> = None;
+    loop {
+        let response = with_retries(max_retries, &format!("Listing parts of upload {}", state.upload_id), || async {
+            client
+                .list_parts()
+                .bucket(&state.bucket)
+                .key(&state.key)
+                .upload_id(&state.upload_id)
+                .set_part_number_marker(marker.clone())
+                .send()
+                .await
+                .with_context(|| format!("Failed to list parts of upload {} of s3://{}/{}", state.upload_id, state.bucket, state.key))
+        })
+        .await?;
+        for part in response.parts() {
+            if let (Some(number), Some(e_tag)) = (part.part_number(), part.e_tag()) {
+                parts.insert(number, (e_tag.to_string(), part.size().unwrap_or(0).max(0) as u64));
+            }
+        }
+        match response.next_part_number_marker() {
+            Some(next) if response.is_truncated().unwrap_or(false) => marker = Some(next.to_string()),
+            _ => break,
+        }
+    }
+    Ok(parts)
+}
+
+/// Uploads every part not already stored, `concurrency` at a time.
+///
+/// A stored part is reused only if its size and ETag (the MD5 of the part) match the local file,
+/// so a file that changed since the interrupted run is uploaded again.
+async fn upload_file_parts(
+    client: &s3::Client,
+    state: &UploadState,
+    file_path: &Path,
+    parts: &[PartRange],
+    existing_parts: HashMap<i32, (String, u64)>,
+    concurrency: usize,
+    max_retries: u32,
+) -> Result<Vec<CompletedPart>> {
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let existing_parts = Arc::new(existing_parts);
+    let mut join_set = JoinSet::new();
+    for part in parts.iter().copied() {
+        let semaphore = Arc::clone(&semaphore);
+        let existing_parts = Arc::clone(&existing_parts);
+        let client = client.clone();
+        let state = state.clone();
+        let file_path = file_path.to_path_buf();
+        join_set.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.context("Upload semaphore closed")?;
+            let data = read_file_range(&file_path, part.offset, part.len).await?;
+            if let Some((e_tag, size)) = existing_parts.get(&part.number)
+                && *size == part.len
+                && e_tag.trim_matches('"') == hex::encode(Md5::digest(&data))
+            {
+                return Ok((build_completed_part(part.number, e_tag), false));
+            }
+            let completed = upload_part_with_retries(&client, &state.bucket, &state.key, &state.upload_id, part.number, data, max_retries).await?;
+            Ok::<_, anyhow::Error>((completed, true))
+        });
+    }
+
+    let mut completed_parts = Vec::with_capacity(parts.len());
+    let mut uploaded_bytes = 0u64;
+    while let Some(joined) = join_set.join_next().await {
+        let (completed, uploaded) = match joined.context("Part upload task panicked").and_then(|result| result) {
+            Ok(result) => result,
+            Err(e) => {
+                join_set.abort_all();
+                return Err(e);
+            }
+        };
+        let part = parts[completed.part_number().unwrap_or(1) as usize - 1];
+        uploaded_bytes += part.len;
+        println!(
+            "   {} part {}/{} ({} of {})",
+            if uploaded { "Uploaded" } else { "Reused" },
+            part.number,
+            parts.len(),
+            crate::catalog::format_size(uploaded_bytes),
+            crate::catalog::format_size(state.file_size)
+        );
+        completed_parts.push(completed);
+    }
+    completed_parts.sort_by_key(|part| part.part_number());
+    Ok(completed_parts)
+}
+
+async fn read_file_range(file_path: &Path, offset: u64, len: u64) -> Result<Bytes> {
+    let mut file = tokio::fs::File::open(file_path)
+        .await
+        .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .with_context(|| format!("Failed to seek in file: {}", file_path.display()))?;
+    let mut data = vec![0u8; len as usize];
+    file.read_exact(&mut data)
+        .await
+        .with_context(|| format!("Failed to read {} bytes at offset {} from {}", len, offset, file_path.display()))?;
+    Ok(Bytes::from(data))
+}
+
+fn build_completed_part(part_number: i32, e_tag: &str) -> CompletedPart {
+    CompletedPart::builder().part_number(part_number).e_tag(e_tag).build()
+}
+
+async fn upload_part_with_retries(
+    client: &s3::Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    part_number: i32,
+    data: Bytes,
+    max_retries: u32,
+) -> Result<CompletedPart> {
+    with_retries(max_retries, &format!("Upload of part {} of {}", part_number, key), || async {
+        let output = client
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(data.clone()))
+            .send()
+            .await
+            .with_context(|| format!("Failed to upload part {} of s3://{}/{}", part_number, bucket, key))?;
+        let e_tag = output.e_tag().context("S3 did not return an ETag for the uploaded part")?;
+        Ok(build_completed_part(part_number, e_tag))
+    })
+    .await
+}
+
+async fn complete_multipart_upload(
+    client: &s3::Client,
+    state: &UploadState,
+    completed_parts: Vec<CompletedPart>,
+    max_retries: u32,
+) -> Result<()> {
+    with_retries(max_retries, &format!("Completing multipart upload of {}", state.key), || async {
+        client
+            .complete_multipart_upload()
+            .bucket(&state.bucket)
+            .key(&state.key)
+            .upload_id(&state.upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts.clone()))
+                    .build(),
+            )
+            .send()
+            .await
+            .with_context(|| format!("Failed to complete multipart upload of s3://{}/{}", state.bucket, state.key))
+    })
+    .await?;
+    Ok(())
+}
+
+async fn abort_multipart_upload(client: &s3::Client, state: &UploadState) -> Result<()> {
+    println!("Aborting multipart upload {} of s3://{}/{}", state.upload_id, state.bucket, state.key);
+    client
+        .abort_multipart_upload()
+        .bucket(&state.bucket)
+        .key(&state.key)
+        .upload_id(&state.upload_id)
+        .send()
+        .await
+        .with_context(|| format!("Failed to abort multipart upload {} of s3://{}/{}", state.upload_id, state.bucket, state.key))?;
+    Ok(())
+}
+
+/// Whether a failed part upload may still succeed when the upload is resumed later: the network
+/// or the service failed, rather than S3 rejecting the request or the local file being unreadable.
+fn is_transient_failure(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<SdkError<UploadPartError>>())
+        .any(is_transient_sdk_error)
+}
+
+fn is_transient_sdk_error<E: ProvideErrorMetadata>(error: &SdkError<E>) -> bool {
+    match error {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => true,
+        SdkError::ServiceError(service_error) => {
+            let status = service_error.raw().status();
+            status.is_server_error()
+                || status.as_u16() == 429
+                || matches!(service_error.err().code(), Some("RequestTimeout" | "SlowDown" | "InternalError"))
+        }
+        _ => false,
+    }
+}
+
+/// Delay before retry number `attempt` (starting at 1): 500 ms, doubling up to 30 s.
+pub(crate) fn backoff_delay(attempt: u32) -> Duration {
+    let millis = 500u64.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    Duration::from_millis(millis.min(30_000))
+}
+
+/// Runs `operation` and retries it up to `max_retries` times with exponential backoff.
+pub(crate) async fn with_retries<T, F, Fut>(max_retries: u32, description: &str, mut operation: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < max_retries => {
+                attempt += 1;
+                let delay = backoff_delay(attempt);
+                println!(
+                    "⚠️  {} failed (attempt {} of {}): {:#}. Retrying in {:.1}s...",
+                    description,
+                    attempt,
+                    max_retries + 1,
+                    e,
+                    delay.as_secs_f64()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(e).with_context(|| format!("{} failed after {} attempt(s)", description, attempt + 1)),
+        }
+    }
+}
+
+/// An in-progress S3 multipart upload fed part by part, used when the total size is not known upfront.
+///
+/// Parts are uploaded in order as they are handed over, each retried with backoff; the object only
+/// becomes visible once `complete` succeeds. Call `abort` on failure so the uploaded parts are
+/// discarded instead of being billed as an abandoned upload.
+pub struct MultipartUpload {
+    client: s3::Client,
+    state: UploadState,
+    max_retries: u32,
+    completed_parts: Vec<CompletedPart>,
+    uploaded_bytes: u64,
+}
+
+impl MultipartUpload {
+    /// Starts a multipart upload of `s3_key` with the given user metadata.
+    pub async fn start(
+        spaces_config: &SpacesConfig,
+        s3_key: &str,
+        metadata: HashMap<String, String>,
+    ) -> Result<Self> {
+        let client = build_s3_client(spaces_config).await;
+        let max_retries = spaces_config.transfer.max_retries;
+        let bucket = spaces_config.bucket_name.clone();
+        let upload_id = with_retries(max_retries, &format!("Starting multipart upload of {}", s3_key), || async {
+            let output = client
+                .create_multipart_upload()
+                .bucket(&bucket)
+                .key(s3_key)
+                .set_metadata(Some(metadata.clone()))
+                .send()
+                .await
+                .with_context(|| format!("Failed to start multipart upload to s3://{}/{}", bucket, s3_key))?;
+            output
+                .upload_id()
+                .map(str::to_string)
+                .context("S3 did not return an upload ID for the multipart upload")
+        })
+        .await?;
+
+        println!("Started multipart upload to s3://{}/{} (upload ID {})", bucket, s3_key, upload_id);
+        Ok(MultipartUpload {
+            client,
+            state: UploadState {
+                bucket,
+                key: s3_key.to_string(),
+                upload_id,
+                part_size: spaces_config.transfer.part_size_bytes,
+                file_size: 0,
+            },
+            max_retries,
+            completed_parts: Vec::new(),
+            uploaded_bytes: 0,
+        })
+    }
+
+    /// Uploads the next part. Every part except the last must be at least 5 MiB.
+    pub async fn upload_part(&mut self, data: Vec<u8>) -> Result<()> {
+        let part_number = self.completed_parts.len() as i32 + 1;
+        let size = data.len() as u64;
+        let completed = upload_part_with_retries(
+            &self.client,
+            &self.state.bucket,
+            &self.state.key,
+            &self.state.upload_id,
+            part_number,
+            Bytes::from(data),
+            self.max_retries,
+        )
+        .await?;
+        self.completed_parts.push(completed);
+        self.uploaded_bytes += size;
+        println!("   Uploaded part {} ({} total)", part_number, crate::catalog::format_size(self.uploaded_bytes));
+        Ok(())
+    }
+
+    /// Size of the parts this upload is fed with.
+    pub fn part_size(&self) -> usize {
+        self.state.part_size as usize
+    }
+
+    /// Number of parts uploaded so far.
+    pub fn part_count(&self) -> usize {
+        self.completed_parts.len()
+    }
+
+    /// Completes the upload, making the object visible.
+    pub async fn complete(self) -> Result<u64> {
+        complete_multipart_upload(&self.client, &self.state, self.completed_parts, self.max_retries).await?;
+        Ok(self.uploaded_bytes)
+    }
+
+    /// Aborts the upload and discards every part uploaded so far.
+    pub async fn abort(self) -> Result<()> {
+        abort_multipart_upload(&self.client, &self.state).await
+    }
+}
+
 // Basic check for S3 credentials and connectivity (optional, can be expanded)
 #[allow(dead_code)]
 pub async fn check_s3_connection(spaces_config: &SpacesConfig) -> Result<()> {
@@ -116,4 +702,137 @@ pub async fn check_s3_connection(spaces_config: &SpacesConfig) -> Result<()> {
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::S3TransferConfig;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_plan_parts_covers_file() {
+        let parts = plan_parts(12 * MIB + 3, 5 * MIB);
+        assert_eq!(
+            parts,
+            vec![
+                PartRange { number: 1, offset: 0, len: 5 * MIB },
+                PartRange { number: 2, offset: 5 * MIB, len: 5 * MIB },
+                PartRange { number: 3, offset: 10 * MIB, len: 2 * MIB + 3 },
+            ]
+        );
+        assert_eq!(plan_parts(0, 5 * MIB), vec![PartRange { number: 1, offset: 0, len: 0 }]);
+    }
+
+    #[test]
+    fn test_effective_part_size_respects_part_limit() {
+        assert_eq!(effective_part_size(100 * MIB, 8 * MIB), 8 * MIB);
+        // 1 TiB in 8 MiB parts would need 131,072 parts.
+        let part_size = effective_part_size(1024 * 1024 * MIB, 8 * MIB);
+        assert_eq!(part_size, 105 * MIB);
+        assert!(plan_parts(1024 * 1024 * MIB, part_size).len() as u64 <= MAX_MULTIPART_PARTS);
+    }
+
+    #[test]
+    fn test_backoff_delay_doubles_and_caps() {
+        assert_eq!(backoff_delay(1), Duration::from_millis(500));
+        assert_eq!(backoff_delay(2), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(4), Duration::from_millis(4000));
+        assert_eq!(backoff_delay(10), Duration::from_secs(30));
+        assert_eq!(backoff_delay(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_upload_state_path_is_hidden_from_archive_listing() {
+        let state_path = upload_state_path(Path::new("/backups/2024-05-01_02-00-00.tar.gz"));
+        assert_eq!(state_path, PathBuf::from("/backups/.2024-05-01_02-00-00.tar.gz.upload"));
+        let state_name = state_path.file_name().unwrap().to_string_lossy();
+        assert!(crate::backup::retention::parse_archive_timestamp(&state_name).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_with_retries_retries_until_success_or_limit() -> Result<()> {
+        let calls = AtomicU32::new(0);
+        let value = with_retries(1, "Flaky call", || async {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                anyhow::bail!("transient failure");
+            }
+            Ok(42)
+        })
+        .await?;
+        assert_eq!((value, calls.load(Ordering::SeqCst)), (42, 2));
+
+        let calls = AtomicU32::new(0);
+        let result: Result<()> = with_retries(0, "Broken call", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            anyhow::bail!("permanent failure")
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_transport_and_service_failures_keep_the_upload() {
+        let timeout: anyhow::Error = SdkError::<UploadPartError>::timeout_error("connection timed out").into();
+        assert!(is_transient_failure(&timeout.context("Failed to upload part 3").context("Upload of part 3 failed after 6 attempt(s)")));
+
+        let rejected: anyhow::Error = SdkError::<UploadPartError>::construction_failure("invalid part number").into();
+        assert!(!is_transient_failure(&rejected.context("Failed to upload part 3")));
+        assert!(!is_transient_failure(&anyhow::anyhow!("Failed to read 5242880 bytes at offset 0")));
+    }
+
+    /// Uploads through a local S3 stand-in (MinIO, moto, ...), interrupting once to exercise resume.
+    ///
+    /// Run with `cargo test -- --ignored` and `DATABASETOOL_TEST_S3_ENDPOINT` / `DATABASETOOL_TEST_S3_BUCKET`
+    /// set; credentials come from `DATABASETOOL_TEST_S3_ACCESS_KEY` / `DATABASETOOL_TEST_S3_SECRET_KEY`.
+    #[tokio::test]
+    #[ignore]
+    async fn test_multipart_upload_resumes_against_local_s3() -> Result<()> {
+        let env = |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+        let spaces_config = SpacesConfig {
+            endpoint_url: env("DATABASETOOL_TEST_S3_ENDPOINT", "http://127.0.0.1:9000"),
+            region: "us-east-1".to_string(),
+            access_key_id: env("DATABASETOOL_TEST_S3_ACCESS_KEY", "minioadmin"),
+            secret_access_key: env("DATABASETOOL_TEST_S3_SECRET_KEY", "minioadmin"),
+            bucket_name: env("DATABASETOOL_TEST_S3_BUCKET", "databasetool-test"),
+            folder_prefix: Some("multipart-test".to_string()),
+            force_path_style: true,
+            transfer: S3TransferConfig { part_size_bytes: 5 * MIB, concurrency: 2, max_retries: 1 },
+        };
+
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("2024-05-01_02-00-00.tar.gz");
+        let content: Vec<u8> = (0..12 * MIB).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&file_path, &content)?;
+        let s3_key = backup_object_key(&spaces_config, "2024-05-01_02-00-00.tar.gz");
+
+        // Simulate a run that stopped after the first part: the upload ID is saved, part 2 and 3 are missing.
+        let mut interrupted = MultipartUpload::start(&spaces_config, &s3_key, HashMap::new()).await?;
+        interrupted.upload_part(content[..5 * MIB as usize].to_vec()).await?;
+        let state = UploadState {
+            bucket: spaces_config.bucket_name.clone(),
+            key: s3_key.clone(),
+            upload_id: interrupted.state.upload_id.clone(),
+            part_size: 5 * MIB,
+            file_size: content.len() as u64,
+        };
+        write_upload_state(&upload_state_path(&file_path), &state)?;
+
+        upload_file_to_s3(&spaces_config, &file_path, &s3_key, HashMap::new(), None).await?;
+        assert!(!upload_state_path(&file_path).exists());
+
+        // The interrupted upload was completed rather than abandoned next to a fresh one.
+        let client = build_s3_client(&spaces_config).await;
+        let pending = client.list_multipart_uploads().bucket(&spaces_config.bucket_name).prefix(&s3_key).send().await?;
+        assert!(pending.uploads().is_empty());
+
+        let object = client.get_object().bucket(&spaces_config.bucket_name).key(&s3_key).send().await?;
+        let downloaded = object.body.collect().await?.into_bytes();
+        assert_eq!(downloaded.as_ref(), content.as_slice());
+        client.delete_object().bucket(&spaces_config.bucket_name).key(&s3_key).send().await?;
+        Ok(())
+    }
+}
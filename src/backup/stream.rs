@@ -0,0 +1,445 @@
+// databasetool/src/backup/stream.rs
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tar::{Builder, Header};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::backup::manifest::{self, ManifestFile};
+use crate::backup::s3_upload::{self, MultipartUpload};
+use crate::backup::compression::CompressionWriter;
+use crate::backup::encryption::ArchiveWriter;
+use crate::backup::{archive, db_dump, globals};
+use crate::catalog;
+use crate::config::{BackupConfig, DumpFormat, SpacesConfig};
+use crate::utils::find_psql_executable;
+use crate::utils::table_filter::{self, TableSelection};
+
+/// Largest tar entry buffered in memory; bigger dumps are split into `.part-NNNNN` entries.
+const STREAM_SEGMENT_SIZE: usize = 32 * 1024 * 1024;
+
+/// Size and number of compressed chunks in flight between the archiver thread and the uploader.
+const CHANNEL_CHUNK_SIZE: usize = 1024 * 1024;
+const CHANNEL_CAPACITY: usize = 8;
+
+/// A database of a streamed backup: its resolved filters and the snapshot every dump step reads.
+struct StreamedDatabase {
+    name: String,
+    selection: TableSelection,
+    snapshot_id: String,
+}
+
+/// Everything written into the archive stream before the manifest.
+struct StreamedContents {
+    globals_sql: Option<Vec<u8>>, // pg_dumpall --globals-only output
+    databases: Vec<StreamedDatabase>,
+}
+
+/// Result of a streamed backup.
+#[derive(Debug)]
+pub struct StreamedBackup {
+    pub s3_key: String,
+    pub uploaded_bytes: u64,
+    pub local_copy: Option<PathBuf>,
+}
+
+/// Streams every database straight from pg_dump through tar and the configured codec into an S3 multipart upload.
+///
+/// Nothing is staged on disk except the optional local copy, and memory use is bounded by one
+/// tar segment, one upload part (`multipart_part_size_mb`) and a few in-flight chunks. Databases are dumped one after another
+/// since they share a single archive stream, and any failure aborts the whole upload.
+///
+/// # Arguments
+/// * `backup_config` - Backup settings (format, local copy, source URL).
+/// * `spaces_config` - Destination bucket.
+/// * `archive_file_name` - Name of the archive, e.g. `2024-05-01_02-00-00.tar.gz`.
+/// * `databases` - Databases to dump, already resolved and validated.
+pub async fn stream_backup_to_s3(
+    backup_config: &BackupConfig,
+    spaces_config: &SpacesConfig,
+    archive_file_name: &str,
+    databases: &[String],
+) -> Result<StreamedBackup> {
+    let pg_dump_path = db_dump::find_pg_dump_executable()?;
+    let source_base_url = db_dump::get_base_url_without_db(&backup_config.source_db_url)?;
+    let globals_sql = if backup_config.dump_globals {
+        Some(
+            globals::dump_globals(&source_base_url, backup_config.include_role_passwords)
+                .await
+                .context("Failed to dump cluster globals")?,
+        )
+    } else {
+        None
+    };
+    let globals_file = globals_sql.as_ref().map(|sql| ManifestFile {
+        path: globals::GLOBALS_FILE_NAME.to_string(),
+        kind: "globals".to_string(),
+        size_bytes: sql.len() as u64,
+        sha256: hex::encode(Sha256::digest(sql)),
+    });
+    // Filters are resolved and snapshots exported up front: the archive is written on a blocking
+    // thread without database access. The snapshot transactions stay open until the manifest is built.
+    let mut contents = StreamedContents { globals_sql, databases: Vec::with_capacity(databases.len()) };
+    let mut snapshots = Vec::with_capacity(databases.len());
+    for db_name in databases.iter().cloned() {
+        let db_url = format!("{}/{}", source_base_url.trim_end_matches('/'), db_name);
+        let selection = table_filter::resolve_database_filters(&db_url, &backup_config.filters)
+            .await
+            .with_context(|| format!("Failed to apply filters to database {}", db_name))?;
+        let snapshot = db_dump::DumpSnapshot::export(&db_url)
+            .await
+            .with_context(|| format!("Failed to export a snapshot of database {}", db_name))?;
+        contents.databases.push(StreamedDatabase {
+            name: db_name.clone(),
+            selection: selection.clone(),
+            snapshot_id: snapshot.id.clone(),
+        });
+        snapshots.push((db_name, selection, snapshot));
+    }
+
+    let local_copy = match (&backup_config.local_backup_path, backup_config.keep_local_copy) {
+        (Some(dir), true) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create local backup directory: {}", dir.display()))?;
+            Some(dir.join(archive_file_name))
+        }
+        _ => None,
+    };
+
+    let s3_key = s3_upload::backup_object_key(spaces_config, archive_file_name);
+    let metadata = HashMap::from([(catalog::DATABASES_METADATA_KEY.to_string(), databases.join(","))]);
+    let upload = MultipartUpload::start(spaces_config, &s3_key, metadata).await?;
+
+    println!(
+        "Streaming {} database(s) to s3://{}/{}{}",
+        databases.len(),
+        spaces_config.bucket_name,
+        s3_key,
+        local_copy
+            .as_ref()
+            .map(|path| format!(" (local copy: {})", path.display()))
+            .unwrap_or_default()
+    );
+
+    let (chunk_tx, chunk_rx) = mpsc::channel::<Vec<u8>>(CHANNEL_CAPACITY);
+    let (files_tx, files_rx) = oneshot::channel::<Vec<(String, Vec<ManifestFile>)>>();
+    let (manifest_tx, manifest_rx) = oneshot::channel::<String>();
+
+    let archiver = {
+        let source_base_url = source_base_url.clone();
+        let dump_format = backup_config.dump_format;
+        let encryption = backup_config.encryption.clone();
+        let compression = backup_config.compression;
+        tokio::task::spawn_blocking(move || {
+            write_archive_stream(
+                CompressionWriter::new(ArchiveWriter::new(ChannelWriter::new(chunk_tx), encryption.as_ref())?, &compression)?,
+                &pg_dump_path,
+                &source_base_url,
+                &contents,
+                dump_format,
+                files_tx,
+                manifest_rx,
+            )
+        })
+    };
+    let uploader = tokio::spawn(upload_chunks(upload, chunk_rx, local_copy.clone()));
+
+    // The manifest goes last in the archive: checksums are only known once every dump has streamed.
+    // If the archiver already failed it has dropped `files_tx`; its own error is reported below.
+    let mut manifest_result = Ok(());
+    if let Ok(files_by_database) = files_rx.await {
+        let mut stats = BTreeMap::new();
+        for (db_name, selection, snapshot) in snapshots {
+            match snapshot.collect_stats(&selection, backup_config.table_checksums).await {
+                Ok(db_stats) => {
+                    stats.insert(db_name, db_stats);
+                }
+                Err(e) => println!("⚠️  Could not collect row counts for {}: {:#}", db_name, e),
+            }
+        }
+        let backup_manifest = manifest::build_manifest(
+            &source_base_url,
+            files_by_database,
+            globals_file,
+            &stats,
+            backup_config.dump_format,
+            backup_config.compression.codec,
+        )
+        .await;
+        match backup_manifest.to_json() {
+            Ok(json) => {
+                let _ = manifest_tx.send(json);
+            }
+            // Dropping the sender makes the archiver stop instead of waiting forever.
+            Err(e) => manifest_result = Err(e),
+        }
+    }
+
+    let archive_result = archiver.await.context("Archive streaming task panicked")?;
+    let (upload, upload_result) = uploader.await.context("Upload task panicked")?;
+
+    if let Err(e) = manifest_result.and(archive_result).and(upload_result) {
+        if let Err(abort_err) = upload.abort().await {
+            println!("⚠️  {:#}", abort_err);
+        }
+        if let Some(path) = &local_copy {
+            let _ = std::fs::remove_file(path);
+        }
+        return Err(e).context("Streaming backup failed; the multipart upload was aborted");
+    }
+
+    let uploaded_bytes = upload.complete().await?;
+    Ok(StreamedBackup { s3_key, uploaded_bytes, local_copy })
+}
+
+/// Receives compressed chunks, uploads them in parts of the configured size and mirrors them to the local copy.
+///
+/// Runs until the archiver closes the channel. The upload is handed back unfinished so the
+/// caller can complete or abort it depending on whether the archive was written successfully.
+async fn upload_chunks(
+    mut upload: MultipartUpload,
+    mut chunk_rx: mpsc::Receiver<Vec<u8>>,
+    local_copy: Option<PathBuf>,
+) -> (MultipartUpload, Result<()>) {
+    let result = async {
+        let mut local_file = match &local_copy {
+            Some(path) => Some(
+                tokio::fs::File::create(path)
+                    .await
+                    .with_context(|| format!("Failed to create local archive copy: {}", path.display()))?,
+            ),
+            None => None,
+        };
+
+        let part_size = upload.part_size();
+        let mut part = Vec::with_capacity(part_size);
+        while let Some(chunk) = chunk_rx.recv().await {
+            if let Some(file) = &mut local_file {
+                file.write_all(&chunk).await.context("Failed to write local archive copy")?;
+            }
+            part.extend_from_slice(&chunk);
+            if part.len() >= part_size {
+                let full_part = std::mem::replace(&mut part, Vec::with_capacity(part_size));
+                upload.upload_part(full_part).await?;
+            }
+        }
+        // S3 needs at least one part, even for an empty final part.
+        if !part.is_empty() || upload.part_count() == 0 {
+            upload.upload_part(part).await?;
+        }
+        if let Some(mut file) = local_file {
+            file.flush().await.context("Failed to flush local archive copy")?;
+        }
+        Ok(())
+    }
+    .await;
+    (upload, result)
+}
+
+/// Writes the compressed (and optionally encrypted) tar stream: `globals.sql`, every dump, then `manifest.json`.
+///
+/// Runs on a blocking thread. Sends the described files back through `files_tx` once all dumps
+/// are written and waits for the serialized manifest on `manifest_rx` before finishing the archive.
+fn write_archive_stream(
+    sink: CompressionWriter<ArchiveWriter<ChannelWriter>>,
+    pg_dump_path: &Path,
+    source_base_url: &str,
+    contents: &StreamedContents,
+    dump_format: DumpFormat,
+    files_tx: oneshot::Sender<Vec<(String, Vec<ManifestFile>)>>,
+    manifest_rx: oneshot::Receiver<String>,
+) -> Result<()> {
+    let mut tar_builder = Builder::new(sink);
+    let mut files_by_database = Vec::with_capacity(contents.databases.len());
+    if let Some(sql) = &contents.globals_sql {
+        append_entry(&mut tar_builder, globals::GLOBALS_FILE_NAME, sql)?;
+    }
+
+    for StreamedDatabase { name: db_name, selection, snapshot_id } in &contents.databases {
+        println!("Streaming pg_dump of {} ({} format)...", db_name, dump_format.as_str());
+        let db_url = format!("{}/{}", source_base_url.trim_end_matches('/'), db_name);
+        let mut files = Vec::new();
+        for step in db_dump::dump_steps(db_name, dump_format, 1, selection, Some(snapshot_id)) {
+            let program_path = match step.program {
+                db_dump::DumpProgram::PgDump => pg_dump_path.to_path_buf(),
+                db_dump::DumpProgram::Psql => find_psql_executable()?,
+            };
+            let file = stream_pg_dump_into_tar(&mut tar_builder, &program_path, &step.args, &db_url, &step.entry_name, step.kind)
+                .with_context(|| format!("Dump ({}) for database {} failed", step.kind, db_name))?;
+            println!("✓ Streamed {} ({})", file.path, catalog::format_size(file.size_bytes));
+            files.push(file);
+        }
+        files_by_database.push((db_name.clone(), files));
+    }
+
+    files_tx
+        .send(files_by_database)
+        .map_err(|_| anyhow::anyhow!("Streaming backup was cancelled before the manifest was written"))?;
+    let manifest_json = manifest_rx
+        .blocking_recv()
+        .context("Streaming backup was cancelled before the manifest was written")?;
+    append_entry(&mut tar_builder, manifest::MANIFEST_FILE_NAME, manifest_json.as_bytes())?;
+
+    let encoder = tar_builder.into_inner().context("Failed to finish tar stream")?;
+    let mut sink = encoder.finish()?.finish()?;
+    sink.flush().context("Failed to send the end of the archive stream")?;
+    Ok(())
+}
+
+/// Runs pg_dump (or psql for filtered rows) with its output on stdout and appends it to the tar stream.
+///
+/// Output that fits in one segment becomes a single `entry_name` entry; longer output is
+/// written as numbered segments that restore joins back together.
+fn stream_pg_dump_into_tar<W: Write>(
+    tar_builder: &mut Builder<W>,
+    pg_dump_path: &Path,
+    args: &[String],
+    db_url: &str,
+    entry_name: &str,
+    kind: &str,
+) -> Result<ManifestFile> {
+    let mut child = Command::new(pg_dump_path)
+        .args(args)
+        .arg(db_url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute pg_dump")?;
+    let mut stdout = child.stdout.take().context("pg_dump stdout was not captured")?;
+    // Drain stderr on its own thread so a chatty pg_dump cannot block on a full pipe.
+    let mut stderr = child.stderr.take().context("pg_dump stderr was not captured")?;
+    let stderr_reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stderr.read_to_string(&mut output);
+        output
+    });
+
+    let result = (|| -> Result<ManifestFile> {
+        let mut hasher = Sha256::new();
+        let mut total = 0u64;
+        let mut segment = Vec::with_capacity(STREAM_SEGMENT_SIZE);
+        let mut index = 0;
+        loop {
+            segment.clear();
+            (&mut stdout)
+                .take(STREAM_SEGMENT_SIZE as u64)
+                .read_to_end(&mut segment)
+                .context("Failed to read pg_dump output")?;
+            let is_last = segment.len() < STREAM_SEGMENT_SIZE;
+            if is_last && index == 0 {
+                append_entry(tar_builder, entry_name, &segment)?;
+            } else if !segment.is_empty() {
+                append_entry(tar_builder, &archive::segment_entry_name(entry_name, index), &segment)?;
+            }
+            hasher.update(&segment);
+            total += segment.len() as u64;
+            index += 1;
+            if is_last {
+                break;
+            }
+        }
+        Ok(ManifestFile {
+            path: entry_name.to_string(),
+            kind: kind.to_string(),
+            size_bytes: total,
+            sha256: hex::encode(hasher.finalize()),
+        })
+    })();
+
+    if result.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait().context("Failed to wait for pg_dump")?;
+    let stderr_output = stderr_reader.join().unwrap_or_default();
+    let file = result?;
+    if !status.success() {
+        anyhow::bail!("pg_dump exited with status: {}\nStderr: {}", status, stderr_output);
+    }
+    Ok(file)
+}
+
+fn append_entry<W: Write>(tar_builder: &mut Builder<W>, name: &str, data: &[u8]) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    tar_builder
+        .append_data(&mut header, name, data)
+        .with_context(|| format!("Failed to append {} to archive stream", name))
+}
+
+/// `Write` adapter that hands fixed-size chunks to the async uploader over a bounded channel.
+///
+/// Blocks when the channel is full, which is what keeps memory bounded when the upload is
+/// slower than pg_dump. Fails with `BrokenPipe` once the uploader has stopped.
+struct ChannelWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn new(sender: mpsc::Sender<Vec<u8>>) -> Self {
+        ChannelWriter { sender, buffer: Vec::with_capacity(CHANNEL_CHUNK_SIZE) }
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHANNEL_CHUNK_SIZE));
+        self.sender
+            .blocking_send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "archive upload stopped"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let room = CHANNEL_CHUNK_SIZE - self.buffer.len();
+        let written = data.len().min(room);
+        self.buffer.extend_from_slice(&data[..written]);
+        if self.buffer.len() >= CHANNEL_CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_writer_chunks_and_flushes() -> Result<()> {
+        let (tx, mut rx) = mpsc::channel(16);
+        let mut writer = ChannelWriter::new(tx);
+        writer.write_all(&vec![7u8; CHANNEL_CHUNK_SIZE + 10])?;
+        writer.flush()?;
+        drop(writer);
+
+        let mut chunks = Vec::new();
+        while let Ok(chunk) = rx.try_recv() {
+            chunks.push(chunk.len());
+        }
+        assert_eq!(chunks, vec![CHANNEL_CHUNK_SIZE, 10]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_writer_reports_stopped_uploader() {
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+        let mut writer = ChannelWriter::new(tx);
+        let err = writer.write_all(&vec![0u8; CHANNEL_CHUNK_SIZE]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
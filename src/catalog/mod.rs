@@ -0,0 +1,440 @@
+// databasetool/src/catalog/mod.rs
+use anyhow::{Context, Result};
+use aws_sdk_s3 as s3;
+use chrono::{NaiveDate, NaiveDateTime};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::backup::{archive, compression};
+use crate::backup::encryption::ENCRYPTED_ARCHIVE_SUFFIX;
+use crate::backup::retention::{parse_archive_timestamp, ARCHIVE_TIMESTAMP_FORMAT};
+use crate::backup::s3_upload;
+use crate::config::{AppConfig, ListConfig, SpacesConfig};
+
+/// S3 user metadata key holding the comma separated list of databases in an archive.
+pub const DATABASES_METADATA_KEY: &str = "databases";
+
+/// Where a catalogued archive is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveLocation {
+    Local(PathBuf),
+    S3 { bucket: String, key: String },
+}
+
+impl ArchiveLocation {
+    /// Returns the location in the form accepted by `archive_file_path_for_restore`.
+    pub fn to_restore_source(&self) -> String {
+        match self {
+            ArchiveLocation::Local(path) => path.to_string_lossy().into_owned(),
+            ArchiveLocation::S3 { bucket, key } => format!("s3://{}/{}", bucket, key),
+        }
+    }
+}
+
+impl fmt::Display for ArchiveLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_restore_source())
+    }
+}
+
+/// One backup archive found in the local backup directory or under the S3 prefix.
+///
+/// The `id` is the timestamp stem of the archive name (e.g. `2024-05-01_02-00-00`). The same
+/// archive stored both locally and in S3 appears twice with the same id.
+#[derive(Debug, Clone)]
+pub struct CatalogEntry {
+    pub id: String,
+    pub timestamp: NaiveDateTime,
+    pub size_bytes: u64,
+    pub location: ArchiveLocation,
+    pub databases: Option<Vec<String>>, // None when the contents were not inspected or are unknown
+}
+
+/// How the user referred to the archive to restore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveReference {
+    /// A literal local path or `s3://bucket/key` URI.
+    Literal(String),
+    /// The newest archive in the catalog.
+    Latest,
+    /// The newest archive strictly older than the given time.
+    LatestBefore(NaiveDateTime),
+    /// A catalog id as printed by `list`.
+    Id(String),
+}
+
+/// Parses `archive_file_path_for_restore` into a literal path or a catalog selector.
+///
+/// Accepts `latest`, `latest-before:<datetime>` (`YYYY-MM-DD`, `YYYY-MM-DD HH:MM[:SS]`,
+/// `YYYY-MM-DDTHH:MM[:SS]` or the archive stamp format) and bare catalog ids.
+pub fn parse_archive_reference(reference: &str) -> Result<ArchiveReference> {
+    let reference = reference.trim();
+    if reference.eq_ignore_ascii_case("latest") {
+        return Ok(ArchiveReference::Latest);
+    }
+    if let Some(datetime) = reference.strip_prefix("latest-before:") {
+        return parse_selector_datetime(datetime.trim())
+            .map(ArchiveReference::LatestBefore)
+            .with_context(|| format!("Invalid datetime in archive selector '{}'", reference));
+    }
+    if reference.len() == 19 && NaiveDateTime::parse_from_str(reference, ARCHIVE_TIMESTAMP_FORMAT).is_ok() {
+        return Ok(ArchiveReference::Id(reference.to_string()));
+    }
+    Ok(ArchiveReference::Literal(reference.to_string()))
+}
+
+fn parse_selector_datetime(value: &str) -> Result<NaiveDateTime> {
+    for format in [ARCHIVE_TIMESTAMP_FORMAT, "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(datetime);
+        }
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .with_context(|| format!("Unrecognised datetime '{}'. Use YYYY-MM-DD or YYYY-MM-DD HH:MM:SS.", value))?;
+    Ok(date.and_hms_opt(0, 0, 0).expect("midnight is always valid"))
+}
+
+/// Picks the archive matching a selector from a catalog sorted newest first.
+/// Local copies are preferred over S3 copies of the same archive since they need no download.
+pub fn select_entry<'a>(entries: &'a [CatalogEntry], reference: &ArchiveReference) -> Option<&'a CatalogEntry> {
+    let mut matching: Vec<&CatalogEntry> = entries
+        .iter()
+        .filter(|entry| match reference {
+            ArchiveReference::Latest => true,
+            ArchiveReference::LatestBefore(limit) => entry.timestamp < *limit,
+            ArchiveReference::Id(id) => &entry.id == id,
+            ArchiveReference::Literal(_) => false,
+        })
+        .collect();
+    matching.sort_by_key(|entry| (std::cmp::Reverse(entry.timestamp), matches!(entry.location, ArchiveLocation::S3 { .. })));
+    matching.into_iter().next()
+}
+
+/// Resolves `archive_file_path_for_restore` to a concrete local path or `s3://` URI.
+///
+/// Literal paths and URIs are returned unchanged; selectors are looked up in the catalog.
+pub async fn resolve_archive_reference(
+    reference: &str,
+    local_backup_dir: Option<&Path>,
+    spaces_config: Option<&SpacesConfig>,
+) -> Result<String> {
+    let parsed = parse_archive_reference(reference)?;
+    if let ArchiveReference::Literal(path) = parsed {
+        return Ok(path);
+    }
+
+    println!("Resolving archive selector '{}' from the backup catalog...", reference);
+    let entries = build_catalog(local_backup_dir, spaces_config, false).await?;
+    let entry = select_entry(&entries, &parsed).with_context(|| {
+        format!(
+            "No archive matches '{}'. Searched {} archive(s); run the `list` command to see what is available.",
+            reference,
+            entries.len()
+        )
+    })?;
+    println!("✓ Selected archive {} at {}", entry.id, entry.location);
+    Ok(entry.location.to_restore_source())
+}
+
+/// Builds the catalog of archives from the local backup directory and the S3 prefix.
+///
+/// When `include_contents` is set, the database names in each archive are determined as well.
+/// For local archives this reads the archive; for S3 it uses object metadata written at upload.
+pub async fn build_catalog(
+    local_backup_dir: Option<&Path>,
+    spaces_config: Option<&SpacesConfig>,
+    include_contents: bool,
+) -> Result<Vec<CatalogEntry>> {
+    let mut entries = Vec::new();
+    if let Some(dir) = local_backup_dir {
+        entries.extend(list_local_archives(dir, include_contents)?);
+    }
+    if let Some(spaces) = spaces_config {
+        let client = s3_upload::build_s3_client(spaces).await;
+        entries.extend(list_s3_archives(&client, spaces, include_contents).await?);
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
+    Ok(entries)
+}
+
+/// Lists archives directly inside the local backup directory.
+pub fn list_local_archives(backup_dir: &Path, include_contents: bool) -> Result<Vec<CatalogEntry>> {
+    let mut entries = Vec::new();
+    if !backup_dir.is_dir() {
+        return Ok(entries);
+    }
+    for dir_entry in fs::read_dir(backup_dir)
+        .with_context(|| format!("Failed to read local backup directory: {}", backup_dir.display()))?
+    {
+        let dir_entry = dir_entry?;
+        let path = dir_entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+        let Some(timestamp) = parse_archive_timestamp(&file_name) else { continue };
+
+        // Encrypted archives cannot be inspected without the key.
+        let databases = if include_contents && !file_name.ends_with(ENCRYPTED_ARCHIVE_SUFFIX) {
+            match databases_in_local_archive(&path) {
+                Ok(databases) => Some(databases),
+                Err(e) => {
+                    println!("⚠️  Could not read contents of {}: {:#}", path.display(), e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        entries.push(CatalogEntry {
+            id: file_name[..19].to_string(),
+            timestamp,
+            size_bytes: dir_entry.metadata()?.len(),
+            location: ArchiveLocation::Local(path),
+            databases,
+        });
+    }
+    Ok(entries)
+}
+
+/// Lists archives stored directly under the backup prefix in the bucket.
+pub async fn list_s3_archives(
+    client: &s3::Client,
+    spaces_config: &SpacesConfig,
+    include_contents: bool,
+) -> Result<Vec<CatalogEntry>> {
+    let prefix = s3_upload::backup_key_prefix(spaces_config);
+    let list_prefix = if prefix.is_empty() { String::new() } else { format!("{}/", prefix) };
+
+    let mut entries = Vec::new();
+    let mut continuation_token: Option<String> = None;
+    loop {
+        let response = client
+            .list_objects_v2()
+            .bucket(&spaces_config.bucket_name)
+            .prefix(&list_prefix)
+            .delimiter("/")
+            .set_continuation_token(continuation_token.clone())
+            .send()
+            .await
+            .with_context(|| format!("Failed to list objects in s3://{}/{}", spaces_config.bucket_name, list_prefix))?;
+
+        for object in response.contents() {
+            let Some(key) = object.key() else { continue };
+            let file_name = key.strip_prefix(&list_prefix).unwrap_or(key);
+            let Some(timestamp) = parse_archive_timestamp(file_name) else { continue };
+
+            let databases = if include_contents {
+                databases_from_s3_metadata(client, &spaces_config.bucket_name, key).await
+            } else {
+                None
+            };
+
+            entries.push(CatalogEntry {
+                id: file_name[..19].to_string(),
+                timestamp,
+                size_bytes: object.size().unwrap_or(0).max(0) as u64,
+                location: ArchiveLocation::S3 {
+                    bucket: spaces_config.bucket_name.clone(),
+                    key: key.to_string(),
+                },
+                databases,
+            });
+        }
+
+        match response.next_continuation_token() {
+            Some(token) if response.is_truncated().unwrap_or(false) => continuation_token = Some(token.to_string()),
+            _ => break,
+        }
+    }
+    Ok(entries)
+}
+
+async fn databases_from_s3_metadata(client: &s3::Client, bucket: &str, key: &str) -> Option<Vec<String>> {
+    let head = client.head_object().bucket(bucket).key(key).send().await.ok()?;
+    let value = head.metadata()?.get(DATABASES_METADATA_KEY)?;
+    Some(value.split(',').filter(|name| !name.is_empty()).map(str::to_string).collect())
+}
+
+/// Reads the entry names of a local (unencrypted) archive and derives the database names from them.
+pub(crate) fn databases_in_local_archive(archive_path: &Path) -> Result<Vec<String>> {
+    let file = File::open(archive_path)
+        .with_context(|| format!("Failed to open archive file: {}", archive_path.display()))?;
+    let (_, reader) = compression::decompressing_reader(BufReader::new(file))?;
+    let mut archive = tar::Archive::new(reader);
+    let mut names = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        names.push(entry.path()?.to_string_lossy().into_owned());
+    }
+    Ok(databases_from_file_names(names.iter().map(String::as_str)))
+}
+
+/// Derives database names from archive entry paths: `<db>_schema.sql`, `<db>_data.sql`,
+/// `<db>.dump` or anything inside a `<db>.dir/` directory.
+pub fn databases_from_file_names<'a>(file_names: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut databases: Vec<String> = file_names
+        .filter_map(|path| {
+            let path = path.trim_start_matches("./");
+            let path = archive::parse_segment_entry_name(path).map_or(path, |(file_name, _)| file_name);
+            match path.split_once('/') {
+                Some((directory, _)) => directory.strip_suffix(".dir"),
+                None => path
+                    .strip_suffix("_schema.sql")
+                    .or_else(|| path.strip_suffix("_data.sql"))
+                    .or_else(|| path.strip_suffix(".dump")),
+            }
+        })
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    databases.sort();
+    databases.dedup();
+    databases
+}
+
+/// Public entry point for the list command.
+pub async fn run_list_flow(app_config: &AppConfig) -> Result<()> {
+    let list_config = match &app_config.operation {
+        Some(crate::config::OperationConfig::List(cfg)) => cfg,
+        _ => anyhow::bail!("List operation selected but no list configuration found."),
+    };
+
+    perform_list_orchestration(app_config, list_config).await
+}
+
+/// Prints the catalog of archives as a table.
+async fn perform_list_orchestration(
+    app_config: &AppConfig,
+    list_config: &ListConfig,
+) -> Result<()> {
+    let spaces_config = app_config.spaces_config.as_ref().filter(|_| list_config.list_spaces);
+    let entries = build_catalog(list_config.local_backup_path.as_deref(), spaces_config, true).await?;
+
+    if entries.is_empty() {
+        println!("No backup archives found.");
+        return Ok(());
+    }
+
+    println!("{:<19}  {:<19}  {:>10}  {:<30}  LOCATION", "ID", "TIMESTAMP", "SIZE", "DATABASES");
+    for entry in &entries {
+        let databases = entry
+            .databases
+            .as_ref()
+            .map(|dbs| dbs.join(","))
+            .unwrap_or_else(|| "unknown".to_string());
+        println!(
+            "{:<19}  {:<19}  {:>10}  {:<30}  {}",
+            entry.id,
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            format_size(entry.size_bytes),
+            databases,
+            entry.location
+        );
+    }
+    println!("{} archive(s) found.", entries.len());
+    Ok(())
+}
+
+/// Formats a byte count with a binary unit suffix, e.g. `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, location: ArchiveLocation) -> CatalogEntry {
+        CatalogEntry {
+            id: id.to_string(),
+            timestamp: NaiveDateTime::parse_from_str(id, ARCHIVE_TIMESTAMP_FORMAT).unwrap(),
+            size_bytes: 0,
+            location,
+            databases: None,
+        }
+    }
+
+    fn local(id: &str) -> CatalogEntry {
+        entry(id, ArchiveLocation::Local(PathBuf::from(format!("/backups/{}.tar.gz", id))))
+    }
+
+    fn remote(id: &str) -> CatalogEntry {
+        entry(id, ArchiveLocation::S3 { bucket: "b".to_string(), key: format!("database_backups/{}.tar.gz", id) })
+    }
+
+    #[test]
+    fn test_parse_archive_reference() -> Result<()> {
+        assert_eq!(parse_archive_reference("latest")?, ArchiveReference::Latest);
+        assert_eq!(
+            parse_archive_reference("2024-05-01_02-00-00")?,
+            ArchiveReference::Id("2024-05-01_02-00-00".to_string())
+        );
+        assert_eq!(
+            parse_archive_reference("latest-before:2024-05-01")?,
+            ArchiveReference::LatestBefore(NaiveDateTime::parse_from_str("2024-05-01 00:00:00", "%Y-%m-%d %H:%M:%S")?)
+        );
+        assert_eq!(
+            parse_archive_reference("latest-before:2024-05-01T12:30")?,
+            ArchiveReference::LatestBefore(NaiveDateTime::parse_from_str("2024-05-01 12:30:00", "%Y-%m-%d %H:%M:%S")?)
+        );
+        assert_eq!(
+            parse_archive_reference("s3://bucket/2024-05-01_02-00-00.tar.gz")?,
+            ArchiveReference::Literal("s3://bucket/2024-05-01_02-00-00.tar.gz".to_string())
+        );
+        assert!(parse_archive_reference("latest-before:yesterday").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_entry() {
+        let entries = vec![
+            remote("2024-05-03_02-00-00"),
+            local("2024-05-02_02-00-00"),
+            remote("2024-05-02_02-00-00"),
+            local("2024-05-01_02-00-00"),
+        ];
+
+        let latest = select_entry(&entries, &ArchiveReference::Latest).unwrap();
+        assert_eq!(latest.location, remote("2024-05-03_02-00-00").location);
+
+        let before = NaiveDateTime::parse_from_str("2024-05-03 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let selected = select_entry(&entries, &ArchiveReference::LatestBefore(before)).unwrap();
+        assert_eq!(selected.location, local("2024-05-02_02-00-00").location);
+
+        let by_id = select_entry(&entries, &ArchiveReference::Id("2024-05-01_02-00-00".to_string())).unwrap();
+        assert_eq!(by_id.location, local("2024-05-01_02-00-00").location);
+
+        assert!(select_entry(&entries, &ArchiveReference::Id("2023-01-01_00-00-00".to_string())).is_none());
+    }
+
+    #[test]
+    fn test_databases_from_file_names() {
+        let names = ["app_schema.sql", "app_data.sql", "analytics_schema.sql", "readme.txt", "manifest.json"];
+        assert_eq!(databases_from_file_names(names.into_iter()), vec!["analytics", "app"]);
+
+        let names = ["billing.dump.part-00000", "billing.dump.part-00001", "crm.dir/", "crm.dir/toc.dat", "crm.dir/3921.dat.gz"];
+        assert_eq!(databases_from_file_names(names.into_iter()), vec!["billing", "crm"]);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
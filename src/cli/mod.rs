@@ -0,0 +1,623 @@
+// databasetool/src/cli/mod.rs
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use crate::config::RawJsonConfig;
+
+/// Command-line interface for the backup/restore/sync tool.
+///
+/// Values given on the command line override the corresponding fields of `config.json`
+/// for this run only; the file itself is never modified.
+#[derive(Debug, Parser)]
+#[command(name = "databasetool", version, about = "PostgreSQL backup, restore and sync tool")]
+pub struct Cli {
+    /// Path to the JSON configuration file.
+    #[arg(short, long, global = true, value_name = "PATH", default_value = "config.json")]
+    pub config: PathBuf,
+
+    /// Never prompt or read from stdin. Fails instead of asking when input would be required.
+    #[arg(long, global = true)]
+    pub non_interactive: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Dump databases from the source server into a timestamped archive.
+    #[command(alias = "1")]
+    Backup(BackupArgs),
+    /// Restore databases from a local or S3 archive into the target server.
+    #[command(alias = "2")]
+    Restore(RestoreArgs),
+    /// Copy databases directly from the source server to the target server.
+    #[command(alias = "3")]
+    Sync(SyncArgs),
+    /// Delete old archives locally and in S3 according to the retention policy.
+    #[command(alias = "4")]
+    Prune(PruneArgs),
+    /// List backup archives in the local backup directory and under the S3 prefix.
+    #[command(alias = "5")]
+    List,
+    /// Upload an existing local archive to S3, resuming an interrupted multipart upload if one was saved.
+    Upload(UploadArgs),
+    /// Compare the schemas of source and target databases, or of an archive and the target.
+    Diff(DiffArgs),
+    /// Archive a completed WAL segment. Meant for PostgreSQL's `archive_command` (`wal-push %p`).
+    WalPush(WalPushArgs),
+    /// Copy an archived WAL segment into place. Meant for `restore_command` (`wal-fetch %f %p`).
+    WalFetch(WalFetchArgs),
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct BackupArgs {
+    /// Overrides `source_database_url` from the config file.
+    #[arg(long, value_name = "URL")]
+    pub source_database_url: Option<String>,
+
+    /// Overrides `database_list`. Comma separated names, or `source:target` pairs.
+    #[arg(long, value_name = "LIST")]
+    pub database_list: Option<String>,
+
+    /// Overrides `max_parallel_databases`: how many databases are dumped at the same time.
+    #[arg(long, value_name = "N")]
+    pub max_parallel_databases: Option<usize>,
+
+    /// Overrides `dump_format`: plain, custom or directory.
+    #[arg(long, value_name = "FORMAT")]
+    pub dump_format: Option<String>,
+
+    /// Overrides `jobs`: parallel pg_dump jobs per database (directory format only).
+    #[arg(long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Record a content checksum of every table in the manifest (`table_checksums`), which
+    /// restore compares. Reads every row once more.
+    #[arg(long)]
+    pub table_checksums: bool,
+
+    /// Take a physical base backup of the whole cluster with pg_basebackup instead of
+    /// logical dumps. Together with `wal-push` it allows point-in-time recovery.
+    #[arg(long, conflicts_with_all = ["database_list", "max_parallel_databases", "dump_format", "jobs", "table_checksums"])]
+    pub physical: bool,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct RestoreArgs {
+    /// Overrides `target_database_url` from the config file.
+    #[arg(long, value_name = "URL")]
+    pub target_database_url: Option<String>,
+
+    /// Overrides `archive_file_path_for_restore`: a local path, `s3://bucket/key`,
+    /// `latest`, `latest-before:<datetime>` or a catalog id shown by `list`.
+    #[arg(long, value_name = "ARCHIVE")]
+    pub archive: Option<String>,
+
+    /// Overrides `database_list`. Comma separated names, or `source:target` pairs.
+    #[arg(long, value_name = "LIST")]
+    pub database_list: Option<String>,
+
+    /// Overrides `jobs`: parallel pg_restore jobs for custom and directory format archives.
+    #[arg(long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Overrides `masking_rules_file`: mask personal data in the restored databases.
+    #[arg(long, value_name = "PATH")]
+    pub masking_rules: Option<PathBuf>,
+
+    /// Print which databases would be dropped, created, truncated or overwritten, from which
+    /// archive files, and the rows currently in the target, without changing anything.
+    #[arg(long)]
+    pub plan: bool,
+
+    /// Drop, truncate or overwrite non-empty target databases without typing their names.
+    /// Protected hosts and databases are still refused.
+    #[arg(long = "yes-i-know")]
+    pub yes_i_know: bool,
+
+    /// Sets `globals.restore`: create the roles of the archive's `globals.sql` that the target
+    /// lacks, then give each restored database its original owner, grants and settings.
+    #[arg(long)]
+    pub globals: bool,
+
+    /// Sets `restore_options.preserve_ownership`: keep the owners and grants of the dumps,
+    /// with `role_mapping` applied, instead of giving every object to the connecting user.
+    #[arg(long)]
+    pub preserve_ownership: bool,
+
+    /// Overrides `restore_options.schema_mapping`: comma separated `source:target` pairs, e.g.
+    /// `public:snapshot_2026_10` to restore `public` next to the existing one.
+    #[arg(long, value_name = "LIST")]
+    pub schema_mapping: Option<String>,
+
+    /// Rebuild a data directory from a physical base backup and archived WAL instead of
+    /// restoring logical dumps into the target server.
+    #[arg(long, requires = "data_dir", conflicts_with_all = ["archive", "database_list", "jobs", "masking_rules", "plan", "globals", "preserve_ownership", "schema_mapping"])]
+    pub physical: bool,
+
+    /// With --physical: the data directory to create. It must not exist or be empty.
+    #[arg(long, value_name = "PATH", requires = "physical")]
+    pub data_dir: Option<PathBuf>,
+
+    /// With --physical: recover up to this time (RFC 3339, or `YYYY-MM-DD HH:MM:SS` in local time).
+    #[arg(long, value_name = "DATETIME", requires = "physical", conflicts_with = "target_lsn")]
+    pub target_time: Option<String>,
+
+    /// With --physical: recover up to this WAL location, e.g. `0/3000060`.
+    #[arg(long, value_name = "LSN", requires = "physical")]
+    pub target_lsn: Option<String>,
+
+    /// With --physical: start from this base backup id instead of the newest one before the target.
+    #[arg(long, value_name = "ID", requires = "physical")]
+    pub base_backup: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct SyncArgs {
+    /// Overrides `source_database_url` from the config file.
+    #[arg(long, value_name = "URL")]
+    pub source_database_url: Option<String>,
+
+    /// Overrides `target_database_url` from the config file.
+    #[arg(long, value_name = "URL")]
+    pub target_database_url: Option<String>,
+
+    /// Overrides `database_list`. Comma separated names, or `source:target` pairs.
+    #[arg(long, value_name = "LIST")]
+    pub database_list: Option<String>,
+
+    /// Print which target databases would be dropped and recreated and the rows currently
+    /// in them, without changing anything.
+    #[arg(long)]
+    pub plan: bool,
+
+    /// Drop non-empty target databases without typing their names.
+    /// Protected hosts and databases are still refused.
+    #[arg(long = "yes-i-know")]
+    pub yes_i_know: bool,
+
+    /// Sets `sync_options.incremental`: re-copy only the tables whose fingerprints differ.
+    #[arg(long)]
+    pub incremental: bool,
+
+    /// Overrides `sync_options.fingerprint`: stats or checksum.
+    #[arg(long, value_name = "METHOD")]
+    pub fingerprint: Option<String>,
+
+    /// Sets `sync_options.streaming`: COPY tables directly between the servers, without dump files.
+    #[arg(long)]
+    pub streaming: bool,
+
+    /// Overrides `sync_options.workers`: tables streamed at the same time.
+    #[arg(long, value_name = "N")]
+    pub workers: Option<usize>,
+
+    /// Keep the targets up to date with logical replication: create a publication on the source
+    /// and a subscription on the target, wait for the initial copy and report the lag.
+    /// Running it again only reports the lag.
+    #[arg(long)]
+    pub continuous: bool,
+
+    /// With --continuous: drop the subscriptions and publications. The targets keep their data.
+    #[arg(long, requires = "continuous")]
+    pub teardown: bool,
+
+    /// Overrides `sync_options.initial_copy_timeout_secs`: how long --continuous waits for the initial copy.
+    #[arg(long, value_name = "SECS", requires = "continuous")]
+    pub initial_copy_timeout: Option<u64>,
+
+    /// Overrides `masking_rules_file`: mask personal data in the target databases.
+    #[arg(long, value_name = "PATH")]
+    pub masking_rules: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct PruneArgs {
+    /// List which archives would be kept and deleted without deleting anything.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct UploadArgs {
+    /// Archive to upload: a path, or a file name inside `local_backup_dir`.
+    #[arg(value_name = "ARCHIVE")]
+    pub archive: PathBuf,
+
+    /// Continue this multipart upload ID instead of the one saved next to the archive.
+    #[arg(long, value_name = "ID")]
+    pub upload_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct DiffArgs {
+    /// Overrides `source_database_url` from the config file.
+    #[arg(long, value_name = "URL")]
+    pub source_database_url: Option<String>,
+
+    /// Overrides `target_database_url` from the config file.
+    #[arg(long, value_name = "URL")]
+    pub target_database_url: Option<String>,
+
+    /// Overrides `database_list`. Comma separated names, or `source:target` pairs.
+    #[arg(long, value_name = "LIST")]
+    pub database_list: Option<String>,
+
+    /// Compare the schema dumped in this archive instead of the source server: a local path,
+    /// `s3://bucket/key`, `latest`, `latest-before:<datetime>` or a catalog id shown by `list`.
+    /// Each archived schema is loaded into a temporary `databasetool_diff_*` database, created
+    /// and dropped on the scratch server (the target server unless --scratch-database-url is
+    /// given), which needs CREATEDB there. Protected hosts are refused.
+    #[arg(long, value_name = "ARCHIVE")]
+    pub archive: Option<String>,
+
+    /// Overrides `scratch_database_url`: the server archived schemas are loaded into with --archive.
+    #[arg(long, value_name = "URL", requires = "archive")]
+    pub scratch_database_url: Option<String>,
+
+    /// Report format: text or json.
+    #[arg(long, value_name = "FORMAT", default_value = "text")]
+    pub format: String,
+
+    /// Write the report to this file instead of printing it.
+    #[arg(long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct WalPushArgs {
+    /// Path of the segment or history file to archive (`%p`).
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct WalFetchArgs {
+    /// Name of the archived file to fetch (`%f`).
+    #[arg(value_name = "NAME")]
+    pub name: String,
+
+    /// Where PostgreSQL expects the file (`%p`).
+    #[arg(value_name = "DESTINATION")]
+    pub destination: PathBuf,
+}
+
+impl Command {
+    /// Maps an interactive menu answer ("1"/"backup", ...) to a command with no overrides.
+    pub fn from_choice(choice: &str) -> Result<Self> {
+        match choice.trim() {
+            "1" | "backup" => Ok(Command::Backup(BackupArgs::default())),
+            "2" | "restore" => Ok(Command::Restore(RestoreArgs::default())),
+            "3" | "sync" => Ok(Command::Sync(SyncArgs::default())),
+            "4" | "prune" => Ok(Command::Prune(PruneArgs::default())),
+            "5" | "list" => Ok(Command::List),
+            _ => {
+                println!("❌ Invalid choice. Please enter '1' (backup), '2' (restore), '3' (sync), '4' (prune) or '5' (list).");
+                anyhow::bail!("Invalid operation choice")
+            }
+        }
+    }
+
+    /// Applies the command-line overrides of this command onto the parsed config file.
+    pub fn apply_overrides(&self, raw_config: &mut RawJsonConfig) -> Result<()> {
+        match self {
+            Command::Backup(args) => {
+                override_field(&mut raw_config.source_database_url, &args.source_database_url);
+                override_database_list(raw_config, &args.database_list)?;
+                if args.max_parallel_databases.is_some() {
+                    raw_config.max_parallel_databases = args.max_parallel_databases;
+                }
+                override_field(&mut raw_config.dump_format, &args.dump_format);
+                if args.jobs.is_some() {
+                    raw_config.jobs = args.jobs;
+                }
+                if args.table_checksums {
+                    raw_config.table_checksums = Some(true);
+                }
+            }
+            Command::Restore(args) => {
+                override_field(&mut raw_config.target_database_url, &args.target_database_url);
+                override_field(&mut raw_config.archive_file_path_for_restore, &args.archive);
+                override_database_list(raw_config, &args.database_list)?;
+                if args.jobs.is_some() {
+                    raw_config.jobs = args.jobs;
+                }
+                if args.masking_rules.is_some() {
+                    raw_config.masking_rules_file = args.masking_rules.clone();
+                }
+                if args.globals {
+                    raw_config.globals.get_or_insert_with(Default::default).restore = Some(true);
+                }
+                if args.preserve_ownership
+                    && let Some(restore_options) = raw_config.restore_options.as_mut()
+                {
+                    restore_options.preserve_ownership = true;
+                }
+                if let Some(list) = &args.schema_mapping
+                    && let Some(restore_options) = raw_config.restore_options.as_mut()
+                {
+                    restore_options.schema_mapping = parse_schema_mapping_arg(list)?;
+                }
+            }
+            Command::Sync(args) => {
+                override_field(&mut raw_config.source_database_url, &args.source_database_url);
+                override_field(&mut raw_config.target_database_url, &args.target_database_url);
+                override_database_list(raw_config, &args.database_list)?;
+                let sync_options = raw_config.sync_options.get_or_insert_with(Default::default);
+                if args.incremental {
+                    sync_options.incremental = Some(true);
+                }
+                override_field(&mut sync_options.fingerprint, &args.fingerprint);
+                if args.streaming {
+                    sync_options.streaming = Some(true);
+                }
+                if args.workers.is_some() {
+                    sync_options.workers = args.workers;
+                }
+                if args.initial_copy_timeout.is_some() {
+                    sync_options.initial_copy_timeout_secs = args.initial_copy_timeout;
+                }
+                if args.masking_rules.is_some() {
+                    raw_config.masking_rules_file = args.masking_rules.clone();
+                }
+            }
+            Command::Diff(args) => {
+                override_field(&mut raw_config.source_database_url, &args.source_database_url);
+                override_field(&mut raw_config.target_database_url, &args.target_database_url);
+                override_field(&mut raw_config.scratch_database_url, &args.scratch_database_url);
+                override_database_list(raw_config, &args.database_list)?;
+            }
+            Command::Prune(_) | Command::List | Command::Upload(_) | Command::WalPush(_) | Command::WalFetch(_) => {}
+        }
+        Ok(())
+    }
+}
+
+fn override_field(field: &mut Option<String>, value: &Option<String>) {
+    if let Some(value) = value {
+        *field = Some(value.clone());
+    }
+}
+
+fn override_database_list(raw_config: &mut RawJsonConfig, value: &Option<String>) -> Result<()> {
+    if let Some(list) = value {
+        raw_config.database_list = Some(parse_database_list_arg(list)?);
+    }
+    Ok(())
+}
+
+/// Parses a `--database-list` argument into the same JSON shape `config.json` accepts.
+///
+/// `db1,db2` becomes `["db1", "db2"]`; as soon as one entry uses `source:target`,
+/// the whole list becomes a mapping object and plain entries map to themselves.
+fn parse_database_list_arg(list: &str) -> Result<serde_json::Value> {
+    let entries: Vec<&str> = list
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect();
+
+    if entries.is_empty() {
+        anyhow::bail!("--database-list was given but contains no database names");
+    }
+
+    if !entries.iter().any(|entry| entry.contains(':')) {
+        return Ok(serde_json::json!(entries));
+    }
+
+    let mut mapping = HashMap::new();
+    for entry in entries {
+        let (source, target) = match entry.split_once(':') {
+            Some((source, target)) => (source.trim(), target.trim()),
+            None => (entry, entry),
+        };
+        if source.is_empty() || target.is_empty() {
+            anyhow::bail!("Invalid --database-list entry '{}'. Expected 'name' or 'source:target'.", entry);
+        }
+        mapping.insert(source.to_string(), target.to_string());
+    }
+    serde_json::to_value(mapping).context("Failed to convert --database-list mapping to JSON")
+}
+
+/// Parses a `--schema-mapping` argument: `public:snapshot,app:app_copy`.
+fn parse_schema_mapping_arg(list: &str) -> Result<BTreeMap<String, String>> {
+    let mut mapping = BTreeMap::new();
+    for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match entry.split_once(':') {
+            Some((source, target)) if !source.trim().is_empty() && !target.trim().is_empty() => {
+                mapping.insert(source.trim().to_string(), target.trim().to_string());
+            }
+            _ => anyhow::bail!("Invalid --schema-mapping entry '{}'. Expected 'source:target'.", entry),
+        }
+    }
+    if mapping.is_empty() {
+        anyhow::bail!("--schema-mapping was given but contains no schemas");
+    }
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_database_list_arg_plain_names() -> Result<()> {
+        let value = parse_database_list_arg("db1, db2,,db3")?;
+        assert_eq!(value, json!(["db1", "db2", "db3"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_database_list_arg_mapping() -> Result<()> {
+        let value = parse_database_list_arg("prod:dev,analytics")?;
+        assert_eq!(value, json!({"prod": "dev", "analytics": "analytics"}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_database_list_arg_invalid() {
+        assert!(parse_database_list_arg(" , ").is_err());
+        assert!(parse_database_list_arg("prod:").is_err());
+    }
+
+    #[test]
+    fn test_parse_schema_mapping_arg() -> Result<()> {
+        let mapping = parse_schema_mapping_arg("public:snapshot_2026_10, app : app_copy")?;
+        assert_eq!(mapping.get("public").map(String::as_str), Some("snapshot_2026_10"));
+        assert_eq!(mapping.get("app").map(String::as_str), Some("app_copy"));
+        assert!(parse_schema_mapping_arg("public").is_err());
+        assert!(parse_schema_mapping_arg(",").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_overrides_only_touches_given_fields() -> Result<()> {
+        let mut raw_config = RawJsonConfig {
+            source_database_url: Some("postgres://file-source/".to_string()),
+            target_database_url: Some("postgres://file-target/".to_string()),
+            ..Default::default()
+        };
+        let command = Command::Sync(SyncArgs {
+            target_database_url: Some("postgres://cli-target/".to_string()),
+            database_list: Some("app".to_string()),
+            incremental: true,
+            ..Default::default()
+        });
+        command.apply_overrides(&mut raw_config)?;
+
+        assert_eq!(raw_config.source_database_url.as_deref(), Some("postgres://file-source/"));
+        assert_eq!(raw_config.target_database_url.as_deref(), Some("postgres://cli-target/"));
+        assert_eq!(raw_config.database_list, Some(json!(["app"])));
+        let sync_options = raw_config.sync_options.context("sync_options should be set")?;
+        assert_eq!(sync_options.incremental, Some(true));
+        assert!(sync_options.fingerprint.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_parses_subcommand_with_overrides() -> Result<()> {
+        let cli = Cli::try_parse_from([
+            "databasetool",
+            "--config",
+            "/etc/databasetool.json",
+            "restore",
+            "--non-interactive",
+            "--archive",
+            "s3://bucket/backups/2024.tar.gz",
+            "--plan",
+        ])?;
+        assert_eq!(cli.config, PathBuf::from("/etc/databasetool.json"));
+        assert!(cli.non_interactive);
+        match cli.command {
+            Some(Command::Restore(args)) => {
+                assert_eq!(args.archive.as_deref(), Some("s3://bucket/backups/2024.tar.gz"));
+                assert!(args.target_database_url.is_none());
+                assert!(args.plan);
+            }
+            other => panic!("Expected restore command, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_teardown_requires_continuous() -> Result<()> {
+        assert!(Cli::try_parse_from(["databasetool", "sync", "--teardown"]).is_err());
+        let cli = Cli::try_parse_from(["databasetool", "sync", "--continuous", "--teardown"])?;
+        match cli.command {
+            Some(Command::Sync(args)) => assert!(args.continuous && args.teardown),
+            other => panic!("Expected sync command, got {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_parses_upload_with_upload_id() -> Result<()> {
+        let cli = Cli::try_parse_from(["databasetool", "upload", "2024-05-01_02-00-00.tar.gz", "--upload-id", "abc123"])?;
+        match cli.command {
+            Some(Command::Upload(args)) => {
+                assert_eq!(args.archive, PathBuf::from("2024-05-01_02-00-00.tar.gz"));
+                assert_eq!(args.upload_id.as_deref(), Some("abc123"));
+            }
+            other => panic!("Expected upload command, got {:?}", other),
+        }
+        assert!(Cli::try_parse_from(["databasetool", "upload"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_parses_diff_against_archive() -> Result<()> {
+        let cli = Cli::try_parse_from([
+            "databasetool",
+            "diff",
+            "--archive",
+            "latest",
+            "--format",
+            "json",
+            "--database-list",
+            "app:app_dev",
+            "--scratch-database-url",
+            "postgres://scratch:5432/",
+        ])?;
+        let Some(command @ Command::Diff(_)) = cli.command else {
+            panic!("Expected diff command");
+        };
+        let mut raw_config = RawJsonConfig::default();
+        command.apply_overrides(&mut raw_config)?;
+        assert_eq!(raw_config.database_list, Some(json!({"app": "app_dev"})));
+        assert_eq!(raw_config.scratch_database_url.as_deref(), Some("postgres://scratch:5432/"));
+        let Command::Diff(args) = command else { unreachable!() };
+        assert_eq!(args.archive.as_deref(), Some("latest"));
+        assert_eq!(args.format, "json");
+        assert!(args.output.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_parses_physical_restore_and_wal_commands() -> Result<()> {
+        let cli = Cli::try_parse_from(["databasetool", "restore", "--physical", "--data-dir", "/srv/pg", "--target-lsn", "0/3000060"])?;
+        match cli.command {
+            Some(Command::Restore(args)) => {
+                assert!(args.physical);
+                assert_eq!(args.data_dir, Some(PathBuf::from("/srv/pg")));
+                assert_eq!(args.target_lsn.as_deref(), Some("0/3000060"));
+            }
+            other => panic!("Expected restore command, got {:?}", other),
+        }
+        assert!(Cli::try_parse_from(["databasetool", "restore", "--physical"]).is_err());
+        assert!(Cli::try_parse_from(["databasetool", "restore", "--target-time", "2024-05-01 12:00"]).is_err());
+        assert!(Cli::try_parse_from(["databasetool", "backup", "--physical", "--dump-format", "custom"]).is_err());
+
+        let cli = Cli::try_parse_from(["databasetool", "wal-fetch", "000000010000000000000003", "pg_wal/RECOVERYXLOG"])?;
+        match cli.command {
+            Some(Command::WalFetch(args)) => {
+                assert_eq!(args.name, "000000010000000000000003");
+                assert_eq!(args.destination, PathBuf::from("pg_wal/RECOVERYXLOG"));
+            }
+            other => panic!("Expected wal-fetch command, got {:?}", other),
+        }
+        assert!(matches!(Cli::try_parse_from(["databasetool", "wal-push", "pg_wal/0000000100000000000000A1"])?.command, Some(Command::WalPush(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_globals_flag_sets_globals_restore() -> Result<()> {
+        let cli = Cli::try_parse_from(["databasetool", "restore", "--globals"])?;
+        let mut raw = RawJsonConfig::default();
+        cli.command.expect("restore command").apply_overrides(&mut raw)?;
+        assert_eq!(raw.globals.and_then(|globals| globals.restore), Some(true));
+        assert!(Cli::try_parse_from(["databasetool", "restore", "--physical", "--data-dir", "/srv/pg", "--globals"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cli_accepts_legacy_numeric_choice() -> Result<()> {
+        let cli = Cli::try_parse_from(["databasetool", "3"])?;
+        assert!(matches!(cli.command, Some(Command::Sync(_))));
+        assert_eq!(cli.config, PathBuf::from("config.json"));
+        Ok(())
+    }
+}
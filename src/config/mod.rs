@@ -6,7 +6,7 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 // Structs for deserializing config.json
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct JsonS3StorageConfig {
     pub bucket_name: Option<String>,
     pub region: Option<String>,
@@ -14,15 +14,98 @@ pub struct JsonS3StorageConfig {
     pub secret_access_key: Option<String>,
     pub endpoint_url: Option<String>,
     pub folder_prefix: Option<String>,
+    pub force_path_style: Option<bool>,
+    pub multipart_part_size_mb: Option<u64>,
+    pub multipart_concurrency: Option<usize>,
+    pub max_retries: Option<u32>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct JsonRestoreOptions {
     pub drop_target_database_if_exists: bool,
     pub create_target_database_if_not_exists: bool,
+    #[serde(default)]
+    pub preserve_ownership: bool,
+    #[serde(default)]
+    pub schema_mapping: std::collections::BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JsonRetentionPolicy {
+    pub keep_last: Option<usize>,
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+    pub keep_monthly: Option<usize>,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct JsonEncryptionConfig {
+    pub passphrase: Option<String>,
+    pub key_file: Option<PathBuf>,
+    pub recipients: Option<Vec<String>>,
+}
+
+impl std::fmt::Debug for JsonEncryptionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JsonEncryptionConfig")
+            .field("passphrase", &self.passphrase.as_ref().map(|_| "<redacted>"))
+            .field("key_file", &self.key_file)
+            .field("recipients", &self.recipients)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JsonCompressionConfig {
+    pub codec: Option<String>,
+    pub level: Option<i32>,
+    pub threads: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JsonSyncOptions {
+    pub incremental: Option<bool>,
+    pub fingerprint: Option<String>,
+    pub streaming: Option<bool>,
+    pub workers: Option<usize>,
+    pub publisher_url: Option<String>,
+    pub initial_copy_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JsonGlobalsOptions {
+    pub dump: Option<bool>,
+    pub include_passwords: Option<bool>,
+    pub restore: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JsonTableFilters {
+    pub include_schemas: Option<Vec<String>>,
+    pub exclude_schemas: Option<Vec<String>>,
+    pub include_tables: Option<Vec<String>>,
+    pub exclude_tables: Option<Vec<String>>,
+    pub schema_only_tables: Option<Vec<String>>,
+    #[serde(rename = "where")]
+    pub row_filters: Option<std::collections::BTreeMap<String, String>>,
 }
 
-#[derive(Debug, Clone, Deserialize)] // Added Deserialize here
+/// Contents of the file named by `masking_rules_file`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct JsonMaskingRules {
+    pub seed: Option<String>,
+    pub rules: Option<std::collections::BTreeMap<String, JsonMaskingStrategy>>,
+}
+
+/// A strategy name, or an object for strategies that take a value.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum JsonMaskingStrategy {
+    Name(String),
+    Detailed { strategy: String, value: Option<String> },
+}
+
+#[derive(Debug, Clone, Default, Deserialize)] // Added Deserialize here
 pub struct RawJsonConfig {
     pub source_database_url: Option<String>,
     pub target_database_url: Option<String>,
@@ -32,6 +115,23 @@ pub struct RawJsonConfig {
     pub database_list: Option<serde_json::Value>,
     pub restore_options: Option<JsonRestoreOptions>,
     pub s3_storage: Option<JsonS3StorageConfig>,
+    pub retention: Option<JsonRetentionPolicy>,
+    pub max_parallel_databases: Option<usize>,
+    pub dump_format: Option<String>,
+    pub jobs: Option<usize>,
+    pub streaming_upload: Option<bool>,
+    pub keep_local_copy: Option<bool>,
+    pub encryption: Option<JsonEncryptionConfig>,
+    pub compression: Option<JsonCompressionConfig>,
+    pub protected_hosts: Option<Vec<String>>,
+    pub protected_databases: Option<Vec<String>>,
+    pub sync_options: Option<JsonSyncOptions>,
+    pub filters: Option<JsonTableFilters>,
+    pub masking_rules_file: Option<PathBuf>,
+    pub table_checksums: Option<bool>,
+    pub globals: Option<JsonGlobalsOptions>,
+    pub role_mapping: Option<std::collections::BTreeMap<String, String>>,
+    pub scratch_database_url: Option<String>,
 }
 
 // Application's internal configuration structs
@@ -43,25 +143,211 @@ pub struct SpacesConfig {
     pub secret_access_key: String,
     pub bucket_name: String,
     pub folder_prefix: Option<String>,
+    pub force_path_style: bool, // Bucket in the URL path instead of the host name (MinIO and similar)
+    pub transfer: S3TransferConfig,
+}
+
+/// How large archives are moved to and from S3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct S3TransferConfig {
+    pub part_size_bytes: u64, // Multipart part size, between 5 MiB and 5 GiB
+    pub concurrency: usize,   // Parts uploaded at the same time, at least 1
+    pub max_retries: u32,     // Retries per request, with exponential backoff
+}
+
+impl Default for S3TransferConfig {
+    fn default() -> Self {
+        S3TransferConfig {
+            part_size_bytes: 64 * 1024 * 1024,
+            concurrency: 4,
+            max_retries: 5,
+        }
+    }
+}
+
+/// Grandfather-father-son retention: an archive survives if any rule keeps it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub keep_last: usize,    // Most recent N archives, regardless of age
+    pub keep_daily: usize,   // Newest archive of each of the last N days that have backups
+    pub keep_weekly: usize,  // Newest archive of each of the last N ISO weeks that have backups
+    pub keep_monthly: usize, // Newest archive of each of the last N months that have backups
+}
+
+/// Output format of `pg_dump` for each database in a backup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// `<db>_schema.sql` and `<db>_data.sql`, replayed with psql.
+    #[default]
+    Plain,
+    /// A single `<db>.dump` file (`pg_dump -Fc`), restored with pg_restore.
+    Custom,
+    /// A `<db>.dir/` directory (`pg_dump -Fd`), dumped and restored in parallel.
+    Directory,
+}
+
+impl DumpFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DumpFormat::Plain => "plain",
+            DumpFormat::Custom => "custom",
+            DumpFormat::Directory => "directory",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "plain" | "p" => Ok(DumpFormat::Plain),
+            "custom" | "c" => Ok(DumpFormat::Custom),
+            "directory" | "d" => Ok(DumpFormat::Directory),
+            other => anyhow::bail!("Unknown dump_format '{}'. Expected plain, custom or directory.", other),
+        }
+    }
+}
+
+/// Compression applied to the tar stream of a backup archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompressionCodec {
+    /// `.tar.gz`, the historical default.
+    #[default]
+    Gzip,
+    /// `.tar.zst`, much faster than gzip at a similar ratio; can use several threads.
+    Zstd,
+    /// `.tar.lz4`, the fastest codec with the lowest ratio.
+    Lz4,
+    /// `.tar`, for dumps that are already compressed (custom and directory format).
+    None,
+}
+
+impl CompressionCodec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionCodec::Gzip => "gzip",
+            CompressionCodec::Zstd => "zstd",
+            CompressionCodec::Lz4 => "lz4",
+            CompressionCodec::None => "none",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "gz" => Ok(CompressionCodec::Gzip),
+            "zstd" | "zst" => Ok(CompressionCodec::Zstd),
+            "lz4" => Ok(CompressionCodec::Lz4),
+            "none" => Ok(CompressionCodec::None),
+            other => anyhow::bail!("Unknown compression codec '{}'. Expected gzip, zstd, lz4 or none.", other),
+        }
+    }
+
+    /// Archive extension for this codec, without the leading dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressionCodec::Gzip => "tar.gz",
+            CompressionCodec::Zstd => "tar.zst",
+            CompressionCodec::Lz4 => "tar.lz4",
+            CompressionCodec::None => "tar",
+        }
+    }
+
+    /// Accepted compression levels, or `None` when the codec has no levels.
+    pub fn level_range(&self) -> Option<std::ops::RangeInclusive<i32>> {
+        match self {
+            CompressionCodec::Gzip => Some(0..=9),
+            CompressionCodec::Zstd => Some(1..=22),
+            CompressionCodec::Lz4 => Some(0..=12),
+            CompressionCodec::None => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionConfig {
+    pub codec: CompressionCodec,
+    pub level: Option<i32>, // Codec default when unset
+    pub threads: u32,       // zstd worker threads; 1 compresses on the writing thread
+}
+
+/// Client-side archive encryption (age): a passphrase, or x25519 keys.
+///
+/// Backups are encrypted to the passphrase, or to every recipient plus the public keys of
+/// `key_file`. Restoring needs the passphrase or `key_file`; recipients alone cannot decrypt.
+#[derive(Clone, Default)]
+pub struct EncryptionConfig {
+    pub passphrase: Option<String>,
+    pub key_file: Option<PathBuf>, // age identity file (AGE-SECRET-KEY-1...)
+    pub recipients: Vec<String>,   // age public keys (age1...)
+}
+
+impl std::fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionConfig")
+            .field("passphrase", &self.passphrase.as_ref().map(|_| "<redacted>"))
+            .field("key_file", &self.key_file)
+            .field("recipients", &self.recipients)
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct BackupConfig {
     pub source_db_url: String,
     pub databases_to_backup: Option<Vec<String>>,
-    pub local_backup_path: PathBuf,
+    pub local_backup_path: Option<PathBuf>, // Always set unless streaming without a local copy
     pub temp_dump_root: Option<PathBuf>,
     pub upload_to_spaces: bool,
+    pub streaming_upload: bool, // pg_dump -> tar -> gzip -> S3 multipart, without staging on disk
+    pub keep_local_copy: bool,
+    pub retention: Option<RetentionPolicy>, // Applied after a successful backup when set
+    pub max_parallel_databases: usize, // Databases dumped concurrently, at least 1
+    pub dump_format: DumpFormat,
+    pub jobs: usize, // pg_dump -j for directory format, at least 1
+    pub encryption: Option<EncryptionConfig>, // Archives are encrypted when set
+    pub compression: CompressionConfig,
+    pub filters: TableFilters,
+    pub table_checksums: bool, // Record a content checksum of every table in the manifest
+    pub dump_globals: bool, // pg_dumpall --globals-only into globals.sql
+    pub include_role_passwords: bool, // Otherwise pg_dumpall runs with --no-role-passwords
+}
+
+#[derive(Debug, Clone)]
+pub struct ListConfig {
+    pub local_backup_path: Option<PathBuf>,
+    pub list_spaces: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct PruneConfig {
+    pub local_backup_path: Option<PathBuf>,
+    pub prune_spaces: bool,
+    pub retention: RetentionPolicy,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub archive_path: PathBuf,
+    pub resume_upload_id: Option<String>, // Continue this multipart upload instead of the saved one
 }
 
 #[derive(Debug, Clone)]
 pub struct RestoreConfig {
     pub target_db_url: String,
-    pub archive_source_path: String,
+    pub archive_source_path: String, // Local path, s3:// URI, or catalog selector (latest, latest-before:..., id)
+    pub local_backup_path: Option<PathBuf>, // Searched when archive_source_path is a catalog selector
     pub databases_to_restore: Option<HashMap<String, String>>,
-    pub download_from_spaces: bool,
     pub drop_target_database_if_exists: bool,
     pub create_target_database_if_not_exists: bool,
+    pub jobs: usize, // pg_restore -j for custom and directory format archives
+    pub download_dir: PathBuf, // S3 archives are downloaded here; partial downloads resume from it
+    pub encryption: Option<EncryptionConfig>, // Keys for encrypted archives
+    pub plan_only: bool, // Print what would be dropped, created and overwritten, then stop
+    pub safety: SafetyConfig,
+    pub filters: TableFilters, // Applied to each database after it is restored
+    pub masking: Option<MaskingConfig>, // Applied to each database after its data is loaded
+    pub restore_globals: bool, // Create missing roles, then re-apply database ownership, grants and settings
+    pub role_mapping: std::collections::BTreeMap<String, String>, // Source role -> target role
+    pub preserve_ownership: bool, // Keep the dump's owners and grants instead of --no-owner --no-acl
+    pub schema_mapping: std::collections::BTreeMap<String, String>, // Source schema -> schema it is restored into
 }
 
 #[derive(Debug, Clone)]
@@ -69,6 +355,297 @@ pub struct SyncConfig {
     pub source_db_url: String,
     pub target_db_url: String,
     pub databases_to_sync: Option<Vec<String>>, // If None, sync all eligible from source based on its list.
+    pub plan_only: bool, // Print what would be dropped and copied, then stop
+    pub safety: SafetyConfig,
+    pub incremental: bool, // Re-copy only tables whose fingerprints differ instead of recreating the database
+    pub fingerprint: FingerprintMethod,
+    pub streaming: bool, // COPY table by table between the servers instead of dump files
+    pub workers: usize,  // Tables streamed at the same time, at least 1
+    pub replication: Option<ReplicationAction>, // sync --continuous: logical replication instead of a copy
+    pub publisher_url: String, // How the target server reaches the source; source_db_url unless set
+    pub initial_copy_timeout: std::time::Duration, // How long sync --continuous waits for the initial copy
+    pub filters: TableFilters,
+    pub masking: Option<MaskingConfig>,
+}
+
+/// What `diff` compares: each source database, or its schema inside an archive, with a target database.
+#[derive(Debug, Clone)]
+pub struct DiffConfig {
+    pub source_db_url: Option<String>, // None when the source side is an archive
+    pub target_db_url: String,
+    pub archive: Option<String>, // Local path, s3:// URI or catalog selector; its schema dump replaces the source database
+    pub local_backup_path: Option<PathBuf>, // Searched when archive is a catalog selector
+    pub download_dir: PathBuf,
+    pub encryption: Option<EncryptionConfig>, // Keys for encrypted archives
+    pub databases: Option<HashMap<String, String>>, // Source -> target; None compares every source database with its namesake
+    pub format: DiffFormat,
+    pub output_path: Option<PathBuf>, // Report file; printed when unset
+    pub scratch_db_url: Option<String>, // Server archived schemas are loaded into; the target server when unset
+    pub safety: SafetyConfig,           // protected_hosts also keep scratch databases off a server
+}
+
+/// Output of the `diff` report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiffFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl DiffFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiffFormat::Text => "text",
+            DiffFormat::Json => "json",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(DiffFormat::Text),
+            "json" => Ok(DiffFormat::Json),
+            other => anyhow::bail!("Unknown diff format '{}'. Expected text or json.", other),
+        }
+    }
+}
+
+/// Where physical base backups and archived WAL segments are kept.
+///
+/// S3 is used whenever `s3_storage` is configured, under `<folder_prefix>/physical/`;
+/// otherwise `local_backup_dir/physical/`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PhysicalStorage {
+    Local(PathBuf),
+    S3,
+}
+
+/// `backup --physical`: a `pg_basebackup` of the whole source cluster.
+#[derive(Debug, Clone)]
+pub struct PhysicalBackupConfig {
+    pub source_db_url: String, // Needs the REPLICATION privilege
+    pub storage: PhysicalStorage,
+    pub temp_dump_root: Option<PathBuf>, // Staging directory before an S3 upload
+}
+
+/// What `wal-push` and `wal-fetch` do, called by PostgreSQL as `archive_command` and `restore_command`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalAction {
+    /// Archive the completed segment (or history file) at this path.
+    Push(PathBuf),
+    /// Copy the archived file `name` to `destination`.
+    Fetch { name: String, destination: PathBuf },
+}
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    pub storage: PhysicalStorage,
+    pub action: WalAction,
+}
+
+/// Where recovery of a physical restore stops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecoveryTarget {
+    /// Replay every archived WAL segment.
+    Latest,
+    /// Stop before the first transaction that committed after this time.
+    Time(chrono::DateTime<chrono::FixedOffset>),
+    /// Stop at this WAL location (`X/Y`).
+    Lsn(u64),
+}
+
+/// `restore --physical`: rebuild a data directory from a base backup and archived WAL.
+#[derive(Debug, Clone)]
+pub struct PhysicalRestoreConfig {
+    pub storage: PhysicalStorage,
+    pub data_dir: PathBuf, // Must not exist or be empty
+    pub target: RecoveryTarget,
+    pub base_backup: Option<String>, // Base backup id; the newest one before the target when unset
+    pub config_path: PathBuf, // Absolute path of config.json, for the restore_command written into the data directory
+    pub download_dir: PathBuf,
+}
+
+/// What `sync --continuous` does with the logical replication of each database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicationAction {
+    /// Create the publication and subscription if missing, wait for the initial copy and report lag.
+    Start,
+    /// Drop the subscription (and its slot) and the publication; the target keeps its data.
+    Teardown,
+}
+
+/// How incremental sync decides that a table differs between source and target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FingerprintMethod {
+    /// Row count, `max()` of a single-column primary key and `max(updated_at)`. Cheap, but misses
+    /// updates to tables without an `updated_at` column that keep the row count.
+    #[default]
+    Stats,
+    /// Row count and a sum of per-row md5 hashes. Reads each table in full on both servers.
+    Checksum,
+}
+
+impl FingerprintMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FingerprintMethod::Stats => "stats",
+            FingerprintMethod::Checksum => "checksum",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "stats" => Ok(FingerprintMethod::Stats),
+            "checksum" => Ok(FingerprintMethod::Checksum),
+            other => anyhow::bail!("Unknown sync_options.fingerprint '{}'. Expected stats or checksum.", other),
+        }
+    }
+}
+
+/// Schemas, tables and rows a backup, restore or sync covers (`filters` in config.json).
+///
+/// Table patterns are `schema.table`, or `table` for a table in any schema; `*` matches any
+/// characters and case is ignored. Empty lists leave everything in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableFilters {
+    pub include_schemas: Vec<String>, // Only these schemas when not empty
+    pub exclude_schemas: Vec<String>,
+    pub include_tables: Vec<String>, // Only these tables when not empty
+    pub exclude_tables: Vec<String>,
+    pub schema_only_tables: Vec<String>, // Created without their rows
+    pub row_filters: Vec<(String, String)>, // (table pattern, WHERE condition) from `filters.where`
+}
+
+impl TableFilters {
+    pub fn is_empty(&self) -> bool {
+        *self == TableFilters::default()
+    }
+
+    /// One line for plans and logs, e.g. `exclude tables public.audit_log; WHERE on public.events`.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        for (label, patterns) in [
+            ("only schemas", &self.include_schemas),
+            ("exclude schemas", &self.exclude_schemas),
+            ("only tables", &self.include_tables),
+            ("exclude tables", &self.exclude_tables),
+            ("schema only", &self.schema_only_tables),
+        ] {
+            if !patterns.is_empty() {
+                parts.push(format!("{} {}", label, patterns.join(", ")));
+            }
+        }
+        if !self.row_filters.is_empty() {
+            let tables: Vec<&str> = self.row_filters.iter().map(|(table, _)| table.as_str()).collect();
+            parts.push(format!("WHERE on {}", tables.join(", ")));
+        }
+        parts.join("; ")
+    }
+}
+
+/// How one masked column is rewritten. Every strategy except `Null` and `Fixed` derives its
+/// output from the original value and the seed, so equal values mask to equal values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaskingStrategy {
+    FakeEmail,
+    FakeName,
+    Hash,
+    Null,
+    Fixed(String),
+    PreserveFormat, // Digits, lower and upper case letters are replaced in place; the rest is kept
+}
+
+impl MaskingStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MaskingStrategy::FakeEmail => "fake_email",
+            MaskingStrategy::FakeName => "fake_name",
+            MaskingStrategy::Hash => "hash",
+            MaskingStrategy::Null => "null",
+            MaskingStrategy::Fixed(_) => "fixed",
+            MaskingStrategy::PreserveFormat => "preserve_format",
+        }
+    }
+
+    pub fn parse(name: &str, value: Option<&str>) -> Result<Self> {
+        let strategy = match name.trim().to_ascii_lowercase().as_str() {
+            "fake_email" => MaskingStrategy::FakeEmail,
+            "fake_name" => MaskingStrategy::FakeName,
+            "hash" => MaskingStrategy::Hash,
+            "null" => MaskingStrategy::Null,
+            "preserve_format" => MaskingStrategy::PreserveFormat,
+            "fixed" => {
+                let value = value.context("Masking strategy \"fixed\" needs a value.")?;
+                return Ok(MaskingStrategy::Fixed(value.to_string()));
+            }
+            other => anyhow::bail!(
+                "Unknown masking strategy '{}'. Expected fake_email, fake_name, hash, null, fixed or preserve_format.",
+                other
+            ),
+        };
+        if value.is_some() {
+            anyhow::bail!("Masking strategy \"{}\" does not take a value; only \"fixed\" does.", strategy.as_str());
+        }
+        Ok(strategy)
+    }
+}
+
+/// One entry of the masking rules file: `schema.table.column` or `table.column`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskingRule {
+    pub table: String,  // Table pattern, as in `filters`
+    pub column: String, // Column pattern, `*` matches any characters
+    pub strategy: MaskingStrategy,
+}
+
+impl MaskingRule {
+    /// The rule as written in the rules file.
+    pub fn key(&self) -> String {
+        format!("{}.{}", self.table, self.column)
+    }
+}
+
+/// PII masking for restore and sync, read from `masking_rules_file`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MaskingConfig {
+    pub rules_file: PathBuf,
+    pub rules: Vec<MaskingRule>,
+    pub seed: String,     // The file's seed, or a random one shared by every database of this run
+    pub fixed_seed: bool, // Whether the seed came from the file, so the output is the same on every run
+}
+
+impl std::fmt::Debug for MaskingConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MaskingConfig")
+            .field("rules_file", &self.rules_file)
+            .field("rules", &self.rules)
+            .field("seed", &"<redacted>")
+            .field("fixed_seed", &self.fixed_seed)
+            .finish()
+    }
+}
+
+impl MaskingConfig {
+    /// One line for plans and logs, e.g. `4 rule(s) from masking.json, fixed seed`.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} rule(s) from {}, {}",
+            self.rules.len(),
+            self.rules_file.display(),
+            if self.fixed_seed { "fixed seed" } else { "random seed for this run" }
+        )
+    }
+}
+
+/// Guards against destructive restores and syncs into the wrong server.
+///
+/// Protected targets are never dropped, truncated or overwritten. Any other target that
+/// holds tables needs its name typed back, or `--yes-i-know`.
+#[derive(Debug, Clone, Default)]
+pub struct SafetyConfig {
+    pub protected_hosts: Vec<String>,     // Host or host:port patterns, `*` matches any characters
+    pub protected_databases: Vec<String>, // Database name patterns, `*` matches any characters
+    pub assume_yes: bool,                 // --yes-i-know: skip the typed confirmation
+    pub interactive: bool,                // The confirmation may be read from stdin
 }
 
 #[derive(Debug, Clone)]
@@ -83,6 +660,13 @@ pub enum OperationConfig {
     Backup(BackupConfig),
     Restore(RestoreConfig),
     Sync(SyncConfig),
+    Prune(PruneConfig),
+    List(ListConfig),
+    Upload(UploadConfig),
+    Diff(DiffConfig),
+    PhysicalBackup(PhysicalBackupConfig),
+    PhysicalRestore(PhysicalRestoreConfig),
+    Wal(WalConfig),
 }
 
 impl AppConfig {
@@ -97,6 +681,10 @@ impl AppConfig {
                 )
             })?;
 
+        let transfer = match &raw_json_config.s3_storage {
+            Some(s3_raw) => parse_s3_transfer_config(s3_raw)?,
+            None => S3TransferConfig::default(),
+        };
         let spaces_config = raw_json_config.s3_storage.as_ref().and_then(|s3_raw| {
             if let (
                 Some(bucket),
@@ -118,6 +706,8 @@ impl AppConfig {
                     secret_access_key: secret.clone(),
                     endpoint_url: endpoint.clone(),
                     folder_prefix: s3_raw.folder_prefix.clone().filter(|s| !s.is_empty()),
+                    force_path_style: s3_raw.force_path_style.unwrap_or(false),
+                    transfer,
                 })
             } else {
                 if s3_raw.bucket_name.is_some()
@@ -150,16 +740,47 @@ pub fn load_backup_config_from_json(
         .as_ref()
         .context("source_database_url must be set in config.json for backup")?
         .clone();
-    let local_backup_path = raw_config
-        .local_backup_dir
-        .as_ref()
-        .context("local_backup_dir must be set in config.json for backup")?
-        .clone();
+    let streaming_upload = raw_config.streaming_upload.unwrap_or(false);
+    if streaming_upload && !spaces_is_configured {
+        anyhow::bail!("streaming_upload requires S3 storage (s3_storage) to be fully configured in config.json.");
+    }
+    let keep_local_copy = raw_config.keep_local_copy.unwrap_or(true);
+    if !keep_local_copy && !spaces_is_configured {
+        anyhow::bail!("keep_local_copy = false requires S3 storage (s3_storage); otherwise the backup would be discarded.");
+    }
 
-    if local_backup_path.to_string_lossy().is_empty() {
-        return Err(anyhow::anyhow!(
-            "local_backup_dir cannot be empty in config.json."
-        ));
+    // Without streaming the archive is always built locally first.
+    let local_backup_path = raw_config.local_backup_dir.clone();
+    if keep_local_copy || !streaming_upload {
+        let path = local_backup_path
+            .as_ref()
+            .context("local_backup_dir must be set in config.json for backup")?;
+        if path.to_string_lossy().is_empty() {
+            return Err(anyhow::anyhow!(
+                "local_backup_dir cannot be empty in config.json."
+            ));
+        }
+    }
+
+    let max_parallel_databases = raw_config.max_parallel_databases.unwrap_or(1);
+    if max_parallel_databases == 0 {
+        anyhow::bail!("max_parallel_databases must be at least 1 in config.json.");
+    }
+
+    let dump_format = match &raw_config.dump_format {
+        Some(value) => DumpFormat::parse(value)?,
+        None => DumpFormat::default(),
+    };
+    if streaming_upload && dump_format == DumpFormat::Directory {
+        anyhow::bail!("dump_format \"directory\" cannot be streamed; use \"plain\" or \"custom\" with streaming_upload.");
+    }
+    let jobs = parse_jobs(raw_config.jobs)?;
+    if jobs > 1 && dump_format != DumpFormat::Directory {
+        anyhow::bail!(
+            "jobs = {} requires dump_format \"directory\"; pg_dump can only dump the {} format with one job.",
+            jobs,
+            dump_format.as_str()
+        );
     }
 
     Ok(BackupConfig {
@@ -168,12 +789,26 @@ pub fn load_backup_config_from_json(
         local_backup_path,
         temp_dump_root: raw_config.temp_dump_root.clone(),
         upload_to_spaces: spaces_is_configured, // Enable upload if S3 is generally configured
+        streaming_upload,
+        keep_local_copy,
+        retention: parse_retention_policy(&raw_config.retention)?,
+        max_parallel_databases,
+        dump_format,
+        jobs,
+        encryption: parse_encryption_config(&raw_config.encryption)?,
+        compression: parse_compression_config(&raw_config.compression)?,
+        filters: parse_table_filters(&raw_config.filters)?,
+        table_checksums: raw_config.table_checksums.unwrap_or(false),
+        dump_globals: raw_config.globals.as_ref().and_then(|globals| globals.dump).unwrap_or(true),
+        include_role_passwords: raw_config.globals.as_ref().and_then(|globals| globals.include_passwords).unwrap_or(false),
     })
 }
 
 pub fn load_restore_config_from_json(
     raw_config: &RawJsonConfig,
     spaces_is_configured: bool,
+    plan_only: bool,
+    safety: SafetyConfig,
 ) -> Result<RestoreConfig> {
     let target_db_url = raw_config
         .target_database_url
@@ -204,18 +839,56 @@ pub fn load_restore_config_from_json(
         ));
     }
 
-    Ok(RestoreConfig {
+    let restore_config = RestoreConfig {
         target_db_url,
         archive_source_path,
+        local_backup_path: raw_config.local_backup_dir.clone(),
         databases_to_restore: parse_database_list_for_restore(&raw_config.database_list)?,
-        download_from_spaces,
         drop_target_database_if_exists: restore_opts.drop_target_database_if_exists,
         create_target_database_if_not_exists: restore_opts.create_target_database_if_not_exists,
-    })
+        jobs: parse_jobs(raw_config.jobs)?,
+        download_dir: download_dir(raw_config),
+        encryption: parse_encryption_config(&raw_config.encryption)?,
+        plan_only,
+        safety,
+        filters: parse_table_filters(&raw_config.filters)?,
+        masking: load_masking_config(&raw_config.masking_rules_file)?,
+        restore_globals: raw_config.globals.as_ref().and_then(|globals| globals.restore).unwrap_or(false),
+        role_mapping: parse_role_mapping(&raw_config.role_mapping)?,
+        preserve_ownership: restore_opts.preserve_ownership,
+        schema_mapping: parse_schema_mapping(&restore_opts.schema_mapping)?,
+    };
+    // Filters and masking rules name tables of the target database, where a mapped restore
+    // usually sits next to the original schema they would then apply to.
+    if !restore_config.schema_mapping.is_empty() && (!restore_config.filters.is_empty() || restore_config.masking.is_some()) {
+        anyhow::bail!("restore_options.schema_mapping cannot be combined with filters or masking_rules_file.");
+    }
+    Ok(restore_config)
+}
+
+/// Directory for S3 downloads: `temp_dump_root` if configured, else the system temp directory.
+///
+/// Unlike dump directories it is stable across runs, so an interrupted download can resume.
+pub fn download_dir(raw_config: &RawJsonConfig) -> PathBuf {
+    raw_config
+        .temp_dump_root
+        .clone()
+        .filter(|path| !path.as_os_str().is_empty())
+        .unwrap_or_else(std::env::temp_dir)
+        .join("databasetool_downloads")
 }
 
+/// Tables streamed in parallel by `sync_options.streaming` when `workers` is not set.
+const DEFAULT_SYNC_WORKERS: usize = 4;
+
+/// How long `sync --continuous` waits for the initial copy when `initial_copy_timeout_secs` is not set.
+const DEFAULT_INITIAL_COPY_TIMEOUT_SECS: u64 = 6 * 60 * 60;
+
 pub fn load_sync_config_from_json(
     raw_config: &RawJsonConfig,
+    plan_only: bool,
+    replication: Option<ReplicationAction>,
+    safety: SafetyConfig,
 ) -> Result<SyncConfig> {
     let source_db_url = raw_config
         .source_database_url
@@ -230,20 +903,510 @@ pub fn load_sync_config_from_json(
         .clone();
 
     let databases_to_sync = parse_database_list_for_backup_sync(&raw_config.database_list)?;
-    if databases_to_sync.as_ref().map_or(true, |dbs| dbs.is_empty()) {
+    if databases_to_sync.as_ref().is_none_or(|dbs| dbs.is_empty()) {
          println!("Warning: 'database_list' in config.json is empty or not provided for sync operation. This means no databases will be synced unless discovered (if that feature is added). Currently, it likely means nothing will happen.");
         // For sync, an empty or None list usually means no operation.
         // Unlike backup where None might mean "all". For sync, explicit is better.
         // Consider making database_list non-optional in RawJsonConfig for sync if it's always required.
     }
 
+    let sync_options = raw_config.sync_options.clone().unwrap_or_default();
+    let fingerprint = match &sync_options.fingerprint {
+        Some(value) => FingerprintMethod::parse(value)?,
+        None => FingerprintMethod::default(),
+    };
+    let publisher_url = sync_options
+        .publisher_url
+        .clone()
+        .filter(|url| !url.trim().is_empty())
+        .unwrap_or_else(|| source_db_url.clone());
+    let workers = sync_options.workers.unwrap_or(DEFAULT_SYNC_WORKERS);
+    if workers == 0 {
+        anyhow::bail!("sync_options.workers must be at least 1.");
+    }
+    let initial_copy_timeout_secs = sync_options.initial_copy_timeout_secs.unwrap_or(DEFAULT_INITIAL_COPY_TIMEOUT_SECS);
+    if initial_copy_timeout_secs == 0 {
+        anyhow::bail!("sync_options.initial_copy_timeout_secs must be at least 1.");
+    }
+    let filters = parse_table_filters(&raw_config.filters)?;
+    if replication.is_some() && !filters.is_empty() {
+        anyhow::bail!("filters cannot be combined with sync --continuous; logical replication publishes every table in full.");
+    }
+    let masking = load_masking_config(&raw_config.masking_rules_file)?;
+    if replication.is_some() && masking.is_some() {
+        anyhow::bail!("masking_rules_file cannot be combined with sync --continuous; replicated rows are applied unmasked.");
+    }
+
     Ok(SyncConfig {
         source_db_url,
         target_db_url,
         databases_to_sync,
+        plan_only,
+        safety,
+        incremental: sync_options.incremental.unwrap_or(false),
+        fingerprint,
+        streaming: sync_options.streaming.unwrap_or(false),
+        workers,
+        replication,
+        publisher_url,
+        initial_copy_timeout: std::time::Duration::from_secs(initial_copy_timeout_secs),
+        filters,
+        masking,
+    })
+}
+
+/// Reads `protected_hosts` and `protected_databases` and combines them with the
+/// confirmation flags of this run.
+///
+/// # Arguments
+/// * `assume_yes` - `--yes-i-know` was given; destructive actions on unprotected targets are not confirmed.
+/// * `interactive` - Whether a typed confirmation may be read from stdin.
+pub fn load_safety_config_from_json(raw_config: &RawJsonConfig, assume_yes: bool, interactive: bool) -> SafetyConfig {
+    let clean = |patterns: &Option<Vec<String>>| -> Vec<String> {
+        patterns
+            .iter()
+            .flatten()
+            .map(|pattern| pattern.trim().to_string())
+            .filter(|pattern| !pattern.is_empty())
+            .collect()
+    };
+    SafetyConfig {
+        protected_hosts: clean(&raw_config.protected_hosts),
+        protected_databases: clean(&raw_config.protected_databases),
+        assume_yes,
+        interactive,
+    }
+}
+
+pub fn load_prune_config_from_json(
+    raw_config: &RawJsonConfig,
+    spaces_is_configured: bool,
+    dry_run: bool,
+) -> Result<PruneConfig> {
+    let retention = parse_retention_policy(&raw_config.retention)?
+        .context("retention must be set in config.json for prune")?;
+
+    let local_backup_path = raw_config
+        .local_backup_dir
+        .clone()
+        .filter(|path| !path.to_string_lossy().is_empty());
+
+    if local_backup_path.is_none() && !spaces_is_configured {
+        return Err(anyhow::anyhow!(
+            "Nothing to prune: neither local_backup_dir nor s3_storage is configured in config.json."
+        ));
+    }
+
+    Ok(PruneConfig {
+        local_backup_path,
+        prune_spaces: spaces_is_configured,
+        retention,
+        dry_run,
     })
 }
 
+pub fn load_list_config_from_json(
+    raw_config: &RawJsonConfig,
+    spaces_is_configured: bool,
+) -> Result<ListConfig> {
+    let local_backup_path = raw_config
+        .local_backup_dir
+        .clone()
+        .filter(|path| !path.to_string_lossy().is_empty());
+
+    if local_backup_path.is_none() && !spaces_is_configured {
+        return Err(anyhow::anyhow!(
+            "Nothing to list: neither local_backup_dir nor s3_storage is configured in config.json."
+        ));
+    }
+
+    Ok(ListConfig {
+        local_backup_path,
+        list_spaces: spaces_is_configured,
+    })
+}
+
+/// Reads the multipart settings of the `s3_storage` block, falling back to the defaults.
+fn parse_s3_transfer_config(s3_raw: &JsonS3StorageConfig) -> Result<S3TransferConfig> {
+    let defaults = S3TransferConfig::default();
+    let part_size_bytes = match s3_raw.multipart_part_size_mb {
+        Some(mb) if !(5..=5 * 1024).contains(&mb) => {
+            anyhow::bail!("s3_storage.multipart_part_size_mb must be between 5 and 5120 (S3 part size limits), got {}.", mb)
+        }
+        Some(mb) => mb * 1024 * 1024,
+        None => defaults.part_size_bytes,
+    };
+    let concurrency = s3_raw.multipart_concurrency.unwrap_or(defaults.concurrency);
+    if concurrency == 0 {
+        anyhow::bail!("s3_storage.multipart_concurrency must be at least 1 in config.json.");
+    }
+    Ok(S3TransferConfig {
+        part_size_bytes,
+        concurrency,
+        max_retries: s3_raw.max_retries.unwrap_or(defaults.max_retries),
+    })
+}
+
+/// Builds the config for uploading an existing archive, e.g. to finish an interrupted upload.
+///
+/// A bare file name that does not exist in the working directory is looked up in `local_backup_dir`.
+pub fn load_upload_config_from_json(
+    raw_config: &RawJsonConfig,
+    spaces_is_configured: bool,
+    archive: &Path,
+    resume_upload_id: Option<String>,
+) -> Result<UploadConfig> {
+    if !spaces_is_configured {
+        return Err(anyhow::anyhow!(
+            "upload requires S3 storage (s3_storage) to be fully configured in config.json."
+        ));
+    }
+
+    let archive_path = match &raw_config.local_backup_dir {
+        Some(dir) if !archive.exists() && archive.parent().is_none_or(|parent| parent.as_os_str().is_empty()) => dir.join(archive),
+        _ => archive.to_path_buf(),
+    };
+    if !archive_path.is_file() {
+        anyhow::bail!("Archive to upload not found: {}", archive_path.display());
+    }
+
+    Ok(UploadConfig {
+        archive_path,
+        resume_upload_id: resume_upload_id.filter(|id| !id.trim().is_empty()),
+    })
+}
+
+/// Settings of `diff`. Without `archive`, `source_database_url` is the source side; with it,
+/// the archive is, and its schema is loaded into a scratch database on the target server.
+pub fn load_diff_config_from_json(
+    raw_config: &RawJsonConfig,
+    spaces_is_configured: bool,
+    archive: Option<String>,
+    format: Option<&str>,
+    output_path: Option<PathBuf>,
+) -> Result<DiffConfig> {
+    let target_db_url = raw_config
+        .target_database_url
+        .as_ref()
+        .context("target_database_url must be set in config.json for diff")?
+        .clone();
+    let archive = archive.filter(|archive| !archive.trim().is_empty());
+    let source_db_url = match &archive {
+        Some(archive) => {
+            if archive.starts_with("s3://") && !spaces_is_configured {
+                anyhow::bail!("--archive is an S3 URI, but S3 storage (s3_storage) is not fully configured in config.json.");
+            }
+            None
+        }
+        None => Some(
+            raw_config
+                .source_database_url
+                .as_ref()
+                .context("source_database_url must be set in config.json for diff (or pass --archive)")?
+                .clone(),
+        ),
+    };
+
+    Ok(DiffConfig {
+        source_db_url,
+        target_db_url,
+        archive,
+        local_backup_path: raw_config.local_backup_dir.clone(),
+        download_dir: download_dir(raw_config),
+        encryption: parse_encryption_config(&raw_config.encryption)?,
+        databases: parse_database_list_for_restore(&raw_config.database_list)?,
+        format: format.map(DiffFormat::parse).transpose()?.unwrap_or_default(),
+        output_path,
+        scratch_db_url: raw_config.scratch_database_url.clone().filter(|url| !url.trim().is_empty()),
+        safety: load_safety_config_from_json(raw_config, false, false),
+    })
+}
+
+/// Where `backup --physical`, `wal-push`, `wal-fetch` and `restore --physical` keep their files.
+pub fn physical_storage(raw_config: &RawJsonConfig, spaces_is_configured: bool) -> Result<PhysicalStorage> {
+    if spaces_is_configured {
+        return Ok(PhysicalStorage::S3);
+    }
+    match raw_config.local_backup_dir.clone().filter(|path| !path.as_os_str().is_empty()) {
+        Some(dir) => Ok(PhysicalStorage::Local(dir.join("physical"))),
+        None => anyhow::bail!("Physical backups need local_backup_dir or s3_storage in config.json."),
+    }
+}
+
+pub fn load_physical_backup_config_from_json(
+    raw_config: &RawJsonConfig,
+    spaces_is_configured: bool,
+) -> Result<PhysicalBackupConfig> {
+    let source_db_url = raw_config
+        .source_database_url
+        .as_ref()
+        .context("source_database_url must be set in config.json for a physical backup")?
+        .clone();
+    Ok(PhysicalBackupConfig {
+        source_db_url,
+        storage: physical_storage(raw_config, spaces_is_configured)?,
+        temp_dump_root: raw_config.temp_dump_root.clone(),
+    })
+}
+
+pub fn load_wal_config_from_json(raw_config: &RawJsonConfig, spaces_is_configured: bool, action: WalAction) -> Result<WalConfig> {
+    Ok(WalConfig {
+        storage: physical_storage(raw_config, spaces_is_configured)?,
+        action,
+    })
+}
+
+/// Settings of `restore --physical`.
+///
+/// # Arguments
+/// * `target_time` - Recover up to this time: RFC 3339, or `YYYY-MM-DD HH:MM[:SS]` in local time.
+/// * `target_lsn` - Recover up to this WAL location. At most one target may be given.
+/// * `config_path` - The config file of this run; `restore_command` calls `wal-fetch` with it.
+pub fn load_physical_restore_config_from_json(
+    raw_config: &RawJsonConfig,
+    spaces_is_configured: bool,
+    data_dir: Option<PathBuf>,
+    target_time: Option<&str>,
+    target_lsn: Option<&str>,
+    base_backup: Option<String>,
+    config_path: &Path,
+) -> Result<PhysicalRestoreConfig> {
+    let data_dir = data_dir.context("restore --physical needs --data-dir, the data directory to rebuild")?;
+    let target = match (target_time, target_lsn) {
+        (Some(_), Some(_)) => anyhow::bail!("Give either --target-time or --target-lsn, not both."),
+        (Some(time), None) => RecoveryTarget::Time(parse_recovery_time(time)?),
+        (None, Some(lsn)) => RecoveryTarget::Lsn(parse_lsn(lsn)?),
+        (None, None) => RecoveryTarget::Latest,
+    };
+    let config_path = std::path::absolute(config_path)
+        .with_context(|| format!("Failed to resolve the config path {}", config_path.display()))?;
+    Ok(PhysicalRestoreConfig {
+        storage: physical_storage(raw_config, spaces_is_configured)?,
+        data_dir,
+        target,
+        base_backup: base_backup.filter(|id| !id.trim().is_empty()),
+        config_path,
+        download_dir: download_dir(raw_config),
+    })
+}
+
+/// Parses a recovery target time. Times without an offset are local time, like archive stamps.
+pub fn parse_recovery_time(value: &str) -> Result<chrono::DateTime<chrono::FixedOffset>> {
+    let value = value.trim();
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time);
+    }
+    // As PostgreSQL prints timestamptz values, e.g. `2024-05-01 12:30:00.5+02`.
+    for format in ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%dT%H:%M:%S%.f%#z"] {
+        if let Ok(time) = chrono::DateTime::parse_from_str(value, format) {
+            return Ok(time);
+        }
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"] {
+        if let Ok(naive) = chrono::NaiveDateTime::parse_from_str(value, format) {
+            let local = naive
+                .and_local_timezone(chrono::Local)
+                .earliest()
+                .with_context(|| format!("{} does not exist in the local time zone", value))?;
+            return Ok(local.fixed_offset());
+        }
+    }
+    anyhow::bail!("Unrecognised recovery target time '{}'. Use RFC 3339, YYYY-MM-DD HH:MM:SS[+TZ] or YYYY-MM-DD HH:MM.", value)
+}
+
+/// Parses a WAL location as printed by PostgreSQL (`16/B374D848`) into a byte position.
+pub fn parse_lsn(value: &str) -> Result<u64> {
+    let (high, low) = value
+        .trim()
+        .split_once('/')
+        .with_context(|| format!("Invalid LSN '{}'. Expected the form X/Y, e.g. 0/3000060.", value))?;
+    let high = u32::from_str_radix(high, 16).with_context(|| format!("Invalid LSN '{}'", value))?;
+    let low = u32::from_str_radix(low, 16).with_context(|| format!("Invalid LSN '{}'", value))?;
+    Ok(((high as u64) << 32) | low as u64)
+}
+
+/// Formats a byte position as a PostgreSQL WAL location.
+pub fn format_lsn(lsn: u64) -> String {
+    format!("{:X}/{:X}", lsn >> 32, lsn & 0xFFFF_FFFF)
+}
+
+/// Validates the optional `encryption` block.
+///
+/// age cannot combine a passphrase with public keys, so exactly one of the two kinds may be set.
+fn parse_encryption_config(encryption: &Option<JsonEncryptionConfig>) -> Result<Option<EncryptionConfig>> {
+    let Some(raw) = encryption else {
+        return Ok(None);
+    };
+    let passphrase = raw.passphrase.clone().filter(|passphrase| !passphrase.is_empty());
+    let key_file = raw.key_file.clone().filter(|path| !path.as_os_str().is_empty());
+    let recipients: Vec<String> = raw
+        .recipients
+        .iter()
+        .flatten()
+        .map(|recipient| recipient.trim().to_string())
+        .filter(|recipient| !recipient.is_empty())
+        .collect();
+
+    let uses_keys = key_file.is_some() || !recipients.is_empty();
+    if passphrase.is_some() && uses_keys {
+        anyhow::bail!("encryption in config.json sets both a passphrase and keys (key_file/recipients); use one or the other.");
+    }
+    if passphrase.is_none() && !uses_keys {
+        anyhow::bail!("encryption in config.json needs a passphrase, a key_file or recipients. Remove the block to disable encryption.");
+    }
+    Ok(Some(EncryptionConfig { passphrase, key_file, recipients }))
+}
+
+/// Validates the optional `compression` block; gzip at its default level when absent.
+fn parse_compression_config(compression: &Option<JsonCompressionConfig>) -> Result<CompressionConfig> {
+    let Some(raw) = compression else {
+        return Ok(CompressionConfig { threads: 1, ..Default::default() });
+    };
+    let codec = match &raw.codec {
+        Some(value) => CompressionCodec::parse(value)?,
+        None => CompressionCodec::default(),
+    };
+    if let Some(level) = raw.level {
+        match codec.level_range() {
+            Some(range) if !range.contains(&level) => anyhow::bail!(
+                "compression.level {} is out of range for {} ({} to {}).",
+                level,
+                codec.as_str(),
+                range.start(),
+                range.end()
+            ),
+            None => anyhow::bail!("compression.level cannot be set for codec \"none\"."),
+            _ => {}
+        }
+    }
+    let threads = raw.threads.unwrap_or(1);
+    if threads == 0 {
+        anyhow::bail!("compression.threads must be at least 1 in config.json.");
+    }
+    if threads > 1 && codec != CompressionCodec::Zstd {
+        anyhow::bail!("compression.threads = {} is only supported by zstd; {} compresses on one thread.", threads, codec.as_str());
+    }
+    Ok(CompressionConfig { codec, level: raw.level, threads })
+}
+
+fn parse_jobs(jobs: Option<usize>) -> Result<usize> {
+    match jobs {
+        Some(0) => Err(anyhow::anyhow!("jobs must be at least 1 in config.json.")),
+        Some(n) => Ok(n),
+        None => Ok(1),
+    }
+}
+
+/// Converts the optional `filters` block into table filters, validating its patterns.
+fn parse_table_filters(filters: &Option<JsonTableFilters>) -> Result<TableFilters> {
+    let Some(filters) = filters else {
+        return Ok(TableFilters::default());
+    };
+    let patterns = |field: &str, values: &Option<Vec<String>>, qualified: bool| -> Result<Vec<String>> {
+        let mut cleaned = Vec::new();
+        for value in values.iter().flatten() {
+            let value = value.trim();
+            if value.is_empty() {
+                anyhow::bail!("filters.{} cannot contain empty patterns.", field);
+            }
+            if value.matches('.').count() > usize::from(qualified) {
+                anyhow::bail!(
+                    "filters.{} entry '{}' is not a valid pattern; expected {}.",
+                    field,
+                    value,
+                    if qualified { "schema.table or table" } else { "a schema name" }
+                );
+            }
+            cleaned.push(value.to_string());
+        }
+        Ok(cleaned)
+    };
+
+    let mut row_filters = Vec::new();
+    for (table, condition) in filters.row_filters.iter().flatten() {
+        let table = patterns("where", &Some(vec![table.clone()]), true)?.remove(0);
+        if condition.trim().is_empty() {
+            anyhow::bail!("filters.where condition for '{}' cannot be empty.", table);
+        }
+        row_filters.push((table, condition.trim().to_string()));
+    }
+
+    Ok(TableFilters {
+        include_schemas: patterns("include_schemas", &filters.include_schemas, false)?,
+        exclude_schemas: patterns("exclude_schemas", &filters.exclude_schemas, false)?,
+        include_tables: patterns("include_tables", &filters.include_tables, true)?,
+        exclude_tables: patterns("exclude_tables", &filters.exclude_tables, true)?,
+        schema_only_tables: patterns("schema_only_tables", &filters.schema_only_tables, true)?,
+        row_filters,
+    })
+}
+
+/// Reads and validates the masking rules file, if one is configured.
+fn load_masking_config(rules_file: &Option<PathBuf>) -> Result<Option<MaskingConfig>> {
+    let Some(rules_file) = rules_file.as_ref().filter(|path| !path.as_os_str().is_empty()) else {
+        return Ok(None);
+    };
+    let content = fs::read_to_string(rules_file)
+        .with_context(|| format!("Failed to read masking rules file at {}", rules_file.display()))?;
+    let raw: JsonMaskingRules = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse JSON from masking rules file at {}", rules_file.display()))?;
+    parse_masking_rules(rules_file, &raw).map(Some)
+}
+
+/// Converts the contents of a masking rules file. Without a `seed`, a random one is drawn,
+/// so masked values are consistent within the run but cannot be recomputed from the originals.
+fn parse_masking_rules(rules_file: &Path, raw: &JsonMaskingRules) -> Result<MaskingConfig> {
+    let mut rules = Vec::new();
+    for (key, strategy) in raw.rules.iter().flatten() {
+        let key = key.trim();
+        let (table, column) = key
+            .rsplit_once('.')
+            .filter(|(table, column)| !table.is_empty() && !column.is_empty() && table.matches('.').count() <= 1)
+            .filter(|(table, _)| !table.starts_with('.') && !table.ends_with('.'))
+            .with_context(|| format!("Masking rule '{}' is not valid; expected schema.table.column or table.column.", key))?;
+        let strategy = match strategy {
+            JsonMaskingStrategy::Name(name) => MaskingStrategy::parse(name, None),
+            JsonMaskingStrategy::Detailed { strategy, value } => MaskingStrategy::parse(strategy, value.as_deref()),
+        }
+        .with_context(|| format!("Invalid masking rule '{}'", key))?;
+        rules.push(MaskingRule { table: table.to_string(), column: column.to_string(), strategy });
+    }
+    if rules.is_empty() {
+        anyhow::bail!("Masking rules file {} has no rules.", rules_file.display());
+    }
+
+    if raw.seed.as_deref().is_some_and(|seed| seed.trim().is_empty()) {
+        anyhow::bail!("seed in masking rules file {} cannot be empty.", rules_file.display());
+    }
+    let seed = raw.seed.clone();
+    Ok(MaskingConfig {
+        rules_file: rules_file.to_path_buf(),
+        rules,
+        fixed_seed: seed.is_some(),
+        seed: seed.unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()),
+    })
+}
+
+/// Converts the optional `retention` block into a policy.
+/// Rejects a policy that would keep nothing, since applying it deletes every archive.
+fn parse_retention_policy(retention: &Option<JsonRetentionPolicy>) -> Result<Option<RetentionPolicy>> {
+    let Some(raw) = retention else {
+        return Ok(None);
+    };
+    let policy = RetentionPolicy {
+        keep_last: raw.keep_last.unwrap_or(0),
+        keep_daily: raw.keep_daily.unwrap_or(0),
+        keep_weekly: raw.keep_weekly.unwrap_or(0),
+        keep_monthly: raw.keep_monthly.unwrap_or(0),
+    };
+    if policy == RetentionPolicy::default() {
+        return Err(anyhow::anyhow!(
+            "retention in config.json keeps no archives (all of keep_last, keep_daily, keep_weekly and keep_monthly are 0 or missing). Remove the block to disable pruning."
+        ));
+    }
+    Ok(Some(policy))
+}
+
 /// Parses the database_list configuration for backup and sync operations
 /// Returns a vector of source database names
 fn parse_database_list_for_backup_sync(database_list: &Option<serde_json::Value>) -> Result<Option<Vec<String>>> {
@@ -293,6 +1456,36 @@ fn parse_database_list_for_restore(database_list: &Option<serde_json::Value>) ->
     }
 }
 
+/// Parses `role_mapping`: source role name -> role to use on the target instead.
+fn parse_role_mapping(
+    role_mapping: &Option<std::collections::BTreeMap<String, String>>,
+) -> Result<std::collections::BTreeMap<String, String>> {
+    let mapping = role_mapping.clone().unwrap_or_default();
+    if let Some((source, target)) = mapping.iter().find(|(source, target)| source.trim().is_empty() || target.trim().is_empty()) {
+        anyhow::bail!("role_mapping contains an empty role name: {:?} -> {:?}", source, target);
+    }
+    Ok(mapping)
+}
+
+/// Parses `restore_options.schema_mapping`: source schema -> schema to restore it into.
+fn parse_schema_mapping(
+    schema_mapping: &std::collections::BTreeMap<String, String>,
+) -> Result<std::collections::BTreeMap<String, String>> {
+    let mut targets = std::collections::BTreeSet::new();
+    for (source, target) in schema_mapping {
+        if source.trim().is_empty() || target.trim().is_empty() {
+            anyhow::bail!("schema_mapping contains an empty schema name: {:?} -> {:?}", source, target);
+        }
+        if target.starts_with("pg_") || target == "information_schema" {
+            anyhow::bail!("schema_mapping cannot restore schema '{}' into the system schema '{}'", source, target);
+        }
+        if !targets.insert(target) {
+            anyhow::bail!("schema_mapping restores more than one schema into '{}'", target);
+        }
+    }
+    Ok(schema_mapping.clone())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -380,6 +1573,362 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_retention_policy() -> anyhow::Result<()> {
+        assert_eq!(parse_retention_policy(&None)?, None);
+
+        let raw = JsonRetentionPolicy { keep_last: Some(3), keep_monthly: Some(6), ..Default::default() };
+        let policy = parse_retention_policy(&Some(raw))?.unwrap();
+        assert_eq!(policy, RetentionPolicy { keep_last: 3, keep_daily: 0, keep_weekly: 0, keep_monthly: 6 });
+
+        assert!(parse_retention_policy(&Some(JsonRetentionPolicy::default())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_backup_config_max_parallel_databases() -> anyhow::Result<()> {
+        let mut raw = RawJsonConfig {
+            source_database_url: Some("postgres://localhost:5432/".to_string()),
+            local_backup_dir: Some(PathBuf::from("/tmp/backups")),
+            ..Default::default()
+        };
+        assert_eq!(load_backup_config_from_json(&raw, false)?.max_parallel_databases, 1);
+
+        raw.max_parallel_databases = Some(4);
+        assert_eq!(load_backup_config_from_json(&raw, false)?.max_parallel_databases, 4);
+
+        raw.max_parallel_databases = Some(0);
+        assert!(load_backup_config_from_json(&raw, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_sync_config_options() -> anyhow::Result<()> {
+        let mut raw = RawJsonConfig {
+            source_database_url: Some("postgres://source:5432/".to_string()),
+            target_database_url: Some("postgres://target:5432/".to_string()),
+            database_list: Some(serde_json::json!(["app"])),
+            ..Default::default()
+        };
+        let config = load_sync_config_from_json(&raw, false, None, SafetyConfig::default())?;
+        assert!(!config.incremental);
+        assert_eq!(config.fingerprint, FingerprintMethod::Stats);
+
+        assert!(!config.streaming);
+        assert_eq!(config.workers, 4);
+        assert_eq!(config.publisher_url, "postgres://source:5432/");
+        assert_eq!(config.initial_copy_timeout, std::time::Duration::from_secs(6 * 60 * 60));
+
+        raw.sync_options = Some(JsonSyncOptions {
+            incremental: Some(true),
+            fingerprint: Some("Checksum".to_string()),
+            streaming: Some(true),
+            workers: Some(8),
+            publisher_url: Some("postgres://source.internal:5432/".to_string()),
+            initial_copy_timeout_secs: Some(600),
+        });
+        let config = load_sync_config_from_json(&raw, false, None, SafetyConfig::default())?;
+        assert!(config.incremental);
+        assert_eq!(config.fingerprint, FingerprintMethod::Checksum);
+        assert!(config.streaming);
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.publisher_url, "postgres://source.internal:5432/");
+        assert_eq!(config.initial_copy_timeout, std::time::Duration::from_secs(600));
+
+        raw.sync_options = Some(JsonSyncOptions { fingerprint: Some("rowcount".to_string()), ..Default::default() });
+        assert!(load_sync_config_from_json(&raw, false, None, SafetyConfig::default()).is_err());
+        raw.sync_options = Some(JsonSyncOptions { workers: Some(0), ..Default::default() });
+        assert!(load_sync_config_from_json(&raw, false, None, SafetyConfig::default()).is_err());
+        raw.sync_options = Some(JsonSyncOptions { initial_copy_timeout_secs: Some(0), ..Default::default() });
+        assert!(load_sync_config_from_json(&raw, false, None, SafetyConfig::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_table_filters() -> anyhow::Result<()> {
+        assert!(parse_table_filters(&None)?.is_empty());
+
+        let raw: JsonTableFilters = serde_json::from_value(serde_json::json!({
+            "exclude_schemas": ["audit"],
+            "exclude_tables": [" public.sessions ", "*_log"],
+            "schema_only_tables": ["public.events_archive"],
+            "where": {"public.events": "created_at > now() - interval '90 days'"}
+        }))?;
+        let filters = parse_table_filters(&Some(raw))?;
+        assert_eq!(filters.exclude_tables, vec!["public.sessions", "*_log"]);
+        assert_eq!(
+            filters.row_filters,
+            vec![("public.events".to_string(), "created_at > now() - interval '90 days'".to_string())]
+        );
+        assert_eq!(
+            filters.describe(),
+            "exclude schemas audit; exclude tables public.sessions, *_log; schema only public.events_archive; WHERE on public.events"
+        );
+
+        let invalid = [
+            serde_json::json!({"exclude_tables": [""]}),
+            serde_json::json!({"exclude_schemas": ["a.b"]}),
+            serde_json::json!({"include_tables": ["db.public.items"]}),
+            serde_json::json!({"where": {"public.events": " "}}),
+        ];
+        for value in invalid {
+            let raw: JsonTableFilters = serde_json::from_value(value)?;
+            assert!(parse_table_filters(&Some(raw)).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_masking_rules() -> anyhow::Result<()> {
+        let raw: JsonMaskingRules = serde_json::from_value(serde_json::json!({
+            "seed": "dev-2024",
+            "rules": {
+                "public.users.email": "fake_email",
+                "*.phone": "preserve_format",
+                "users.country": {"strategy": "fixed", "value": "NL"}
+            }
+        }))?;
+        let masking = parse_masking_rules(Path::new("masking.json"), &raw)?;
+        assert_eq!(masking.rules.len(), 3);
+        assert_eq!(masking.rules[0].table, "*");
+        assert_eq!(masking.rules[0].strategy, MaskingStrategy::PreserveFormat);
+        assert_eq!(masking.rules[1].key(), "public.users.email");
+        assert_eq!(masking.rules[2].strategy, MaskingStrategy::Fixed("NL".to_string()));
+        assert_eq!(masking.seed, "dev-2024");
+        assert_eq!(masking.describe(), "3 rule(s) from masking.json, fixed seed");
+
+        let unseeded: JsonMaskingRules = serde_json::from_value(serde_json::json!({"rules": {"users.name": "fake_name"}}))?;
+        let first = parse_masking_rules(Path::new("m.json"), &unseeded)?;
+        assert!(!first.fixed_seed);
+        assert_ne!(first.seed, parse_masking_rules(Path::new("m.json"), &unseeded)?.seed);
+
+        for rules in [
+            serde_json::json!({"email": "hash"}),
+            serde_json::json!({"a.b.c.d": "hash"}),
+            serde_json::json!({"users.email": "scramble"}),
+            serde_json::json!({"users.email": {"strategy": "fixed"}}),
+            serde_json::json!({"users.email": {"strategy": "hash", "value": "x"}}),
+        ] {
+            let raw: JsonMaskingRules = serde_json::from_value(serde_json::json!({"rules": rules}))?;
+            assert!(parse_masking_rules(Path::new("m.json"), &raw).is_err());
+        }
+        assert!(parse_masking_rules(Path::new("m.json"), &JsonMaskingRules::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_backup_config_streaming_upload() -> anyhow::Result<()> {
+        let mut raw = RawJsonConfig {
+            source_database_url: Some("postgres://localhost:5432/".to_string()),
+            streaming_upload: Some(true),
+            keep_local_copy: Some(false),
+            ..Default::default()
+        };
+        // No local directory is needed when nothing is kept locally.
+        let config = load_backup_config_from_json(&raw, true)?;
+        assert!(config.streaming_upload && !config.keep_local_copy);
+        assert_eq!(config.local_backup_path, None);
+
+        // Streaming needs S3, and a local copy needs a directory.
+        assert!(load_backup_config_from_json(&raw, false).is_err());
+        raw.keep_local_copy = None;
+        assert!(load_backup_config_from_json(&raw, true).is_err());
+
+        raw.local_backup_dir = Some(PathBuf::from("/tmp/backups"));
+        raw.dump_format = Some("directory".to_string());
+        assert!(load_backup_config_from_json(&raw, true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_diff_config_sides() -> anyhow::Result<()> {
+        let mut raw = RawJsonConfig {
+            target_database_url: Some("postgres://localhost:5432/".to_string()),
+            ..Default::default()
+        };
+        // Without an archive the source server is the source side.
+        assert!(load_diff_config_from_json(&raw, false, None, None, None).is_err());
+        raw.source_database_url = Some("postgres://prod:5432/".to_string());
+        let config = load_diff_config_from_json(&raw, false, Some(" ".to_string()), Some("JSON"), None)?;
+        assert_eq!(config.source_db_url.as_deref(), Some("postgres://prod:5432/"));
+        assert_eq!(config.archive, None);
+        assert_eq!(config.format, DiffFormat::Json);
+
+        let config = load_diff_config_from_json(&raw, false, Some("latest".to_string()), None, None)?;
+        assert_eq!(config.source_db_url, None);
+        assert_eq!(config.format, DiffFormat::Text);
+        assert!(load_diff_config_from_json(&raw, false, Some("s3://bucket/a.tar.gz".to_string()), None, None).is_err());
+        assert!(load_diff_config_from_json(&raw, false, None, Some("yaml"), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_physical_restore_config_targets() -> anyhow::Result<()> {
+        let raw = RawJsonConfig { local_backup_dir: Some(PathBuf::from("/backups")), ..Default::default() };
+        let load = |time: Option<&str>, lsn: Option<&str>| {
+            load_physical_restore_config_from_json(&raw, false, Some(PathBuf::from("/data")), time, lsn, None, Path::new("config.json"))
+        };
+        let config = load(None, None)?;
+        assert_eq!(config.storage, PhysicalStorage::Local(PathBuf::from("/backups/physical")));
+        assert_eq!(config.target, RecoveryTarget::Latest);
+        assert!(config.config_path.is_absolute());
+
+        assert_eq!(load(None, Some("16/B374D848"))?.target, RecoveryTarget::Lsn(0x16_B374_D848));
+        let RecoveryTarget::Time(time) = load(Some("2024-05-01T12:30:00+02:00"), None)?.target else { panic!("Expected a time target") };
+        assert_eq!(time.to_rfc3339(), "2024-05-01T12:30:00+02:00");
+        assert!(matches!(load(Some("2024-05-01 12:30"), None)?.target, RecoveryTarget::Time(_)));
+        assert_eq!(parse_recovery_time("2024-05-01 10:30:00.101167+00")?, parse_recovery_time("2024-05-01T12:30:00.101167+02:00")?);
+
+        assert!(load(Some("2024-05-01 12:30"), Some("0/1")).is_err());
+        assert!(load(None, Some("0x1000")).is_err());
+        assert!(load_physical_restore_config_from_json(&raw, false, None, None, None, None, Path::new("c.json")).is_err());
+        assert_eq!(physical_storage(&RawJsonConfig::default(), true)?, PhysicalStorage::S3);
+        assert!(physical_storage(&RawJsonConfig::default(), false).is_err());
+        assert_eq!(format_lsn(0x16_B374_D848), "16/B374D848");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_s3_transfer_config() -> anyhow::Result<()> {
+        let mut raw = JsonS3StorageConfig::default();
+        assert_eq!(parse_s3_transfer_config(&raw)?, S3TransferConfig::default());
+
+        raw.multipart_part_size_mb = Some(8);
+        raw.multipart_concurrency = Some(2);
+        raw.max_retries = Some(0);
+        let transfer = parse_s3_transfer_config(&raw)?;
+        assert_eq!(transfer, S3TransferConfig { part_size_bytes: 8 * 1024 * 1024, concurrency: 2, max_retries: 0 });
+
+        raw.multipart_part_size_mb = Some(4);
+        assert!(parse_s3_transfer_config(&raw).is_err());
+        raw.multipart_part_size_mb = None;
+        raw.multipart_concurrency = Some(0);
+        assert!(parse_s3_transfer_config(&raw).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_encryption_config() -> anyhow::Result<()> {
+        assert!(parse_encryption_config(&None)?.is_none());
+
+        let raw = JsonEncryptionConfig { passphrase: Some("secret".to_string()), ..Default::default() };
+        let config = parse_encryption_config(&Some(raw))?.unwrap();
+        assert_eq!(config.passphrase.as_deref(), Some("secret"));
+        assert!(!format!("{:?}", config).contains("secret"));
+
+        let raw = JsonEncryptionConfig { recipients: Some(vec![" age1abc ".to_string(), "".to_string()]), ..Default::default() };
+        assert_eq!(parse_encryption_config(&Some(raw))?.unwrap().recipients, vec!["age1abc".to_string()]);
+
+        let mixed = JsonEncryptionConfig {
+            passphrase: Some("secret".to_string()),
+            key_file: Some(PathBuf::from("/etc/databasetool/backup.key")),
+            ..Default::default()
+        };
+        assert!(parse_encryption_config(&Some(mixed)).is_err());
+        assert!(parse_encryption_config(&Some(JsonEncryptionConfig::default())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_compression_config() -> anyhow::Result<()> {
+        let config = parse_compression_config(&None)?;
+        assert_eq!((config.codec, config.level, config.threads), (CompressionCodec::Gzip, None, 1));
+
+        let raw = JsonCompressionConfig { codec: Some("ZSTD".to_string()), level: Some(19), threads: Some(4) };
+        let config = parse_compression_config(&Some(raw))?;
+        assert_eq!((config.codec, config.level, config.threads), (CompressionCodec::Zstd, Some(19), 4));
+        assert_eq!(config.codec.extension(), "tar.zst");
+
+        let too_high = JsonCompressionConfig { codec: Some("gzip".to_string()), level: Some(10), ..Default::default() };
+        assert!(parse_compression_config(&Some(too_high)).is_err());
+        let level_without_codec = JsonCompressionConfig { codec: Some("none".to_string()), level: Some(1), ..Default::default() };
+        assert!(parse_compression_config(&Some(level_without_codec)).is_err());
+        let threaded_lz4 = JsonCompressionConfig { codec: Some("lz4".to_string()), threads: Some(2), ..Default::default() };
+        assert!(parse_compression_config(&Some(threaded_lz4)).is_err());
+        assert!(parse_compression_config(&Some(JsonCompressionConfig { codec: Some("brotli".to_string()), ..Default::default() })).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_backup_config_dump_format_and_jobs() -> anyhow::Result<()> {
+        let mut raw = RawJsonConfig {
+            source_database_url: Some("postgres://localhost:5432/".to_string()),
+            local_backup_dir: Some(PathBuf::from("/tmp/backups")),
+            ..Default::default()
+        };
+        let config = load_backup_config_from_json(&raw, false)?;
+        assert_eq!((config.dump_format, config.jobs), (DumpFormat::Plain, 1));
+
+        raw.dump_format = Some("Directory".to_string());
+        raw.jobs = Some(8);
+        let config = load_backup_config_from_json(&raw, false)?;
+        assert_eq!((config.dump_format, config.jobs), (DumpFormat::Directory, 8));
+
+        raw.dump_format = Some("custom".to_string());
+        assert!(load_backup_config_from_json(&raw, false).is_err());
+
+        raw.dump_format = Some("tar".to_string());
+        raw.jobs = None;
+        assert!(load_backup_config_from_json(&raw, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_globals_options_and_role_mapping() -> anyhow::Result<()> {
+        let mut raw: RawJsonConfig = serde_json::from_value(json!({
+            "source_database_url": "postgres://localhost:5432/",
+            "target_database_url": "postgres://localhost:5432/",
+            "local_backup_dir": "/tmp/backups",
+            "archive_file_path_for_restore": "latest",
+            "restore_options": {"drop_target_database_if_exists": false, "create_target_database_if_not_exists": true},
+            "role_mapping": {"prod_app": "dev_app"}
+        }))?;
+        let safety = load_safety_config_from_json(&raw, false, false);
+
+        // Globals are dumped without passwords by default, and only restored on request.
+        let backup = load_backup_config_from_json(&raw, false)?;
+        assert!(backup.dump_globals && !backup.include_role_passwords);
+        let restore = load_restore_config_from_json(&raw, false, false, safety.clone())?;
+        assert!(!restore.restore_globals && !restore.preserve_ownership);
+        assert_eq!(restore.role_mapping.get("prod_app").map(String::as_str), Some("dev_app"));
+        if let Some(options) = raw.restore_options.as_mut() {
+            options.preserve_ownership = true;
+        }
+        assert!(load_restore_config_from_json(&raw, false, false, safety.clone())?.preserve_ownership);
+        assert!(load_restore_config_from_json(&raw, false, false, safety.clone())?.schema_mapping.is_empty());
+        if let Some(options) = raw.restore_options.as_mut() {
+            options.schema_mapping = std::collections::BTreeMap::from([("public".to_string(), "snapshot_2026_10".to_string())]);
+        }
+        let restore = load_restore_config_from_json(&raw, false, false, safety.clone())?;
+        assert_eq!(restore.schema_mapping.get("public").map(String::as_str), Some("snapshot_2026_10"));
+        if let Some(options) = raw.restore_options.as_mut() {
+            options.schema_mapping.insert("app".to_string(), "snapshot_2026_10".to_string());
+        }
+        assert!(load_restore_config_from_json(&raw, false, false, safety.clone()).is_err()); // Two schemas into one
+        if let Some(options) = raw.restore_options.as_mut() {
+            options.schema_mapping = std::collections::BTreeMap::from([("public".to_string(), "pg_snapshot".to_string())]);
+        }
+        assert!(load_restore_config_from_json(&raw, false, false, safety.clone()).is_err());
+        if let Some(options) = raw.restore_options.as_mut() {
+            options.schema_mapping = std::collections::BTreeMap::from([("public".to_string(), "snapshot_2026_10".to_string())]);
+        }
+        raw.filters = Some(JsonTableFilters { exclude_tables: Some(vec!["public.audit_log".to_string()]), ..Default::default() });
+        assert!(load_restore_config_from_json(&raw, false, false, safety.clone()).is_err());
+        raw.filters = None;
+        if let Some(options) = raw.restore_options.as_mut() {
+            options.schema_mapping.clear();
+        }
+
+        raw.globals = Some(JsonGlobalsOptions { dump: Some(false), include_passwords: Some(true), restore: Some(true) });
+        let backup = load_backup_config_from_json(&raw, false)?;
+        assert!(!backup.dump_globals && backup.include_role_passwords);
+        assert!(load_restore_config_from_json(&raw, false, false, safety.clone())?.restore_globals);
+
+        raw.role_mapping = Some(std::collections::BTreeMap::from([("prod_app".to_string(), " ".to_string())]));
+        assert!(load_restore_config_from_json(&raw, false, false, safety).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_complete_database_renaming_workflow() -> anyhow::Result<()> {
         // Test the complete workflow from configuration to restore mapping
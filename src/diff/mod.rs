@@ -0,0 +1,16 @@
+// databasetool/src/diff/mod.rs
+pub(crate) mod logic;
+pub(crate) mod schema; // pg_catalog introspection, comparison and the text/JSON report
+
+use anyhow::Result;
+use crate::config::AppConfig;
+
+/// Public entry point for the diff process.
+pub async fn run_diff_flow(app_config: &AppConfig) -> Result<()> {
+    let diff_config = match &app_config.operation {
+        Some(crate::config::OperationConfig::Diff(cfg)) => cfg,
+        _ => anyhow::bail!("Diff operation selected but no diff configuration found."),
+    };
+
+    logic::perform_diff_orchestration(app_config, diff_config).await
+}
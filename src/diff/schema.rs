@@ -0,0 +1,410 @@
+// databasetool/src/diff/schema.rs
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sqlx::PgConnection;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// Schemas that belong to PostgreSQL itself and are never compared.
+const SYSTEM_SCHEMAS: &str = "NOT IN ('pg_catalog', 'information_schema') AND {0} NOT LIKE 'pg_toast%' AND {0} NOT LIKE 'pg_temp_%'";
+
+/// The schema of one database as read from `pg_catalog`, keyed by qualified name.
+///
+/// Definitions are what PostgreSQL prints for the object (`pg_get_indexdef`, `pg_get_viewdef`, ...),
+/// so two objects are equal when the server would recreate them identically. Objects that belong
+/// to an extension are left out; the extension itself is compared by version.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaSnapshot {
+    pub tables: BTreeMap<String, BTreeMap<String, String>>, // schema.table -> column -> type, nullability, default
+    pub indexes: BTreeMap<String, String>,     // schema.index; indexes backing a constraint are compared as the constraint
+    pub constraints: BTreeMap<String, String>, // schema.table.constraint
+    pub sequences: BTreeMap<String, String>,
+    pub views: BTreeMap<String, String>,     // Views and materialized views
+    pub functions: BTreeMap<String, String>, // schema.name(argument types), procedures and aggregates too
+    pub triggers: BTreeMap<String, String>,  // schema.table.trigger
+    pub extensions: BTreeMap<String, String>, // name -> version
+}
+
+/// Kind of object a change is about, in report order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectKind {
+    Extension,
+    Table,
+    Column,
+    Index,
+    Constraint,
+    Sequence,
+    View,
+    Function,
+    Trigger,
+}
+
+impl ObjectKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Extension => "extension",
+            ObjectKind::Table => "table",
+            ObjectKind::Column => "column",
+            ObjectKind::Index => "index",
+            ObjectKind::Constraint => "constraint",
+            ObjectKind::Sequence => "sequence",
+            ObjectKind::View => "view",
+            ObjectKind::Function => "function",
+            ObjectKind::Trigger => "trigger",
+        }
+    }
+}
+
+/// Direction of a change, seen from the source: `Added` objects only exist in the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One difference between the source and the target schema.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaChange {
+    pub object: ObjectKind,
+    pub name: String,
+    pub change: ChangeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>, // Definition in the source; absent for added objects
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>, // Definition in the target; absent for removed objects
+}
+
+/// The differences of one source database and its target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DatabaseDiff {
+    pub source: String,
+    pub target: String,
+    pub changes: Vec<SchemaChange>,
+}
+
+impl DatabaseDiff {
+    /// Number of added, removed and changed objects.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let count = |kind: ChangeKind| self.changes.iter().filter(|change| change.change == kind).count();
+        (count(ChangeKind::Added), count(ChangeKind::Removed), count(ChangeKind::Changed))
+    }
+}
+
+/// Reads the schema of the database behind `conn`.
+pub async fn collect_schema(conn: &mut PgConnection) -> Result<SchemaSnapshot> {
+    // Objects created by CREATE EXTENSION are recorded with a pg_depend entry of type 'e'.
+    let not_in_extension = |class: &str, oid: &str| {
+        format!(
+            "NOT EXISTS (SELECT 1 FROM pg_depend e WHERE e.classid = '{}'::regclass AND e.objid = {} AND e.deptype = 'e')",
+            class, oid
+        )
+    };
+    let user_schema = |column: &str| format!("{} {}", column, SYSTEM_SCHEMAS.replace("{0}", column));
+
+    let columns: Vec<(String, String, String)> = sqlx::query_as(&format!(
+        "SELECT n.nspname || '.' || c.relname, a.attname::text, \
+                format_type(a.atttypid, a.atttypmod) \
+                || CASE WHEN a.attnotnull THEN ' not null' ELSE '' END \
+                || CASE WHEN a.attidentity = 'a' THEN ' generated always as identity' \
+                        WHEN a.attidentity = 'd' THEN ' generated by default as identity' \
+                        WHEN a.attgenerated = 's' THEN ' generated always as (' || pg_get_expr(d.adbin, d.adrelid) || ') stored' \
+                        WHEN d.adbin IS NOT NULL THEN ' default ' || pg_get_expr(d.adbin, d.adrelid) ELSE '' END \
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+         JOIN pg_attribute a ON a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped \
+         LEFT JOIN pg_attrdef d ON d.adrelid = c.oid AND d.adnum = a.attnum \
+         WHERE c.relkind IN ('r', 'p', 'f') AND {} AND {}",
+        user_schema("n.nspname"),
+        not_in_extension("pg_class", "c.oid")
+    ))
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to read tables and columns")?;
+    let mut snapshot = SchemaSnapshot::default();
+    for (table, column, definition) in columns {
+        snapshot.tables.entry(table).or_default().insert(column, definition);
+    }
+
+    let definitions = [
+        (
+            "indexes",
+            format!(
+                "SELECT n.nspname || '.' || i.relname, pg_get_indexdef(i.oid) \
+                 FROM pg_index x JOIN pg_class i ON i.oid = x.indexrelid JOIN pg_namespace n ON n.oid = i.relnamespace \
+                 WHERE {} AND {} \
+                   AND NOT EXISTS (SELECT 1 FROM pg_constraint k WHERE k.conindid = i.oid AND k.contype IN ('p', 'u', 'x'))",
+                user_schema("n.nspname"),
+                not_in_extension("pg_class", "x.indrelid")
+            ),
+        ),
+        (
+            "constraints",
+            format!(
+                "SELECT n.nspname || '.' || c.relname || '.' || k.conname, pg_get_constraintdef(k.oid) \
+                 FROM pg_constraint k JOIN pg_class c ON c.oid = k.conrelid JOIN pg_namespace n ON n.oid = c.relnamespace \
+                 WHERE k.contype IN ('p', 'u', 'f', 'c', 'x') AND {} AND {}",
+                user_schema("n.nspname"),
+                not_in_extension("pg_class", "c.oid")
+            ),
+        ),
+        (
+            "sequences",
+            format!(
+                "SELECT schemaname || '.' || sequencename, \
+                        format('%s start %s increment %s min %s max %s cache %s%s', data_type, start_value, increment_by, \
+                               min_value, max_value, cache_size, CASE WHEN cycle THEN ' cycle' ELSE '' END) \
+                 FROM pg_sequences s WHERE {} \
+                   AND {}",
+                user_schema("s.schemaname"),
+                not_in_extension("pg_class", "format('%I.%I', s.schemaname, s.sequencename)::regclass")
+            ),
+        ),
+        (
+            "views",
+            format!(
+                "SELECT n.nspname || '.' || c.relname, \
+                        CASE c.relkind WHEN 'm' THEN 'materialized view: ' ELSE 'view: ' END || pg_get_viewdef(c.oid, true) \
+                 FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+                 WHERE c.relkind IN ('v', 'm') AND {} AND {}",
+                user_schema("n.nspname"),
+                not_in_extension("pg_class", "c.oid")
+            ),
+        ),
+        (
+            "functions",
+            format!(
+                "SELECT n.nspname || '.' || p.proname || '(' || pg_get_function_identity_arguments(p.oid) || ')', \
+                        CASE WHEN p.prokind = 'a' THEN 'aggregate returning ' || format_type(p.prorettype, NULL) \
+                             ELSE pg_get_functiondef(p.oid) END \
+                 FROM pg_proc p JOIN pg_namespace n ON n.oid = p.pronamespace \
+                 WHERE {} AND {}",
+                user_schema("n.nspname"),
+                not_in_extension("pg_proc", "p.oid")
+            ),
+        ),
+        (
+            "triggers",
+            format!(
+                "SELECT n.nspname || '.' || c.relname || '.' || t.tgname, pg_get_triggerdef(t.oid) \
+                 FROM pg_trigger t JOIN pg_class c ON c.oid = t.tgrelid JOIN pg_namespace n ON n.oid = c.relnamespace \
+                 WHERE NOT t.tgisinternal AND {} AND {}",
+                user_schema("n.nspname"),
+                not_in_extension("pg_class", "c.oid")
+            ),
+        ),
+        ("extensions", "SELECT extname::text, extversion FROM pg_extension".to_string()),
+    ];
+    for (kind, query) in definitions {
+        let rows: Vec<(String, String)> = sqlx::query_as(&query)
+            .fetch_all(&mut *conn)
+            .await
+            .with_context(|| format!("Failed to read {}", kind))?;
+        let map: BTreeMap<String, String> = rows.into_iter().collect();
+        match kind {
+            "indexes" => snapshot.indexes = map,
+            "constraints" => snapshot.constraints = map,
+            "sequences" => snapshot.sequences = map,
+            "views" => snapshot.views = map,
+            "functions" => snapshot.functions = map,
+            "triggers" => snapshot.triggers = map,
+            _ => snapshot.extensions = map,
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Lists what differs between the `source` and the `target` schema, in report order.
+///
+/// Columns are only listed for tables that exist on both sides; a table that was added or
+/// removed is one change.
+pub fn compare_schemas(source: &SchemaSnapshot, target: &SchemaSnapshot) -> Vec<SchemaChange> {
+    let mut changes = Vec::new();
+    compare_definitions(ObjectKind::Extension, &source.extensions, &target.extensions, &mut changes);
+
+    let describe_table = |columns: &BTreeMap<String, String>| {
+        columns.iter().map(|(column, definition)| format!("{} {}", column, definition)).collect::<Vec<_>>().join(", ")
+    };
+    for (table, columns) in &source.tables {
+        match target.tables.get(table) {
+            None => changes.push(change(ObjectKind::Table, table, ChangeKind::Removed, Some(describe_table(columns)), None)),
+            Some(target_columns) => {
+                let qualified = |column: &String| format!("{}.{}", table, column);
+                let source_columns = columns.iter().map(|(column, definition)| (qualified(column), definition.clone())).collect();
+                let target_columns = target_columns.iter().map(|(column, definition)| (qualified(column), definition.clone())).collect();
+                compare_definitions(ObjectKind::Column, &source_columns, &target_columns, &mut changes);
+            }
+        }
+    }
+    for (table, columns) in target.tables.iter().filter(|(table, _)| !source.tables.contains_key(*table)) {
+        changes.push(change(ObjectKind::Table, table, ChangeKind::Added, None, Some(describe_table(columns))));
+    }
+
+    compare_definitions(ObjectKind::Index, &source.indexes, &target.indexes, &mut changes);
+    compare_definitions(ObjectKind::Constraint, &source.constraints, &target.constraints, &mut changes);
+    compare_definitions(ObjectKind::Sequence, &source.sequences, &target.sequences, &mut changes);
+    compare_definitions(ObjectKind::View, &source.views, &target.views, &mut changes);
+    compare_definitions(ObjectKind::Function, &source.functions, &target.functions, &mut changes);
+    compare_definitions(ObjectKind::Trigger, &source.triggers, &target.triggers, &mut changes);
+
+    changes.sort_by(|a, b| (a.object, &a.name).cmp(&(b.object, &b.name)));
+    changes
+}
+
+fn compare_definitions(
+    object: ObjectKind,
+    source: &BTreeMap<String, String>,
+    target: &BTreeMap<String, String>,
+    changes: &mut Vec<SchemaChange>,
+) {
+    for (name, definition) in source {
+        match target.get(name) {
+            None => changes.push(change(object, name, ChangeKind::Removed, Some(definition.clone()), None)),
+            Some(other) if other != definition => {
+                changes.push(change(object, name, ChangeKind::Changed, Some(definition.clone()), Some(other.clone())))
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, definition) in target.iter().filter(|(name, _)| !source.contains_key(*name)) {
+        changes.push(change(object, name, ChangeKind::Added, None, Some(definition.clone())));
+    }
+}
+
+fn change(object: ObjectKind, name: &str, change: ChangeKind, source: Option<String>, target: Option<String>) -> SchemaChange {
+    SchemaChange { object, name: name.to_string(), change, source, target }
+}
+
+/// Formats the differences of every database for the terminal: `+` added, `-` removed, `~` changed.
+///
+/// Changed objects show both definitions; added and removed ones only their name, except columns,
+/// whose definition is short.
+pub fn render_text(source_label: &str, target_label: &str, diffs: &[DatabaseDiff]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "\n🔍 Schema diff: {} -> {}", source_label, target_label);
+    for diff in diffs {
+        let (added, removed, changed) = diff.counts();
+        let _ = writeln!(out, "\n{} -> {}", diff.source, diff.target);
+        if diff.changes.is_empty() {
+            let _ = writeln!(out, "  ✓ No differences.");
+            continue;
+        }
+        let _ = writeln!(out, "  {} added, {} removed, {} changed", added, removed, changed);
+        for change in &diff.changes {
+            let sign = match change.change {
+                ChangeKind::Added => '+',
+                ChangeKind::Removed => '-',
+                ChangeKind::Changed => '~',
+            };
+            let _ = write!(out, "  {} {:<10} {}", sign, change.object.as_str(), change.name);
+            match (change.change, change.object) {
+                (ChangeKind::Changed, _) => {
+                    let _ = writeln!(out);
+                    for (side, definition) in [("source", &change.source), ("target", &change.target)] {
+                        let definition = definition.as_deref().unwrap_or_default().trim_end().replace('\n', "\n              ");
+                        let _ = writeln!(out, "      {}: {}", side, definition);
+                    }
+                }
+                (_, ObjectKind::Column | ObjectKind::Extension) => {
+                    let definition = change.source.as_ref().or(change.target.as_ref());
+                    let _ = writeln!(out, " ({})", definition.map(String::as_str).unwrap_or_default());
+                }
+                _ => {
+                    let _ = writeln!(out);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// The differences of every database as a JSON document.
+pub fn render_json(source_label: &str, target_label: &str, diffs: &[DatabaseDiff]) -> Result<String> {
+    let document = serde_json::json!({
+        "source": source_label,
+        "target": target_label,
+        "databases": diffs,
+    });
+    serde_json::to_string_pretty(&document).context("Failed to serialize the schema diff")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definitions(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(name, definition)| (name.to_string(), definition.to_string())).collect()
+    }
+
+    fn sample_source() -> SchemaSnapshot {
+        SchemaSnapshot {
+            tables: BTreeMap::from([
+                ("public.items".to_string(), definitions(&[("id", "integer not null"), ("price", "numeric(10,2)")])),
+                ("public.legacy".to_string(), definitions(&[("id", "integer")])),
+            ]),
+            indexes: definitions(&[("public.items_price_idx", "CREATE INDEX items_price_idx ON public.items USING btree (price)")]),
+            constraints: definitions(&[("public.items.items_pkey", "PRIMARY KEY (id)")]),
+            extensions: definitions(&[("plpgsql", "1.0")]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compare_schemas_lists_added_removed_and_changed_objects() {
+        let source = sample_source();
+        assert!(compare_schemas(&source, &source).is_empty());
+
+        let mut target = source.clone();
+        target.tables.remove("public.legacy");
+        let items = target.tables.get_mut("public.items").unwrap();
+        items.insert("price".to_string(), "numeric(12,2)".to_string());
+        items.insert("sku".to_string(), "text".to_string());
+        target.indexes.clear();
+        target.triggers = definitions(&[("public.items.audit", "CREATE TRIGGER audit AFTER UPDATE ON public.items")]);
+        target.extensions.insert("pgcrypto".to_string(), "1.3".to_string());
+
+        let changes = compare_schemas(&source, &target);
+        let summary: Vec<(ObjectKind, &str, ChangeKind)> =
+            changes.iter().map(|change| (change.object, change.name.as_str(), change.change)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (ObjectKind::Extension, "pgcrypto", ChangeKind::Added),
+                (ObjectKind::Table, "public.legacy", ChangeKind::Removed),
+                (ObjectKind::Column, "public.items.price", ChangeKind::Changed),
+                (ObjectKind::Column, "public.items.sku", ChangeKind::Added),
+                (ObjectKind::Index, "public.items_price_idx", ChangeKind::Removed),
+                (ObjectKind::Trigger, "public.items.audit", ChangeKind::Added),
+            ]
+        );
+        assert_eq!(changes[2].source.as_deref(), Some("numeric(10,2)"));
+        assert_eq!(changes[2].target.as_deref(), Some("numeric(12,2)"));
+    }
+
+    #[test]
+    fn test_render_text_and_json() -> Result<()> {
+        let source = sample_source();
+        let mut target = source.clone();
+        target.tables.get_mut("public.items").unwrap().insert("price".to_string(), "numeric(12,2)".to_string());
+        target.tables.remove("public.legacy");
+        let diffs = vec![
+            DatabaseDiff { source: "app".to_string(), target: "app_dev".to_string(), changes: compare_schemas(&source, &target) },
+            DatabaseDiff { source: "crm".to_string(), target: "crm".to_string(), changes: Vec::new() },
+        ];
+
+        let text = render_text("prod:5432", "staging:5432", &diffs);
+        assert!(text.contains("app -> app_dev\n  0 added, 1 removed, 1 changed\n"), "{}", text);
+        assert!(text.contains("  ~ column     public.items.price\n      source: numeric(10,2)\n      target: numeric(12,2)\n"), "{}", text);
+        assert!(text.contains("  - table      public.legacy\n"), "{}", text);
+        assert!(text.contains("crm -> crm\n  ✓ No differences."), "{}", text);
+
+        let json: serde_json::Value = serde_json::from_str(&render_json("prod:5432", "staging:5432", &diffs)?)?;
+        let change = &json["databases"][0]["changes"][0];
+        assert_eq!(change["object"], "table");
+        assert_eq!(change["change"], "removed");
+        assert!(change.get("target").is_none());
+        assert_eq!(json["databases"][1]["changes"], serde_json::json!([]));
+        Ok(())
+    }
+}
@@ -8,14 +8,20 @@ mod backup;
 mod restore;
 mod sync; // Added sync module
 mod config; // Added config module
+mod cli;
+mod catalog;
+mod diff;
+mod physical;
 
 use anyhow::{Context, Result};
+use clap::Parser;
+use cli::{Cli, Command};
 use config::{
-    AppConfig, OperationConfig, load_backup_config_from_json, load_restore_config_from_json,
-    load_sync_config_from_json,
+    AppConfig, OperationConfig, ReplicationAction, WalAction, load_backup_config_from_json, load_diff_config_from_json,
+    load_list_config_from_json, load_physical_backup_config_from_json, load_physical_restore_config_from_json,
+    load_prune_config_from_json, load_restore_config_from_json, load_sync_config_from_json,
+    load_safety_config_from_json, load_upload_config_from_json, load_wal_config_from_json,
 };
-use std::env;
-use std::path::PathBuf;
 use std::process::ExitCode;
 
 /// Main entry point for the backup/restore tool
@@ -34,23 +40,36 @@ async fn main() -> ExitCode {
 }
 
 async fn run_app() -> Result<()> {
-    // Define the path to config.json. Expects it in the same directory as the executable
+    let cli = Cli::parse();
+
+    // The config path defaults to config.json in the current working directory,
     // or the project root if running with `cargo run`.
-    let config_path = PathBuf::from("config.json");
-    let mut app_config = AppConfig::load_from_json(&config_path)
-        .context(format!("Failed to load application configuration from {}", config_path.display()))?;
+    let mut app_config = AppConfig::load_from_json(&cli.config)
+        .context(format!("Failed to load application configuration from {}", cli.config.display()))?;
 
-    let args: Vec<String> = env::args().collect();
-    let choice = if args.len() > 1 {
-        args[1].trim().to_string()
-    } else {
-        prompt_choice()?
+    let command = match cli.command {
+        Some(command) => command,
+        None if cli.non_interactive => {
+            anyhow::bail!("No command given. With --non-interactive a subcommand (backup, restore, sync, prune, list, upload, diff, wal-push or wal-fetch) is required.");
+        }
+        None => Command::from_choice(&prompt_choice()?)?,
     };
 
+    command
+        .apply_overrides(&mut app_config.raw_json_config)
+        .context("Failed to apply command-line overrides to configuration")?;
+
     let spaces_is_configured = app_config.spaces_config.is_some();
 
-    match choice.as_str() {
-        "1" | "backup" => {
+    match command {
+        Command::Backup(args) if args.physical => {
+            let backup_config = load_physical_backup_config_from_json(&app_config.raw_json_config, spaces_is_configured)
+                .context("Failed to load physical backup configuration from JSON")?;
+            app_config.operation = Some(OperationConfig::PhysicalBackup(backup_config));
+            physical::run_physical_backup_flow(&app_config).await
+                .context("Physical backup failed")?;
+        }
+        Command::Backup(_) => {
             println!("🚀 Starting Backup Process...");
             let backup_config = load_backup_config_from_json(&app_config.raw_json_config, spaces_is_configured)
                 .context("Failed to load backup configuration from JSON")?;
@@ -58,27 +77,106 @@ async fn run_app() -> Result<()> {
             backup::run_backup_flow(&app_config).await
                 .context("Backup process failed")?;
         }
-        "2" | "restore" => {
+        Command::Restore(args) if args.physical => {
+            let restore_config = load_physical_restore_config_from_json(
+                &app_config.raw_json_config,
+                spaces_is_configured,
+                args.data_dir,
+                args.target_time.as_deref(),
+                args.target_lsn.as_deref(),
+                args.base_backup,
+                &cli.config,
+            )
+            .context("Failed to load physical restore configuration from JSON")?;
+            app_config.operation = Some(OperationConfig::PhysicalRestore(restore_config));
+            physical::run_physical_restore_flow(&app_config).await
+                .context("Physical restore failed")?;
+        }
+        Command::Restore(args) => {
             println!("🔄 Starting Restore Process...");
-            let restore_config = load_restore_config_from_json(&app_config.raw_json_config, spaces_is_configured)
-                .context("Failed to load restore configuration from JSON")?;
+            let restore_config = load_restore_config_from_json(
+                &app_config.raw_json_config,
+                spaces_is_configured,
+                args.plan,
+                load_safety_config_from_json(&app_config.raw_json_config, args.yes_i_know, !cli.non_interactive),
+            )
+            .context("Failed to load restore configuration from JSON")?;
             app_config.operation = Some(OperationConfig::Restore(restore_config.clone()));
-            
+
             println!("Restore target: {}, Archive: {}", restore_config.target_db_url, restore_config.archive_source_path);
             restore::run_restore_flow(&app_config).await.context("Restore process failed")?;
 
         }
-        "3" | "sync" => {
+        Command::Sync(args) => {
             println!("⚙️ Starting Sync Process...");
-            let sync_config = load_sync_config_from_json(&app_config.raw_json_config)
-                .context("Failed to load sync configuration from JSON")?;
+            let replication = match (args.continuous, args.teardown) {
+                (true, true) => Some(ReplicationAction::Teardown),
+                (true, false) => Some(ReplicationAction::Start),
+                (false, _) => None,
+            };
+            let sync_config = load_sync_config_from_json(
+                &app_config.raw_json_config,
+                args.plan,
+                replication,
+                load_safety_config_from_json(&app_config.raw_json_config, args.yes_i_know, !cli.non_interactive),
+            )
+            .context("Failed to load sync configuration from JSON")?;
             app_config.operation = Some(OperationConfig::Sync(sync_config));
             sync::run_sync_flow(&app_config).await
                 .context("Sync process failed")?;
         }
-        _ => {
-            println!("❌ Invalid choice. Please enter '1' (backup), '2' (restore), or '3' (sync).");
-            anyhow::bail!("Invalid operation choice");
+        Command::Prune(args) => {
+            println!("🧹 Starting Prune Process...");
+            let prune_config = load_prune_config_from_json(&app_config.raw_json_config, spaces_is_configured, args.dry_run)
+                .context("Failed to load prune configuration from JSON")?;
+            app_config.operation = Some(OperationConfig::Prune(prune_config));
+            backup::run_prune_flow(&app_config).await
+                .context("Prune process failed")?;
+        }
+        Command::List => {
+            println!("📚 Listing Backup Archives...");
+            let list_config = load_list_config_from_json(&app_config.raw_json_config, spaces_is_configured)
+                .context("Failed to load list configuration from JSON")?;
+            app_config.operation = Some(OperationConfig::List(list_config));
+            catalog::run_list_flow(&app_config).await
+                .context("Listing backup archives failed")?;
+        }
+        Command::Upload(args) => {
+            println!("☁️ Starting Upload Process...");
+            let upload_config = load_upload_config_from_json(&app_config.raw_json_config, spaces_is_configured, &args.archive, args.upload_id)
+                .context("Failed to load upload configuration from JSON")?;
+            app_config.operation = Some(OperationConfig::Upload(upload_config));
+            backup::run_upload_flow(&app_config).await
+                .context("Upload process failed")?;
+        }
+        Command::Diff(args) => {
+            println!("🔍 Starting Diff Process...");
+            let diff_config = load_diff_config_from_json(
+                &app_config.raw_json_config,
+                spaces_is_configured,
+                args.archive,
+                Some(&args.format),
+                args.output,
+            )
+            .context("Failed to load diff configuration from JSON")?;
+            app_config.operation = Some(OperationConfig::Diff(diff_config));
+            diff::run_diff_flow(&app_config).await
+                .context("Diff process failed")?;
+        }
+        Command::WalPush(args) => {
+            let wal_config = load_wal_config_from_json(&app_config.raw_json_config, spaces_is_configured, WalAction::Push(args.path))
+                .context("Failed to load WAL archive configuration from JSON")?;
+            app_config.operation = Some(OperationConfig::Wal(wal_config));
+            physical::run_wal_flow(&app_config).await
+                .context("Archiving WAL failed")?;
+        }
+        Command::WalFetch(args) => {
+            let action = WalAction::Fetch { name: args.name, destination: args.destination };
+            let wal_config = load_wal_config_from_json(&app_config.raw_json_config, spaces_is_configured, action)
+                .context("Failed to load WAL archive configuration from JSON")?;
+            app_config.operation = Some(OperationConfig::Wal(wal_config));
+            physical::run_wal_flow(&app_config).await
+                .context("Fetching archived WAL failed")?;
         }
     }
     Ok(())
@@ -94,8 +192,10 @@ fn prompt_choice() -> Result<String> {
     println!("1. Take Backup (or type 'backup')");
     println!("2. Restore Backup (or type 'restore')");
     println!("3. Sync Databases (Source to Target) (or type 'sync')");
+    println!("4. Prune Old Backups (or type 'prune')");
+    println!("5. List Backup Archives (or type 'list')");
     print!("Enter your choice: ");
-    let _ = stdout().flush().context("Failed to flush stdout")?;
+    stdout().flush().context("Failed to flush stdout")?;
 
     let mut input = String::new();
     stdin().read_line(&mut input).context("Failed to read user input")?;
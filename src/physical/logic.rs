@@ -0,0 +1,171 @@
+// databasetool/src/physical/logic.rs
+use anyhow::{Context, Result};
+use sqlx::{Connection, PgConnection};
+use std::fs;
+use std::path::Path;
+use tempfile::Builder as TempFileBuilder;
+use tokio::process::Command;
+use url::Url;
+use which::which;
+
+use crate::backup::retention::ARCHIVE_TIMESTAMP_FORMAT;
+use crate::config::{AppConfig, PhysicalBackupConfig, PhysicalRestoreConfig, PhysicalStorage, WalAction, WalConfig};
+use crate::physical::recovery;
+use crate::physical::storage::{BaseBackupInfo, PhysicalStore};
+
+/// Takes a base backup of the source cluster with `pg_basebackup` and stores it.
+///
+/// The backup is written in tar format with the WAL it needs (`-X stream`), so it can be
+/// restored on its own; WAL archived by `wal-push` afterwards lets recovery go further.
+pub async fn perform_physical_backup_orchestration(app_config: &AppConfig, config: &PhysicalBackupConfig) -> Result<()> {
+    println!("🚀 Starting physical base backup...");
+    let pg_basebackup_path = which("pg_basebackup").context(
+        "pg_basebackup executable not found in PATH. Please ensure PostgreSQL client tools are installed and in your PATH.",
+    )?;
+    let store = PhysicalStore::open(&config.storage, app_config.spaces_config.as_ref()).await?;
+
+    let mut conn = PgConnection::connect(&admin_url(&config.source_db_url)?)
+        .await
+        .context("Failed to connect to the source server")?;
+    let server_version: String = sqlx::query_scalar("SHOW server_version").fetch_one(&mut conn).await?;
+    let archive_mode: String = sqlx::query_scalar("SHOW archive_mode").fetch_one(&mut conn).await?;
+    if archive_mode == "off" {
+        println!(
+            "⚠️  archive_mode is off on the source. The base backup can be restored, but not to a later point in time \
+             until WAL is archived with archive_command = 'databasetool --config <config.json> --non-interactive wal-push %p'."
+        );
+    }
+
+    let id = chrono::Local::now().format(ARCHIVE_TIMESTAMP_FORMAT).to_string();
+    // Local backups are written in place; an S3 backup is staged and removed after the upload.
+    let (backup_dir, _staging_dir) = match &config.storage {
+        PhysicalStorage::Local(root) => (root.join("base").join(&id), None),
+        PhysicalStorage::S3 => {
+            let staging_root = config.temp_dump_root.clone().unwrap_or_else(std::env::temp_dir);
+            fs::create_dir_all(&staging_root).with_context(|| format!("Failed to create {}", staging_root.display()))?;
+            let staging_dir = TempFileBuilder::new()
+                .prefix("physical_backup_")
+                .tempdir_in(&staging_root)
+                .context("Failed to create a staging directory for the base backup")?;
+            (staging_dir.path().join(&id), Some(staging_dir))
+        }
+    };
+    fs::create_dir_all(&backup_dir).with_context(|| format!("Failed to create {}", backup_dir.display()))?;
+
+    println!("Running pg_basebackup into {}...", backup_dir.display());
+    let output = Command::new(&pg_basebackup_path)
+        .arg("--dbname")
+        .arg(&config.source_db_url)
+        .arg("--pgdata")
+        .arg(&backup_dir)
+        .arg("--format=tar")
+        .arg("--gzip")
+        .arg("--wal-method=stream")
+        .arg("--checkpoint=fast")
+        .arg(format!("--label=databasetool {}", id))
+        .arg("--no-password")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to execute pg_basebackup")?;
+    if !output.status.success() {
+        if matches!(config.storage, PhysicalStorage::Local(_)) {
+            let _ = fs::remove_dir_all(&backup_dir);
+        }
+        anyhow::bail!(
+            "pg_basebackup exited with status: {}\nStderr: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    // Read after pg_basebackup returned, so both are at or past the end of the backup.
+    let finished_at = chrono::Utc::now().to_rfc3339();
+    let (end_lsn, timeline): (String, i32) = sqlx::query_as(
+        "SELECT (CASE WHEN pg_is_in_recovery() THEN pg_last_wal_replay_lsn() ELSE pg_current_wal_lsn() END)::text, \
+                timeline_id FROM pg_control_checkpoint()",
+    )
+    .fetch_one(&mut conn)
+    .await
+    .context("Failed to read the WAL position of the source")?;
+
+    let mut files: Vec<String> = fs::read_dir(&backup_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    let info = BaseBackupInfo {
+        id: id.clone(),
+        finished_at,
+        end_lsn,
+        timeline: timeline.max(0) as u32,
+        server_version,
+        files,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    store.store_base_backup(&backup_dir, &info).await?;
+    println!("✓ Base backup {} stored at {} (end LSN {}).", id, store.describe(&format!("base/{}", id)), info.end_lsn);
+    Ok(())
+}
+
+/// Runs `wal-push` or `wal-fetch`.
+pub async fn perform_wal_orchestration(app_config: &AppConfig, config: &WalConfig) -> Result<()> {
+    let store = PhysicalStore::open(&config.storage, app_config.spaces_config.as_ref()).await?;
+    match &config.action {
+        WalAction::Push(path) => store.push_wal(path).await.with_context(|| format!("Failed to archive {}", path.display())),
+        WalAction::Fetch { name, destination } => store.fetch_wal(name, destination).await,
+    }
+}
+
+/// Rebuilds a data directory from the base backup for the recovery target.
+///
+/// The server is not started: PostgreSQL replays archived WAL through `wal-fetch` when it is
+/// started on the directory, stops at the target and promotes itself.
+pub async fn perform_physical_restore_orchestration(app_config: &AppConfig, config: &PhysicalRestoreConfig) -> Result<()> {
+    println!("🔄 Starting physical restore into {}...", config.data_dir.display());
+    let store = PhysicalStore::open(&config.storage, app_config.spaces_config.as_ref()).await?;
+
+    let backups = store.list_base_backups().await?;
+    let info = recovery::choose_base_backup(&backups, &config.target, config.base_backup.as_deref())?;
+    println!(
+        "Using base backup {} (finished {}, end LSN {}) to recover to {}.",
+        info.id,
+        info.finished_at,
+        info.end_lsn,
+        recovery::describe_target(&config.target)
+    );
+
+    let backup_dir = store.fetch_base_backup(info, &config.download_dir).await?;
+    let restore_command = restore_command(&config.config_path)?;
+    let settings = recovery::recovery_settings(&restore_command, &config.target);
+    let rebuilt = recovery::rebuild_data_directory(&backup_dir, info, &config.data_dir, &settings);
+    if matches!(config.storage, PhysicalStorage::S3) {
+        let _ = fs::remove_dir_all(&backup_dir); // Downloaded copy
+    }
+    rebuilt.with_context(|| format!("Failed to rebuild data directory {}", config.data_dir.display()))?;
+
+    println!("✓ Data directory {} is ready for recovery.", config.data_dir.display());
+    println!("Start the server to replay the archived WAL, e.g.:");
+    println!("  pg_ctl -D {} start", config.data_dir.display());
+    Ok(())
+}
+
+/// `restore_command` calling this binary with the config file of this run.
+fn restore_command(config_path: &Path) -> Result<String> {
+    let executable = std::env::current_exe().context("Failed to determine the path of databasetool")?;
+    Ok(format!(
+        "{} --config {} --non-interactive wal-fetch %f %p",
+        recovery::shell_quote(&executable.to_string_lossy()),
+        recovery::shell_quote(&config_path.to_string_lossy())
+    ))
+}
+
+/// The URL with the `postgres` database when it names none, for reading server settings.
+fn admin_url(db_url: &str) -> Result<String> {
+    let mut url = Url::parse(db_url).with_context(|| format!("Invalid database URL format: {}", db_url))?;
+    if url.path().trim_start_matches('/').is_empty() {
+        url.set_path("/postgres");
+    }
+    Ok(url.to_string())
+}
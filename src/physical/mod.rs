@@ -0,0 +1,37 @@
+// databasetool/src/physical/mod.rs
+pub(crate) mod logic;
+pub(crate) mod storage;  // Base backups and archived WAL, locally or under the S3 prefix
+pub(crate) mod recovery; // Base backup selection and data directory rebuild for point-in-time recovery
+
+use anyhow::Result;
+use crate::config::{AppConfig, OperationConfig};
+
+/// Public entry point for `backup --physical`.
+pub async fn run_physical_backup_flow(app_config: &AppConfig) -> Result<()> {
+    let backup_config = match &app_config.operation {
+        Some(OperationConfig::PhysicalBackup(cfg)) => cfg,
+        _ => anyhow::bail!("Physical backup selected but no physical backup configuration found."),
+    };
+
+    logic::perform_physical_backup_orchestration(app_config, backup_config).await
+}
+
+/// Public entry point for `restore --physical`.
+pub async fn run_physical_restore_flow(app_config: &AppConfig) -> Result<()> {
+    let restore_config = match &app_config.operation {
+        Some(OperationConfig::PhysicalRestore(cfg)) => cfg,
+        _ => anyhow::bail!("Physical restore selected but no physical restore configuration found."),
+    };
+
+    logic::perform_physical_restore_orchestration(app_config, restore_config).await
+}
+
+/// Public entry point for `wal-push` and `wal-fetch`.
+pub async fn run_wal_flow(app_config: &AppConfig) -> Result<()> {
+    let wal_config = match &app_config.operation {
+        Some(OperationConfig::Wal(cfg)) => cfg,
+        _ => anyhow::bail!("WAL command selected but no WAL configuration found."),
+    };
+
+    logic::perform_wal_orchestration(app_config, wal_config).await
+}
@@ -0,0 +1,222 @@
+// databasetool/src/physical/recovery.rs
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::{RecoveryTarget, format_lsn, parse_lsn};
+use crate::physical::storage::BaseBackupInfo;
+use crate::utils::sql_script::quote_literal;
+
+/// Picks the base backup to recover from: the requested id, or the newest backup that
+/// finished before the recovery target. `backups` must be sorted oldest first.
+pub fn choose_base_backup<'a>(
+    backups: &'a [BaseBackupInfo],
+    target: &RecoveryTarget,
+    requested_id: Option<&str>,
+) -> Result<&'a BaseBackupInfo> {
+    let reaches_target = |info: &BaseBackupInfo| -> Result<bool> {
+        Ok(match target {
+            RecoveryTarget::Latest => true,
+            RecoveryTarget::Time(time) => {
+                let finished_at = chrono::DateTime::parse_from_rfc3339(&info.finished_at)
+                    .with_context(|| format!("Base backup {} has an invalid finished_at '{}'", info.id, info.finished_at))?;
+                finished_at <= *time
+            }
+            RecoveryTarget::Lsn(lsn) => parse_lsn(&info.end_lsn)
+                .with_context(|| format!("Base backup {} has an invalid end_lsn", info.id))?
+                <= *lsn,
+        })
+    };
+
+    if let Some(id) = requested_id {
+        let info = backups
+            .iter()
+            .find(|info| info.id == id)
+            .with_context(|| format!("Base backup {} not found. Available: {:?}", id, backups.iter().map(|b| &b.id).collect::<Vec<_>>()))?;
+        if !reaches_target(info)? {
+            anyhow::bail!("Base backup {} finished after the recovery target {}; pick an older one.", id, describe_target(target));
+        }
+        return Ok(info);
+    }
+
+    let mut chosen = None;
+    for info in backups {
+        if reaches_target(info)? {
+            chosen = Some(info);
+        }
+    }
+    chosen.with_context(|| {
+        format!(
+            "No base backup finished before the recovery target {} ({} base backup(s) found).",
+            describe_target(target),
+            backups.len()
+        )
+    })
+}
+
+pub fn describe_target(target: &RecoveryTarget) -> String {
+    match target {
+        RecoveryTarget::Latest => "end of the archived WAL".to_string(),
+        RecoveryTarget::Time(time) => time.to_rfc3339(),
+        RecoveryTarget::Lsn(lsn) => format_lsn(*lsn),
+    }
+}
+
+/// Settings appended to `postgresql.auto.conf` so the server replays archived WAL up to the
+/// target and then promotes itself.
+pub fn recovery_settings(restore_command: &str, target: &RecoveryTarget) -> String {
+    let mut settings = format!(
+        "\n# Added by databasetool restore --physical\nrestore_command = {}\n",
+        quote_literal(restore_command)
+    );
+    match target {
+        RecoveryTarget::Latest => {}
+        RecoveryTarget::Time(time) => {
+            settings.push_str(&format!("recovery_target_time = {}\n", quote_literal(&time.format("%Y-%m-%d %H:%M:%S%.f%:z").to_string())));
+        }
+        RecoveryTarget::Lsn(lsn) => settings.push_str(&format!("recovery_target_lsn = {}\n", quote_literal(&format_lsn(*lsn)))),
+    }
+    if *target != RecoveryTarget::Latest {
+        settings.push_str("recovery_target_action = 'promote'\n");
+    }
+    settings
+}
+
+/// Quotes a word for the shell PostgreSQL runs `restore_command` with.
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Unpacks a base backup into `data_dir` and prepares it for recovery.
+///
+/// `data_dir` must not exist or be empty. `base.tar.gz` becomes the data directory and
+/// `pg_wal.tar.gz` its `pg_wal`; `recovery.signal` and the recovery settings are added.
+pub fn rebuild_data_directory(backup_dir: &Path, info: &BaseBackupInfo, data_dir: &Path, settings: &str) -> Result<()> {
+    if data_dir.exists() && fs::read_dir(data_dir).with_context(|| format!("Failed to read {}", data_dir.display()))?.next().is_some() {
+        anyhow::bail!("Data directory {} is not empty; refusing to overwrite it.", data_dir.display());
+    }
+    if let Some(tablespace) = info.files.iter().find(|file| *file != "base.tar.gz" && *file != "pg_wal.tar.gz" && file.ends_with(".tar.gz")) {
+        anyhow::bail!("Base backup {} contains a tablespace ({}); tablespaces are not supported by restore --physical.", info.id, tablespace);
+    }
+    fs::create_dir_all(data_dir).with_context(|| format!("Failed to create data directory {}", data_dir.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // PostgreSQL refuses to start on a data directory others can read.
+        fs::set_permissions(data_dir, fs::Permissions::from_mode(0o700))
+            .with_context(|| format!("Failed to restrict permissions of {}", data_dir.display()))?;
+    }
+
+    unpack_tar_gz(&backup_dir.join("base.tar.gz"), data_dir)?;
+    if info.files.iter().any(|file| file == "pg_wal.tar.gz") {
+        let wal_dir = data_dir.join("pg_wal");
+        fs::create_dir_all(&wal_dir).with_context(|| format!("Failed to create {}", wal_dir.display()))?;
+        unpack_tar_gz(&backup_dir.join("pg_wal.tar.gz"), &wal_dir)?;
+    }
+
+    File::create(data_dir.join("recovery.signal")).context("Failed to create recovery.signal")?;
+    let auto_conf_path = data_dir.join("postgresql.auto.conf");
+    let mut auto_conf = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&auto_conf_path)
+        .with_context(|| format!("Failed to open {}", auto_conf_path.display()))?;
+    auto_conf
+        .write_all(settings.as_bytes())
+        .with_context(|| format!("Failed to write recovery settings to {}", auto_conf_path.display()))
+}
+
+fn unpack_tar_gz(archive_path: &Path, destination: &Path) -> Result<()> {
+    println!("Unpacking {} into {}...", archive_path.display(), destination.display());
+    let file = File::open(archive_path).with_context(|| format!("Failed to open {}", archive_path.display()))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    archive.set_preserve_permissions(true);
+    archive
+        .unpack(destination)
+        .with_context(|| format!("Failed to unpack {} into {}", archive_path.display(), destination.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_recovery_time;
+    use flate2::{Compression, write::GzEncoder};
+
+    fn info(id: &str, finished_at: &str, end_lsn: &str) -> BaseBackupInfo {
+        BaseBackupInfo {
+            id: id.to_string(),
+            finished_at: finished_at.to_string(),
+            end_lsn: end_lsn.to_string(),
+            timeline: 1,
+            server_version: "16.2".to_string(),
+            files: vec!["base.tar.gz".to_string(), "pg_wal.tar.gz".to_string()],
+            tool_version: "0.1.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_choose_base_backup_picks_newest_before_target() -> Result<()> {
+        let backups = vec![
+            info("2024-05-01_02-00-00", "2024-05-01T02:05:00+00:00", "0/5000000"),
+            info("2024-05-02_02-00-00", "2024-05-02T02:05:00+00:00", "1/0"),
+        ];
+        let at = |time: &str| RecoveryTarget::Time(parse_recovery_time(time).unwrap());
+
+        assert_eq!(choose_base_backup(&backups, &RecoveryTarget::Latest, None)?.id, "2024-05-02_02-00-00");
+        assert_eq!(choose_base_backup(&backups, &at("2024-05-02T01:00:00+00:00"), None)?.id, "2024-05-01_02-00-00");
+        assert_eq!(choose_base_backup(&backups, &RecoveryTarget::Lsn(0x1_0000_0000), None)?.id, "2024-05-02_02-00-00");
+        assert_eq!(choose_base_backup(&backups, &RecoveryTarget::Lsn(0x6000000), None)?.id, "2024-05-01_02-00-00");
+        assert!(choose_base_backup(&backups, &at("2024-05-01T02:00:00+00:00"), None).is_err());
+
+        assert_eq!(choose_base_backup(&backups, &RecoveryTarget::Latest, Some("2024-05-01_02-00-00"))?.id, "2024-05-01_02-00-00");
+        assert!(choose_base_backup(&backups, &RecoveryTarget::Lsn(0x6000000), Some("2024-05-02_02-00-00")).is_err());
+        assert!(choose_base_backup(&backups, &RecoveryTarget::Latest, Some("2023-01-01_00-00-00")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_settings() -> Result<()> {
+        let command = format!("{} wal-fetch %f %p", shell_quote("/opt/it's/databasetool"));
+        assert_eq!(command, r"'/opt/it'\''s/databasetool' wal-fetch %f %p");
+
+        let settings = recovery_settings("databasetool wal-fetch %f %p", &RecoveryTarget::Time(parse_recovery_time("2024-05-01T12:30:00+02:00")?));
+        assert!(settings.contains("restore_command = 'databasetool wal-fetch %f %p'\n"), "{}", settings);
+        assert!(settings.contains("recovery_target_time = '2024-05-01 12:30:00+02:00'\n"), "{}", settings);
+        assert!(settings.contains("recovery_target_action = 'promote'\n"), "{}", settings);
+
+        let settings = recovery_settings("a 'b'", &RecoveryTarget::Lsn(0x3000060));
+        assert!(settings.contains("restore_command = 'a ''b'''\n") && settings.contains("recovery_target_lsn = '0/3000060'\n"), "{}", settings);
+        assert!(!recovery_settings("x", &RecoveryTarget::Latest).contains("recovery_target"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuild_data_directory() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let backup_dir = temp.path().join("backup");
+        fs::create_dir_all(&backup_dir)?;
+        for (archive, entry, content) in [("base.tar.gz", "PG_VERSION", "16\n"), ("pg_wal.tar.gz", "000000010000000000000002", "wal")] {
+            let mut builder = tar::Builder::new(GzEncoder::new(File::create(backup_dir.join(archive))?, Compression::fast()));
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, entry, content.as_bytes())?;
+            builder.into_inner()?.finish()?;
+        }
+
+        let data_dir = temp.path().join("data");
+        let info = info("2024-05-01_02-00-00", "2024-05-01T02:05:00+00:00", "0/5000000");
+        rebuild_data_directory(&backup_dir, &info, &data_dir, "\nrestore_command = 'x'\n")?;
+        assert_eq!(fs::read_to_string(data_dir.join("PG_VERSION"))?, "16\n");
+        assert_eq!(fs::read_to_string(data_dir.join("pg_wal/000000010000000000000002"))?, "wal");
+        assert!(data_dir.join("recovery.signal").is_file());
+        assert!(fs::read_to_string(data_dir.join("postgresql.auto.conf"))?.contains("restore_command = 'x'"));
+
+        // A second rebuild would overwrite a cluster.
+        assert!(rebuild_data_directory(&backup_dir, &info, &data_dir, "").is_err());
+        Ok(())
+    }
+}
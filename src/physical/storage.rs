@@ -0,0 +1,340 @@
+// databasetool/src/physical/storage.rs
+use anyhow::{Context, Result};
+use aws_sdk_s3 as s3;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::backup::manifest::sha256_file;
+use crate::backup::s3_upload::{self, with_retries};
+use crate::config::{PhysicalStorage, SpacesConfig};
+use crate::restore::s3_download;
+
+/// Description of a base backup, stored next to its tar files.
+pub const BASE_BACKUP_INFO_FILE_NAME: &str = "backup_info.json";
+
+/// Object metadata key holding the SHA-256 of an archived WAL file.
+const WAL_SHA256_METADATA_KEY: &str = "sha256";
+
+/// Metadata of one `pg_basebackup`, used to pick the base backup for a recovery target.
+///
+/// `finished_at` and `end_lsn` are read after pg_basebackup returned, so they are never earlier
+/// than the true end of the backup: a backup chosen for a target can always reach it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaseBackupInfo {
+    pub id: String,          // Local time stamp in the archive name format, e.g. 2024-05-01_02-00-00
+    pub finished_at: String, // RFC 3339
+    pub end_lsn: String,     // WAL location (X/Y) the server had reached when the backup finished
+    pub timeline: u32,
+    pub server_version: String,
+    pub files: Vec<String>, // base.tar.gz, pg_wal.tar.gz, backup_manifest, ...
+    pub tool_version: String,
+}
+
+/// Physical backups in their storage, reached through the local filesystem or an S3 client.
+pub enum PhysicalStore<'a> {
+    Local(PathBuf),
+    S3 { client: s3::Client, spaces_config: &'a SpacesConfig },
+}
+
+impl<'a> PhysicalStore<'a> {
+    pub async fn open(storage: &PhysicalStorage, spaces_config: Option<&'a SpacesConfig>) -> Result<Self> {
+        match storage {
+            PhysicalStorage::Local(dir) => Ok(PhysicalStore::Local(dir.clone())),
+            PhysicalStorage::S3 => {
+                let spaces_config = spaces_config.context("Physical backups are stored in S3, but s3_storage is not configured")?;
+                Ok(PhysicalStore::S3 { client: s3_upload::build_s3_client(spaces_config).await, spaces_config })
+            }
+        }
+    }
+
+    /// Human readable location of a path relative to the physical backup root.
+    pub fn describe(&self, relative: &str) -> String {
+        match self {
+            PhysicalStore::Local(dir) => dir.join(relative).display().to_string(),
+            PhysicalStore::S3 { spaces_config, .. } => format!("s3://{}/{}", spaces_config.bucket_name, object_key(spaces_config, relative)),
+        }
+    }
+
+    /// Stores the files of a finished base backup from `backup_dir`, then its `backup_info.json`.
+    ///
+    /// The info file comes last: a base backup without one is incomplete and never listed.
+    pub async fn store_base_backup(&self, backup_dir: &Path, info: &BaseBackupInfo) -> Result<()> {
+        let info_json = serde_json::to_string_pretty(info).context("Failed to serialize base backup info")?;
+        fs::write(backup_dir.join(BASE_BACKUP_INFO_FILE_NAME), info_json)
+            .with_context(|| format!("Failed to write {} in {}", BASE_BACKUP_INFO_FILE_NAME, backup_dir.display()))?;
+        match self {
+            PhysicalStore::Local(dir) => {
+                let destination = dir.join("base").join(&info.id);
+                if backup_dir != destination {
+                    anyhow::bail!("Local base backups must be written in place, to {}", destination.display());
+                }
+                Ok(())
+            }
+            PhysicalStore::S3 { spaces_config, .. } => {
+                for file_name in info.files.iter().map(String::as_str).chain([BASE_BACKUP_INFO_FILE_NAME]) {
+                    let key = object_key(spaces_config, &format!("base/{}/{}", info.id, file_name));
+                    s3_upload::upload_file_to_s3(spaces_config, &backup_dir.join(file_name), &key, HashMap::new(), None)
+                        .await
+                        .with_context(|| format!("Failed to upload {} of base backup {}", file_name, info.id))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Lists the complete base backups, oldest first.
+    pub async fn list_base_backups(&self) -> Result<Vec<BaseBackupInfo>> {
+        let mut backups = Vec::new();
+        match self {
+            PhysicalStore::Local(dir) => {
+                let base_dir = dir.join("base");
+                if !base_dir.is_dir() {
+                    return Ok(backups);
+                }
+                for entry in fs::read_dir(&base_dir).with_context(|| format!("Failed to read {}", base_dir.display()))? {
+                    let info_path = entry?.path().join(BASE_BACKUP_INFO_FILE_NAME);
+                    if info_path.is_file() {
+                        let content = fs::read_to_string(&info_path).with_context(|| format!("Failed to read {}", info_path.display()))?;
+                        backups.push(parse_info(&content, &info_path.display().to_string())?);
+                    }
+                }
+            }
+            PhysicalStore::S3 { client, spaces_config } => {
+                let prefix = object_key(spaces_config, "base/");
+                let mut continuation_token: Option<String> = None;
+                loop {
+                    let response = client
+                        .list_objects_v2()
+                        .bucket(&spaces_config.bucket_name)
+                        .prefix(&prefix)
+                        .set_continuation_token(continuation_token.clone())
+                        .send()
+                        .await
+                        .with_context(|| format!("Failed to list objects in s3://{}/{}", spaces_config.bucket_name, prefix))?;
+                    for key in response.contents().iter().filter_map(|object| object.key()) {
+                        if key.ends_with(&format!("/{}", BASE_BACKUP_INFO_FILE_NAME)) {
+                            let content = get_object(client, spaces_config, key).await?;
+                            backups.push(parse_info(&String::from_utf8_lossy(&content), key)?);
+                        }
+                    }
+                    match response.next_continuation_token() {
+                        Some(token) if response.is_truncated().unwrap_or(false) => continuation_token = Some(token.to_string()),
+                        _ => break,
+                    }
+                }
+            }
+        }
+        backups.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(backups)
+    }
+
+    /// Makes the files of a base backup available locally, downloading them under `download_dir` from S3.
+    pub async fn fetch_base_backup(&self, info: &BaseBackupInfo, download_dir: &Path) -> Result<PathBuf> {
+        match self {
+            PhysicalStore::Local(dir) => Ok(dir.join("base").join(&info.id)),
+            PhysicalStore::S3 { spaces_config, .. } => {
+                let destination = download_dir.join("physical").join(&info.id);
+                for file_name in &info.files {
+                    let key = object_key(spaces_config, &format!("base/{}/{}", info.id, file_name));
+                    s3_download::download_file_from_s3(spaces_config, &spaces_config.bucket_name, &key, &destination.join(file_name))
+                        .await
+                        .with_context(|| format!("Failed to download {} of base backup {}", file_name, info.id))?;
+                }
+                Ok(destination)
+            }
+        }
+    }
+
+    /// Archives a WAL segment or timeline history file.
+    ///
+    /// PostgreSQL may archive the same file again after a crash, so a file that is already
+    /// archived with the same contents is accepted; different contents are an error.
+    pub async fn push_wal(&self, source_path: &Path) -> Result<()> {
+        let name = wal_file_name(source_path)?;
+        match self {
+            PhysicalStore::Local(dir) => {
+                let wal_dir = dir.join("wal");
+                let destination = wal_dir.join(&name);
+                if destination.exists() {
+                    if sha256_file(&destination)? == sha256_file(source_path)? {
+                        println!("WAL file {} is already archived.", name);
+                        return Ok(());
+                    }
+                    anyhow::bail!("{} is already archived with different contents", destination.display());
+                }
+                fs::create_dir_all(&wal_dir).with_context(|| format!("Failed to create WAL archive directory {}", wal_dir.display()))?;
+                // Copy under a temporary name and rename, so a crash never leaves a truncated segment behind.
+                let temp_path = wal_dir.join(format!(".{}.tmp", name));
+                copy_synced(source_path, &temp_path)?;
+                fs::rename(&temp_path, &destination)
+                    .with_context(|| format!("Failed to move {} into place", destination.display()))?;
+                println!("✓ Archived WAL file {} to {}", name, destination.display());
+                Ok(())
+            }
+            PhysicalStore::S3 { client, spaces_config } => {
+                let key = object_key(spaces_config, &format!("wal/{}", name));
+                let checksum = sha256_file(source_path)?;
+                if let Some(archived) = archived_wal_checksum(client, spaces_config, &key).await? {
+                    if archived == checksum {
+                        println!("WAL file {} is already archived.", name);
+                        return Ok(());
+                    }
+                    anyhow::bail!("s3://{}/{} is already archived with different contents", spaces_config.bucket_name, key);
+                }
+                let metadata = HashMap::from([(WAL_SHA256_METADATA_KEY.to_string(), checksum)]);
+                s3_upload::upload_file_to_s3(spaces_config, source_path, &key, metadata, None).await
+            }
+        }
+    }
+
+    /// Copies the archived WAL file `name` to `destination`. Fails when it was never archived,
+    /// which is how PostgreSQL learns that recovery reached the end of the archive.
+    pub async fn fetch_wal(&self, name: &str, destination: &Path) -> Result<()> {
+        if name.contains('/') || name.contains('\\') || name.starts_with('.') {
+            anyhow::bail!("Invalid WAL file name '{}'", name);
+        }
+        match self {
+            PhysicalStore::Local(dir) => {
+                let source_path = dir.join("wal").join(name);
+                if !source_path.is_file() {
+                    anyhow::bail!("WAL file {} is not in the archive {}", name, dir.join("wal").display());
+                }
+                copy_synced(&source_path, destination)
+            }
+            PhysicalStore::S3 { client, spaces_config } => {
+                let key = object_key(spaces_config, &format!("wal/{}", name));
+                let content = get_object_if_exists(client, spaces_config, &key)
+                    .await?
+                    .with_context(|| format!("WAL file {} is not in the archive s3://{}/{}", name, spaces_config.bucket_name, key))?;
+                fs::write(destination, content).with_context(|| format!("Failed to write {}", destination.display()))
+            }
+        }
+    }
+}
+
+/// Object key of a path relative to the physical backup root: `<folder_prefix>/physical/<relative>`.
+fn object_key(spaces_config: &SpacesConfig, relative: &str) -> String {
+    s3_upload::backup_object_key(spaces_config, &format!("physical/{}", relative))
+}
+
+async fn get_object(client: &s3::Client, spaces_config: &SpacesConfig, key: &str) -> Result<Vec<u8>> {
+    get_object_if_exists(client, spaces_config, key)
+        .await?
+        .with_context(|| format!("s3://{}/{} does not exist", spaces_config.bucket_name, key))
+}
+
+/// Downloads an object, or returns `None` right away when it does not exist; only transient
+/// failures are retried, since recovery asks for a missing WAL file once it reaches the end of the archive.
+async fn get_object_if_exists(client: &s3::Client, spaces_config: &SpacesConfig, key: &str) -> Result<Option<Vec<u8>>> {
+    let bucket = &spaces_config.bucket_name;
+    with_retries(spaces_config.transfer.max_retries, &format!("Download of s3://{}/{}", bucket, key), || async {
+        let object = match client.get_object().bucket(bucket).key(key).send().await {
+            Ok(object) => object,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) || is_not_found(&e) => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to get s3://{}/{}", bucket, key)),
+        };
+        Ok(Some(object.body.collect().await.with_context(|| format!("Failed to read s3://{}/{}", bucket, key))?.to_vec()))
+    })
+    .await
+}
+
+/// SHA-256 of an archived WAL file, or `None` when it is not archived yet.
+///
+/// Files archived before the checksum was kept in the object metadata are downloaded and hashed.
+async fn archived_wal_checksum(client: &s3::Client, spaces_config: &SpacesConfig, key: &str) -> Result<Option<String>> {
+    let bucket = &spaces_config.bucket_name;
+    let head = with_retries(spaces_config.transfer.max_retries, &format!("Lookup of s3://{}/{}", bucket, key), || async {
+        match client.head_object().bucket(bucket).key(key).send().await {
+            Ok(head) => Ok(Some(head)),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) || is_not_found(&e) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to look up s3://{}/{}", bucket, key)),
+        }
+    })
+    .await?;
+    let Some(head) = head else {
+        return Ok(None);
+    };
+    if let Some(checksum) = head.metadata().and_then(|metadata| metadata.get(WAL_SHA256_METADATA_KEY)) {
+        return Ok(Some(checksum.clone()));
+    }
+    let content = get_object(client, spaces_config, key).await?;
+    Ok(Some(hex::encode(Sha256::digest(&content))))
+}
+
+/// Whether a request failed because the object does not exist, also for S3-compatible services
+/// that answer 404 without a modelled error code.
+fn is_not_found<E>(error: &s3::error::SdkError<E>) -> bool {
+    error.raw_response().is_some_and(|response| response.status().as_u16() == 404)
+}
+
+fn parse_info(content: &str, location: &str) -> Result<BaseBackupInfo> {
+    serde_json::from_str(content).with_context(|| format!("Base backup info is not valid JSON: {}", location))
+}
+
+fn wal_file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .with_context(|| format!("WAL path has no file name: {}", path.display()))
+}
+
+/// Copies a file and flushes it to disk before returning.
+fn copy_synced(source_path: &Path, destination: &Path) -> Result<()> {
+    let mut source = fs::File::open(source_path).with_context(|| format!("Failed to open {}", source_path.display()))?;
+    let mut target = fs::File::create(destination).with_context(|| format!("Failed to create {}", destination.display()))?;
+    std::io::copy(&mut source, &mut target).with_context(|| format!("Failed to copy {} to {}", source_path.display(), destination.display()))?;
+    target.flush()?;
+    target.sync_all().with_context(|| format!("Failed to sync {}", destination.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_wal_push_is_idempotent_and_fetch_fails_for_missing_files() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let store = PhysicalStore::Local(temp.path().join("physical"));
+        let segment = temp.path().join("000000010000000000000001");
+        fs::write(&segment, b"segment contents")?;
+
+        store.push_wal(&segment).await?;
+        store.push_wal(&segment).await?; // PostgreSQL retrying after a crash
+        fs::write(&segment, b"other contents")?;
+        assert!(store.push_wal(&segment).await.is_err());
+
+        let restored = temp.path().join("RECOVERYXLOG");
+        store.fetch_wal("000000010000000000000001", &restored).await?;
+        assert_eq!(fs::read(&restored)?, b"segment contents");
+        assert!(store.fetch_wal("000000010000000000000002", &restored).await.is_err());
+        assert!(store.fetch_wal("../000000010000000000000001", &restored).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_base_backups_are_listed_once_complete() -> Result<()> {
+        let temp = tempfile::tempdir()?;
+        let root = temp.path().join("physical");
+        let store = PhysicalStore::Local(root.clone());
+        let info = BaseBackupInfo {
+            id: "2024-05-01_02-00-00".to_string(),
+            finished_at: "2024-05-01T02:03:00+00:00".to_string(),
+            end_lsn: "0/3000100".to_string(),
+            timeline: 1,
+            server_version: "16.2".to_string(),
+            files: vec!["base.tar.gz".to_string()],
+            tool_version: "0.1.0".to_string(),
+        };
+        fs::create_dir_all(root.join("base/2024-04-30_02-00-00"))?; // Interrupted, no info file
+        let backup_dir = root.join("base").join(&info.id);
+        fs::create_dir_all(&backup_dir)?;
+        store.store_base_backup(&backup_dir, &info).await?;
+
+        assert_eq!(store.list_base_backups().await?, vec![info.clone()]);
+        assert_eq!(store.fetch_base_backup(&info, temp.path()).await?, backup_dir);
+        Ok(())
+    }
+}
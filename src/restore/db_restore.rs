@@ -1,17 +1,23 @@
 // databasetool/src/restore/db_restore.rs
 use anyhow::{Context, Result};
 use sqlx::{Pool, Postgres};
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::{ChildStderr, Stdio};
+use std::thread;
 use tempfile::NamedTempFile;
 use tokio::process::Command;
 use tokio::time::{timeout, Duration};
 use url::Url;
+use crate::restore::rename;
 use crate::utils::find_psql_executable;
+use crate::utils::sql_script::{format_ident, quote_literal};
 
 
 /// Finds the pg_restore executable in the system PATH.
-fn find_pg_restore_executable() -> Result<PathBuf> {
+pub(crate) fn find_pg_restore_executable() -> Result<PathBuf> {
     which::which("pg_restore").context("pg_restore executable not found in PATH. Please ensure PostgreSQL client tools are installed and in your PATH.")
 }
 
@@ -30,12 +36,14 @@ use crate::config::RestoreConfig;
 /// * `log_context` - A string for logging context (e.g., "schema", "data").
 /// * `source_db_name` - Optional source database name for renaming (if provided, replaces occurrences in SQL).
 /// * `target_db_name` - Optional target database name for renaming.
+/// * `schema_mapping` - Schemas to rename (source -> target); the targets are created if missing.
 async fn execute_sql_file_with_psql(
     target_db_url: &str,
     sql_file_path: &Path,
     log_context: &str,
     source_db_name: Option<&str>,
     target_db_name: Option<&str>,
+    schema_mapping: &BTreeMap<String, String>,
 ) -> Result<()> {
     if !sql_file_path.exists() {
         return Err(anyhow::anyhow!(
@@ -75,61 +83,81 @@ async fn execute_sql_file_with_psql(
         println!("   Consider using pg_restore with custom format for better performance on large files.");
     }
 
-    // If database renaming is requested, create a temporary file with replaced content
-    let (sql_file_to_execute, _temp_file_guard) = if let (Some(source), Some(target)) = (source_db_name, target_db_name) {
-        if source != target {
+    // If database or schema renaming is requested, create a temporary file with replaced content
+    let database_renaming = match (source_db_name, target_db_name) {
+        (Some(source), Some(target)) if source != target => Some((source, target)),
+        _ => None,
+    };
+    let (sql_file_to_execute, _temp_file_guard) = if database_renaming.is_some() || !schema_mapping.is_empty() {
+        if let Some((source, target)) = database_renaming {
             println!("Renaming database references from '{}' to '{}' in {} file", source, target, log_context);
-            let sql_content = fs::read_to_string(sql_file_path)
-                .with_context(|| format!("Failed to read {} SQL file: {}", log_context, sql_file_path.display()))?;
-            
-            // Replace database name references intelligently
-            let mut modified_content = replace_database_references(&sql_content, source, target);
-            
-            // Add constraint handling for data files
-            if log_context == "data" {
-                modified_content = format!(
-                    "SET session_replication_role = 'replica';\n\
-                     -- Truncate tables to avoid duplicate key errors\n\
-                     DO $$\n\
-                     DECLARE\n\
-                         table_name text;\n\
-                     BEGIN\n\
-                         FOR table_name IN \n\
-                             SELECT tablename FROM pg_tables \n\
-                             WHERE schemaname = 'public' \n\
-                             AND tablename != 'schema_migrations'\n\
-                         LOOP\n\
-                             EXECUTE 'TRUNCATE TABLE ' || quote_ident(table_name) || ' CASCADE';\n\
-                         END LOOP;\n\
-                     END$$;\n\
-                     {}\n\
-                     SET session_replication_role = 'origin';", 
-                    modified_content
-                );
-            }
-            
-            let temp_file = NamedTempFile::new()?;
-            fs::write(&temp_file, &modified_content)
+        }
+        if !schema_mapping.is_empty() {
+            println!("Renaming schemas in {} file: {}", log_context, rename::describe_schema_mapping(schema_mapping));
+        }
+        let sql_file = fs::File::open(sql_file_path)
+            .with_context(|| format!("Failed to read {} SQL file: {}", log_context, sql_file_path.display()))?;
+
+        let temp_file = NamedTempFile::new()?;
+        let mut writer = BufWriter::new(temp_file.as_file());
+
+        // pg_dump does not create `public`, so the schemas it is mapped to may be missing
+        for target_schema in schema_mapping.values() {
+            writeln!(writer, "CREATE SCHEMA IF NOT EXISTS {};", format_ident(target_schema))
                 .with_context(|| format!("Failed to write modified {} SQL content", log_context))?;
-            
-            // Validate the temporary file was created and has content
-            let temp_path = temp_file.into_temp_path();
-            let file_size = fs::metadata(&temp_path)
-                .with_context(|| format!("Failed to get metadata for temporary {} SQL file", log_context))?
-                .len();
-            
-            if file_size == 0 {
-                return Err(anyhow::anyhow!(
-                    "Temporary {} SQL file is empty after database renaming. This indicates an issue with the renaming process.",
-                    log_context
-                ));
-            }
-            
-            println!("✓ Temporary {} SQL file created with truncation logic", log_context);
-            (temp_path.to_path_buf(), Some(temp_path))
-        } else {
-            (PathBuf::from(sql_file_path), None)
         }
+
+        // Add constraint handling for data files
+        if log_context == "data" && database_renaming.is_some() {
+            let truncated_schema = schema_mapping.get("public").map_or("public", String::as_str);
+            write!(
+                writer,
+                "SET session_replication_role = 'replica';\n\
+                 -- Truncate tables to avoid duplicate key errors\n\
+                 DO $$\n\
+                 DECLARE\n\
+                     table_name text;\n\
+                 BEGIN\n\
+                     FOR table_name IN \n\
+                         SELECT format('%I.%I', schemaname, tablename) FROM pg_tables \n\
+                         WHERE schemaname = {} \n\
+                         AND tablename != 'schema_migrations'\n\
+                     LOOP\n\
+                         EXECUTE 'TRUNCATE TABLE ' || table_name || ' CASCADE';\n\
+                     END LOOP;\n\
+                 END$$;\n",
+                quote_literal(truncated_schema)
+            )
+            .with_context(|| format!("Failed to write modified {} SQL content", log_context))?;
+        }
+
+        // Rename references statement by statement, without loading the whole file
+        rename::rename_references(BufReader::new(sql_file), &mut writer, database_renaming, schema_mapping)
+            .with_context(|| format!("Failed to rename references in {} SQL file: {}", log_context, sql_file_path.display()))?;
+
+        if log_context == "data" && database_renaming.is_some() {
+            writer
+                .write_all(b"\nSET session_replication_role = 'origin';")
+                .with_context(|| format!("Failed to write modified {} SQL content", log_context))?;
+        }
+        writer.flush().with_context(|| format!("Failed to write modified {} SQL content", log_context))?;
+        drop(writer);
+        
+        // Validate the temporary file was created and has content
+        let temp_path = temp_file.into_temp_path();
+        let file_size = fs::metadata(&temp_path)
+            .with_context(|| format!("Failed to get metadata for temporary {} SQL file", log_context))?
+            .len();
+        
+        if file_size == 0 {
+            return Err(anyhow::anyhow!(
+                "Temporary {} SQL file is empty after renaming. This indicates an issue with the renaming process.",
+                log_context
+            ));
+        }
+        
+        println!("✓ Temporary {} SQL file created with renamed references", log_context);
+        (temp_path.to_path_buf(), Some(temp_path))
     } else {
         (PathBuf::from(sql_file_path), None)
     };
@@ -191,7 +219,7 @@ async fn execute_sql_file_with_psql(
             let _ = tokio::process::Command::new("pkill")
                 .arg("-9")
                 .arg("-f")
-                .arg(&format!("psql.*{}", target_db_url))
+                .arg(format!("psql.*{}", target_db_url))
                 .output()
                 .await;
             
@@ -220,7 +248,7 @@ async fn execute_sql_file_with_psql(
             let _ = tokio::process::Command::new("pkill")
                 .arg("-9")
                 .arg("-f")
-                .arg(&format!("psql.*{}", target_db_url))
+                .arg(format!("psql.*{}", target_db_url))
                 .output()
                 .await;
             
@@ -250,7 +278,7 @@ async fn execute_sql_file_with_psql(
                  Stdout: {}\nStderr: {}",
                 log_context,
                 sql_file_path.display(),
-                target_db_url.split('/').last().unwrap_or("unknown"),
+                target_db_url.split('/').next_back().unwrap_or("unknown"),
                 stdout,
                 stderr
             ));
@@ -291,10 +319,10 @@ async fn execute_sql_file_with_psql(
     println!("   Psql execution completed successfully");
     
     // Additional cleanup: remove temporary file if it exists
-    if let Some(temp_path) = _temp_file_guard {
-        if let Err(e) = std::fs::remove_file(&temp_path) {
-            println!("⚠️  Warning: Failed to remove temporary file {}: {}", temp_path.display(), e);
-        }
+    if let Some(temp_path) = _temp_file_guard
+        && let Err(e) = std::fs::remove_file(&temp_path)
+    {
+        println!("⚠️  Warning: Failed to remove temporary file {}: {}", temp_path.display(), e);
     }
     Ok(())
 }
@@ -304,9 +332,12 @@ async fn execute_sql_file_with_psql(
 
 /// Manages the target database based on restore configuration.
 /// This includes potentially dropping and/or creating the database.
+///
+/// A database that is created gets `owner`, or the user of the target URL when it is `None`.
 pub async fn manage_target_database(
     restore_config: &RestoreConfig,
     db_name_to_manage: &str,
+    owner: Option<&str>,
 ) -> Result<bool> {
     println!("Managing target database: {}", db_name_to_manage);
 
@@ -317,7 +348,7 @@ pub async fn manage_target_database(
 
     admin_url.set_path("/postgres"); 
 
-    let admin_pool = Pool::<Postgres>::connect(&admin_url.to_string())
+    let admin_pool = Pool::<Postgres>::connect(admin_url.as_ref())
         .await
         .with_context(|| format!("Failed to connect to 'postgres' database on target server: {}", admin_url.host_str().unwrap_or("unknown_host")))?;
 
@@ -337,10 +368,8 @@ pub async fn manage_target_database(
 
             println!("Dropping database '{}' as per configuration...", db_name_to_manage);
             
-            let terminate_sql = format!(
-                "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = $1 AND pid <> pg_backend_pid();"
-            );
-            sqlx::query(&terminate_sql)
+            let terminate_sql = "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE datname = $1 AND pid <> pg_backend_pid();";
+            sqlx::query(terminate_sql)
                 .bind(db_name_to_manage)
                 .execute(&admin_pool)
                 .await
@@ -352,22 +381,22 @@ pub async fn manage_target_database(
                 .with_context(|| format!("Failed to drop database '{}'", db_name_to_manage))?;
             println!("✓ Database '{}' dropped.", db_name_to_manage);
             
-            create_database_if_not_exists(&admin_pool, db_name_to_manage, &restore_config.target_db_url).await?;
-            return Ok(true); 
+            create_database_if_not_exists(&admin_pool, db_name_to_manage, &restore_config.target_db_url, owner).await?;
+            Ok(true)
         } else {
             println!("Database '{}' exists and 'DROP_TARGET_DATABASE_IF_EXISTS' is false. No action taken on database structure. Tables within might be affected by restore.", db_name_to_manage);
-            return Ok(false); 
+            Ok(false)
         }
     } else {
         println!("Database '{}' does not exist on the target server.", db_name_to_manage);
         if restore_config.create_target_database_if_not_exists {
-            create_database_if_not_exists(&admin_pool, db_name_to_manage, &restore_config.target_db_url).await?;
-            return Ok(true);
+            create_database_if_not_exists(&admin_pool, db_name_to_manage, &restore_config.target_db_url, owner).await?;
+            Ok(true)
         } else {
-            return Err(anyhow::anyhow!(
+            Err(anyhow::anyhow!(
                 "Database '{}' does not exist and 'CREATE_TARGET_DATABASE_IF_NOT_EXISTS' is false. Cannot proceed with restore for this database.",
                 db_name_to_manage
-            ));
+            ))
         }
     }
 }
@@ -376,11 +405,12 @@ async fn create_database_if_not_exists(
     admin_pool: &Pool<Postgres>,
     db_name: &str,
     original_target_db_url: &str,
+    owner: Option<&str>,
 ) -> Result<()> {
     println!("Creating database '{}'...", db_name);
     
     let parsed_original_url = Url::parse(original_target_db_url)?;
-    let owner = parsed_original_url.username();
+    let owner = owner.unwrap_or(parsed_original_url.username());
 
     let mut create_sql = format!(r#"CREATE DATABASE "{}" "#, db_name.replace('\"', "\"\""));
     if !owner.is_empty() {
@@ -395,122 +425,33 @@ async fn create_database_if_not_exists(
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tempfile::tempdir;
-
-    #[test]
-    fn test_database_renaming_in_sql_content() -> Result<()> {
-        // Create a temporary directory and SQL file
-        let temp_dir = tempdir()?;
-        let sql_file_path = temp_dir.path().join("test_schema.sql");
-        
-        // SQL content with original database name
-        let sql_content = r#"
-CREATE DATABASE hotelrule_prod;
-\c hotelrule_prod
-
-CREATE SCHEMA IF NOT EXISTS hotelrule_prod;
-CREATE TABLE hotelrule_prod.users (
-    id SERIAL PRIMARY KEY,
-    name VARCHAR(100)
-);
-
-ALTER TABLE hotelrule_prod.users OWNER TO hotelrule_prod_admin;
-"#;
-        
-        fs::write(&sql_file_path, sql_content)?;
-
-        // Test renaming functionality using the new robust function
-        let modified_content = replace_database_references(sql_content, "hotelrule_prod", "hotelrule_prod_dev");
-
-        // Debug: print the modified content to see what actually happened
-        println!("Original content:\n{}", sql_content);
-        println!("Modified content:\n{}", modified_content);
-        
-        // Verify the replacements worked
-        assert!(modified_content.contains("CREATE DATABASE hotelrule_prod_dev"));
-        assert!(modified_content.contains("\\c hotelrule_prod_dev"));
-        assert!(modified_content.contains("hotelrule_prod_dev.users"));
-        assert!(!modified_content.contains("CREATE DATABASE hotelrule_prod;"));
-        assert!(!modified_content.contains("\\c hotelrule_prod;"));
-        assert!(!modified_content.contains("hotelrule_prod.users"));
-
-        Ok(())
-    }
-}
-
-/// Intelligently replaces database name references in SQL content
-/// Avoids modifying connection URLs and other sensitive patterns
-fn replace_database_references(sql_content: &str, source_db: &str, target_db: &str) -> String {
-    if source_db == target_db {
-        return sql_content.to_string();
-    }
-    
-    // Use a more robust approach that doesn't hardcode specific patterns
-    // Focus on replacing the database name as a standalone identifier
-    let mut result = sql_content.to_string();
-    
-    // Replace database name in common contexts where it appears as an identifier
-    // Avoid patterns that might match connection strings or URLs
-    let patterns = vec![
-        format!(" {} ", source_db),
-        format!("\"{}\" ", source_db),
-        format!(" {}.", source_db),
-        format!("\"{}\".", source_db),
-        format!(" {};", source_db),
-        format!("\"{}\";", source_db),
-        format!("\\c {}", source_db),
-        format!("\\c \"{}\"", source_db),
-    ];
-    
-    for pattern in patterns {
-        let replacement = pattern.replace(source_db, target_db);
-        result = result.replace(&pattern, &replacement);
-    }
-    
-    // Safety check: Ensure we didn't accidentally modify connection URLs
-    // Look for patterns like postgresql://, postgres://, etc. that might have been affected
-    let connection_patterns = vec![
-        format!("postgresql://{}/", target_db),
-        format!("postgres://{}/", target_db),
-        format!("{}.com", target_db),
-        format!("{}.org", target_db),
-        format!("{}.net", target_db),
-    ];
-    
-    for pattern in connection_patterns {
-        if result.contains(&pattern) {
-            println!("⚠️  Warning: Potential connection URL modification detected in SQL content");
-            println!("   Pattern found: {}", pattern);
-            println!("   This might indicate database renaming affected connection strings");
-        }
-    }
-    
-    result
-}
-
 /// Restores schema for a single database from its SQL file using psql.
 pub async fn restore_database_schema(
     target_db_url: &str,
     schema_sql_path: &Path,
     source_db_name: Option<&str>,
     target_db_name: Option<&str>,
+    schema_mapping: &BTreeMap<String, String>,
 ) -> Result<()> {
     println!(
         "Restoring schema from {} into target database (using psql)",
         schema_sql_path.display()
     );
-    execute_sql_file_with_psql(target_db_url, schema_sql_path, "schema", source_db_name, target_db_name).await
+    execute_sql_file_with_psql(target_db_url, schema_sql_path, "schema", source_db_name, target_db_name, schema_mapping).await
 }
 
-/// Restores a database from a .dump file using pg_restore.
+/// Restores a database from a custom format `.dump` file or a directory format dump using pg_restore.
+///
+/// With `jobs > 1` pg_restore loads data and builds indexes in parallel (`-j`). pg_restore cannot
+/// rename schemas, so with a `schema_mapping` the dump is converted to a SQL script instead, which
+/// is renamed and run with psql like a plain dump: in one transaction, without `--clean` and `-j`.
 pub async fn restore_database_from_dump(
     target_db_url: &str,
     dump_file_path: &Path,
     _source_db_name: Option<&str>,
     _target_db_name: Option<&str>,
+    jobs: usize,
+    schema_mapping: &BTreeMap<String, String>,
 ) -> Result<()> {
     if !dump_file_path.exists() {
         return Err(anyhow::anyhow!(
@@ -518,6 +459,9 @@ pub async fn restore_database_from_dump(
             dump_file_path.display()
         ));
     }
+    if !schema_mapping.is_empty() {
+        return restore_dump_as_script(target_db_url, dump_file_path, schema_mapping).await;
+    }
 
     let pg_restore_path = find_pg_restore_executable()?;
     println!(
@@ -526,10 +470,19 @@ pub async fn restore_database_from_dump(
         target_db_url
     );
 
-    // Check file size for informational purposes
-    let file_size = fs::metadata(dump_file_path)
-        .with_context(|| format!("Failed to get file size for {}", dump_file_path.display()))?
-        .len();
+    // Check file size for informational purposes (directory format dumps report the size of their files)
+    let file_size = if dump_file_path.is_dir() {
+        walkdir::WalkDir::new(dump_file_path)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    } else {
+        fs::metadata(dump_file_path)
+            .with_context(|| format!("Failed to get file size for {}", dump_file_path.display()))?
+            .len()
+    };
     
     if file_size > 50 * 1024 * 1024 {
         println!("📦 Large dump file detected ({} MB). This may take significant time.", file_size / 1024 / 1024);
@@ -546,7 +499,11 @@ pub async fn restore_database_from_dump(
         .arg("--if-exists")  // Use IF EXISTS for DROP statements
         .arg("--section=pre-data")  // Restore pre-data section first
         .arg("--section=data")      // Then data section
-        .arg("--section=post-data") // Finally post-data section
+        .arg("--section=post-data"); // Finally post-data section
+    if jobs > 1 {
+        command.arg(format!("--jobs={}", jobs));
+    }
+    command
         .arg("--dbname")
         .arg(target_db_url)
         .arg(dump_file_path);
@@ -590,7 +547,7 @@ pub async fn restore_database_from_dump(
             let _ = tokio::process::Command::new("pkill")
                 .arg("-9")
                 .arg("-f")
-                .arg(&format!("pg_restore.*{}", target_db_url))
+                .arg(format!("pg_restore.*{}", target_db_url))
                 .output()
                 .await;
             
@@ -618,7 +575,7 @@ pub async fn restore_database_from_dump(
             let _ = tokio::process::Command::new("pkill")
                 .arg("-9")
                 .arg("-f")
-                .arg(&format!("pg_restore.*{}", target_db_url))
+                .arg(format!("pg_restore.*{}", target_db_url))
                 .output()
                 .await;
             
@@ -647,7 +604,7 @@ pub async fn restore_database_from_dump(
             println!("   Warning: pg_restore completed with exit code 1 but no stderr/stdout captured.");
             println!("   This often happens when pg_restore encounters ignorable warnings.");
             println!("✓ Database '{}' restored successfully from dump file (warnings ignored).", 
-                target_db_url.split('/').last().unwrap_or("unknown"));
+                target_db_url.split('/').next_back().unwrap_or("unknown"));
             return Ok(()); // Return successfully since we're ignoring this warning
         } else if stderr.contains("unrecognized configuration parameter \"transaction_timeout\"") 
             || stderr.contains("errors ignored on restore: 1")
@@ -655,7 +612,7 @@ pub async fn restore_database_from_dump(
             || stdout.contains("errors ignored on restore") {
             println!("   Warning: Transaction timeout setting not supported, but restore likely completed successfully.");
             println!("✓ Database '{}' restored successfully from dump file (warnings ignored).", 
-                target_db_url.split('/').last().unwrap_or("unknown"));
+                target_db_url.split('/').next_back().unwrap_or("unknown"));
             return Ok(()); // Return successfully since we're ignoring this warning
         } else {
             // Check for common pg_restore hanging issues
@@ -665,7 +622,7 @@ pub async fn restore_database_from_dump(
                      Check if database '{}' is accessible and user has proper permissions.\n\
                      Stdout: {}\nStderr: {}",
                     dump_file_path.display(),
-                    target_db_url.split('/').last().unwrap_or("unknown"),
+                    target_db_url.split('/').next_back().unwrap_or("unknown"),
                     stdout,
                     stderr
                 ));
@@ -706,12 +663,125 @@ pub async fn restore_database_from_dump(
     Ok(())
 }
 
+/// Converts a custom or directory format dump to a SQL script with pg_restore and pipes it into psql,
+/// renaming the schemas of `schema_mapping` on the way, without writing the script to disk.
+async fn restore_dump_as_script(target_db_url: &str, dump_file_path: &Path, schema_mapping: &BTreeMap<String, String>) -> Result<()> {
+    println!("Restoring {} as a SQL script to rename schemas ({})...", dump_file_path.display(), rename::describe_schema_mapping(schema_mapping));
+    let pg_restore_path = find_pg_restore_executable()?;
+    let psql_path = find_psql_executable()?;
+    let target_db_url = target_db_url.to_string();
+    let dump_file_path = dump_file_path.to_path_buf();
+    let schema_mapping = schema_mapping.clone();
+    tokio::task::spawn_blocking(move || pipe_dump_script(&pg_restore_path, &psql_path, &target_db_url, &dump_file_path, &schema_mapping))
+        .await
+        .context("Dump script restore task failed")?
+}
+
+/// Runs `pg_restore --file=-` with its output renamed statement by statement into `psql -1`.
+///
+/// psql is killed rather than handed end of input when pg_restore or the renaming fails, so a
+/// truncated script is never committed.
+fn pipe_dump_script(
+    pg_restore_path: &Path,
+    psql_path: &Path,
+    target_db_url: &str,
+    dump_file_path: &Path,
+    schema_mapping: &BTreeMap<String, String>,
+) -> Result<()> {
+    let mut restore = std::process::Command::new(pg_restore_path)
+        .arg("--no-owner")
+        .arg("--no-acl")
+        .arg("--no-comments")
+        .arg("--file=-")
+        .arg(dump_file_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute pg_restore --file for dump file: {}", dump_file_path.display()))?;
+    let script = restore.stdout.take().context("pg_restore stdout was not captured")?;
+    let restore_stderr = read_in_background(restore.stderr.take());
+
+    let mut psql = match std::process::Command::new(psql_path)
+        .arg("-X")
+        .arg("-q")
+        .arg("-v")
+        .arg("ON_ERROR_STOP=1")
+        .arg("-1")
+        .arg("-d")
+        .arg(target_db_url)
+        .env("PGCONNECT_TIMEOUT", "30")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(psql) => psql,
+        Err(e) => {
+            restore.kill().ok();
+            restore.wait().ok();
+            return Err(e).context("Failed to spawn psql process for dump script restoration");
+        }
+    };
+    let psql_stderr = read_in_background(psql.stderr.take());
+    let mut writer = BufWriter::new(psql.stdin.take().context("psql stdin was not captured")?);
+
+    let written = write_dump_script(BufReader::new(script), &mut writer, schema_mapping);
+    let restore_status = restore
+        .wait()
+        .with_context(|| format!("Failed to wait for pg_restore --file of dump file: {}", dump_file_path.display()))?;
+    if written.is_err() || !restore_status.success() {
+        psql.kill().ok();
+    }
+    drop(writer);
+    let psql_status = psql.wait().context("Failed to wait for psql")?;
+    let restore_stderr = restore_stderr.join().unwrap_or_default();
+    let psql_stderr = psql_stderr.join().unwrap_or_default();
+
+    // A psql that exited on its own has the most specific error; one killed above has no exit code
+    if psql_status.code().is_some() && !psql_status.success() {
+        anyhow::bail!("psql for dump script exited with status: {}\nStderr: {}", psql_status, psql_stderr);
+    }
+    if !restore_status.success() {
+        anyhow::bail!(
+            "pg_restore --file for dump file {} exited with status: {}\nStderr: {}",
+            dump_file_path.display(),
+            restore_status,
+            restore_stderr
+        );
+    }
+    written.with_context(|| format!("Failed to rename references in the script of dump file: {}", dump_file_path.display()))?;
+    println!("✓ Successfully restored database from dump file: {}", dump_file_path.display());
+    Ok(())
+}
+
+/// Writes the schema creation prelude and the renamed script to psql.
+fn write_dump_script<R: BufRead, W: Write>(script: R, mut writer: W, schema_mapping: &BTreeMap<String, String>) -> Result<()> {
+    // pg_restore does not create `public`, so the schemas it is mapped to may be missing
+    for target_schema in schema_mapping.values() {
+        writeln!(writer, "CREATE SCHEMA IF NOT EXISTS {};", format_ident(target_schema))?;
+    }
+    rename::rename_references(script, &mut writer, None, schema_mapping)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Collects the output of a child's stderr on a separate thread, so a full pipe never blocks it.
+fn read_in_background(stderr: Option<ChildStderr>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut stderr) = stderr {
+            stderr.read_to_end(&mut output).ok();
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    })
+}
+
 /// Restores data for a single database from its SQL file using psql.
 pub async fn restore_database_data(
     target_db_url: &str,
     data_sql_path: &Path,
     source_db_name: Option<&str>,
     target_db_name: Option<&str>,
+    schema_mapping: &BTreeMap<String, String>,
 ) -> Result<()> {
     println!(
         "Restoring data from {} into target database (using psql)",
@@ -719,7 +789,72 @@ pub async fn restore_database_data(
     );
     
     // Execute the data restoration (constraint handling is now embedded in the SQL file)
-    execute_sql_file_with_psql(target_db_url, data_sql_path, "data", source_db_name, target_db_name).await
+    execute_sql_file_with_psql(target_db_url, data_sql_path, "data", source_db_name, target_db_name, schema_mapping).await
+}
+
+/// Loads the rows kept by `filters.where` (`<db>.rows.sql`) after the rest of the dump is restored.
+pub async fn restore_row_filtered_data(target_db_url: &str, rows_sql_path: &Path, schema_mapping: &BTreeMap<String, String>) -> Result<()> {
+    println!("Restoring filtered rows from {} into target database (using psql)", rows_sql_path.display());
+    execute_sql_file_with_psql(target_db_url, rows_sql_path, "row-filtered data", None, None, schema_mapping).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_database_renaming_in_sql_content() -> Result<()> {
+        // Create a temporary directory and SQL file
+        let temp_dir = tempdir()?;
+        let sql_file_path = temp_dir.path().join("test_schema.sql");
+        
+        // SQL content with original database name
+        let sql_content = r#"
+CREATE DATABASE hotelrule_prod;
+\c hotelrule_prod
+
+CREATE SCHEMA IF NOT EXISTS hotelrule_prod;
+CREATE TABLE hotelrule_prod.public.users (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(100)
+);
+CREATE TABLE hotelrule_prod.audit (note text DEFAULT 'copied from hotelrule_prod');
+
+ALTER TABLE hotelrule_prod.public.users OWNER TO hotelrule_prod_admin;
+"#;
+        
+        fs::write(&sql_file_path, sql_content)?;
+
+        // Rename the file the way execute_sql_file_with_psql does, streaming it into another file
+        let renamed_file_path = temp_dir.path().join("renamed_schema.sql");
+        rename::rename_references(
+            BufReader::new(fs::File::open(&sql_file_path)?),
+            BufWriter::new(fs::File::create(&renamed_file_path)?),
+            Some(("hotelrule_prod", "hotelrule_prod_dev")),
+            &BTreeMap::new(),
+        )?;
+        let modified_content = fs::read_to_string(&renamed_file_path)?;
+
+        // Debug: print the modified content to see what actually happened
+        println!("Original content:\n{}", sql_content);
+        println!("Modified content:\n{}", modified_content);
+        
+        // Verify the replacements worked
+        assert!(modified_content.contains("CREATE DATABASE hotelrule_prod_dev"));
+        assert!(modified_content.contains("\\c hotelrule_prod_dev"));
+        assert!(modified_content.contains("hotelrule_prod_dev.public.users"));
+        assert!(!modified_content.contains("CREATE DATABASE hotelrule_prod;"));
+        assert!(!modified_content.contains("\\c hotelrule_prod;"));
+        assert!(!modified_content.contains("hotelrule_prod.public.users"));
+
+        // A schema that happens to share the database name, role names and data are not references
+        assert!(modified_content.contains("CREATE SCHEMA IF NOT EXISTS hotelrule_prod;"));
+        assert!(modified_content.contains("CREATE TABLE hotelrule_prod.audit (note text DEFAULT 'copied from hotelrule_prod');"));
+        assert!(modified_content.contains("OWNER TO hotelrule_prod_admin;"));
+
+        Ok(())
+    }
 }
 
 
@@ -0,0 +1,536 @@
+// databasetool/src/restore/globals.rs
+use anyhow::{Context, Result};
+use sqlx::PgConnection;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::backup::manifest::{DatabaseAccess, DatabaseSetting};
+use crate::utils::sql_script::{self, Token, TokenKind, ident_name, is_keyword, quote_ident, quote_literal};
+
+/// What a statement of `globals.sql` does, which decides whether it runs on the target.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GlobalsStatementKind {
+    /// `SET ...` session settings of the script; the restore uses its own session.
+    Session,
+    CreateRole(String),
+    /// `ALTER ROLE`, `COMMENT ON ROLE`: only run for roles this restore created.
+    RoleDetail(String),
+    /// `GRANT role TO role`.
+    Membership,
+    CreateTablespace(String),
+    /// `ALTER TABLESPACE`, `GRANT ... ON TABLESPACE`, `COMMENT ON TABLESPACE`: only run for tablespaces this restore created.
+    TablespaceDetail(String),
+    /// Anything else, e.g. `GRANT SET ON PARAMETER`.
+    Other,
+}
+
+/// A statement of `globals.sql` with the role names already mapped for the target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalsStatement {
+    pub kind: GlobalsStatementKind,
+    pub sql: String,
+    pub roles: Vec<String>, // Role names the statement refers to, after mapping
+}
+
+/// What applying the globals changed on the target.
+#[derive(Debug, Default)]
+pub struct GlobalsReport {
+    pub created_roles: Vec<String>,
+    pub existing_roles: Vec<String>,
+    pub created_tablespaces: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl GlobalsReport {
+    pub fn render(&self) -> String {
+        let mut lines = vec![format!(
+            "Roles: {} created, {} already present{}",
+            self.created_roles.len(),
+            self.existing_roles.len(),
+            if self.created_roles.is_empty() { String::new() } else { format!(" (created: {})", self.created_roles.join(", ")) }
+        )];
+        if !self.created_tablespaces.is_empty() {
+            lines.push(format!("Tablespaces created: {}", self.created_tablespaces.join(", ")));
+        }
+        for warning in &self.warnings {
+            lines.push(format!("⚠️  {}", warning));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Splits `globals.sql` into statements the way psql does, so a quoted value may span lines
+/// and contain `;`. Comments before a statement and psql meta-commands between statements
+/// (`\restrict` in recent versions) are left out.
+pub fn split_statements(sql: &str) -> Result<Vec<String>> {
+    let mut statements = Vec::new();
+    sql_script::rewrite_script(
+        sql.as_bytes(),
+        io::sink(),
+        |statement| {
+            let start = sql_script::statement_start(statement);
+            statements.push(String::from_utf8_lossy(&statement[start..]).trim_end().to_string());
+            None
+        },
+        |_| None,
+    )?;
+    statements.retain(|statement| !statement.is_empty() && statement != ";");
+    Ok(statements)
+}
+
+/// Classifies one statement and maps every role name in it through `role_mapping`.
+///
+/// Role names are only looked for where the grammar puts them: after `ROLE`, `TO`, `FROM`,
+/// `OWNER`, `GRANTED BY`, `FOR ROLE` and `SESSION AUTHORIZATION`, and before `TO` in a role
+/// membership grant. `GRANTED BY` is dropped from memberships since the grantor may not exist
+/// on the target. The same rules map the owner and privilege statements of database dumps.
+pub fn parse_statement(sql: &str, role_mapping: &BTreeMap<String, String>) -> GlobalsStatement {
+    let bytes = sql.as_bytes();
+    let words: Vec<Token> = sql_script::tokenize(bytes).into_iter().filter(|token| token.kind != TokenKind::Space).collect();
+    let is = |n: usize, keyword: &str| words.get(n).is_some_and(|token| is_keyword(bytes, token, keyword));
+    let is_comma = |n: usize| words.get(n).is_some_and(|token| token.kind == TokenKind::Punct && bytes[token.range.start] == b',');
+    let ident = |n: usize| words.get(n).and_then(|token| ident_name(bytes, token));
+    let has_on = (0..words.len()).any(|n| is(n, "ON"));
+    let is_membership = (is(0, "GRANT") || is(0, "REVOKE")) && !has_on;
+
+    let (kind, name_at) = if is(0, "SET") {
+        (GlobalsStatementKind::Session, None)
+    } else if is(0, "CREATE") && is(1, "ROLE") {
+        (GlobalsStatementKind::CreateRole(String::new()), Some(2))
+    } else if is(0, "ALTER") && is(1, "ROLE") {
+        (GlobalsStatementKind::RoleDetail(String::new()), Some(2))
+    } else if is(0, "COMMENT") && is(1, "ON") && is(2, "ROLE") {
+        (GlobalsStatementKind::RoleDetail(String::new()), Some(3))
+    } else if is_membership {
+        (GlobalsStatementKind::Membership, None)
+    } else if is(0, "CREATE") && is(1, "TABLESPACE") {
+        (GlobalsStatementKind::CreateTablespace(String::new()), Some(2))
+    } else if is(0, "ALTER") && is(1, "TABLESPACE") {
+        (GlobalsStatementKind::TablespaceDetail(String::new()), Some(2))
+    } else if is(0, "COMMENT") && is(1, "ON") && is(2, "TABLESPACE") {
+        (GlobalsStatementKind::TablespaceDetail(String::new()), Some(3))
+    } else if let Some(on) = (0..words.len()).position(|n| is(n, "ON"))
+        && (is(0, "GRANT") || is(0, "REVOKE"))
+        && is(on + 1, "TABLESPACE")
+    {
+        (GlobalsStatementKind::TablespaceDetail(String::new()), Some(on + 2))
+    } else {
+        (GlobalsStatementKind::Other, None)
+    };
+
+    // Positions (in `words`) of role names, then the position of GRANTED BY to drop.
+    let is_role_statement = matches!(kind, GlobalsStatementKind::CreateRole(_) | GlobalsStatementKind::RoleDetail(_));
+    let mut role_positions = Vec::new();
+    let mut expect_role = false;
+    let mut granted_by = None;
+    for n in 0..words.len() {
+        if is_membership && n == 0 {
+            expect_role = true; // The granted roles follow GRANT or REVOKE
+            continue;
+        }
+        if expect_role {
+            if ident(n).is_some() && !is(n, "PUBLIC") {
+                role_positions.push(n);
+            }
+            // A list of roles continues over the comma
+            expect_role = is_comma(n) || is_comma(n + 1);
+            continue;
+        }
+        if is_comma(n) {
+            continue;
+        }
+        if is_role_statement && n == name_at.unwrap_or(0) {
+            role_positions.push(n);
+        } else if is_role_statement && is(n, "SET") {
+            break; // The rest of ALTER ROLE ... SET is a setting value
+        } else if is(n, "OWNER") && is(n + 1, "TO") {
+            // ALTER TABLESPACE ... OWNER TO: the name follows TO
+        } else if is(n, "TO") || is(n, "FROM") || is(n, "OWNER") {
+            expect_role = true;
+        } else if is(n, "GRANTED") && is(n + 1, "BY") {
+            if is_membership {
+                granted_by = Some(n);
+            }
+        } else if is(n, "BY") && n > 0 && is(n - 1, "GRANTED") {
+            expect_role = true;
+        } else if (is(n, "ROLE") || is(n, "USER")) && n > 0 && is(n - 1, "FOR") {
+            expect_role = true; // ALTER DEFAULT PRIVILEGES FOR ROLE
+        } else if is(n, "AUTHORIZATION") && n > 0 && is(n - 1, "SESSION") {
+            expect_role = true;
+        }
+    }
+    let is_grantor = |n: usize| granted_by.is_some_and(|granted| n == granted + 2);
+
+    let mapped = |n: usize| ident(n).map(|name| role_mapping.get(&name).cloned().unwrap_or(name));
+    let roles: Vec<String> = role_positions.iter().filter(|&&n| !is_grantor(n)).filter_map(|&n| mapped(n)).collect();
+    let name = name_at.and_then(mapped).unwrap_or_default();
+    let kind = match kind {
+        GlobalsStatementKind::CreateRole(_) => GlobalsStatementKind::CreateRole(name),
+        GlobalsStatementKind::RoleDetail(_) => GlobalsStatementKind::RoleDetail(name),
+        GlobalsStatementKind::CreateTablespace(_) => GlobalsStatementKind::CreateTablespace(name),
+        GlobalsStatementKind::TablespaceDetail(_) => GlobalsStatementKind::TablespaceDetail(name),
+        other => other,
+    };
+
+    let mut replacements: Vec<(Range<usize>, String)> = role_positions
+        .iter()
+        .filter(|&&n| !is_grantor(n))
+        .filter_map(|&n| ident(n).and_then(|name| role_mapping.get(&name)).map(|target| (words[n].range.clone(), quote_ident(target))))
+        .collect();
+    // Drop "GRANTED BY <role>" including the whitespace before it.
+    if let Some(n) = granted_by {
+        let start = if n > 0 { words[n - 1].range.end } else { words[n].range.start };
+        let end = words.get(n + 2).unwrap_or(&words[n + 1]).range.end;
+        replacements.push((start..end, String::new()));
+    }
+    GlobalsStatement { kind, sql: String::from_utf8_lossy(&sql_script::splice(bytes, replacements)).into_owned(), roles }
+}
+
+/// Reads `globals.sql` and maps its role names for the target.
+pub fn read_globals(path: &Path, role_mapping: &BTreeMap<String, String>) -> Result<Vec<GlobalsStatement>> {
+    let sql = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(split_statements(&sql)?.iter().map(|statement| parse_statement(statement, role_mapping)).collect())
+}
+
+/// Names of the roles on the server `conn` is connected to.
+pub async fn existing_roles(conn: &mut PgConnection) -> Result<BTreeSet<String>> {
+    let roles: Vec<String> = sqlx::query_scalar("SELECT rolname::text FROM pg_roles")
+        .fetch_all(&mut *conn)
+        .await
+        .context("Failed to list roles of the target server")?;
+    Ok(roles.into_iter().collect())
+}
+
+/// Creates the roles and tablespaces of the archive that the target lacks, with their
+/// attributes, settings and comments, and grants the role memberships.
+///
+/// Roles and tablespaces that already exist are left exactly as they are. A statement that
+/// fails (no `CREATEROLE`, a missing tablespace directory, ...) is reported and skipped.
+pub async fn apply_globals(conn: &mut PgConnection, statements: &[GlobalsStatement]) -> Result<GlobalsReport> {
+    let mut roles = existing_roles(conn).await?;
+    let tablespaces: Vec<String> = sqlx::query_scalar("SELECT spcname::text FROM pg_tablespace")
+        .fetch_all(&mut *conn)
+        .await
+        .context("Failed to list tablespaces of the target server")?;
+    let mut tablespaces: BTreeSet<String> = tablespaces.into_iter().collect();
+
+    let mut report = GlobalsReport::default();
+    for statement in statements {
+        let run = match &statement.kind {
+            GlobalsStatementKind::Session => false,
+            GlobalsStatementKind::CreateRole(role) => {
+                if roles.contains(role) {
+                    report.existing_roles.push(role.clone());
+                }
+                !roles.contains(role)
+            }
+            GlobalsStatementKind::RoleDetail(role) => report.created_roles.contains(role),
+            GlobalsStatementKind::CreateTablespace(name) => !tablespaces.contains(name),
+            GlobalsStatementKind::TablespaceDetail(name) => report.created_tablespaces.contains(name),
+            GlobalsStatementKind::Membership | GlobalsStatementKind::Other => true,
+        };
+        if !run {
+            continue;
+        }
+        match sqlx::query(&statement.sql).execute(&mut *conn).await {
+            Ok(_) => match &statement.kind {
+                GlobalsStatementKind::CreateRole(role) => {
+                    roles.insert(role.clone());
+                    report.created_roles.push(role.clone());
+                }
+                GlobalsStatementKind::CreateTablespace(name) => {
+                    tablespaces.insert(name.clone());
+                    report.created_tablespaces.push(name.clone());
+                }
+                _ => {}
+            },
+            Err(e) => report.warnings.push(format!("{} failed: {}", statement.sql, e)),
+        }
+    }
+    Ok(report)
+}
+
+/// Statements giving `target_db` the recorded owner, privileges and settings, with role
+/// names mapped. Items naming a role the target does not have are returned as warnings instead.
+pub fn database_access_statements(
+    target_db: &str,
+    access: &DatabaseAccess,
+    role_mapping: &BTreeMap<String, String>,
+    roles: &BTreeSet<String>,
+) -> (Vec<String>, Vec<String>) {
+    let map = |role: &str| role_mapping.get(role).cloned().unwrap_or_else(|| role.to_string());
+    let database = quote_ident(target_db);
+    let mut statements = Vec::new();
+    let mut warnings = Vec::new();
+
+    let owner = map(&access.owner);
+    if roles.contains(&owner) {
+        statements.push(format!("ALTER DATABASE {} OWNER TO {}", database, quote_ident(&owner)));
+    } else {
+        warnings.push(format!("owner {} of {} does not exist on the target; the owner is left unchanged", owner, target_db));
+    }
+
+    if let Some(acl) = &access.acl {
+        // The default privileges let PUBLIC connect; an explicit ACL replaces them.
+        statements.push(format!("REVOKE ALL ON DATABASE {} FROM PUBLIC", database));
+        for item in acl {
+            let Some(AclItem { grantee, privileges }) = parse_acl_item(item) else {
+                warnings.push(format!("privilege {} of {} could not be read and was skipped", item, target_db));
+                continue;
+            };
+            let grantee = match grantee {
+                Some(role) => {
+                    let role = map(&role);
+                    if !roles.contains(&role) {
+                        warnings.push(format!("privileges of {} on {} were skipped: the role does not exist on the target", role, target_db));
+                        continue;
+                    }
+                    quote_ident(&role)
+                }
+                None => "PUBLIC".to_string(),
+            };
+            for with_grant_option in [false, true] {
+                let names: Vec<&str> =
+                    privileges.iter().filter(|(_, grant_option)| *grant_option == with_grant_option).map(|(name, _)| *name).collect();
+                if !names.is_empty() {
+                    statements.push(format!(
+                        "GRANT {} ON DATABASE {} TO {}{}",
+                        names.join(", "),
+                        database,
+                        grantee,
+                        if with_grant_option { " WITH GRANT OPTION" } else { "" }
+                    ));
+                }
+            }
+        }
+    }
+
+    for DatabaseSetting { role, setting } in &access.settings {
+        let Some(assignment) = setting_assignment(setting) else {
+            warnings.push(format!("setting {} of {} could not be read and was skipped", setting, target_db));
+            continue;
+        };
+        match role {
+            None => statements.push(format!("ALTER DATABASE {} SET {}", database, assignment)),
+            Some(role) => {
+                let role = map(role);
+                if roles.contains(&role) {
+                    statements.push(format!("ALTER ROLE {} IN DATABASE {} SET {}", quote_ident(&role), database, assignment));
+                } else {
+                    warnings.push(format!("setting {} of role {} in {} was skipped: the role does not exist on the target", setting, role, target_db));
+                }
+            }
+        }
+    }
+    (statements, warnings)
+}
+
+/// Gives a restored database the owner, privileges and settings recorded in the manifest.
+///
+/// # Returns
+/// Warnings about what could not be applied, e.g. grants to roles missing on the target.
+pub async fn apply_database_access(
+    conn: &mut PgConnection,
+    target_db: &str,
+    access: &DatabaseAccess,
+    role_mapping: &BTreeMap<String, String>,
+) -> Result<Vec<String>> {
+    let roles = existing_roles(conn).await?;
+    let (statements, mut warnings) = database_access_statements(target_db, access, role_mapping, &roles);
+    for statement in statements {
+        if let Err(e) = sqlx::query(&statement).execute(&mut *conn).await {
+            warnings.push(format!("{} failed: {}", statement, e));
+        }
+    }
+    Ok(warnings)
+}
+
+/// A database aclitem: the grantee (`None` for PUBLIC) and the privileges it holds, each
+/// with whether it carries the grant option.
+struct AclItem {
+    grantee: Option<String>,
+    privileges: Vec<(&'static str, bool)>,
+}
+
+/// Parses an aclitem such as `app_ro=c*/postgres`.
+fn parse_acl_item(item: &str) -> Option<AclItem> {
+    let (grantee, rest) = parse_acl_name(item)?;
+    let rest = rest.strip_prefix('=')?;
+    let privileges_text = rest.split('/').next()?;
+    let mut privileges = Vec::new();
+    let mut chars = privileges_text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let name = match c {
+            'C' => "CREATE",
+            'T' => "TEMPORARY",
+            'c' => "CONNECT",
+            _ => return None,
+        };
+        let grant_option = chars.next_if_eq(&'*').is_some();
+        privileges.push((name, grant_option));
+    }
+    Some(AclItem { grantee: (!grantee.is_empty()).then_some(grantee), privileges })
+}
+
+/// Reads a role name at the start of an aclitem, unquoting `"..."`; returns it and the rest.
+fn parse_acl_name(text: &str) -> Option<(String, &str)> {
+    let Some(quoted) = text.strip_prefix('"') else {
+        let end = text.find('=')?;
+        return Some((text[..end].to_string(), &text[end..]));
+    };
+    let mut name = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '"' {
+            if quoted[i + 1..].starts_with('"') {
+                name.push('"');
+                chars.next();
+                continue;
+            }
+            return Some((name, &quoted[i + 1..]));
+        }
+        name.push(c);
+    }
+    None
+}
+
+/// Settings whose value is a list; like pg_dump, each element is quoted on its own.
+const LIST_SETTINGS: [&str; 4] = ["search_path", "temp_tablespaces", "session_preload_libraries", "local_preload_libraries"];
+
+/// `name = 'value'` for `ALTER DATABASE ... SET` from a `name=value` setting.
+fn setting_assignment(setting: &str) -> Option<String> {
+    let (name, value) = setting.split_once('=')?;
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+        return None;
+    }
+    let value = if LIST_SETTINGS.contains(&name.to_lowercase().as_str()) {
+        value.split(',').map(|element| quote_literal(element.trim().trim_matches('"'))).collect::<Vec<_>>().join(", ")
+    } else {
+        quote_literal(value)
+    };
+    Some(format!("{} = {}", name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLOBALS: &str = r#"--
+-- PostgreSQL database cluster dump
+--
+
+\restrict a1b2c3
+
+SET default_transaction_read_only = off;
+
+--
+-- Roles
+--
+
+CREATE ROLE prod_app;
+ALTER ROLE prod_app WITH NOSUPERUSER INHERIT NOCREATEROLE NOCREATEDB LOGIN NOREPLICATION NOBYPASSRLS;
+CREATE ROLE "Report Reader";
+ALTER ROLE "Report Reader" WITH NOSUPERUSER INHERIT NOCREATEROLE NOCREATEDB NOLOGIN NOREPLICATION NOBYPASSRLS;
+COMMENT ON ROLE prod_app IS 'application; owner';
+ALTER ROLE prod_app SET search_path TO 'app', 'public';
+
+--
+-- Role memberships
+--
+
+GRANT "Report Reader" TO prod_app GRANTED BY postgres;
+
+CREATE TABLESPACE fast OWNER prod_app LOCATION '/mnt/fast';
+GRANT CREATE ON TABLESPACE fast TO "Report Reader";
+
+\unrestrict a1b2c3
+"#;
+
+    fn mapping() -> BTreeMap<String, String> {
+        BTreeMap::from([("prod_app".to_string(), "dev_app".to_string())])
+    }
+
+    #[test]
+    fn test_split_and_parse_globals_with_role_mapping() -> Result<()> {
+        let statements: Vec<GlobalsStatement> =
+            split_statements(GLOBALS)?.iter().map(|statement| parse_statement(statement, &mapping())).collect();
+        let kinds: Vec<&GlobalsStatementKind> = statements.iter().map(|statement| &statement.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                &GlobalsStatementKind::Session,
+                &GlobalsStatementKind::CreateRole("dev_app".to_string()),
+                &GlobalsStatementKind::RoleDetail("dev_app".to_string()),
+                &GlobalsStatementKind::CreateRole("Report Reader".to_string()),
+                &GlobalsStatementKind::RoleDetail("Report Reader".to_string()),
+                &GlobalsStatementKind::RoleDetail("dev_app".to_string()),
+                &GlobalsStatementKind::RoleDetail("dev_app".to_string()),
+                &GlobalsStatementKind::Membership,
+                &GlobalsStatementKind::CreateTablespace("fast".to_string()),
+                &GlobalsStatementKind::TablespaceDetail("fast".to_string()),
+            ]
+        );
+        let sql: Vec<&str> = statements.iter().map(|statement| statement.sql.as_str()).collect();
+        assert_eq!(sql[1], r#"CREATE ROLE "dev_app";"#);
+        assert_eq!(sql[3], r#"CREATE ROLE "Report Reader";"#);
+        assert_eq!(sql[5], r#"COMMENT ON ROLE "dev_app" IS 'application; owner';"#);
+        // Setting values are never taken for role names.
+        assert_eq!(sql[6], r#"ALTER ROLE "dev_app" SET search_path TO 'app', 'public';"#);
+        assert_eq!(sql[7], r#"GRANT "Report Reader" TO "dev_app";"#);
+        assert_eq!(sql[8], r#"CREATE TABLESPACE fast OWNER "dev_app" LOCATION '/mnt/fast';"#);
+        assert_eq!(sql[9], r#"GRANT CREATE ON TABLESPACE fast TO "Report Reader";"#);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_membership_with_options() {
+        let statement = parse_statement("GRANT admins TO prod_app, bob WITH INHERIT TRUE, SET FALSE GRANTED BY prod_app;", &mapping());
+        assert_eq!(statement.kind, GlobalsStatementKind::Membership);
+        assert_eq!(statement.sql, r#"GRANT admins TO "dev_app", bob WITH INHERIT TRUE, SET FALSE;"#);
+        assert_eq!(statement.roles, vec!["admins", "dev_app", "bob"]);
+        assert_eq!(parse_statement("ALTER TABLESPACE fast OWNER TO prod_app;", &mapping()).sql, r#"ALTER TABLESPACE fast OWNER TO "dev_app";"#);
+        // Comments are not taken for keywords.
+        let statement = parse_statement("GRANT /* TO ghost */ admins TO prod_app;", &mapping());
+        assert_eq!(statement.sql, r#"GRANT /* TO ghost */ admins TO "dev_app";"#);
+        assert_eq!(statement.roles, vec!["admins", "dev_app"]);
+    }
+
+    #[test]
+    fn test_database_access_statements() {
+        let access = DatabaseAccess {
+            owner: "prod_app".to_string(),
+            acl: Some(vec![
+                "=Tc/prod_app".to_string(),
+                "prod_app=CTc/prod_app".to_string(),
+                "\"Report Reader\"=c*/prod_app".to_string(),
+                "ghost=c/prod_app".to_string(),
+            ]),
+            settings: vec![
+                DatabaseSetting { role: None, setting: "search_path=app, \"$user\"".to_string() },
+                DatabaseSetting { role: Some("prod_app".to_string()), setting: "work_mem=64MB".to_string() },
+            ],
+        };
+        let roles: BTreeSet<String> = ["dev_app", "Report Reader"].iter().map(|role| role.to_string()).collect();
+        let (statements, warnings) = database_access_statements("app_dev", &access, &mapping(), &roles);
+        assert_eq!(
+            statements,
+            vec![
+                r#"ALTER DATABASE "app_dev" OWNER TO "dev_app""#,
+                r#"REVOKE ALL ON DATABASE "app_dev" FROM PUBLIC"#,
+                r#"GRANT TEMPORARY, CONNECT ON DATABASE "app_dev" TO PUBLIC"#,
+                r#"GRANT CREATE, TEMPORARY, CONNECT ON DATABASE "app_dev" TO "dev_app""#,
+                r#"GRANT CONNECT ON DATABASE "app_dev" TO "Report Reader" WITH GRANT OPTION"#,
+                r#"ALTER DATABASE "app_dev" SET search_path = 'app', '$user'"#,
+                r#"ALTER ROLE "dev_app" IN DATABASE "app_dev" SET work_mem = '64MB'"#,
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("ghost"), "{:?}", warnings);
+
+        let (_, warnings) = database_access_statements("app_dev", &access, &BTreeMap::new(), &roles);
+        assert!(warnings.iter().any(|warning| warning.contains("owner prod_app")), "{:?}", warnings);
+    }
+}
@@ -1,48 +1,67 @@
 // databasetool/src/restore/logic.rs
 use anyhow::{Context, Result};
+use sqlx::{Connection, PgConnection};
+use std::collections::BTreeSet;
 use std::fs;
 use std::path::{Path, PathBuf};
-use tempfile::TempDir;
-
 use url::Url;
 
-use crate::config::{AppConfig, RestoreConfig};
-use crate::restore::{db_restore, s3_download, verification};
+use crate::backup::{db_dump, globals as backup_globals, manifest};
+use crate::catalog;
+use crate::config::{AppConfig, DumpFormat, RestoreConfig};
+use crate::restore::plan::{self, PlannedDatabase};
+use crate::restore::ownership::{self, DumpOwnership};
+use crate::restore::{db_restore, globals, rename, s3_download, safety, verification};
 use crate::utils::setting::prepare_archive_for_restore; // Corrected import
+use crate::utils::{masking, table_filter};
 
 
-/// Orchestrates the entire database restore process.
-pub async fn perform_restore_orchestration(
-    app_config: &AppConfig,
-    restore_config: &RestoreConfig,
-) -> Result<()> {
-    println!("🔄 Starting restore orchestration...");
-    println!("Restore configuration: {:?}", restore_config);
+/// A completely downloaded archive, deleted when the restore no longer needs it.
+struct DownloadedArchive(PathBuf);
+
+impl Drop for DownloadedArchive {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
 
-    // 1. Determine archive path: Download from S3 or use local path
-    let local_archive_path: PathBuf;
-    let _s3_download_temp_dir: Option<TempDir> = None; // To hold temp dir if downloaded
+/// An archive on local disk. Archives downloaded from S3 are deleted when this is dropped.
+pub(crate) struct LocalArchive {
+    pub path: PathBuf,
+    _downloaded: Option<DownloadedArchive>,
+}
+
+/// Resolves an archive reference (local path, `s3://` URI or catalog selector) and makes the
+/// archive available on local disk, downloading it into `download_dir` when it is in S3.
+///
+/// # Returns
+/// The resolved reference (for messages) and the local archive.
+pub(crate) async fn fetch_archive(
+    app_config: &AppConfig,
+    archive_reference: &str,
+    local_backup_path: Option<&Path>,
+    download_dir: &Path,
+) -> Result<(String, LocalArchive)> {
+    let archive_source_path =
+        catalog::resolve_archive_reference(archive_reference, local_backup_path, app_config.spaces_config.as_ref())
+            .await
+            .context("Failed to resolve the archive reference")?;
 
-    if restore_config.download_from_spaces {
+    if archive_source_path.starts_with("s3://") {
         let spaces_conf = app_config.spaces_config.as_ref().context(
             "S3 download requested, but S3/Spaces configuration is missing.",
         )?;
-        let (bucket, key) = s3_download::parse_s3_uri(&restore_config.archive_source_path)
+        let (bucket, key) = s3_download::parse_s3_uri(&archive_source_path)
             .context("Failed to parse S3 URI for archive download")?;
 
-        // Create a temporary directory to download the archive
-        let temp_s3_download_dir = tempfile::Builder::new()
-            .prefix("s3_download_")
-            .tempdir()
-            .context("Failed to create temporary directory for S3 download")?;
-        
         let archive_filename = Path::new(&key)
             .file_name()
             .context("Could not determine filename from S3 key")?
             .to_string_lossy()
             .into_owned();
-            
-        let downloaded_path = temp_s3_download_dir.path().join(archive_filename);
+
+        // A stable location (instead of a fresh temp dir) lets an interrupted download resume.
+        let downloaded_path = download_dir.join(archive_filename);
 
         s3_download::download_file_from_s3(
             spaces_conf,
@@ -52,37 +71,65 @@ pub async fn perform_restore_orchestration(
         )
         .await
         .context("Failed to download archive from S3/Spaces")?;
-        
-        local_archive_path = downloaded_path;
-        // _s3_download_temp_dir = Some(temp_s3_download_dir); 
-        // Guard will clean up. We just need the path for now.
-        // Actually, we DO need to keep the guard, otherwise the archive is deleted before extraction.
-        // So, the archive will live in this temp dir, then be extracted to another temp dir.
-        // This is acceptable.
-        // To avoid local_archive_path being dropped, we can move the temp_s3_download_dir
-        // to a variable that lives through the function scope.
-        // For simplicity now, let's assume `download_file_from_s3` returns the path
-        // and we need to ensure this path stays valid.
-        // The current structure: downloaded to temp_s3_download_dir; this dir guard needs to live.
-        // We will pass local_archive_path (which is inside _s3_download_temp_dir) to extraction.
-        // Best to keep _s3_download_temp_dir itself.
-        // Let's re-think. The archive is downloaded. Then prepare_archive_for_restore will extract it.
-        // So, the _s3_download_temp_dir must live until extraction is complete.
-        // The `local_archive_path` is what we need.
-        // The _s3_download_temp_dir will be dropped at end of this function.
-        // If prepare_archive_for_restore reads from local_archive_path while _s3_download_temp_dir
-        // is still in scope, it's fine.
-    } else {
-        local_archive_path = PathBuf::from(&restore_config.archive_source_path);
-        if !local_archive_path.exists() {
-            return Err(anyhow::anyhow!("Local archive path does not exist: {}", local_archive_path.display()));
+
+        // Keep the downloaded archive until it has been extracted and restored.
+        let local_archive = LocalArchive { path: downloaded_path.clone(), _downloaded: Some(DownloadedArchive(downloaded_path)) };
+        return Ok((archive_source_path, local_archive));
+    }
+
+    let local_archive_path = PathBuf::from(&archive_source_path);
+    if !local_archive_path.exists() {
+        return Err(anyhow::anyhow!("Local archive path does not exist: {}", local_archive_path.display()));
+    }
+    Ok((archive_source_path, LocalArchive { path: local_archive_path, _downloaded: None }))
+}
+
+/// The directory holding the dumps of an extracted archive.
+///
+/// Archives that wrap everything in a single subdirectory are unwrapped. A lone `<db>.dir`
+/// next to manifest.json is a directory format dump, not a wrapper.
+pub(crate) fn archive_root(extracted_files_path: &Path) -> PathBuf {
+    if let Ok(entries) = fs::read_dir(extracted_files_path) {
+        let mut subdirs: Vec<PathBuf> = Vec::new();
+        let mut has_files = false;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                subdirs.push(path);
+            } else {
+                has_files = true;
+            }
+        }
+        if subdirs.len() == 1 && !has_files {
+            println!("Using subdirectory for extracted files: {}", subdirs[0].display());
+            return subdirs[0].clone();
         }
     }
+    extracted_files_path.to_path_buf()
+}
+
+/// Orchestrates the entire database restore process.
+pub async fn perform_restore_orchestration(
+    app_config: &AppConfig,
+    restore_config: &RestoreConfig,
+) -> Result<()> {
+    println!("🔄 Starting restore orchestration...");
+    println!("Restore configuration: {:?}", restore_config);
+
+    // 1. Determine archive path: resolve catalog selectors, then download from S3 or use local path
+    let (archive_source_path, local_archive) = fetch_archive(
+        app_config,
+        &restore_config.archive_source_path,
+        restore_config.local_backup_path.as_deref(),
+        &restore_config.download_dir,
+    )
+    .await?;
+    let local_archive_path = &local_archive.path;
     println!("Using archive for restore: {}", local_archive_path.display());
 
     // 2. Prepare working directory by extracting the archive
     // `extraction_temp_dir` guard ensures cleanup of extracted files.
-    let extraction_temp_dir = prepare_archive_for_restore(&local_archive_path)
+    let extraction_temp_dir = prepare_archive_for_restore(local_archive_path, restore_config.encryption.as_ref())
         .context("Failed to prepare archive and extract to temporary directory")?;
     let extracted_files_path = extraction_temp_dir.path();
     println!("Archive extracted to temporary directory: {}", extracted_files_path.display());
@@ -93,44 +140,59 @@ pub async fn perform_restore_orchestration(
         let entry = entry?;
         println!("  - {}", entry.path().display());
     }
-
-    // Check if there's a subdirectory and use that instead
-    let actual_extracted_path = if let Ok(entries) = fs::read_dir(extracted_files_path) {
-        let mut subdirs: Vec<PathBuf> = Vec::new();
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.is_dir() {
-                    subdirs.push(path);
-                }
-            }
-        }
-        if subdirs.len() == 1 {
-            println!("Using subdirectory for extracted files: {}", subdirs[0].display());
-            subdirs[0].clone()
-        } else {
-            extracted_files_path.to_path_buf()
+    let actual_extracted_path = archive_root(extracted_files_path);
+
+    // 3. Verify the archive against its manifest before touching the target server.
+    //    Archives from older versions have no manifest and fall back to filename discovery.
+    let backup_manifest = manifest::read_manifest(&actual_extracted_path)
+        .context("Failed to read backup manifest")?;
+    match &backup_manifest {
+        Some(m) => {
+            println!(
+                "Found backup manifest: created {} by databasetool {}, source server {}",
+                m.created_at,
+                m.tool_version,
+                m.source_server_version.as_deref().unwrap_or("unknown")
+            );
+            m.verify_checksums(&actual_extracted_path)
+                .context("Archive is corrupt or was modified; refusing to restore")?;
+            println!("✓ All file checksums match the manifest.");
         }
-    } else {
-        extracted_files_path.to_path_buf()
+        None => println!("⚠️  Archive has no {}; skipping checksum verification.", manifest::MANIFEST_FILE_NAME),
+    }
+
+    let dump_format = match &backup_manifest {
+        Some(m) => Some(m.dump_format().context("Backup manifest has an unknown dump_format")?),
+        None => None,
     };
 
-    // 3. Determine which databases to restore
+    // 4. Determine which databases to restore
     //    If `restore_config.databases_to_restore` is Some, use that mapping.
-    //    If None, discover databases from the extracted files and map them to themselves.
+    //    If None, take the databases from the manifest (or discover them from file names) and map them to themselves.
+    let databases_in_archive = || -> Result<Vec<String>> {
+        match &backup_manifest {
+            Some(m) => Ok(m.database_names()),
+            None => discover_databases_from_archive(&actual_extracted_path),
+        }
+    };
     let databases_to_process: std::collections::HashMap<String, String>;
     if let Some(dbs_from_config) = &restore_config.databases_to_restore {
         if dbs_from_config.is_empty() {
-             println!("DATABASE_LIST is empty in config. Attempting to discover databases from archive.");
-             let discovered_dbs = discover_databases_from_archive(&actual_extracted_path)?;
-             databases_to_process = discovered_dbs.into_iter().map(|db| (db.clone(), db)).collect();
+             println!("DATABASE_LIST is empty in config. Using the databases contained in the archive.");
+             databases_to_process = databases_in_archive()?.into_iter().map(|db| (db.clone(), db)).collect();
         } else {
+            if let Some(m) = &backup_manifest {
+                let available = m.database_names();
+                let missing: Vec<&String> = dbs_from_config.keys().filter(|db| !available.contains(db)).collect();
+                if !missing.is_empty() {
+                    anyhow::bail!("Database(s) {:?} requested for restore are not in the archive. Archive contains: {:?}", missing, available);
+                }
+            }
             databases_to_process = dbs_from_config.clone();
         }
     } else {
-        println!("No DATABASE_LIST in config. Attempting to discover databases from archive.");
-        let discovered_dbs = discover_databases_from_archive(&actual_extracted_path)?;
-        databases_to_process = discovered_dbs.into_iter().map(|db| (db.clone(), db)).collect();
+        println!("No DATABASE_LIST in config. Using the databases contained in the archive.");
+        databases_to_process = databases_in_archive()?.into_iter().map(|db| (db.clone(), db)).collect();
     }
 
     if databases_to_process.is_empty() {
@@ -138,8 +200,42 @@ pub async fn perform_restore_orchestration(
     }
     println!("Databases to be restored (source -> target): {:?}", databases_to_process);
 
+    // With preserve_ownership, read the owners and grants of every dump first so that roles
+    // missing on the target are reported before anything is changed.
+    let preserved_ownership = if restore_config.preserve_ownership {
+        Some(read_preserved_ownership(restore_config, &actual_extracted_path, &databases_to_process, dump_format, backup_manifest.as_ref()).await?)
+    } else {
+        None
+    };
 
-    // 4. For each database mapping:
+    // Inspect every target up front: --plan prints the result, a real run asks for confirmation
+    // before the first database is touched.
+    let planned = plan_restore(restore_config, &actual_extracted_path, &databases_to_process, dump_format, preserved_ownership.as_ref()).await?;
+    if restore_config.plan_only {
+        let mut guarded = planned;
+        for db in guarded.iter_mut() {
+            db.action = safety::guard_action(&restore_config.safety, &restore_config.target_db_url, &db.target_name, db.action)?;
+        }
+        println!("{}", plan::render_plan("Restore plan", &archive_source_path, &guarded));
+        return Ok(());
+    }
+    if let Some(preserved) = &preserved_ownership
+        && !preserved.missing_roles.is_empty()
+    {
+        anyhow::bail!(
+            "preserve_ownership is set, but the owners and grants in the archive refer to roles the target server does not have: {}. \
+             Create them, map them to existing roles with role_mapping, or create them from the archive with --globals.",
+            preserved.missing_roles.iter().cloned().collect::<Vec<_>>().join(", ")
+        );
+    }
+    safety::confirm_destructive_actions(&restore_config.safety, &restore_config.target_db_url, &planned)?;
+
+    // 4b. Roles first: the databases are given their owners and grants after they are restored.
+    if restore_config.restore_globals {
+        restore_cluster_globals(restore_config, &actual_extracted_path).await?;
+    }
+
+    // 5. For each database mapping:
     for (db_name_from_archive, target_db_name) in &databases_to_process {
         println!("\nProcessing restore for database from archive: {} -> {}", db_name_from_archive, target_db_name);
 
@@ -163,7 +259,8 @@ pub async fn perform_restore_orchestration(
 
         // Manage the target database (drop/create if configured)
         // This function uses the target database name from the mapping to manage the DB on the server.
-        let _db_was_created_or_modified = db_restore::manage_target_database(restore_config, target_db_name)
+        let database_owner = preserved_ownership.as_ref().and_then(|preserved| preserved.database_owners.get(db_name_from_archive));
+        let db_was_created = db_restore::manage_target_database(restore_config, target_db_name, database_owner.map(String::as_str))
             .await
             .with_context(|| format!("Failed to manage target database: {}", target_db_name))?;
 
@@ -195,144 +292,162 @@ pub async fn perform_restore_orchestration(
             .with_context(|| format!("Target database '{}' exists but is not responsive", target_db_name))?;
         println!("✓ Target database '{}' is accessible", target_db_name);
 
-        // Find schema and data files for `db_name_from_archive`
-        // The archive files are named like `dbname_YYYY-MM-DD_HH_MM_SS_schema.sql` or `dbname_schema.sql`
-        // Or for dump files: `dbname_YYYY-MM-DD_HH_MM_SS.dump`
-        // We need to find the correct schema/data files within `actual_extracted_path`
-        // that correspond to `db_name_from_archive`.
-        // The `db_dump` module created files like `DBNAME_schema.sql` and `DBNAME_data.sql`.
-    
-        let schema_file_name = format!("{}_schema.sql", db_name_from_archive);
-        let schema_file_path = actual_extracted_path.join(&schema_file_name);
-
-        let data_file_name = format!("{}_data.sql", db_name_from_archive);
-        let data_file_path = actual_extracted_path.join(&data_file_name);
-    
-        // Check for dump file (alternative format)
-        let mut dump_file_path = None;
-        for entry in fs::read_dir(&actual_extracted_path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() {
-                if let Some(file_name_os) = path.file_name() {
-                    let file_name = file_name_os.to_string_lossy();
-                    // Check if this is a dump file for our database
-                    if file_name.starts_with(&format!("{}_", db_name_from_archive)) && file_name.ends_with(".dump") {
-                        dump_file_path = Some(path);
-                        break;
-                    }
+        // Locate the dump of `db_name_from_archive`: plain `<db>_schema.sql`/`<db>_data.sql`,
+        // or a custom/directory format archive restored with pg_restore.
+        let archived_dump = locate_database_dump(&actual_extracted_path, db_name_from_archive, dump_format)?;
+        let expected = backup_manifest
+            .as_ref()
+            .and_then(|m| m.databases.iter().find(|db| db.name == *db_name_from_archive));
+        // Filters on a database that existed before the restore only touch the tables the archive brought in
+        let filter_scope = if restore_config.filters.is_empty() || db_was_created {
+            None
+        } else {
+            let expected = expected.with_context(|| {
+                format!(
+                    "Cannot apply filters to the existing database '{}': the archive has no manifest listing the tables it restores. \
+                     Restore into a new database or set drop_target_database_if_exists.",
+                    target_db_name
+                )
+            })?;
+            Some(expected.row_counts.keys().cloned().collect::<BTreeSet<String>>())
+        };
+
+        match &archived_dump {
+            ArchivedDump::PgRestore(dump_path) => {
+                println!("Found pg_restore archive for database '{}': {}", db_name_from_archive, dump_path.display());
+                println!(
+                    "Restoring database '{}' from {} using pg_restore with {} job(s)...",
+                    db_name_from_archive,
+                    dump_path.display(),
+                    restore_config.jobs
+                );
+                db_restore::restore_database_from_dump(&actual_target_db_conn_url_str, dump_path, Some(db_name_from_archive), Some(target_db_name), restore_config.jobs, &restore_config.schema_mapping)
+                    .await
+                    .with_context(|| format!("Failed to restore database '{}' from dump {}", db_name_from_archive, dump_path.display()))?;
+                println!("✓ Database '{}' restored successfully from dump.", db_name_from_archive);
+
+                // pg_restore ran with --no-owner --no-acl; the mapped owners and grants follow.
+                if let Some(dump_ownership) = preserved_ownership.as_ref().and_then(|preserved| preserved.dumps.get(db_name_from_archive)) {
+                    let statements: Vec<String> = dump_ownership
+                        .statements
+                        .iter()
+                        .map(|statement| rename::rename_schemas(statement, &restore_config.schema_mapping))
+                        .collect();
+                    let mut conn = target_db_pool.acquire().await.context("Failed to get a connection for owners and privileges")?;
+                    ownership::apply_ownership(&mut conn, &statements)
+                        .await
+                        .with_context(|| format!("Failed to apply the owners and privileges of database \'{}\'", target_db_name))?;
+                    println!("✓ {} owner and privilege statement(s) applied to {}.", dump_ownership.statements.len(), target_db_name);
                 }
             }
-        }
-
-        // Also check in subdirectories
-        if dump_file_path.is_none() {
-            if let Ok(entries) = fs::read_dir(&actual_extracted_path) {
-                for entry in entries {
-                    if let Ok(entry) = entry {
-                        let path = entry.path();
-                        if path.is_dir() {
-                            if let Ok(sub_entries) = fs::read_dir(&path) {
-                                for sub_entry in sub_entries {
-                                    if let Ok(sub_entry) = sub_entry {
-                                        let sub_path = sub_entry.path();
-                                        if sub_path.is_file() {
-                                            if let Some(file_name_os) = sub_path.file_name() {
-                                                let file_name = file_name_os.to_string_lossy();
-                                                // Check if this is a dump file for our database
-                                                if file_name.starts_with(&format!("{}_", db_name_from_archive)) && file_name.ends_with(".dump") {
-                                                    dump_file_path = Some(sub_path);
-                                                    break;
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                if dump_file_path.is_some() {
-                                    break;
-                                }
+            ArchivedDump::Plain { schema_file_path, data_file_path } => {
+                // For data restoration, perform additional connection stress test
+                if data_file_path.is_some() {
+                    println!("Performing connection stress test before large data restore...");
+                    for i in 1..=3 {
+                        match sqlx::query(&format!("SELECT {} as test_value", i))
+                            .fetch_one(&target_db_pool)
+                            .await
+                        {
+                            Ok(_) => println!("✓ Connection test {} successful", i),
+                            Err(e) => {
+                                println!("⚠️  Connection test {} failed: {}", i, e);
+                                println!("   This may indicate database performance issues that could cause large data restore to hang");
                             }
                         }
-                    }
-                    if dump_file_path.is_some() {
-                        break;
+                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                     }
                 }
-            }
-        }
 
-        // For data restoration, perform additional connection stress test
-        if data_file_path.exists() {
-            println!("Performing connection stress test before large data restore...");
-            for i in 1..=3 {
-                match sqlx::query(&format!("SELECT {} as test_value", i))
-                    .fetch_one(&target_db_pool)
+                // 5a. Restore schema, with the owner and privilege statements mapped or left out
+                println!("Restoring schema for {} from {}...", db_name_from_archive, schema_file_path.display());
+                let schema_script = ownership::rewrite_schema_script(schema_file_path, restore_config.preserve_ownership, &restore_config.role_mapping)
+                    .with_context(|| format!("Failed to prepare schema file {}", schema_file_path.display()))?;
+                db_restore::restore_database_schema(&actual_target_db_conn_url_str, &schema_script, Some(db_name_from_archive), Some(target_db_name), &restore_config.schema_mapping)
                     .await
-                {
-                    Ok(_) => println!("✓ Connection test {} successful", i),
-                    Err(e) => {
-                        println!("⚠️  Connection test {} failed: {}", i, e);
-                        println!("   This may indicate database performance issues that could cause large data restore to hang");
-                    }
+                    .with_context(|| format!("Failed to restore schema for database \'{}\' from file {}", db_name_from_archive, schema_file_path.display()))?;
+                println!("✓ Schema restoration completed for {}.", db_name_from_archive);
+
+                // 5b. Restore data (if data file exists)
+                if let Some(data_file_path) = data_file_path {
+                    println!("Restoring data for {} from {}...", db_name_from_archive, data_file_path.display());
+                    db_restore::restore_database_data(&actual_target_db_conn_url_str, data_file_path, Some(db_name_from_archive), Some(target_db_name), &restore_config.schema_mapping)
+                        .await
+                        .with_context(|| format!("Failed to restore data for database \'{}\' from file {}", db_name_from_archive, data_file_path.display()))?;
+                    println!("✓ Data restoration completed for {}.", db_name_from_archive);
+
+                    // 5c. Reset sequences immediately after data restore to prevent key conflicts
+                    println!("Resetting sequences for database {} after data restore...", target_db_name);
+                    let schemas = verification::restored_schemas(&restore_config.schema_mapping, expected);
+                    crate::utils::sequence_reset::reset_sequences_with_timeout(&target_db_pool, target_db_name, schemas.as_ref())
+                        .await
+                        .with_context(|| format!("Failed to reset sequences for database \'{}\'", target_db_name))?;
+                    println!("✓ Sequences reset completed for {}.", target_db_name);
+                } else {
+                    println!("Skipping data restoration for {} as data file was not found.", db_name_from_archive);
                 }
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
             }
         }
 
-        // Check if we have dump file instead of separate schema/data files
-        if let Some(dump_path) = &dump_file_path {
-            println!("Found dump file for database '{}': {}", db_name_from_archive, dump_path.display());
-            // Use dump file restoration
-            println!("Restoring database '{}' from dump file {}...", db_name_from_archive, dump_path.display());
-            db_restore::restore_database_from_dump(&actual_target_db_conn_url_str, dump_path, Some(db_name_from_archive), Some(target_db_name))
+        // 5d. Rows of tables limited by filters.where at backup time
+        let rows_file_path = actual_extracted_path.join(db_dump::row_filter_entry_name(db_name_from_archive));
+        if rows_file_path.is_file() {
+            db_restore::restore_row_filtered_data(&actual_target_db_conn_url_str, &rows_file_path, &restore_config.schema_mapping)
                 .await
-                .with_context(|| format!("Failed to restore database '{}' from dump file {}", db_name_from_archive, dump_path.display()))?;
-            println!("✓ Database '{}' restored successfully from dump file.", db_name_from_archive);
-        } else {
-            // Use separate schema/data files
-            if !schema_file_path.exists() {
-                return Err(anyhow::anyhow!(
-                    "Schema file not found for database '{}' in extracted archive: {}. Expected pattern: {}_schema.sql",
-                    db_name_from_archive, schema_file_path.display(), db_name_from_archive
-                ));
+                .with_context(|| format!("Failed to restore filtered rows for database \'{}\' from file {}", db_name_from_archive, rows_file_path.display()))?;
+            println!("✓ Filtered rows restored for {}.", db_name_from_archive);
+        }
+
+        // 5e. Drop, empty and trim what the restore filters leave out
+        if !restore_config.filters.is_empty() {
+            println!("Applying filters to {} ({})...", target_db_name, restore_config.filters.describe());
+            let mut conn = target_db_pool.acquire().await.context("Failed to get a connection for filtering")?;
+            let mut selection = table_filter::resolve_filters(&mut conn, &restore_config.filters)
+                .await
+                .with_context(|| format!("Failed to apply filters to database \'{}\'", target_db_name))?;
+            if let Some(restored) = &filter_scope {
+                selection = selection.restricted_to(restored);
             }
-             if !data_file_path.exists() {
-                 // Data file might be optional for some backup types (e.g. schema-only)
-                 // However, our current backup process creates both.
-                 println!(
-                     "Warning: Data file not found for database '{}' in extracted archive: {}. Expected pattern: {}_data.sql. Proceeding with schema restore only.",
-                     db_name_from_archive, data_file_path.display(), db_name_from_archive
-                 );
-             }
-
-            // 4a. Restore schema
-            println!("Restoring schema for {} from {}...", db_name_from_archive, schema_file_path.display());
-            db_restore::restore_database_schema(&actual_target_db_conn_url_str, &schema_file_path, Some(db_name_from_archive), Some(target_db_name))
+            table_filter::prune_database(&mut conn, &selection)
                 .await
-                .with_context(|| format!("Failed to restore schema for database \'{}\' from file {}", db_name_from_archive, schema_file_path.display()))?;
-            println!("✓ Schema restoration completed for {}.", db_name_from_archive);
+                .with_context(|| format!("Failed to apply filters to database \'{}\'", target_db_name))?;
+            println!("✓ Filters applied to {}.", target_db_name);
+        }
 
-            // 4b. Restore data (if data file exists)
-            if data_file_path.exists() {
-                println!("Restoring data for {} from {}...", db_name_from_archive, data_file_path.display());
-                db_restore::restore_database_data(&actual_target_db_conn_url_str, &data_file_path, Some(db_name_from_archive), Some(target_db_name))
-                    .await
-                    .with_context(|| format!("Failed to restore data for database \'{}\' from file {}", db_name_from_archive, data_file_path.display()))?;
-                println!("✓ Data restoration completed for {}.", db_name_from_archive);
-                
-                // 4c. Reset sequences immediately after data restore to prevent key conflicts
-                println!("Resetting sequences for database {} after data restore...", target_db_name);
-                crate::utils::sequence_reset::reset_sequences_with_timeout(&target_db_pool, target_db_name)
-                    .await
-                    .with_context(|| format!("Failed to reset sequences for database \'{}\'", target_db_name))?;
-                println!("✓ Sequences reset completed for {}.", target_db_name);
-            } else {
-                 println!("Skipping data restoration for {} as data file was not found.", db_name_from_archive);
+        // 5f. Mask personal data before anyone uses the restored database
+        let mut masked_tables = Vec::new();
+        if let Some(masking_config) = &restore_config.masking {
+            println!("Masking {} ({})...", target_db_name, masking_config.describe());
+            let mut conn = target_db_pool.acquire().await.context("Failed to get a connection for masking")?;
+            let masking_plan = masking::resolve_masking(&mut conn, masking_config)
+                .await
+                .with_context(|| format!("Failed to mask database \'{}\'; its restored data is NOT masked", target_db_name))?;
+            let masked_rows = masking::mask_database(&mut conn, &masking_plan)
+                .await
+                .with_context(|| format!("Failed to mask database \'{}\'; its restored data is NOT masked", target_db_name))?;
+            println!("{}", masking_plan.render_report(target_db_name, &masked_rows));
+            masked_tables = masking_plan.tables.iter().map(|masked| masked.table.clone()).collect();
+        }
+
+        // 5g. Original owner, privileges and settings of the database itself
+        if restore_config.restore_globals {
+            match expected.and_then(|db| db.access.as_ref()) {
+                Some(access) => {
+                    let mut conn = target_db_pool.acquire().await.context("Failed to get a connection for database privileges")?;
+                    let warnings = globals::apply_database_access(&mut conn, target_db_name, access, &restore_config.role_mapping)
+                        .await
+                        .with_context(|| format!("Failed to apply the owner and privileges of database \'{}\'", target_db_name))?;
+                    for warning in &warnings {
+                        println!("⚠️  {}", warning);
+                    }
+                    println!("✓ Owner, privileges and settings applied to {}.", target_db_name);
+                }
+                None => println!("⚠️  The archive records no owner or privileges for {}; they are left as restored.", db_name_from_archive),
             }
         }
 
-        // 4d. Verify restore for this database (this will also do a final sequence check)
-        verification::verify_restore(&target_db_pool, restore_config, target_db_name, &actual_extracted_path)
+        // 5h. Compare with the counts and checksums in the manifest (this will also do a final sequence check)
+        verification::verify_restore(&target_db_pool, restore_config, target_db_name, expected, &masked_tables)
             .await
             .with_context(|| format!("Failed to verify_restore for database \'{}\'", target_db_name))?;
         
@@ -340,66 +455,357 @@ pub async fn perform_restore_orchestration(
         target_db_pool.close().await;
     }
 
-    // 5. Cleanup: extraction_temp_dir and _s3_download_temp_dir (if any) will be cleaned up when they go out of scope.
+    // 6. Cleanup: extraction_temp_dir and _downloaded_archive (if any) will be cleaned up when they go out of scope.
     println!("✓ Restore orchestration completed.");
     Ok(())
 }
 
+/// Creates the roles of the archive's `globals.sql` that the target server lacks and grants
+/// their memberships. Nothing is changed for roles that already exist.
+async fn restore_cluster_globals(restore_config: &RestoreConfig, extracted_path: &Path) -> Result<()> {
+    let globals_path = extracted_path.join(backup_globals::GLOBALS_FILE_NAME);
+    if !globals_path.is_file() {
+        println!("⚠️  Archive has no {}; no roles are created.", backup_globals::GLOBALS_FILE_NAME);
+        return Ok(());
+    }
+    println!("Creating missing roles from {}...", backup_globals::GLOBALS_FILE_NAME);
+    let statements = globals::read_globals(&globals_path, &restore_config.role_mapping)?;
+    let mut admin_url = Url::parse(&restore_config.target_db_url).context("Invalid target_database_url")?;
+    admin_url.set_path("/postgres");
+    let mut conn = PgConnection::connect(admin_url.as_str())
+        .await
+        .context("Failed to connect to 'postgres' database on the target server to create roles")?;
+    let report = globals::apply_globals(&mut conn, &statements).await?;
+    conn.close().await.ok();
+    println!("{}", report.render());
+    println!("✓ Cluster globals applied.");
+    Ok(())
+}
+
+/// The owners and grants a `preserve_ownership` restore gives the databases, by archive database name.
+struct PreservedOwnership {
+    dumps: std::collections::BTreeMap<String, DumpOwnership>,
+    database_owners: std::collections::BTreeMap<String, String>, // Recorded in the manifest, mapped
+    missing_roles: std::collections::BTreeSet<String>, // Named by the dumps or owners, absent on the target
+}
+
+/// Reads the owner and privilege statements of every database to restore and checks that the
+/// target server has the roles they name after `role_mapping`. With `--globals`, the roles
+/// the restore creates from `globals.sql` count as present.
+async fn read_preserved_ownership(
+    restore_config: &RestoreConfig,
+    extracted_path: &Path,
+    databases_to_process: &std::collections::HashMap<String, String>,
+    dump_format: Option<DumpFormat>,
+    backup_manifest: Option<&manifest::BackupManifest>,
+) -> Result<PreservedOwnership> {
+    let mut preserved = PreservedOwnership {
+        dumps: Default::default(),
+        database_owners: Default::default(),
+        missing_roles: Default::default(),
+    };
+    let mut required = std::collections::BTreeSet::new();
+    for db_name_from_archive in databases_to_process.keys() {
+        let dump = locate_database_dump(extracted_path, db_name_from_archive, dump_format)?;
+        let dump_ownership = ownership::dump_ownership(&dump, &restore_config.role_mapping)
+            .await
+            .with_context(|| format!("Failed to read the owners and privileges of database '{}'", db_name_from_archive))?;
+        required.extend(dump_ownership.roles.iter().cloned());
+        preserved.dumps.insert(db_name_from_archive.clone(), dump_ownership);
+
+        let access = backup_manifest
+            .and_then(|m| m.databases.iter().find(|db| db.name == *db_name_from_archive))
+            .and_then(|db| db.access.as_ref());
+        if let Some(access) = access {
+            let owner = restore_config.role_mapping.get(&access.owner).unwrap_or(&access.owner).clone();
+            required.insert(owner.clone());
+            preserved.database_owners.insert(db_name_from_archive.clone(), owner);
+        }
+    }
+
+    let mut admin_url = Url::parse(&restore_config.target_db_url).context("Invalid target_database_url")?;
+    admin_url.set_path("/postgres");
+    let mut conn = PgConnection::connect(admin_url.as_str())
+        .await
+        .context("Failed to connect to 'postgres' database on the target server to check roles")?;
+    let mut available = globals::existing_roles(&mut conn).await?;
+    conn.close().await.ok();
+    let globals_path = extracted_path.join(backup_globals::GLOBALS_FILE_NAME);
+    if restore_config.restore_globals && globals_path.is_file() {
+        for statement in globals::read_globals(&globals_path, &restore_config.role_mapping)? {
+            if let globals::GlobalsStatementKind::CreateRole(role) = statement.kind {
+                available.insert(role);
+            }
+        }
+    }
+    preserved.missing_roles = required.difference(&available).cloned().collect();
+    Ok(preserved)
+}
+
+/// Works out what the restore will do to every target database, reading but not changing the target server.
+///
+/// Exact row counts are only taken for `--plan`; a real run uses planner estimates.
+async fn plan_restore(
+    restore_config: &RestoreConfig,
+    extracted_path: &Path,
+    databases_to_process: &std::collections::HashMap<String, String>,
+    dump_format: Option<DumpFormat>,
+    preserved_ownership: Option<&PreservedOwnership>,
+) -> Result<Vec<PlannedDatabase>> {
+    let mut mappings: Vec<(&String, &String)> = databases_to_process.iter().collect();
+    mappings.sort_by(|a, b| a.1.cmp(b.1));
+
+    let mut planned = Vec::with_capacity(mappings.len());
+    for (db_name_from_archive, target_db_name) in mappings {
+        let target = plan::inspect_database(&restore_config.target_db_url, target_db_name, restore_config.plan_only)
+            .await
+            .with_context(|| format!("Failed to inspect target database '{}'", target_db_name))?;
+        let action = plan::decide_target_action(
+            target_db_name,
+            target.exists,
+            restore_config.drop_target_database_if_exists,
+            restore_config.create_target_database_if_not_exists,
+        );
+
+        let relative = |path: &Path| path.strip_prefix(extracted_path).unwrap_or(path).display().to_string();
+        let renamed = db_name_from_archive != target_db_name;
+        let schema_mapping = &restore_config.schema_mapping;
+        let mut steps = Vec::new();
+        let mut truncated_schema = None;
+        match locate_database_dump(extracted_path, db_name_from_archive, dump_format)? {
+            ArchivedDump::PgRestore(dump_path) if !schema_mapping.is_empty() => steps.push(format!(
+                "{} -> pg_restore --file (SQL script) -> psql, single transaction (pg_restore cannot rename schemas; nothing is dropped, jobs is not used)",
+                relative(&dump_path)
+            )),
+            ArchivedDump::PgRestore(dump_path) => steps.push(format!(
+                "{} -> pg_restore --clean --if-exists -j {} (objects in the dump are dropped and recreated)",
+                relative(&dump_path),
+                restore_config.jobs
+            )),
+            ArchivedDump::Plain { schema_file_path, data_file_path } => {
+                steps.push(format!("{} -> psql (schema)", relative(&schema_file_path)));
+                match data_file_path {
+                    Some(data_file_path) => {
+                        steps.push(format!("{} -> psql, single transaction (data), then sequences are reset", relative(&data_file_path)));
+                        if renamed {
+                            truncated_schema = Some(schema_mapping.get("public").cloned().unwrap_or_else(|| "public".to_string()));
+                        }
+                    }
+                    None => steps.push("no data file in the archive; schema only".to_string()),
+                }
+                if renamed {
+                    steps.push(format!("references to database '{}' are rewritten to '{}'", db_name_from_archive, target_db_name));
+                }
+            }
+        }
+        if !schema_mapping.is_empty() {
+            steps.push(format!(
+                "schemas renamed: {}; the new schemas are created if missing, the other schemas of the target are left alone",
+                rename::describe_schema_mapping(schema_mapping)
+            ));
+        }
+        let rows_file_path = extracted_path.join(db_dump::row_filter_entry_name(db_name_from_archive));
+        if rows_file_path.is_file() {
+            steps.push(format!("{} -> psql (rows kept by the backup's filters.where)", relative(&rows_file_path)));
+        }
+        if !restore_config.filters.is_empty() {
+            steps.push(format!("filters: {}; excluded objects are dropped and filtered rows deleted", restore_config.filters.describe()));
+        }
+        if let Some(masking_config) = &restore_config.masking {
+            steps.push(format!("masking: {}; matching columns are rewritten after loading", masking_config.describe()));
+        }
+        if restore_config.restore_globals {
+            steps.push("globals: missing roles are created first; the database gets its original owner, grants and settings".to_string());
+        }
+        if let Some(preserved) = preserved_ownership
+            && let Some(dump_ownership) = preserved.dumps.get(db_name_from_archive)
+        {
+            let owner = preserved.database_owners.get(db_name_from_archive);
+            let missing: Vec<&str> = dump_ownership
+                .roles
+                .iter()
+                .chain(owner)
+                .filter(|role| preserved.missing_roles.contains(*role))
+                .map(String::as_str)
+                .collect();
+            steps.push(format!(
+                "ownership: {} owner and privilege statement(s) kept, roles mapped with role_mapping{}{}",
+                dump_ownership.statements.len(),
+                owner.map(|owner| format!("; a created database is owned by {}", owner)).unwrap_or_default(),
+                if missing.is_empty() { String::new() } else { format!("; ⚠️  roles missing on the target: {}", missing.join(", ")) }
+            ));
+        }
+
+        planned.push(PlannedDatabase {
+            source_name: db_name_from_archive.clone(),
+            target_name: target_db_name.clone(),
+            action,
+            steps,
+            truncated_schema,
+            target,
+        });
+    }
+
+    Ok(planned)
+}
+
+/// Where the dump of one database lives inside an extracted archive.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ArchivedDump {
+    /// Plain SQL replayed with psql. The data file is absent for schema-only dumps.
+    Plain { schema_file_path: PathBuf, data_file_path: Option<PathBuf> },
+    /// A custom format file or directory format directory, restored with pg_restore.
+    PgRestore(PathBuf),
+}
+
+/// Finds the dump of `db_name` in an extracted archive.
+///
+/// With a manifest the recorded format decides; older archives are probed for
+/// `<db>.dir/toc.dat`, `<db>.dump` (or the legacy `<db>_<timestamp>.dump`) and
+/// finally `<db>_schema.sql`.
+pub(crate) fn locate_database_dump(extracted_path: &Path, db_name: &str, dump_format: Option<DumpFormat>) -> Result<ArchivedDump> {
+    let directory_path = extracted_path.join(format!("{}.dir", db_name));
+    let custom_path = extracted_path.join(format!("{}.dump", db_name));
+    let schema_file_path = extracted_path.join(format!("{}_schema.sql", db_name));
+    let data_file_path = extracted_path.join(format!("{}_data.sql", db_name));
+
+    let dump_format = match dump_format {
+        Some(format) => format,
+        None if directory_path.join("toc.dat").is_file() => DumpFormat::Directory,
+        None if custom_path.is_file() => DumpFormat::Custom,
+        None => match find_legacy_dump_file(extracted_path, db_name)? {
+            Some(legacy_path) => return Ok(ArchivedDump::PgRestore(legacy_path)),
+            None => DumpFormat::Plain,
+        },
+    };
+
+    let expected = match dump_format {
+        DumpFormat::Directory => directory_path,
+        DumpFormat::Custom => custom_path,
+        DumpFormat::Plain => {
+            if !schema_file_path.exists() {
+                return Err(anyhow::anyhow!(
+                    "Schema file not found for database '{}' in extracted archive: {}. Expected pattern: {}_schema.sql",
+                    db_name, schema_file_path.display(), db_name
+                ));
+            }
+            if !data_file_path.exists() {
+                // Data file might be optional for some backup types (e.g. schema-only)
+                println!(
+                    "Warning: Data file not found for database '{}' in extracted archive: {}. Expected pattern: {}_data.sql. Proceeding with schema restore only.",
+                    db_name, data_file_path.display(), db_name
+                );
+            }
+            return Ok(ArchivedDump::Plain {
+                schema_file_path,
+                data_file_path: data_file_path.exists().then_some(data_file_path),
+            });
+        }
+    };
+    if !expected.exists() {
+        anyhow::bail!(
+            "Dump for database '{}' ({} format) not found in extracted archive: {}",
+            db_name,
+            dump_format.as_str(),
+            expected.display()
+        );
+    }
+    Ok(ArchivedDump::PgRestore(expected))
+}
+
+/// Looks for a `<db>_*.dump` file in the extracted directory or one level below it.
+fn find_legacy_dump_file(extracted_path: &Path, db_name: &str) -> Result<Option<PathBuf>> {
+    let prefix = format!("{}_", db_name);
+    let is_dump_for_db = |path: &Path| {
+        path.is_file()
+            && path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".dump"))
+    };
+
+    for entry in fs::read_dir(extracted_path)?.flatten() {
+        let path = entry.path();
+        if is_dump_for_db(&path) {
+            return Ok(Some(path));
+        }
+    }
+    for entry in fs::read_dir(extracted_path)?.flatten() {
+        let path = entry.path();
+        if path.is_dir()
+            && let Ok(sub_entries) = fs::read_dir(&path)
+        {
+            for sub_entry in sub_entries.flatten() {
+                if is_dump_for_db(&sub_entry.path()) {
+                    return Ok(Some(sub_entry.path()));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
 
 /// Discovers database names from the files in the extracted archive directory.
-/// Looks for files matching `*_schema.sql`, `*_data.sql`, or `*.dump`.
-fn discover_databases_from_archive(extracted_path: &Path) -> Result<Vec<String>> {
+/// Looks for files matching `*_schema.sql`, `*_data.sql`, `*.dump`, or `*.dir` directories.
+pub(crate) fn discover_databases_from_archive(extracted_path: &Path) -> Result<Vec<String>> {
     let mut db_names = Vec::new();
     for entry in fs::read_dir(extracted_path)? {
         let entry = entry?;
         let path = entry.path();
-        if path.is_file() {
-            if let Some(file_name_os) = path.file_name() {
-                let file_name = file_name_os.to_string_lossy();
-                if file_name.ends_with("_schema.sql") {
-                    if let Some(db_name) = file_name.strip_suffix("_schema.sql") {
-                        if !db_name.is_empty() {
-                            db_names.push(db_name.to_string());
-                        }
-                    }
-                } else if file_name.ends_with("_data.sql") {
-                    if let Some(db_name) = file_name.strip_suffix("_data.sql") {
-                        if !db_name.is_empty() && !db_names.contains(&db_name.to_string()) {
-                            db_names.push(db_name.to_string());
-                        }
-                    }
-                } else if file_name.ends_with(".dump") {
-                    // Handle .dump files from pg_dump --format=custom
-                    // Pattern: DBNAME_YYYY-MM-DD_HH_MM_SS.dump
-                    let file_name_without_ext = file_name.trim_end_matches(".dump");
-                    // Extract database name by removing the timestamp part
-                    // Find the last underscore before the timestamp
-                    if let Some(last_underscore_pos) = file_name_without_ext.rfind('_') {
-                        // Check if the part after the last underscore looks like HH_MM_SS
-                        let time_part = &file_name_without_ext[last_underscore_pos + 1..];
-                        if time_part.len() == 8 && time_part.chars().all(|c| c.is_ascii_digit() || c == '_') {
-                            // Remove the time part
-                            let db_name_with_date = &file_name_without_ext[..last_underscore_pos];
-                            // Find another underscore for the date part
-                            if let Some(date_underscore_pos) = db_name_with_date.rfind('_') {
-                                // Check if the part after the underscore looks like YYYY-MM-DD
-                                let date_part = &db_name_with_date[date_underscore_pos + 1..];
-                                if date_part.len() >= 10 && date_part.chars().take(4).all(|c| c.is_ascii_digit()) {
-                                    // Extract the database name
-                                    let db_name = &db_name_with_date[..date_underscore_pos];
-                                    if !db_name.is_empty() && !db_names.contains(&db_name.to_string()) {
-                                        db_names.push(db_name.to_string());
-                                    }
+        if path.is_dir()
+            && path.join("toc.dat").is_file()
+            && let Some(db_name) = path.file_name().and_then(|name| name.to_str()).and_then(|name| name.strip_suffix(".dir"))
+            && !db_name.is_empty()
+        {
+            db_names.push(db_name.to_string());
+        } else if path.is_file()
+            && let Some(file_name_os) = path.file_name()
+        {
+            let file_name = file_name_os.to_string_lossy();
+            if file_name.ends_with("_schema.sql") {
+                if let Some(db_name) = file_name.strip_suffix("_schema.sql")
+                    && !db_name.is_empty()
+                {
+                    db_names.push(db_name.to_string());
+                }
+            } else if file_name.ends_with("_data.sql") {
+                if let Some(db_name) = file_name.strip_suffix("_data.sql")
+                    && !db_name.is_empty()
+                    && !db_names.contains(&db_name.to_string())
+                {
+                    db_names.push(db_name.to_string());
+                }
+            } else if file_name.ends_with(".dump") {
+                // Handle .dump files from pg_dump --format=custom
+                // Pattern: DBNAME_YYYY-MM-DD_HH_MM_SS.dump
+                let file_name_without_ext = file_name.trim_end_matches(".dump");
+                let mut matched_timestamp = false;
+                // Extract database name by removing the timestamp part
+                // Find the last underscore before the timestamp
+                if let Some(last_underscore_pos) = file_name_without_ext.rfind('_') {
+                    // Check if the part after the last underscore looks like HH_MM_SS
+                    let time_part = &file_name_without_ext[last_underscore_pos + 1..];
+                    if time_part.len() == 8 && time_part.chars().all(|c| c.is_ascii_digit() || c == '_') {
+                        // Remove the time part
+                        let db_name_with_date = &file_name_without_ext[..last_underscore_pos];
+                        // Find another underscore for the date part
+                        if let Some(date_underscore_pos) = db_name_with_date.rfind('_') {
+                            // Check if the part after the underscore looks like YYYY-MM-DD
+                            let date_part = &db_name_with_date[date_underscore_pos + 1..];
+                            if date_part.len() >= 10 && date_part.chars().take(4).all(|c| c.is_ascii_digit()) {
+                                // Extract the database name
+                                let db_name = &db_name_with_date[..date_underscore_pos];
+                                if !db_name.is_empty() {
+                                    matched_timestamp = true;
+                                    db_names.push(db_name.to_string());
                                 }
                             }
                         }
                     }
-                    // Fallback: if we couldn't extract a database name, use the whole filename without .dump
-                    if db_names.is_empty() {
-                        if !file_name_without_ext.is_empty() {
-                            db_names.push(file_name_without_ext.to_string());
-                        }
-                    }
+                }
+                // Fallback: if we couldn't extract a database name (e.g. `<db>.dump`), use the whole filename without .dump
+                if !matched_timestamp && !file_name_without_ext.is_empty() {
+                    db_names.push(file_name_without_ext.to_string());
                 }
             }
         }
@@ -407,7 +813,7 @@ fn discover_databases_from_archive(extracted_path: &Path) -> Result<Vec<String>>
     db_names.sort();
     db_names.dedup();
     if db_names.is_empty() {
-         println!("Warning: Could not discover any database files (*_schema.sql, *_data.sql, or *.dump) in the archive at {}", extracted_path.display());
+         println!("Warning: Could not discover any database files (*_schema.sql, *_data.sql, *.dump or *.dir) in the archive at {}", extracted_path.display());
     } else {
         println!("Discovered databases from archive: {:?}", db_names);
     }
@@ -422,4 +828,42 @@ split_sql_with_dollar_quotes, check_db_connection (within restore),
 extract_table_name_from_create, extract_table_name, create_table_from_insert.
 These are being replaced by the new modular approach with perform_restore_orchestration,
 db_restore module, etc.
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_database_dump_detects_format() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("app_schema.sql"), "")?;
+        fs::write(dir.path().join("app_data.sql"), "")?;
+        fs::write(dir.path().join("billing.dump"), "")?;
+        fs::create_dir(dir.path().join("crm.dir"))?;
+        fs::write(dir.path().join("crm.dir").join("toc.dat"), "")?;
+
+        assert_eq!(
+            locate_database_dump(dir.path(), "app", None)?,
+            ArchivedDump::Plain {
+                schema_file_path: dir.path().join("app_schema.sql"),
+                data_file_path: Some(dir.path().join("app_data.sql")),
+            }
+        );
+        assert_eq!(
+            locate_database_dump(dir.path(), "billing", None)?,
+            ArchivedDump::PgRestore(dir.path().join("billing.dump"))
+        );
+        assert_eq!(
+            locate_database_dump(dir.path(), "crm", None)?,
+            ArchivedDump::PgRestore(dir.path().join("crm.dir"))
+        );
+        // The manifest format is authoritative: a missing directory dump is an error, not a fallback.
+        assert!(locate_database_dump(dir.path(), "app", Some(DumpFormat::Directory)).is_err());
+
+        let mut discovered = discover_databases_from_archive(dir.path())?;
+        discovered.sort();
+        assert_eq!(discovered, vec!["app", "billing", "crm"]);
+        Ok(())
+    }
+}
@@ -1,7 +1,12 @@
-mod logic; // Keep existing logic, will be refactored internally
+pub(crate) mod logic; // Keep existing logic, will be refactored internally
 pub(crate) mod s3_download; // New module for S3 download interactions
 pub(crate) mod db_restore;   // New module for database restoration logic (executing SQL, etc.)
 pub(crate) mod verification; // New module for restore verification logic
+pub(crate) mod plan;         // --plan output for restore and sync
+pub(crate) mod safety;       // Protected targets and typed confirmation before destructive actions
+pub(crate) mod globals;      // Roles from globals.sql and database ownership, grants and settings
+pub(crate) mod ownership;    // Owner and privilege statements of database dumps (preserve_ownership)
+pub(crate) mod rename;       // Renaming a database inside plain dumps (database_list source:target)
 
 use anyhow::Result;
 use crate::config::AppConfig;
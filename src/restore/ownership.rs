@@ -0,0 +1,212 @@
+// databasetool/src/restore/ownership.rs
+use anyhow::{Context, Result};
+use sqlx::{Connection, PgConnection};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter};
+use std::path::Path;
+use tempfile::{NamedTempFile, TempPath};
+use tokio::process::Command;
+
+use crate::restore::db_restore::find_pg_restore_executable;
+use crate::restore::globals;
+use crate::restore::logic::ArchivedDump;
+use crate::utils::sql_script::{self, Token, TokenKind, is_keyword};
+
+/// The owner and privilege statements of one database dump, with role names mapped for the target.
+#[derive(Debug, Default)]
+pub struct DumpOwnership {
+    pub statements: Vec<String>,
+    pub roles: BTreeSet<String>, // Every role the statements name, after mapping
+}
+
+/// Whether a statement of a pg_dump script is an owner or privilege statement: `ALTER ... OWNER TO`,
+/// `GRANT`, `REVOKE`, `ALTER DEFAULT PRIVILEGES`, and the `SET SESSION AUTHORIZATION` pair
+/// pg_dump puts around grants made by a role other than the owner.
+pub fn is_ownership_statement(sql: &[u8]) -> bool {
+    let words: Vec<Token> = sql_script::tokenize(sql).into_iter().filter(|token| token.kind != TokenKind::Space).collect();
+    let is = |n: usize, keyword: &str| words.get(n).is_some_and(|token| is_keyword(sql, token, keyword));
+    // pg_dump ends the statement with OWNER TO <role>;
+    let owner_to = words.len().checked_sub(4).is_some_and(|n| is(n, "OWNER") && is(n + 1, "TO"));
+    (is(0, "ALTER") && owner_to)
+        || is(0, "GRANT")
+        || is(0, "REVOKE")
+        || (is(0, "ALTER") && is(1, "DEFAULT") && is(2, "PRIVILEGES"))
+        || ((is(0, "SET") || is(0, "RESET")) && is(1, "SESSION") && is(2, "AUTHORIZATION"))
+}
+
+/// The owner or privilege statement in `statement` (as `rewrite_script` passes it, with the
+/// comments before it) with role names mapped, or `None` for any other statement.
+fn mapped_ownership_statement(statement: &[u8], role_mapping: &BTreeMap<String, String>) -> Option<globals::GlobalsStatement> {
+    let sql = &statement[sql_script::statement_start(statement)..];
+    if !is_ownership_statement(sql) {
+        return None;
+    }
+    let sql = std::str::from_utf8(sql).ok()?;
+    Some(globals::parse_statement(sql.trim_end(), role_mapping))
+}
+
+/// Collects the owner and privilege statements of a pg_dump script, mapping role names through `role_mapping`.
+pub fn read_ownership<R: BufRead>(reader: R, role_mapping: &BTreeMap<String, String>) -> Result<DumpOwnership> {
+    let mut ownership = DumpOwnership::default();
+    sql_script::rewrite_script(
+        reader,
+        io::sink(),
+        |statement| {
+            if let Some(statement) = mapped_ownership_statement(statement, role_mapping) {
+                ownership.roles.extend(statement.roles);
+                ownership.statements.push(statement.sql);
+            }
+            None
+        },
+        |_| None,
+    )
+    .context("Failed to read dump script")?;
+    Ok(ownership)
+}
+
+/// Reads the owner and privilege statements of an archived dump: from the schema script of a
+/// plain dump, or from the script `pg_restore --schema-only` prints for a custom or directory dump.
+pub async fn dump_ownership(dump: &ArchivedDump, role_mapping: &BTreeMap<String, String>) -> Result<DumpOwnership> {
+    match dump {
+        ArchivedDump::Plain { schema_file_path, .. } => {
+            let file = File::open(schema_file_path).with_context(|| format!("Failed to open {}", schema_file_path.display()))?;
+            read_ownership(BufReader::new(file), role_mapping)
+        }
+        ArchivedDump::PgRestore(dump_path) => {
+            let output = Command::new(find_pg_restore_executable()?)
+                .arg("--schema-only")
+                .arg("--file=-")
+                .arg(dump_path)
+                .kill_on_drop(true)
+                .output()
+                .await
+                .with_context(|| format!("Failed to execute pg_restore --schema-only for {}", dump_path.display()))?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "pg_restore --schema-only for {} exited with status: {}\nStderr: {}",
+                    dump_path.display(),
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            read_ownership(output.stdout.as_slice(), role_mapping)
+        }
+    }
+}
+
+/// Copies a plain schema script into a temporary file for psql, statement by statement.
+///
+/// With `preserve` its owner and privilege statements are kept with role names mapped;
+/// otherwise they are left out, as pg_restore does with `--no-owner --no-acl`, and the
+/// restored objects belong to the user the restore connects as.
+pub fn rewrite_schema_script(schema_path: &Path, preserve: bool, role_mapping: &BTreeMap<String, String>) -> Result<TempPath> {
+    let input = File::open(schema_path).with_context(|| format!("Failed to open {}", schema_path.display()))?;
+    let output = NamedTempFile::new().context("Failed to create a temporary schema script")?;
+    let mut writer = BufWriter::new(output);
+    sql_script::rewrite_script(
+        BufReader::new(input),
+        &mut writer,
+        |statement| {
+            let mapped = mapped_ownership_statement(statement, role_mapping)?;
+            // Keep the comments before the statement, so the script reads the same.
+            let mut rewritten = statement[..sql_script::statement_start(statement)].to_vec();
+            if preserve {
+                rewritten.extend_from_slice(mapped.sql.as_bytes());
+            }
+            Some(rewritten)
+        },
+        |_| None,
+    )
+    .with_context(|| format!("Failed to rewrite {}", schema_path.display()))?;
+    let output = writer.into_inner().context("Failed to write the temporary schema script")?;
+    Ok(output.into_temp_path())
+}
+
+/// Gives the objects of a database restored with `--no-owner --no-acl` their owners and
+/// privileges, in one transaction.
+pub async fn apply_ownership(conn: &mut PgConnection, statements: &[String]) -> Result<()> {
+    let mut transaction = conn.begin().await.context("Failed to start a transaction for owners and privileges")?;
+    for statement in statements {
+        sqlx::query(statement)
+            .execute(&mut *transaction)
+            .await
+            .with_context(|| format!("Failed to run: {}", statement))?;
+    }
+    transaction.commit().await.context("Failed to commit owners and privileges")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"--
+-- Name: users; Type: TABLE; Schema: public; Owner: prod_app
+--
+
+CREATE TABLE public.users (
+    id integer NOT NULL,
+    note text DEFAULT 'GRANT ALL;'::text
+);
+
+
+ALTER TABLE public.users OWNER TO prod_app;
+
+CREATE FUNCTION public.touch() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$BEGIN NEW.note := 'x';
+REVOKE ALL ON TABLE public.users FROM prod_app;
+RETURN NEW; END$$;
+
+
+ALTER FUNCTION public.touch() OWNER TO "Report Reader";
+
+ALTER DEFAULT PRIVILEGES FOR ROLE prod_app IN SCHEMA public GRANT SELECT ON TABLES TO "Report Reader";
+
+SET SESSION AUTHORIZATION prod_admin;
+GRANT SELECT ON TABLE public.users TO prod_app;
+RESET SESSION AUTHORIZATION;
+REVOKE ALL ON TABLE public.users FROM PUBLIC;
+"#;
+
+    fn mapping() -> BTreeMap<String, String> {
+        BTreeMap::from([("prod_app".to_string(), "dev_app".to_string())])
+    }
+
+    #[test]
+    fn test_read_ownership_maps_roles() -> Result<()> {
+        let ownership = read_ownership(SCHEMA.as_bytes(), &mapping())?;
+        assert_eq!(
+            ownership.statements,
+            vec![
+                r#"ALTER TABLE public.users OWNER TO "dev_app";"#,
+                r#"ALTER FUNCTION public.touch() OWNER TO "Report Reader";"#,
+                r#"ALTER DEFAULT PRIVILEGES FOR ROLE "dev_app" IN SCHEMA public GRANT SELECT ON TABLES TO "Report Reader";"#,
+                "SET SESSION AUTHORIZATION prod_admin;",
+                r#"GRANT SELECT ON TABLE public.users TO "dev_app";"#,
+                "RESET SESSION AUTHORIZATION;",
+                "REVOKE ALL ON TABLE public.users FROM PUBLIC;",
+            ]
+        );
+        let roles: Vec<&str> = ownership.roles.iter().map(String::as_str).collect();
+        assert_eq!(roles, vec!["Report Reader", "dev_app", "prod_admin"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rewrite_schema_script_keeps_or_drops_ownership() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let schema_path = dir.path().join("app_schema.sql");
+        std::fs::write(&schema_path, SCHEMA)?;
+
+        let stripped = std::fs::read_to_string(rewrite_schema_script(&schema_path, false, &mapping())?)?;
+        assert!(!stripped.contains("OWNER TO") && !stripped.contains("SESSION AUTHORIZATION"), "{}", stripped);
+        assert!(stripped.contains("note text DEFAULT 'GRANT ALL;'::text"));
+        assert!(stripped.contains("NEW.note := 'x';\nREVOKE ALL ON TABLE public.users FROM prod_app;\nRETURN NEW; END$$;"));
+
+        let preserved = std::fs::read_to_string(rewrite_schema_script(&schema_path, true, &mapping())?)?;
+        assert_eq!(preserved, SCHEMA.replace("TO prod_app;", "TO \"dev_app\";").replace("ROLE prod_app", "ROLE \"dev_app\""));
+        Ok(())
+    }
+}
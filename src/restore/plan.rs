@@ -0,0 +1,242 @@
+// databasetool/src/restore/plan.rs
+use anyhow::{Context, Result};
+use sqlx::{Connection, PgConnection};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use url::Url;
+
+use crate::backup::manifest;
+use crate::utils::sql_script::quote_ident;
+
+/// What happens to a target database before anything is restored into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetAction {
+    /// Exists and may be dropped: connections are terminated, the database is dropped and created again.
+    DropAndCreate,
+    /// Does not exist yet and is created.
+    Create,
+    /// Exists and is kept; the restore writes into it.
+    RestoreIntoExisting,
+    /// Exists, is already up to date and is left alone (incremental sync).
+    Unchanged,
+    /// The run would stop with an error at this database.
+    Refuse(&'static str),
+}
+
+/// Mirrors the decisions of `db_restore::manage_target_database` without executing them.
+pub fn decide_target_action(db_name: &str, exists: bool, drop_if_exists: bool, create_if_missing: bool) -> TargetAction {
+    match (exists, drop_if_exists, create_if_missing) {
+        (true, true, _) if db_name.eq_ignore_ascii_case("postgres") => {
+            TargetAction::Refuse("is a critical system database and cannot be dropped")
+        }
+        (true, true, _) => TargetAction::DropAndCreate,
+        (true, false, _) => TargetAction::RestoreIntoExisting,
+        (false, _, true) => TargetAction::Create,
+        (false, _, false) => TargetAction::Refuse("does not exist and create_target_database_if_not_exists is false"),
+    }
+}
+
+/// The current state of a target database, read without modifying it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TargetState {
+    pub exists: bool,
+    pub active_connections: i64,
+    pub row_counts: BTreeMap<String, i64>, // Empty when the database does not exist
+    pub estimated: bool, // Row counts come from planner statistics instead of COUNT(*)
+}
+
+/// Reads whether `db_name` exists on the server of `server_url`, how many sessions use it
+/// and the row count of each of its tables.
+///
+/// # Arguments
+/// * `server_url` - Any URL of the server; the database path is replaced.
+/// * `db_name` - Database to inspect.
+/// * `exact` - Count rows with `COUNT(*)`; otherwise read the planner estimates, which is instant on large tables.
+pub async fn inspect_database(server_url: &str, db_name: &str, exact: bool) -> Result<TargetState> {
+    let mut admin_url = Url::parse(server_url).context("Invalid database URL")?;
+    admin_url.set_path("/postgres");
+    let mut admin = PgConnection::connect(admin_url.as_str())
+        .await
+        .with_context(|| format!("Failed to connect to 'postgres' database on {}", admin_url.host_str().unwrap_or("unknown_host")))?;
+
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM pg_database WHERE datname = $1)")
+        .bind(db_name)
+        .fetch_one(&mut admin)
+        .await
+        .with_context(|| format!("Failed to check existence of database '{}'", db_name))?;
+    if !exists {
+        return Ok(TargetState::default());
+    }
+
+    let active_connections: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM pg_stat_activity WHERE datname = $1 AND pid <> pg_backend_pid()")
+            .bind(db_name)
+            .fetch_one(&mut admin)
+            .await
+            .with_context(|| format!("Failed to count connections to database '{}'", db_name))?;
+    admin.close().await.ok();
+
+    let mut db_url = Url::parse(server_url).context("Invalid database URL")?;
+    db_url.set_path(db_name);
+    let mut conn = PgConnection::connect(db_url.as_str())
+        .await
+        .with_context(|| format!("Failed to connect to database '{}'", db_name))?;
+    let row_counts = if exact {
+        manifest::collect_row_counts(&mut conn).await
+    } else {
+        estimate_row_counts(&mut conn).await
+    }
+    .with_context(|| format!("Failed to count rows in database '{}'", db_name))?;
+    conn.close().await.ok();
+
+    Ok(TargetState { exists: true, active_connections, row_counts, estimated: !exact })
+}
+
+/// Row estimates of every user table from `pg_class.reltuples`, keyed by `schema.table`.
+/// Tables that were never analyzed report 0.
+async fn estimate_row_counts(conn: &mut PgConnection) -> Result<BTreeMap<String, i64>> {
+    let rows: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT n.nspname || '.' || c.relname, GREATEST(c.reltuples, 0)::bigint \
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+         WHERE c.relkind IN ('r', 'p') AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+         AND n.nspname NOT LIKE 'pg_toast%'",
+    )
+    .fetch_all(conn)
+    .await
+    .context("Failed to read table statistics")?;
+    Ok(rows.into_iter().collect())
+}
+
+/// One database of a restore or sync plan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedDatabase {
+    pub source_name: String,
+    pub target_name: String,
+    pub action: TargetAction,
+    /// Where the data comes from and how it is applied, one line per file or command.
+    pub steps: Vec<String>,
+    /// Plain-format restores under a different name truncate every table of this schema before
+    /// loading data: `public`, or the schema `schema_mapping` restores it into.
+    pub truncated_schema: Option<String>,
+    pub target: TargetState,
+}
+
+/// Formats a plan for the terminal. Nothing in it has been executed.
+///
+/// # Arguments
+/// * `title` - First line, e.g. "Restore plan".
+/// * `source` - Archive or server the data comes from.
+/// * `databases` - Planned databases in execution order.
+pub fn render_plan(title: &str, source: &str, databases: &[PlannedDatabase]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "\n📋 {} (dry run, nothing will be changed)", title);
+    let _ = writeln!(out, "Source: {}", source);
+
+    for db in databases {
+        let _ = writeln!(out, "\n{} -> {}", db.source_name, db.target_name);
+        let target = &db.target;
+        let quoted = quote_ident(&db.target_name);
+        let action = match db.action {
+            TargetAction::DropAndCreate => format!(
+                "DROP DATABASE {} WITH (FORCE), terminating {} active connection(s), then CREATE DATABASE {}",
+                quoted, target.active_connections, quoted
+            ),
+            TargetAction::Create => format!("CREATE DATABASE {}", quoted),
+            TargetAction::RestoreIntoExisting => format!(
+                "keep existing database {} ({} active connection(s)) and overwrite the objects restored into it",
+                quoted, target.active_connections
+            ),
+            TargetAction::Unchanged => format!("keep existing database {}, nothing is written", quoted),
+            TargetAction::Refuse(reason) => format!("❌ would fail: {} {}", quoted, reason),
+        };
+        let _ = writeln!(out, "  Target:   {}", action);
+
+        for step in &db.steps {
+            let _ = writeln!(out, "  Restore:  {}", step);
+        }
+
+        if let Some(schema) = &db.truncated_schema {
+            let prefix = format!("{}.", schema);
+            let schema_tables: Vec<&String> = target.row_counts.keys().filter(|table| table.starts_with(&prefix)).collect();
+            let affected = if db.action == TargetAction::RestoreIntoExisting && !schema_tables.is_empty() {
+                format!(" ({} existing: {})", schema_tables.len(), schema_tables.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(", "))
+            } else {
+                String::new()
+            };
+            let _ = writeln!(
+                out,
+                "  Truncate: every table in schema {} except schema_migrations, before loading data{}",
+                schema, affected
+            );
+        }
+
+        if !target.exists {
+            let _ = writeln!(out, "  Current:  target does not exist");
+        } else if target.row_counts.is_empty() {
+            let _ = writeln!(out, "  Current:  target exists and has no tables");
+        } else {
+            let total: i64 = target.row_counts.values().sum();
+            let _ = writeln!(out, "  Current:  {} row(s) in {} table(s) of the target", total, target.row_counts.len());
+            let width = target.row_counts.keys().map(String::len).max().unwrap_or(0);
+            for (table, count) in &target.row_counts {
+                let _ = writeln!(out, "    {:<width$}  {:>12}", table, count, width = width);
+            }
+        }
+    }
+
+    let failing = databases.iter().filter(|db| matches!(db.action, TargetAction::Refuse(_))).count();
+    if failing > 0 {
+        let _ = writeln!(out, "\n⚠️  {} database(s) would make the run fail; see above.", failing);
+    }
+    let _ = writeln!(out, "\nRun again without --plan to apply.");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decide_target_action() {
+        assert_eq!(decide_target_action("app", true, true, true), TargetAction::DropAndCreate);
+        assert_eq!(decide_target_action("app", true, false, true), TargetAction::RestoreIntoExisting);
+        assert_eq!(decide_target_action("app", false, true, true), TargetAction::Create);
+        assert!(matches!(decide_target_action("app", false, true, false), TargetAction::Refuse(_)));
+        assert!(matches!(decide_target_action("Postgres", true, true, true), TargetAction::Refuse(_)));
+    }
+
+    #[test]
+    fn test_render_plan_lists_destructive_actions_and_row_counts() {
+        let plan = PlannedDatabase {
+            source_name: "app".to_string(),
+            target_name: "app_copy".to_string(),
+            action: TargetAction::RestoreIntoExisting,
+            steps: vec!["app_data.sql -> psql".to_string()],
+            truncated_schema: Some("public".to_string()),
+            target: TargetState {
+                exists: true,
+                active_connections: 2,
+                row_counts: BTreeMap::from([("public.items".to_string(), 1000), ("sales.orders".to_string(), 500)]),
+                estimated: false,
+            },
+        };
+        let out = render_plan("Restore plan", "/backups/a.tar.gz", &[plan]);
+        assert!(out.contains("app -> app_copy"));
+        assert!(out.contains("2 active connection(s)"));
+        assert!(out.contains("Truncate: every table in schema public except schema_migrations, before loading data (1 existing: public.items)"));
+        assert!(out.contains("1500 row(s) in 2 table(s)"));
+        assert!(!out.contains("would make the run fail"));
+
+        let refused = PlannedDatabase {
+            source_name: "crm".to_string(),
+            target_name: "crm".to_string(),
+            action: decide_target_action("crm", false, true, false),
+            steps: Vec::new(),
+            truncated_schema: None,
+            target: TargetState::default(),
+        };
+        let out = render_plan("Restore plan", "x", &[refused]);
+        assert!(out.contains("would fail"));
+        assert!(out.contains("target does not exist"));
+    }
+}
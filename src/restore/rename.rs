@@ -0,0 +1,500 @@
+// databasetool/src/restore/rename.rs
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use crate::utils::sql_script::{self, Token, TokenKind, format_ident, ident_name, is_keyword, quote_ident, quote_literal};
+
+/// Copies a plain dump from `reader` to `writer`, renaming the database `(source, target)` and
+/// the schemas of `schema_mapping` (source -> target). For the database only these are touched:
+///
+/// - `\connect` meta-commands, with a database name or a `dbname=` connection string,
+/// - the name after `DATABASE`: `CREATE`/`ALTER`/`DROP DATABASE`, `COMMENT ON DATABASE`,
+///   `GRANT ... ON DATABASE`, `ALTER ROLE ... IN DATABASE`,
+/// - the database part of qualified names: `source.schema.table` where a table, view,
+///   sequence, routine or type is named (`schema.table.column` is left alone), and
+///   `source.schema.table.column` anywhere.
+///
+/// A schema is renamed where the script names it: after `SCHEMA` (`CREATE`, `ALTER`, `COMMENT ON`,
+/// `GRANT ... ON`, `IN SCHEMA`), as the qualifier of an object name, and inside the literals pg_dump
+/// uses to name objects: `'public.items_id_seq'::regclass` and the first argument of `setval`.
+/// `CREATE SCHEMA` of a mapped schema gets `IF NOT EXISTS`, so a restore can load into a schema
+/// that was created beforehand. Quoted function bodies are left as they are.
+///
+/// Literals, comments, and the rows of `INSERT` and `COPY` are copied unchanged, so data
+/// that mentions the names stays as it is. The dump is streamed statement by statement.
+pub fn rename_references<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    database: Option<(&str, &str)>,
+    schema_mapping: &BTreeMap<String, String>,
+) -> Result<()> {
+    sql_script::rewrite_script(
+        reader,
+        writer,
+        |sql| {
+            let renamed = database.and_then(|(source, target)| rename_database_in_statement(sql, source, target));
+            rename_schemas_in_statement(renamed.as_deref().unwrap_or(sql), schema_mapping).or(renamed)
+        },
+        |line| database.and_then(|(source, target)| rename_database_in_meta_command(line, source, target)),
+    )
+}
+
+/// `public -> snapshot, app -> app_copy`, for messages.
+pub fn describe_schema_mapping(schema_mapping: &BTreeMap<String, String>) -> String {
+    schema_mapping.iter().map(|(source, target)| format!("{} -> {}", source, target)).collect::<Vec<_>>().join(", ")
+}
+
+/// `sql` with the schemas of `schema_mapping` renamed, for statements run outside a script.
+pub fn rename_schemas(sql: &str, schema_mapping: &BTreeMap<String, String>) -> String {
+    match rename_schemas_in_statement(sql.as_bytes(), schema_mapping) {
+        Some(renamed) => String::from_utf8_lossy(&renamed).into_owned(),
+        None => sql.to_string(),
+    }
+}
+
+/// Keywords followed by the name of a relation, routine or type. Three-part names elsewhere
+/// are `schema.table.column`, as in `ALTER SEQUENCE ... OWNED BY`.
+const OBJECT_NAME_KEYWORDS: [&str; 17] = [
+    "TABLE", "VIEW", "SEQUENCE", "INDEX", "FUNCTION", "PROCEDURE", "TYPE", "DOMAIN", "INTO", "FROM", "JOIN", "REFERENCES",
+    "UPDATE", "COPY", "ONLY", "EXISTS", "TRUNCATE",
+];
+
+/// Whether `sql` contains `name`, ignoring case. Most statements never mention the names being
+/// renamed; they are passed through without tokenizing.
+fn mentions(sql: &[u8], name: &str) -> bool {
+    !name.is_empty() && sql.windows(name.len()).any(|window| window.eq_ignore_ascii_case(name.as_bytes()))
+}
+
+/// The tokens of `sql` without whitespace and comments, and how many of them may name objects:
+/// in `INSERT` and `COPY` only the table name comes before the column list and the rows.
+fn statement_tokens(sql: &[u8]) -> (Vec<Token>, usize) {
+    let tokens: Vec<Token> = sql_script::tokenize(sql).into_iter().filter(|token| token.kind != TokenKind::Space).collect();
+    let keyword = |n: usize, word: &str| tokens.get(n).is_some_and(|token| is_keyword(sql, token, word));
+    let end = if keyword(0, "INSERT") || keyword(0, "COPY") {
+        (0..tokens.len())
+            .find(|&n| {
+                (tokens[n].kind == TokenKind::Punct && sql[tokens[n].range.start] == b'(')
+                    || ["VALUES", "SELECT", "DEFAULT", "OVERRIDING", "FROM", "TO"].iter().any(|word| keyword(n, word))
+            })
+            .unwrap_or(tokens.len())
+    } else {
+        tokens.len()
+    };
+    (tokens, end)
+}
+
+/// The statement with its references to database `source` renamed, or `None` if it has none.
+fn rename_database_in_statement(sql: &[u8], source: &str, target: &str) -> Option<Vec<u8>> {
+    if !mentions(sql, source) {
+        return None;
+    }
+    let (tokens, end) = statement_tokens(sql);
+    let keyword = |n: usize, word: &str| tokens.get(n).is_some_and(|token| is_keyword(sql, token, word));
+    let punct = |n: usize, c: u8| tokens.get(n).is_some_and(|token| token.kind == TokenKind::Punct && sql[token.range.start] == c);
+    let refers = |n: usize| tokens.get(n).and_then(|token| ident_name(sql, token)).is_some_and(|name| name == source);
+
+    let mut renamed = Vec::new();
+    let mut n = 0;
+    while n < end {
+        if keyword(n, "DATABASE") {
+            let mut name = n + 1;
+            if keyword(name, "IF") {
+                name += if keyword(name + 1, "NOT") { 3 } else { 2 }; // IF [NOT] EXISTS
+            }
+            loop {
+                if refers(name) {
+                    renamed.push(name);
+                }
+                if !punct(name + 1, b',') {
+                    break;
+                }
+                name += 2; // GRANT ... ON DATABASE a, b
+            }
+            n = name + 1;
+            continue;
+        }
+        let starts_name = n == 0 || !punct(n - 1, b'.');
+        if refers(n) && starts_name {
+            let parts = 1 + (0..).take_while(|&part| punct(n + 1 + 2 * part, b'.')).count();
+            let names_object = n > 0 && OBJECT_NAME_KEYWORDS.iter().any(|word| keyword(n - 1, word));
+            if parts >= 4 || (parts == 3 && names_object) {
+                renamed.push(n); // database.schema.object
+            }
+        }
+        n += 1;
+    }
+    if renamed.is_empty() {
+        return None;
+    }
+    let replacements = renamed
+        .into_iter()
+        .map(|n| {
+            let token = &tokens[n];
+            let name = match token.kind {
+                TokenKind::QuotedIdent => quote_ident(target),
+                _ => format_ident(target),
+            };
+            (token.range.clone(), name)
+        })
+        .collect();
+    Some(sql_script::splice(sql, replacements))
+}
+
+/// Casts pg_dump applies to literals that name an object, e.g. `nextval('public.items_id_seq'::regclass)`.
+const OBJECT_NAME_TYPES: [&str; 10] = [
+    "regclass", "regtype", "regproc", "regprocedure", "regoper", "regoperator", "regconfig", "regdictionary", "regcollation",
+    "regnamespace",
+];
+
+/// The statement with the schemas of `schema_mapping` renamed, or `None` if it names none of them.
+fn rename_schemas_in_statement(sql: &[u8], schema_mapping: &BTreeMap<String, String>) -> Option<Vec<u8>> {
+    if !schema_mapping.keys().any(|schema| mentions(sql, schema)) {
+        return None;
+    }
+    let (tokens, end) = statement_tokens(sql);
+    let keyword = |n: usize, word: &str| tokens.get(n).is_some_and(|token| is_keyword(sql, token, word));
+    let punct = |n: usize, c: u8| tokens.get(n).is_some_and(|token| token.kind == TokenKind::Punct && sql[token.range.start] == c);
+    let mapped = |n: usize| tokens.get(n).and_then(|token| ident_name(sql, token)).and_then(|name| schema_mapping.get(&name));
+    let renamed = |n: usize, target: &str| match tokens[n].kind {
+        TokenKind::QuotedIdent => quote_ident(target),
+        _ => format_ident(target),
+    };
+
+    let mut replacements = Vec::new();
+    let mut n = 0;
+    while n < end {
+        if keyword(n, "SCHEMA") {
+            let mut name = n + 1;
+            let if_exists = keyword(name, "IF");
+            if if_exists {
+                name += if keyword(name + 1, "NOT") { 3 } else { 2 }; // IF [NOT] EXISTS
+            }
+            loop {
+                if let Some(target) = mapped(name) {
+                    let mut replacement = renamed(name, target);
+                    if n > 0 && keyword(n - 1, "CREATE") && !if_exists {
+                        replacement.insert_str(0, "IF NOT EXISTS ");
+                    }
+                    replacements.push((tokens[name].range.clone(), replacement));
+                }
+                if !punct(name + 1, b',') {
+                    break;
+                }
+                name += 2; // DROP SCHEMA a, b
+            }
+            n = name + 1;
+            continue;
+        }
+        if tokens[n].kind == TokenKind::Literal {
+            // 'schema.name'::regclass, or 'schema.name'::pg_catalog.regclass
+            let cast_to = if keyword(n + 3, "pg_catalog") && punct(n + 4, b'.') { n + 5 } else { n + 3 };
+            let cast = (punct(n + 1, b':') && punct(n + 2, b':'))
+                .then(|| OBJECT_NAME_TYPES.iter().find(|name| keyword(cast_to, name)))
+                .flatten();
+            let sequence_argument = n >= 2 && punct(n - 1, b'(') && keyword(n - 2, "setval");
+            if (cast.is_some() || sequence_argument)
+                && let Some(literal) = rename_schema_in_literal(&sql[tokens[n].range.clone()], schema_mapping, cast == Some(&"regnamespace"))
+            {
+                replacements.push((tokens[n].range.clone(), literal));
+            }
+            n += 1;
+            continue;
+        }
+        let starts_name = n == 0 || !punct(n - 1, b'.');
+        if starts_name && punct(n + 1, b'.') && ident_name(sql, &tokens[n]).is_some() {
+            let parts = 1 + (0..).take_while(|&part| punct(n + 1 + 2 * part, b'.')).count();
+            let names_object = n > 0 && OBJECT_NAME_KEYWORDS.iter().any(|word| keyword(n - 1, word));
+            // schema.object, schema.table.column, database.schema.object, database.schema.table.column
+            let schema = match parts {
+                2 => n,
+                3 if !names_object => n,
+                _ => n + 2,
+            };
+            if let Some(target) = mapped(schema) {
+                replacements.push((tokens[schema].range.clone(), renamed(schema, target)));
+            }
+            n += 2 * parts - 1;
+            continue;
+        }
+        n += 1;
+    }
+    if replacements.is_empty() {
+        return None;
+    }
+    Some(sql_script::splice(sql, replacements))
+}
+
+/// A `'schema.name'` literal with its schema renamed, or `None` if the schema is not mapped.
+/// With `whole_name` the literal is the schema name itself (`'public'::regnamespace`).
+fn rename_schema_in_literal(literal: &[u8], schema_mapping: &BTreeMap<String, String>, whole_name: bool) -> Option<String> {
+    let text = std::str::from_utf8(literal).ok()?;
+    let value = text.strip_prefix('\'')?.strip_suffix('\'')?.replace("''", "'");
+    let (schema, rest) = match value.strip_prefix('"') {
+        Some(quoted) => {
+            let mut name = String::new();
+            let mut chars = quoted.char_indices().peekable();
+            let mut rest = None;
+            while let Some((i, c)) = chars.next() {
+                if c != '"' {
+                    name.push(c);
+                } else if chars.peek().is_some_and(|&(_, next)| next == '"') {
+                    name.push(chars.next()?.1);
+                } else {
+                    rest = Some(&quoted[i + 1..]);
+                    break;
+                }
+            }
+            (name, rest?)
+        }
+        None => {
+            let end = value.find(['.', '(']).unwrap_or(value.len());
+            (value[..end].to_lowercase(), &value[end..])
+        }
+    };
+    let names_schema = if whole_name { rest.is_empty() } else { rest.starts_with('.') };
+    if !names_schema {
+        return None;
+    }
+    let target = schema_mapping.get(&schema)?;
+    Some(quote_literal(&format!("{}{}", format_ident(target), rest)))
+}
+
+/// The `\connect` line with database `source` replaced, or `None` for other lines.
+fn rename_database_in_meta_command(line: &[u8], source: &str, target: &str) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(line).ok()?;
+    let command_start = text.find('\\')? + 1;
+    let command_end = text[command_start..].find(char::is_whitespace).map_or(text.len(), |n| command_start + n);
+    if !matches!(&text[command_start..command_end], "c" | "connect") {
+        return None;
+    }
+    // The first argument that is not an option (-reuse-previous=on) names the database.
+    let mut position = command_end;
+    loop {
+        let argument_start = position + text[position..].find(|c: char| !c.is_whitespace())?;
+        let argument_end = meta_argument_end(text, argument_start);
+        let argument = &text[argument_start..argument_end];
+        if argument.starts_with('-') {
+            position = argument_end;
+            continue;
+        }
+        let value = unquote_meta_argument(argument);
+        let replacement = if value == source {
+            if target.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                target.to_string()
+            } else {
+                quote_ident(target)
+            }
+        } else {
+            let connection_string = rename_in_connection_string(&value, source, target)?;
+            quote_ident(&connection_string)
+        };
+        let mut renamed = text[..argument_start].to_string();
+        renamed.push_str(&replacement);
+        renamed.push_str(&text[argument_end..]);
+        return Some(renamed.into_bytes());
+    }
+}
+
+/// Where a meta-command argument starting at `start` ends: at whitespace outside quotes.
+fn meta_argument_end(text: &str, start: usize) -> usize {
+    let mut quote = None;
+    let mut chars = text[start..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => return start + i,
+            (None, '"' | '\'') => quote = Some(c),
+            (Some('\''), '\\') => {
+                chars.next();
+            }
+            (Some(open), c) if c == open => quote = None,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// The value of a meta-command argument: `"..."` and `'...'` quoting removed.
+fn unquote_meta_argument(argument: &str) -> String {
+    if argument.len() >= 2 && argument.starts_with('"') && argument.ends_with('"') {
+        return argument[1..argument.len() - 1].replace("\"\"", "\"");
+    }
+    if argument.len() >= 2 && argument.starts_with('\'') && argument.ends_with('\'') {
+        return unescape_single_quoted(&argument[1..argument.len() - 1]);
+    }
+    argument.to_string()
+}
+
+fn unescape_single_quoted(text: &str) -> String {
+    let mut value = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next()),
+            _ => value.push(c),
+        }
+    }
+    value.replace("''", "'")
+}
+
+/// A `key=value` connection string with `dbname=source` replaced, or `None` if it names
+/// another database or is not a connection string.
+fn rename_in_connection_string(connection_string: &str, source: &str, target: &str) -> Option<String> {
+    let key_start = connection_string.find("dbname")?;
+    let after_key = connection_string[key_start + "dbname".len()..].trim_start();
+    let value_text = after_key.strip_prefix('=')?.trim_start();
+    let value_start = connection_string.len() - value_text.len();
+    let (value, value_len) = match value_text.strip_prefix('\'') {
+        Some(quoted) => {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = None;
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '\'' => {
+                        end = Some(i + 2);
+                        break;
+                    }
+                    _ => value.push(c),
+                }
+            }
+            (value, end?)
+        }
+        None => {
+            let len = value_text.find(char::is_whitespace).unwrap_or(value_text.len());
+            (value_text[..len].to_string(), len)
+        }
+    };
+    if value != source {
+        return None;
+    }
+    let quoted_target = format!("'{}'", target.replace('\\', "\\\\").replace('\'', "\\'"));
+    Some(format!("{}{}{}", &connection_string[..value_start], quoted_target, &connection_string[value_start + value_len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rename(sql: &str) -> String {
+        let mut output = Vec::new();
+        rename_references(sql.as_bytes(), &mut output, Some(("app_prod", "app_dev")), &BTreeMap::new()).expect("rename");
+        String::from_utf8(output).expect("utf-8")
+    }
+
+    #[test]
+    fn test_renames_only_database_references() {
+        let dump = r#"CREATE DATABASE app_prod WITH TEMPLATE = template0;
+ALTER DATABASE app_prod OWNER TO app_prod_owner;
+\connect app_prod
+COMMENT ON DATABASE "app_prod" IS 'Production copy of app_prod';
+GRANT CONNECT ON DATABASE app_prod, reports TO app_prod_reader;
+ALTER ROLE app_prod_reader IN DATABASE app_prod SET work_mem TO '64MB';
+CREATE SCHEMA app_prod;
+CREATE TABLE app_prod.notes (id integer, body text);
+ALTER SEQUENCE app_prod.notes_id_seq OWNED BY app_prod.notes.id;
+CREATE VIEW public.v AS SELECT body FROM app_prod.public.notes WHERE app_prod.public.notes.id > 0;
+INSERT INTO app_prod.public.notes (id, body) VALUES (1, 'see app_prod.public.notes; \connect app_prod');
+COPY public.notes (id, body) FROM stdin;
+1	app_prod.public.notes
+\.
+-- app_prod.public.notes in a comment
+"#;
+        let expected = r#"CREATE DATABASE app_dev WITH TEMPLATE = template0;
+ALTER DATABASE app_dev OWNER TO app_prod_owner;
+\connect app_dev
+COMMENT ON DATABASE "app_dev" IS 'Production copy of app_prod';
+GRANT CONNECT ON DATABASE app_dev, reports TO app_prod_reader;
+ALTER ROLE app_prod_reader IN DATABASE app_dev SET work_mem TO '64MB';
+CREATE SCHEMA app_prod;
+CREATE TABLE app_prod.notes (id integer, body text);
+ALTER SEQUENCE app_prod.notes_id_seq OWNED BY app_prod.notes.id;
+CREATE VIEW public.v AS SELECT body FROM app_dev.public.notes WHERE app_dev.public.notes.id > 0;
+INSERT INTO app_dev.public.notes (id, body) VALUES (1, 'see app_prod.public.notes; \connect app_prod');
+COPY public.notes (id, body) FROM stdin;
+1	app_prod.public.notes
+\.
+-- app_prod.public.notes in a comment
+"#;
+        assert_eq!(rename(dump), expected);
+    }
+
+    #[test]
+    fn test_renames_connect_arguments() {
+        assert_eq!(rename("\\c app_prod\n"), "\\c app_dev\n");
+        assert_eq!(rename("\\connect \"app_prod\" postgres\n"), "\\connect app_dev postgres\n");
+        assert_eq!(
+            rename("\\connect -reuse-previous=on \"dbname='app_prod'\"\n"),
+            "\\connect -reuse-previous=on \"dbname='app_dev'\"\n"
+        );
+        assert_eq!(rename("\\connect other\n\\restrict app_prod\n"), "\\connect other\n\\restrict app_prod\n");
+        let mut output = Vec::new();
+        rename_references("\\connect app_prod\n".as_bytes(), &mut output, Some(("app_prod", "App Dev")), &BTreeMap::new()).expect("rename");
+        assert_eq!(output, b"\\connect \"App Dev\"\n");
+    }
+
+    #[test]
+    fn test_renames_mapped_schemas() -> Result<()> {
+        let dump = r#"CREATE SCHEMA app;
+COMMENT ON SCHEMA public IS 'standard public schema';
+CREATE TYPE public.mood AS ENUM ('happy', 'public.sad');
+CREATE TABLE public.notes (
+    id integer DEFAULT nextval('public.notes_id_seq'::regclass) NOT NULL,
+    mood public.mood,
+    body text DEFAULT 'public.notes'
+);
+ALTER SEQUENCE public.notes_id_seq OWNED BY public.notes.id;
+CREATE VIEW app.v AS SELECT notes.body FROM public.notes;
+CREATE FUNCTION public.f() RETURNS bigint LANGUAGE sql AS $$SELECT count(*) FROM public.notes$$;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public GRANT SELECT ON TABLES TO reader;
+COPY public.notes (id, mood, body) FROM stdin;
+1	happy	public.notes
+\.
+SELECT pg_catalog.setval('public.notes_id_seq', 1, true);
+INSERT INTO "public"."notes" VALUES (2, 'happy', 'public.notes');
+"#;
+        let expected = r#"CREATE SCHEMA IF NOT EXISTS "App Copy";
+COMMENT ON SCHEMA snapshot_2026_10 IS 'standard public schema';
+CREATE TYPE snapshot_2026_10.mood AS ENUM ('happy', 'public.sad');
+CREATE TABLE snapshot_2026_10.notes (
+    id integer DEFAULT nextval('snapshot_2026_10.notes_id_seq'::regclass) NOT NULL,
+    mood snapshot_2026_10.mood,
+    body text DEFAULT 'public.notes'
+);
+ALTER SEQUENCE snapshot_2026_10.notes_id_seq OWNED BY snapshot_2026_10.notes.id;
+CREATE VIEW "App Copy".v AS SELECT notes.body FROM snapshot_2026_10.notes;
+CREATE FUNCTION snapshot_2026_10.f() RETURNS bigint LANGUAGE sql AS $$SELECT count(*) FROM public.notes$$;
+ALTER DEFAULT PRIVILEGES IN SCHEMA snapshot_2026_10 GRANT SELECT ON TABLES TO reader;
+COPY snapshot_2026_10.notes (id, mood, body) FROM stdin;
+1	happy	public.notes
+\.
+SELECT pg_catalog.setval('snapshot_2026_10.notes_id_seq', 1, true);
+INSERT INTO "snapshot_2026_10"."notes" VALUES (2, 'happy', 'public.notes');
+"#;
+        let mapping = BTreeMap::from([
+            ("public".to_string(), "snapshot_2026_10".to_string()),
+            ("app".to_string(), "App Copy".to_string()),
+        ]);
+        let mut output = Vec::new();
+        rename_references(dump.as_bytes(), &mut output, None, &mapping)?;
+        assert_eq!(String::from_utf8(output)?, expected);
+
+        assert_eq!(rename_schemas("SELECT 'public'::regnamespace, 'app.v'::pg_catalog.regclass", &mapping), "SELECT 'snapshot_2026_10'::regnamespace, '\"App Copy\".v'::pg_catalog.regclass");
+        assert_eq!(rename_schemas("GRANT SELECT ON TABLE other.notes TO reader;", &mapping), "GRANT SELECT ON TABLE other.notes TO reader;");
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyword_targets_are_quoted() -> Result<()> {
+        let dump = "ALTER DATABASE app_prod OWNER TO app;\n\\connect app_prod\nCREATE TABLE public.notes (id integer);\nSELECT pg_catalog.setval('public.notes_id_seq', 1, true);\n";
+        let mapping = BTreeMap::from([("public".to_string(), "order".to_string())]);
+        let mut output = Vec::new();
+        rename_references(dump.as_bytes(), &mut output, Some(("app_prod", "user")), &mapping)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "ALTER DATABASE \"user\" OWNER TO app;\n\\connect user\nCREATE TABLE \"order\".notes (id integer);\nSELECT pg_catalog.setval('\"order\".notes_id_seq', 1, true);\n"
+        );
+        Ok(())
+    }
+}
@@ -1,11 +1,18 @@
 // databasetool/src/restore/s3_download.rs
 use anyhow::{Context, Result};
 use aws_sdk_s3 as s3;
-use s3::config::Region;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt; // For write_all
+use std::sync::Arc;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
+use crate::backup::s3_upload::{self, with_retries};
 use crate::config::SpacesConfig;
 
 /// Parses an S3 URI (s3://bucket/key) into bucket and key.
@@ -23,8 +30,65 @@ pub fn parse_s3_uri(s3_uri: &str) -> Result<(String, String)> {
     Ok((bucket, key))
 }
 
+/// Progress of a download, saved next to the partial file so an interrupted download can resume.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct DownloadState {
+    bucket: String,
+    key: String,
+    e_tag: String,
+    size: u64,
+    chunk_size: u64,
+    completed_chunks: BTreeSet<u64>,
+}
+
+impl DownloadState {
+    fn matches(&self, other: &DownloadState) -> bool {
+        self.bucket == other.bucket
+            && self.key == other.key
+            && self.e_tag == other.e_tag
+            && self.size == other.size
+            && self.chunk_size == other.chunk_size
+    }
+}
+
+/// Where the incomplete download of `destination_path` is written.
+fn partial_download_path(destination_path: &Path) -> PathBuf {
+    let file_name = destination_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    destination_path.with_file_name(format!("{}.part", file_name))
+}
+
+/// Where the progress of the download of `destination_path` is saved.
+fn download_state_path(destination_path: &Path) -> PathBuf {
+    let file_name = destination_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    destination_path.with_file_name(format!(".{}.download", file_name))
+}
+
+fn read_download_state(state_path: &Path) -> Option<DownloadState> {
+    let content = std::fs::read_to_string(state_path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn write_download_state(state_path: &Path, state: &DownloadState) -> Result<()> {
+    let json = serde_json::to_string(state).context("Failed to serialize download state")?;
+    std::fs::write(state_path, json)
+        .with_context(|| format!("Failed to write download state: {}", state_path.display()))
+}
+
+/// Number of `chunk_size` chunks needed for `size` bytes.
+fn chunk_count(size: u64, chunk_size: u64) -> u64 {
+    size.div_ceil(chunk_size)
+}
+
 /// Downloads a file from an S3-compatible object storage service.
 ///
+/// The object is fetched in ranged GETs of `transfer.part_size_bytes`, `transfer.concurrency`
+/// at a time, each retried with backoff and pinned to the ETag seen at the start so a changing
+/// object cannot be mixed into the file. Data goes to `<destination>.part`; the completed chunks
+/// are recorded next to it, so running the same download again after an interruption only
+/// fetches what is missing. The finished file is checked against Content-Length and, where the
+/// ETag is an MD5, against the ETag before it is moved to `destination_path`. Any failure is a
+/// hard error; a truncated archive is never returned.
+///
 /// # Arguments
 /// * `spaces_config` - Configuration for the S3-compatible service.
 /// * `s3_bucket` - The name of the S3 bucket.
@@ -35,7 +99,7 @@ pub fn parse_s3_uri(s3_uri: &str) -> Result<(String, String)> {
 /// Path to the downloaded file.
 pub async fn download_file_from_s3(
     spaces_config: &SpacesConfig,
-    s3_bucket: &str, 
+    s3_bucket: &str,
     s3_key: &str,
     destination_path: &Path,
 ) -> Result<PathBuf> {
@@ -46,55 +110,347 @@ pub async fn download_file_from_s3(
         destination_path.display()
     );
 
-    if let Some(parent_dir) = destination_path.parent() {
-        if !parent_dir.exists() {
-            tokio::fs::create_dir_all(parent_dir)
-                .await
-                .with_context(|| format!("Failed to create directory for download: {}", parent_dir.display()))?;
-        }
+    if let Some(parent_dir) = destination_path.parent()
+        && !parent_dir.exists()
+    {
+        tokio::fs::create_dir_all(parent_dir)
+            .await
+            .with_context(|| format!("Failed to create directory for download: {}", parent_dir.display()))?;
     }
 
-    let sdk_config = aws_config::defaults(s3::config::BehaviorVersion::latest())
-        .endpoint_url(&spaces_config.endpoint_url)
-        .region(Region::new(spaces_config.region.clone()))
-        .credentials_provider(s3::config::Credentials::new(
-            &spaces_config.access_key_id,
-            &spaces_config.secret_access_key,
-            None, // session_token
-            None, // expiry
-            "Static", // provider_name
-        ))
-        .load()
-        .await;
+    let client = s3_upload::build_s3_client(spaces_config).await;
+    let transfer = spaces_config.transfer;
 
-    let client = s3::Client::new(&sdk_config);
+    let head = with_retries(transfer.max_retries, &format!("Reading metadata of s3://{}/{}", s3_bucket, s3_key), || async {
+        client
+            .head_object()
+            .bucket(s3_bucket)
+            .key(s3_key)
+            .send()
+            .await
+            .with_context(|| format!("Failed to get object metadata for s3://{}/{}", s3_bucket, s3_key))
+    })
+    .await?;
+    let size = head
+        .content_length()
+        .filter(|length| *length >= 0)
+        .context("S3 did not return the Content-Length of the archive")? as u64;
+    let e_tag = head.e_tag().context("S3 did not return an ETag for the archive")?.to_string();
 
-    let mut output_file = File::create(destination_path)
-        .await
-        .with_context(|| format!("Failed to create destination file: {}", destination_path.display()))?;
+    let partial_path = partial_download_path(destination_path);
+    let state_path = download_state_path(destination_path);
+    let fresh_state = DownloadState {
+        bucket: s3_bucket.to_string(),
+        key: s3_key.to_string(),
+        e_tag: e_tag.clone(),
+        size,
+        chunk_size: transfer.part_size_bytes,
+        completed_chunks: BTreeSet::new(),
+    };
+    let mut state = match read_download_state(&state_path) {
+        Some(saved) if saved.matches(&fresh_state) && partial_path.is_file() => {
+            println!(
+                "Resuming download of s3://{}/{} ({} of {} chunk(s) already downloaded)",
+                s3_bucket,
+                s3_key,
+                saved.completed_chunks.len(),
+                chunk_count(size, saved.chunk_size)
+            );
+            saved
+        }
+        _ => {
+            let file = File::create(&partial_path)
+                .await
+                .with_context(|| format!("Failed to create destination file: {}", partial_path.display()))?;
+            file.set_len(size)
+                .await
+                .with_context(|| format!("Failed to allocate {} bytes for {}", size, partial_path.display()))?;
+            write_download_state(&state_path, &fresh_state)?;
+            fresh_state
+        }
+    };
 
-    let mut object = client
-        .get_object()
-        .bucket(s3_bucket)
-        .key(s3_key)
-        .send()
+    download_missing_chunks(&client, &mut state, &partial_path, &state_path, transfer.concurrency, transfer.max_retries)
         .await
-        .with_context(|| format!("Failed to get object s3://{}/{}", s3_bucket, s3_key))?;
+        .with_context(|| {
+            format!(
+                "Download of s3://{}/{} is incomplete; run the restore again to resume it",
+                s3_bucket, s3_key
+            )
+        })?;
 
-    let mut total_bytes_downloaded = 0;
-    // Corrected loop pattern here:
-    while let Ok(Some(bytes_chunk)) = object.body.try_next().await {
-        output_file.write_all(&bytes_chunk).await // Use write_all, which takes &[u8]
-            .with_context(|| format!("Failed to write to destination file: {}", destination_path.display()))?;
-        total_bytes_downloaded += bytes_chunk.len();
+    let downloaded_size = tokio::fs::metadata(&partial_path)
+        .await
+        .with_context(|| format!("Failed to read metadata of {}", partial_path.display()))?
+        .len();
+    if downloaded_size != size {
+        anyhow::bail!(
+            "Downloaded {} bytes of s3://{}/{} but Content-Length is {}",
+            downloaded_size,
+            s3_bucket,
+            s3_key,
+            size
+        );
+    }
+    if let Err(e) = verify_e_tag(&client, s3_bucket, s3_key, &partial_path, &e_tag).await {
+        // A corrupt file must not be resumed from; start over next time.
+        let _ = std::fs::remove_file(&partial_path);
+        let _ = std::fs::remove_file(&state_path);
+        return Err(e);
     }
-    
+
+    tokio::fs::rename(&partial_path, destination_path)
+        .await
+        .with_context(|| format!("Failed to move {} to {}", partial_path.display(), destination_path.display()))?;
+    let _ = std::fs::remove_file(&state_path);
+
     println!(
         "✅ Successfully downloaded {} bytes from s3://{}/{} to {}",
-        total_bytes_downloaded,
+        size,
         s3_bucket,
         s3_key,
         destination_path.display()
     );
     Ok(destination_path.to_path_buf())
-}
\ No newline at end of file
+}
+
+/// Fetches every chunk not yet recorded in `state`, saving progress after each one.
+async fn download_missing_chunks(
+    client: &s3::Client,
+    state: &mut DownloadState,
+    partial_path: &Path,
+    state_path: &Path,
+    concurrency: usize,
+    max_retries: u32,
+) -> Result<()> {
+    let total_chunks = chunk_count(state.size, state.chunk_size);
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let mut join_set = JoinSet::new();
+    for index in (0..total_chunks).filter(|index| !state.completed_chunks.contains(index)) {
+        let semaphore = Arc::clone(&semaphore);
+        let client = client.clone();
+        let (bucket, key, e_tag) = (state.bucket.clone(), state.key.clone(), state.e_tag.clone());
+        let partial_path = partial_path.to_path_buf();
+        let offset = index * state.chunk_size;
+        let len = state.chunk_size.min(state.size - offset);
+        join_set.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.context("Download semaphore closed")?;
+            let description = format!("Download of bytes {}-{} of {}", offset, offset + len - 1, key);
+            let data = with_retries(max_retries, &description, || {
+                fetch_range(&client, &bucket, &key, &e_tag, offset, len)
+            })
+            .await?;
+            let mut file = OpenOptions::new()
+                .write(true)
+                .open(&partial_path)
+                .await
+                .with_context(|| format!("Failed to open {}", partial_path.display()))?;
+            file.seek(SeekFrom::Start(offset)).await.context("Failed to seek in partial download")?;
+            file.write_all(&data).await.context("Failed to write partial download")?;
+            file.sync_data().await.context("Failed to flush partial download")?;
+            Ok::<_, anyhow::Error>(index)
+        });
+    }
+
+    while let Some(joined) = join_set.join_next().await {
+        let index = match joined.context("Chunk download task panicked").and_then(|result| result) {
+            Ok(index) => index,
+            Err(e) => {
+                join_set.abort_all();
+                return Err(e);
+            }
+        };
+        state.completed_chunks.insert(index);
+        write_download_state(state_path, state)?;
+        let downloaded = (state.completed_chunks.len() as u64 * state.chunk_size).min(state.size);
+        println!(
+            "   Downloaded chunk {}/{} ({} of {})",
+            index + 1,
+            total_chunks,
+            crate::catalog::format_size(downloaded),
+            crate::catalog::format_size(state.size)
+        );
+    }
+    Ok(())
+}
+
+/// Fetches `len` bytes at `offset`, failing if the object changed or the body is short.
+async fn fetch_range(client: &s3::Client, bucket: &str, key: &str, e_tag: &str, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let range = format!("bytes={}-{}", offset, offset + len - 1);
+    let object = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .range(&range)
+        .if_match(e_tag)
+        .send()
+        .await
+        .with_context(|| format!("Failed to get {} of s3://{}/{}", range, bucket, key))?;
+    if let Some(content_length) = object.content_length()
+        && content_length as u64 != len
+    {
+        anyhow::bail!("S3 returned {} bytes for {} of s3://{}/{}", content_length, range, bucket, key);
+    }
+    let data = object
+        .body
+        .collect()
+        .await
+        .with_context(|| format!("Failed to read {} of s3://{}/{}", range, bucket, key))?
+        .to_vec();
+    if data.len() as u64 != len {
+        anyhow::bail!("Received {} of {} bytes for {} of s3://{}/{}", data.len(), len, range, bucket, key);
+    }
+    Ok(data)
+}
+
+/// Checks the downloaded file against the object's ETag when the ETag is derived from MD5.
+///
+/// Single-part uploads have the MD5 of the object as ETag. Multipart uploads have
+/// `md5(md5(part 1) .. md5(part N))-N`; the part size is read from the first part's headers.
+/// ETags that are neither (e.g. with SSE-KMS) are skipped with a note.
+async fn verify_e_tag(client: &s3::Client, bucket: &str, key: &str, file_path: &Path, e_tag: &str) -> Result<()> {
+    let e_tag = e_tag.trim_matches('"');
+    let expected_parts = match e_tag.split_once('-') {
+        None => None,
+        Some((_, parts)) => Some(parts.parse::<u64>().with_context(|| format!("Unexpected ETag format: {}", e_tag))?),
+    };
+
+    let part_size = match expected_parts {
+        None => None,
+        Some(_) => {
+            let head = client.head_object().bucket(bucket).key(key).part_number(1).send().await;
+            match head.ok().and_then(|head| head.content_length()) {
+                Some(length) if length > 0 => Some(length as u64),
+                _ => {
+                    println!("Note: could not read the part size of s3://{}/{}; ETag not verified.", bucket, key);
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    let computed = compute_e_tag(file_path, part_size).await?;
+    if computed != e_tag {
+        let looks_like_md5 = e_tag.split('-').next().is_some_and(|hash| hash.len() == 32 && hash.chars().all(|c| c.is_ascii_hexdigit()));
+        if !looks_like_md5 {
+            println!("Note: ETag of s3://{}/{} is not MD5 based; only the size was verified.", bucket, key);
+            return Ok(());
+        }
+        anyhow::bail!(
+            "Downloaded archive does not match s3://{}/{}: ETag is {} but the file hashes to {}",
+            bucket,
+            key,
+            e_tag,
+            computed
+        );
+    }
+    println!("✓ Downloaded archive matches ETag {}", e_tag);
+    Ok(())
+}
+
+/// Computes the S3 ETag of a file: its MD5, or the multipart ETag when uploaded in `part_size` parts.
+async fn compute_e_tag(file_path: &Path, part_size: Option<u64>) -> Result<String> {
+    let mut file = File::open(file_path)
+        .await
+        .with_context(|| format!("Failed to open {}", file_path.display()))?;
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut whole = Md5::new();
+    let mut part = Md5::new();
+    let mut part_digests = Vec::new();
+    let mut in_part = 0u64;
+    loop {
+        let read = file.read(&mut buffer).await.context("Failed to read downloaded archive")?;
+        if read == 0 {
+            break;
+        }
+        let mut data = &buffer[..read];
+        while !data.is_empty() {
+            let take = match part_size {
+                Some(part_size) => data.len().min((part_size - in_part) as usize),
+                None => data.len(),
+            };
+            whole.update(&data[..take]);
+            part.update(&data[..take]);
+            in_part += take as u64;
+            if part_size == Some(in_part) {
+                part_digests.extend_from_slice(&std::mem::take(&mut part).finalize());
+                in_part = 0;
+            }
+            data = &data[take..];
+        }
+    }
+
+    match part_size {
+        None => Ok(hex::encode(whole.finalize())),
+        Some(_) => {
+            let mut part_count = part_digests.len() as u64 / 16;
+            if in_part > 0 || part_count == 0 {
+                part_digests.extend_from_slice(&part.finalize());
+                part_count += 1;
+            }
+            Ok(format!("{}-{}", hex::encode(Md5::digest(&part_digests)), part_count))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_s3_uri() -> Result<()> {
+        assert_eq!(
+            parse_s3_uri("s3://bucket/backups/2024-05-01_02-00-00.tar.gz")?,
+            ("bucket".to_string(), "backups/2024-05-01_02-00-00.tar.gz".to_string())
+        );
+        assert!(parse_s3_uri("s3://bucket/").is_err());
+        assert!(parse_s3_uri("https://bucket/key").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_count() {
+        assert_eq!(chunk_count(0, 5), 0);
+        assert_eq!(chunk_count(10, 5), 2);
+        assert_eq!(chunk_count(11, 5), 3);
+    }
+
+    #[tokio::test]
+    async fn test_compute_e_tag_single_and_multipart() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let file_path = temp_dir.path().join("archive.tar.gz");
+        let content: Vec<u8> = (0..2_500_000u32).map(|i| (i % 253) as u8).collect();
+        std::fs::write(&file_path, &content)?;
+
+        assert_eq!(compute_e_tag(&file_path, None).await?, hex::encode(Md5::digest(&content)));
+
+        let part_size = 1_000_000;
+        let mut digests = Vec::new();
+        for chunk in content.chunks(part_size) {
+            digests.extend_from_slice(&Md5::digest(chunk));
+        }
+        let expected = format!("{}-3", hex::encode(Md5::digest(&digests)));
+        assert_eq!(compute_e_tag(&file_path, Some(part_size as u64)).await?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_state_only_resumes_same_object() {
+        let state = DownloadState {
+            bucket: "bucket".to_string(),
+            key: "db/2024-05-01_02-00-00.tar.gz".to_string(),
+            e_tag: "\"abc\"".to_string(),
+            size: 100,
+            chunk_size: 10,
+            completed_chunks: BTreeSet::from([0, 1]),
+        };
+        let mut fresh = DownloadState { completed_chunks: BTreeSet::new(), ..state.clone() };
+        assert!(state.matches(&fresh));
+        fresh.e_tag = "\"def\"".to_string();
+        assert!(!state.matches(&fresh));
+        assert_eq!(
+            download_state_path(Path::new("/tmp/x/a.tar.gz")),
+            PathBuf::from("/tmp/x/.a.tar.gz.download")
+        );
+    }
+}
@@ -0,0 +1,191 @@
+// databasetool/src/restore/safety.rs
+use anyhow::{Context, Result};
+use std::io::{self, BufRead, Write};
+use url::Url;
+
+use crate::config::SafetyConfig;
+use crate::restore::plan::{PlannedDatabase, TargetAction};
+use crate::utils::table_filter::matches_pattern;
+
+/// Reason shown when a protected target would be modified.
+pub const PROTECTED_REASON: &str = "is protected (protected_hosts/protected_databases) and cannot be dropped or overwritten";
+
+/// Drop, truncation or an overwrite of an existing database; creating a new one is not destructive.
+fn is_destructive(action: TargetAction) -> bool {
+    matches!(action, TargetAction::DropAndCreate | TargetAction::RestoreIntoExisting)
+}
+
+fn describe(target: &PlannedDatabase) -> &'static str {
+    match (target.action, target.truncated_schema.is_some()) {
+        (TargetAction::DropAndCreate, _) => "dropped and recreated",
+        (TargetAction::RestoreIntoExisting, true) => "truncated and overwritten",
+        _ => "overwritten",
+    }
+}
+
+/// Explains why `db_name` on the server of `target_db_url` is protected, if it is.
+pub fn protection_match(safety: &SafetyConfig, target_db_url: &str, db_name: &str) -> Result<Option<String>> {
+    if let Some(pattern) = safety.protected_databases.iter().find(|pattern| matches_pattern(pattern, db_name)) {
+        return Ok(Some(format!("database '{}' matches protected_databases entry '{}'", db_name, pattern)));
+    }
+
+    protected_host_match(safety, target_db_url)
+}
+
+/// Explains why the server of `db_url` is protected, if it is.
+pub fn protected_host_match(safety: &SafetyConfig, db_url: &str) -> Result<Option<String>> {
+    let url = Url::parse(db_url).context("Invalid target database URL")?;
+    let host = url.host_str().unwrap_or_default();
+    let host_port = format!("{}:{}", host, url.port().unwrap_or(5432));
+    if let Some(pattern) = safety
+        .protected_hosts
+        .iter()
+        .find(|pattern| matches_pattern(pattern, host) || matches_pattern(pattern, &host_port))
+    {
+        return Ok(Some(format!("host '{}' matches protected_hosts entry '{}'", host_port, pattern)));
+    }
+    Ok(None)
+}
+
+/// Applies the protection of a target to a planned action: destructive actions on protected
+/// targets become refusals.
+pub fn guard_action(safety: &SafetyConfig, target_db_url: &str, db_name: &str, action: TargetAction) -> Result<TargetAction> {
+    if is_destructive(action) && protection_match(safety, target_db_url, db_name)?.is_some() {
+        return Ok(TargetAction::Refuse(PROTECTED_REASON));
+    }
+    Ok(action)
+}
+
+/// Checks every target before anything is changed.
+///
+/// Refuses destructive actions on protected targets outright. Targets that already hold
+/// tables must then be confirmed by typing their name, unless `--yes-i-know` was given;
+/// without a terminal (`--non-interactive`) the run stops instead of asking.
+///
+/// # Arguments
+/// * `safety` - Protected targets and confirmation flags.
+/// * `target_db_url` - URL of the target server.
+/// * `targets` - Every database the run will touch, with the action planned for it.
+pub fn confirm_destructive_actions(safety: &SafetyConfig, target_db_url: &str, targets: &[PlannedDatabase]) -> Result<()> {
+    let mut refused = Vec::new();
+    for target in targets.iter().filter(|target| is_destructive(target.action)) {
+        if let Some(reason) = protection_match(safety, target_db_url, &target.target_name)? {
+            refused.push(format!("'{}' would be {}, but {}", target.target_name, describe(target), reason));
+        }
+    }
+    if !refused.is_empty() {
+        anyhow::bail!(
+            "🛑 Refusing to modify protected target(s); nothing was changed:\n  {}\nRemove the entry from config.json if this target really should be replaced.",
+            refused.join("\n  ")
+        );
+    }
+
+    let at_risk: Vec<&PlannedDatabase> = targets
+        .iter()
+        .filter(|target| is_destructive(target.action) && !target.target.row_counts.is_empty())
+        .collect();
+    if at_risk.is_empty() {
+        return Ok(());
+    }
+    if safety.assume_yes {
+        for target in &at_risk {
+            println!("⚠️  --yes-i-know: '{}' will be {} without confirmation.", target.target_name, describe(target));
+        }
+        return Ok(());
+    }
+    if !safety.interactive {
+        anyhow::bail!(
+            "Target database(s) {} hold data and would be dropped, truncated or overwritten. \
+             Run interactively to confirm, or pass --yes-i-know.",
+            at_risk.iter().map(|target| format!("'{}'", target.target_name)).collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    for target in at_risk {
+        let rows: i64 = target.target.row_counts.values().sum();
+        println!(
+            "\n⚠️  Database '{}' holds {}{} row(s) in {} table(s) and will be {}.",
+            target.target_name,
+            if target.target.estimated { "about " } else { "" },
+            rows,
+            target.target.row_counts.len(),
+            describe(target)
+        );
+        print!("Type the database name to confirm: ");
+        io::stdout().flush().context("Failed to flush stdout")?;
+        let mut answer = String::new();
+        input.read_line(&mut answer).context("Failed to read confirmation")?;
+        if answer.trim() != target.target_name {
+            anyhow::bail!("Confirmation for '{}' did not match; nothing was changed.", target.target_name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::restore::plan::TargetState;
+    use std::collections::BTreeMap;
+
+    fn safety(hosts: &[&str], databases: &[&str]) -> SafetyConfig {
+        SafetyConfig {
+            protected_hosts: hosts.iter().map(|s| s.to_string()).collect(),
+            protected_databases: databases.iter().map(|s| s.to_string()).collect(),
+            assume_yes: false,
+            interactive: false,
+        }
+    }
+
+    fn pending(db_name: &str, action: TargetAction, tables: usize) -> PlannedDatabase {
+        PlannedDatabase {
+            source_name: db_name.to_string(),
+            target_name: db_name.to_string(),
+            action,
+            steps: Vec::new(),
+            truncated_schema: None,
+            target: TargetState {
+                exists: action != TargetAction::Create,
+                active_connections: 0,
+                row_counts: (0..tables).map(|i| (format!("public.t{}", i), 10)).collect::<BTreeMap<_, _>>(),
+                estimated: false,
+            },
+        }
+    }
+
+    #[test]
+    fn test_protected_targets_are_refused() -> Result<()> {
+        let url = "postgres://admin@db1.prod.example.com:5432/postgres";
+        let config = safety(&["*.prod.example.com"], &["billing"]);
+        assert!(protection_match(&config, url, "app")?.is_some());
+        assert!(protection_match(&config, "postgres://localhost/", "billing")?.is_some());
+        assert!(protection_match(&config, "postgres://localhost/", "app")?.is_none());
+        assert!(protection_match(&safety(&["localhost:5433"], &[]), "postgres://localhost/", "app")?.is_none());
+        assert!(protection_match(&safety(&["localhost:5432"], &[]), "postgres://localhost/", "app")?.is_some());
+        assert!(protected_host_match(&config, "postgres://localhost/")?.is_none());
+        assert!(protected_host_match(&config, url)?.is_some());
+
+        // Creating a new database on a protected host is allowed; replacing one is not.
+        confirm_destructive_actions(&config, url, &[pending("new_db", TargetAction::Create, 0)])?;
+        let err = confirm_destructive_actions(&config, url, &[pending("app", TargetAction::DropAndCreate, 0)]).unwrap_err();
+        assert!(err.to_string().contains("Refusing"), "{}", err);
+        assert_eq!(guard_action(&config, url, "app", TargetAction::RestoreIntoExisting)?, TargetAction::Refuse(PROTECTED_REASON));
+        Ok(())
+    }
+
+    #[test]
+    fn test_non_empty_targets_need_confirmation() -> Result<()> {
+        let url = "postgres://localhost/";
+        let mut config = safety(&[], &[]);
+        // Empty targets and new databases go ahead.
+        confirm_destructive_actions(&config, url, &[pending("app", TargetAction::DropAndCreate, 0)])?;
+        // Non-interactive runs stop instead of asking.
+        let err = confirm_destructive_actions(&config, url, &[pending("app", TargetAction::DropAndCreate, 2)]).unwrap_err();
+        assert!(err.to_string().contains("--yes-i-know"), "{}", err);
+        config.assume_yes = true;
+        confirm_destructive_actions(&config, url, &[pending("app", TargetAction::RestoreIntoExisting, 2)])?;
+        Ok(())
+    }
+}
@@ -1,73 +1,307 @@
 // databasetool/src/restore/verification.rs
-use anyhow::Result;
-use sqlx::{Pool, Postgres};
+use anyhow::{Context, Result};
+use sqlx::{Connection, Pool, Postgres};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+use crate::backup::manifest::{self, DatabaseStats, ManifestDatabase};
 use crate::config::RestoreConfig;
 use crate::utils::sequence_reset;
+use crate::utils::table_filter::{TableName, TableSelection};
+
+/// One way the restored database differs from the backup manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub item: String, // e.g. "rows public.items", "checksum public.items", "indexes"
+    pub expected: String,
+    pub found: String,
+}
 
-/// Verifies the integrity of the restored database.
+/// Compares what the manifest expects with what was measured after the restore.
 ///
-/// This function can check for table existence, row counts, or specific data
-/// as defined by the verification strategy.
+/// Every table in `expected.row_counts` must have been counted; a table that is not in
+/// `actual.row_counts` was not restored. Checksums are compared for the tables in
+/// `expected.table_checksums` that were restored, and object counts when `expected` has any.
+pub fn compare_stats(expected: &DatabaseStats, actual: &DatabaseStats) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    for (table, rows) in &expected.row_counts {
+        match actual.row_counts.get(table) {
+            None => differences.push(Difference {
+                item: format!("rows {}", table),
+                expected: rows.to_string(),
+                found: "table missing".to_string(),
+            }),
+            Some(found) if found != rows => differences.push(Difference {
+                item: format!("rows {}", table),
+                expected: rows.to_string(),
+                found: found.to_string(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (table, checksum) in &expected.table_checksums {
+        if let Some(found) = actual.table_checksums.get(table)
+            && found != checksum
+        {
+            differences.push(Difference {
+                item: format!("checksum {}", table),
+                expected: checksum.clone(),
+                found: found.clone(),
+            });
+        }
+    }
+    for kind in manifest::OBJECT_KINDS {
+        if let Some(count) = expected.object_counts.get(kind) {
+            let found = actual.object_counts.get(kind).copied().unwrap_or(0);
+            if found != *count {
+                differences.push(Difference { item: kind.to_string(), expected: count.to_string(), found: found.to_string() });
+            }
+        }
+    }
+    differences
+}
+
+/// The name a table of the manifest (`schema.table`) has after a restore with `schema_mapping`.
+pub fn restored_table_key(key: &str, schema_mapping: &BTreeMap<String, String>) -> String {
+    match key.split_once('.') {
+        Some((schema, name)) if schema_mapping.contains_key(schema) => format!("{}.{}", schema_mapping[schema], name),
+        _ => key.to_string(),
+    }
+}
+
+/// The schemas a restore with `schema_mapping` writes to: the mapped schemas and the schemas of
+/// the tables in the manifest. `None` without a mapping, when the whole database is restored.
+pub fn restored_schemas(schema_mapping: &BTreeMap<String, String>, expected: Option<&ManifestDatabase>) -> Option<BTreeSet<String>> {
+    if schema_mapping.is_empty() {
+        return None;
+    }
+    let mut schemas: BTreeSet<String> = schema_mapping.values().cloned().collect();
+    for key in expected.iter().flat_map(|expected| expected.row_counts.keys()) {
+        if let Some((schema, _)) = restored_table_key(key, schema_mapping).split_once('.') {
+            schemas.insert(schema.to_string());
+        }
+    }
+    Some(schemas)
+}
+
+/// Formats differences as an aligned `item: expected X, found Y` list.
+pub fn render_differences(differences: &[Difference]) -> String {
+    let width = differences.iter().map(|difference| difference.item.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for difference in differences {
+        let _ = write!(
+            out,
+            "\n  {:<width$}  expected {}, found {}",
+            difference.item,
+            difference.expected,
+            difference.found,
+            width = width
+        );
+    }
+    out
+}
+
+/// Verifies the restored database against the statistics the backup recorded in its manifest.
+///
+/// Every table's row count is compared, its content checksum when the backup took one (except
+/// for tables rewritten by masking), and the number of tables, indexes, constraints, sequences,
+/// views and functions. Restore filters are taken into account: tables they drop are skipped,
+/// schema-only tables must be empty, tables limited by a `where` condition are not counted and
+/// object counts are not compared. With a `schema_mapping` the tables are looked up under their
+/// new schema, and objects and sequences outside the restored schemas are left out. Finally,
+/// sequences are reset.
 ///
 /// # Arguments
 /// * `db_pool` - A connection pool to the newly restored database.
-/// * `restore_config` - The restore configuration, which might contain verification parameters.
-/// * `expected_schema_files` - A list of schema files that were restored (e.g., dbname_schema.sql).
-///                             This can be used to infer expected tables.
-/// * `extracted_backup_path` - Path to the directory where backup files were extracted.
+/// * `restore_config` - The restore configuration; its filters decide what is compared.
+/// * `restored_db_name` - Name of the restored database, for messages.
+/// * `expected` - The manifest entry of the database, `None` for archives without a manifest.
+/// * `masked_tables` - Tables whose columns were masked after loading.
 ///
 /// # Returns
-/// `Ok(())` if verification passes, or an `Err` if issues are found.
+/// `Ok(())` if the database matches, or an `Err` listing every difference. Manifests from
+/// before object counts were recorded only produce warnings: their row counts were not taken
+/// in the dump's snapshot.
 pub async fn verify_restore(
     db_pool: &Pool<Postgres>,
-    _restore_config: &RestoreConfig,
-    _restored_db_name: &str,
-    _extracted_backup_path: &std::path::Path,
+    restore_config: &RestoreConfig,
+    restored_db_name: &str,
+    expected: Option<&ManifestDatabase>,
+    masked_tables: &[TableName],
 ) -> Result<()> {
-    println!("Performing basic restore verification for database: {}", _restored_db_name);
-
-    // Example: Check if any tables exist (a very basic check)
-    let tables: Vec<(String,)> = sqlx::query_as(
-        "SELECT tablename FROM pg_catalog.pg_tables WHERE schemaname = 'public'",
-    )
-    .fetch_all(db_pool)
-    .await?;
-
-    if tables.is_empty() {
-        println!("Warning: No tables found in the public schema of the restored database '{}'. Verification might be incomplete or the database is expected to be empty.", _restored_db_name);
-        // Depending on strictness, this could be an error:
-        // return Err(anyhow::anyhow!("No tables found in public schema after restore of '{}'", restored_db_name));
-    } else {
-        println!("Found {} tables in public schema: {:?}", tables.len(), tables.iter().map(|t| &t.0).collect::<Vec<&String>>());
-    }
-
-    // Debug: Check for common system tables (framework-agnostic)
-    println!("Checking for common system tables...");
-    let common_tables = vec!["migrations", "schema_migrations", "users", "permissions"];
-    for table_name in common_tables {
-        let check_query = format!("SELECT EXISTS (SELECT 1 FROM information_schema.tables WHERE table_name = '{}')", table_name);
-        let exists: (bool,) = sqlx::query_as(&check_query)
-            .fetch_one(db_pool)
-            .await
-            .unwrap_or((false,));
-        println!("   Table {} exists: {}", table_name, exists.0);
+    match expected {
+        Some(expected) => {
+            println!("Verifying {} against the backup manifest...", restored_db_name);
+            let (wanted, found) = measure_restored_database(db_pool, restore_config, expected, masked_tables)
+                .await
+                .with_context(|| format!("Failed to measure restored database '{}'", restored_db_name))?;
+            let differences = compare_stats(&wanted, &found);
+            if differences.is_empty() {
+                println!(
+                    "✓ {} table(s), {} checksum(s){} of {} match the manifest.",
+                    wanted.row_counts.len(),
+                    wanted.table_checksums.len(),
+                    if wanted.object_counts.is_empty() { "" } else { " and the object counts" },
+                    restored_db_name
+                );
+            } else if expected.object_counts.is_none() {
+                println!(
+                    "⚠️  {} differs from the row counts of an older manifest (taken after the dump, so concurrent writes may explain it):{}",
+                    restored_db_name,
+                    render_differences(&differences)
+                );
+            } else {
+                anyhow::bail!(
+                    "Restored database {} does not match the backup manifest ({} difference(s)):{}",
+                    restored_db_name,
+                    differences.len(),
+                    render_differences(&differences)
+                );
+            }
+        }
+        None => println!("⚠️  No manifest entry for {}; row counts cannot be verified.", restored_db_name),
     }
 
-    // TODO: Implement more comprehensive verification steps:
-    // 1. Parse schema files from `extracted_backup_path` to get a list of expected tables.
-    //    - For each expected table, query `information_schema.tables` to confirm its existence.
-    // 2. For selected tables (perhaps configured or heuristically chosen):
-    //    - Use `crate::utils::setting::get_row_count` to check if data was loaded (count > 0 if data expected).
-    //    - Compare row counts against metadata potentially stored during backup (advanced).
-    // 3. Check for specific sentinel data if applicable.
-
-    println!("✓ Basic restore verification completed for {}.", _restored_db_name);
-    
     // Reset sequences to prevent migration failures in any framework
-    println!("Starting sequence reset for database: {}", _restored_db_name);
-    sequence_reset::reset_sequences_with_timeout(db_pool, _restored_db_name).await?;
-    println!("✅ Sequence reset completed for {}", _restored_db_name);
-    
+    println!("Starting sequence reset for database: {}", restored_db_name);
+    let schemas = restored_schemas(&restore_config.schema_mapping, expected);
+    sequence_reset::reset_sequences_with_timeout(db_pool, restored_db_name, schemas.as_ref()).await?;
+    println!("✅ Sequence reset completed for {}", restored_db_name);
+
     Ok(())
 }
 
+/// Measures the restored database: returns what the manifest leads us to expect once the restore
+/// filters and masking are accounted for, and what the database actually contains.
+async fn measure_restored_database(
+    db_pool: &Pool<Postgres>,
+    restore_config: &RestoreConfig,
+    expected: &ManifestDatabase,
+    masked_tables: &[TableName],
+) -> Result<(DatabaseStats, DatabaseStats)> {
+    let filters = &restore_config.filters;
+    let schema_mapping = &restore_config.schema_mapping;
+    let mut conn = db_pool.acquire().await.context("Failed to get a connection for verification")?;
+    let mut transaction = conn.begin().await.context("Failed to start the verification transaction")?;
+    manifest::apply_checksum_settings(&mut transaction).await?;
+
+    let present: BTreeMap<String, TableName> = manifest::list_tables(&mut transaction)
+        .await?
+        .into_iter()
+        .map(|table| (table.to_string(), table))
+        .collect();
+
+    let mut wanted = DatabaseStats::default();
+    let mut found = DatabaseStats::default();
+    for (source_key, rows) in &expected.row_counts {
+        let key = &restored_table_key(source_key, schema_mapping);
+        let table = present.get(key).cloned().or_else(|| {
+            key.split_once('.').map(|(schema, name)| TableName { schema: schema.to_string(), name: name.to_string() })
+        });
+        let Some(table) = table else {
+            continue;
+        };
+        if !filters.includes_table(&table) {
+            continue;
+        }
+        let schema_only = !filters.includes_data(&table);
+        if !schema_only && filters.row_condition(&table)?.is_some() {
+            continue;
+        }
+        wanted.row_counts.insert(key.clone(), if schema_only { 0 } else { *rows });
+        let checksum = expected.table_checksums.get(source_key).filter(|_| !schema_only && !masked_tables.contains(&table));
+        if let Some(checksum) = checksum {
+            wanted.table_checksums.insert(key.clone(), checksum.clone());
+        }
+        if !present.contains_key(key) {
+            continue;
+        }
+
+        let (count, actual_checksum): (i64, Option<String>) =
+            sqlx::query_as(&manifest::table_stats_query(&table, None, checksum.is_some()))
+                .fetch_one(&mut *transaction)
+                .await
+                .with_context(|| format!("Failed to count rows of {}", table))?;
+        found.row_counts.insert(key.clone(), count);
+        if let Some(actual_checksum) = actual_checksum {
+            found.table_checksums.insert(key.clone(), actual_checksum);
+        }
+    }
+
+    match &expected.object_counts {
+        Some(object_counts) if filters.is_empty() => {
+            // Other schemas of the database are not part of a restore into mapped schemas
+            let mut selection = TableSelection::default();
+            if let Some(restored) = restored_schemas(schema_mapping, Some(expected)) {
+                let schemas: Vec<String> = sqlx::query_scalar("SELECT nspname::text FROM pg_namespace")
+                    .fetch_all(&mut *transaction)
+                    .await
+                    .context("Failed to list schemas")?;
+                selection.excluded_schemas = schemas.into_iter().filter(|schema| !restored.contains(schema)).collect();
+            }
+            wanted.object_counts = object_counts.clone();
+            found.object_counts = manifest::collect_object_counts(&mut transaction, &selection).await?;
+        }
+        Some(_) => println!("Object counts are not compared: the restore filters removed objects the manifest counts."),
+        None => println!("⚠️  The manifest has no object counts (made by an older version); only rows are compared."),
+    }
+    transaction.commit().await.ok();
+    Ok((wanted, found))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(rows: &[(&str, i64)], checksums: &[(&str, &str)], objects: &[(&str, i64)]) -> DatabaseStats {
+        DatabaseStats {
+            row_counts: rows.iter().map(|(table, count)| (table.to_string(), *count)).collect(),
+            table_checksums: checksums.iter().map(|(table, sum)| (table.to_string(), sum.to_string())).collect(),
+            object_counts: objects.iter().map(|(kind, count)| (kind.to_string(), *count)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_compare_stats_reports_every_difference() {
+        let expected = stats(
+            &[("public.items", 1051), ("sales.orders", 500), ("public.empty", 0)],
+            &[("public.items", "aaa"), ("sales.orders", "bbb")],
+            &[("tables", 3), ("indexes", 4), ("views", 1)],
+        );
+        assert!(compare_stats(&expected, &expected).is_empty());
+
+        let actual = stats(
+            &[("public.items", 1050), ("public.empty", 0)],
+            &[("public.items", "ccc")],
+            &[("tables", 2), ("indexes", 4)],
+        );
+        let differences = compare_stats(&expected, &actual);
+        let items: Vec<&str> = differences.iter().map(|difference| difference.item.as_str()).collect();
+        // The checksum of the missing table is not reported on top of the missing table itself.
+        assert_eq!(items, vec!["rows public.items", "rows sales.orders", "checksum public.items", "tables", "views"]);
+        assert_eq!(differences[1].found, "table missing");
+
+        let rendered = render_differences(&differences);
+        assert!(rendered.contains("\n  rows public.items      expected 1051, found 1050"), "{}", rendered);
+        assert!(rendered.contains("\n  views                  expected 1, found 0"), "{}", rendered);
+    }
+
+    #[test]
+    fn test_restored_schemas_follow_schema_mapping() {
+        let mapping = BTreeMap::from([("public".to_string(), "snapshot_2026_10".to_string())]);
+        assert_eq!(restored_table_key("public.items", &mapping), "snapshot_2026_10.items");
+        assert_eq!(restored_table_key("sales.orders", &mapping), "sales.orders");
+
+        let expected = ManifestDatabase {
+            name: "app".to_string(),
+            files: Vec::new(),
+            row_counts: BTreeMap::from([("public.items".to_string(), 3), ("sales.orders".to_string(), 5)]),
+            table_checksums: BTreeMap::new(),
+            object_counts: None,
+            access: None,
+        };
+        let schemas = restored_schemas(&mapping, Some(&expected)).expect("a mapping limits the schemas");
+        assert_eq!(schemas.into_iter().collect::<Vec<_>>(), vec!["sales", "snapshot_2026_10"]);
+        assert!(restored_schemas(&BTreeMap::new(), Some(&expected)).is_none());
+    }
+}
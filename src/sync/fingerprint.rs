@@ -0,0 +1,253 @@
+// databasetool/src/sync/fingerprint.rs
+use anyhow::{Context, Result};
+use sqlx::{Connection, PgConnection, Row};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::backup::manifest;
+use crate::config::{FingerprintMethod, TableFilters};
+use crate::utils::sql_script::quote_ident;
+use crate::utils::table_filter::TableName;
+
+/// What incremental sync compares for one table. Equal fingerprints mean the table is skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableFingerprint {
+    pub columns: String, // Column names and types in order; a difference means the schema changed
+    pub row_count: i64,
+    pub max_key: Option<String>,        // max() of a single-column primary key (stats)
+    pub max_updated_at: Option<String>, // max(updated_at) when the column exists (stats)
+    pub checksum: Option<String>,       // manifest::rows_checksum over every row (checksum)
+}
+
+/// Outcome of comparing a source database with its target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IncrementalDecision {
+    /// The target cannot be updated table by table; it is dropped and recreated as in a full sync.
+    Full(String),
+    /// Tables to truncate and re-copy, with the reason for each. Empty when the target is up to date.
+    Copy(Vec<(TableName, String)>),
+}
+
+/// Reads the fingerprint of every ordinary table of the database at `db_url`.
+///
+/// Partitioned parents are skipped; their partitions are ordinary tables and compared one by one.
+/// Tables whose data `filters` leave out are skipped as well, and a `where` condition limits
+/// the rows that are fingerprinted, on both servers alike. `row_texts` replaces `t::text` in
+/// the checksum of some tables, so masked columns can be computed on the source before comparing.
+pub async fn collect_fingerprints(
+    db_url: &str,
+    method: FingerprintMethod,
+    filters: &TableFilters,
+    row_texts: &BTreeMap<TableName, String>,
+) -> Result<BTreeMap<TableName, TableFingerprint>> {
+    let mut conn = PgConnection::connect(db_url).await.context("Failed to connect to read table fingerprints")?;
+    // Both servers render values as text the same way, whatever their configuration.
+    let mut transaction = conn.begin().await.context("Failed to start the fingerprint transaction")?;
+    manifest::apply_checksum_settings(&mut transaction).await?;
+
+    let tables = sqlx::query(
+        "SELECT n.nspname AS schema_name, c.relname AS table_name, \
+                (SELECT string_agg(a.attname || ' ' || format_type(a.atttypid, a.atttypmod), ', ' ORDER BY a.attnum) \
+                   FROM pg_attribute a WHERE a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped) AS columns, \
+                (SELECT a.attname FROM pg_index i JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = i.indkey[0] \
+                   WHERE i.indrelid = c.oid AND i.indisprimary AND i.indnatts = 1) AS key_column, \
+                EXISTS(SELECT 1 FROM pg_attribute a WHERE a.attrelid = c.oid AND a.attname = 'updated_at' \
+                   AND a.attnum > 0 AND NOT a.attisdropped) AS has_updated_at \
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+         WHERE c.relkind = 'r' AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+           AND n.nspname NOT LIKE 'pg_toast%' \
+         ORDER BY 1, 2",
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to list tables for fingerprints")?;
+
+    let mut fingerprints = BTreeMap::new();
+    for table in tables {
+        let name = TableName { schema: table.try_get("schema_name")?, name: table.try_get("table_name")? };
+        if !filters.includes_data(&name) {
+            continue;
+        }
+        let condition = filters.row_condition(&name)?.map(|condition| format!(" WHERE ({})", condition)).unwrap_or_default();
+        let columns: Option<String> = table.try_get("columns")?;
+        let key_column: Option<String> = table.try_get("key_column")?;
+        let has_updated_at: bool = table.try_get("has_updated_at")?;
+
+        let query = match method {
+            FingerprintMethod::Stats => format!(
+                "SELECT COUNT(*), {}, {}, NULL::text FROM {} t{}",
+                key_column
+                    .map(|column| format!("MAX(t.{})::text", quote_ident(&column)))
+                    .unwrap_or_else(|| "NULL::text".to_string()),
+                if has_updated_at { "MAX(t.updated_at)::text" } else { "NULL::text" },
+                name.quoted(),
+                condition
+            ),
+            FingerprintMethod::Checksum => {
+                let row_text = row_texts.get(&name).map(String::as_str).unwrap_or("t::text");
+                format!(
+                    "SELECT COUNT(*), NULL::text, NULL::text, {} FROM {} t{}",
+                    manifest::rows_checksum(row_text),
+                    name.quoted(),
+                    condition
+                )
+            }
+        };
+        let (row_count, max_key, max_updated_at, checksum): (i64, Option<String>, Option<String>, Option<String>) =
+            sqlx::query_as(&query)
+                .fetch_one(&mut *transaction)
+                .await
+                .with_context(|| format!("Failed to fingerprint table {}", name))?;
+
+        fingerprints.insert(
+            name,
+            TableFingerprint { columns: columns.unwrap_or_default(), row_count, max_key, max_updated_at, checksum },
+        );
+    }
+    transaction.rollback().await.ok();
+    conn.close().await.ok();
+    Ok(fingerprints)
+}
+
+/// Foreign keys of the database at `db_url` as (referencing table, referenced table) pairs.
+pub async fn collect_foreign_keys(db_url: &str) -> Result<Vec<(TableName, TableName)>> {
+    let mut conn = PgConnection::connect(db_url).await.context("Failed to connect to read foreign keys")?;
+    let rows = sqlx::query(
+        "SELECT fn.nspname AS from_schema, fc.relname AS from_table, tn.nspname AS to_schema, tc.relname AS to_table \
+         FROM pg_constraint k \
+         JOIN pg_class fc ON fc.oid = k.conrelid JOIN pg_namespace fn ON fn.oid = fc.relnamespace \
+         JOIN pg_class tc ON tc.oid = k.confrelid JOIN pg_namespace tn ON tn.oid = tc.relnamespace \
+         WHERE k.contype = 'f'",
+    )
+    .fetch_all(&mut conn)
+    .await
+    .context("Failed to read foreign keys")?;
+    conn.close().await.ok();
+
+    rows.into_iter()
+        .map(|row| {
+            Ok((
+                TableName { schema: row.try_get("from_schema")?, name: row.try_get("from_table")? },
+                TableName { schema: row.try_get("to_schema")?, name: row.try_get("to_table")? },
+            ))
+        })
+        .collect()
+}
+
+/// Compares source and target fingerprints and decides what incremental sync has to do.
+///
+/// A table that is missing on either side or has different columns needs a full sync, because
+/// only data is copied incrementally. Tables that reference a re-copied table through a foreign
+/// key are re-copied too: `TRUNCATE` refuses to empty a referenced table on its own.
+///
+/// # Arguments
+/// * `source` - Fingerprints of the source database.
+/// * `target` - Fingerprints of the target database.
+/// * `foreign_keys` - (referencing, referenced) pairs of the target database.
+pub fn compare_fingerprints(
+    source: &BTreeMap<TableName, TableFingerprint>,
+    target: &BTreeMap<TableName, TableFingerprint>,
+    foreign_keys: &[(TableName, TableName)],
+) -> IncrementalDecision {
+    let mut schema_changes = Vec::new();
+    for (table, fingerprint) in source {
+        match target.get(table) {
+            None => schema_changes.push(format!("{} is missing in the target", table)),
+            Some(existing) if existing.columns != fingerprint.columns => {
+                schema_changes.push(format!("columns of {} differ", table))
+            }
+            Some(_) => {}
+        }
+    }
+    for table in target.keys().filter(|table| !source.contains_key(*table)) {
+        schema_changes.push(format!("{} only exists in the target", table));
+    }
+    if !schema_changes.is_empty() {
+        return IncrementalDecision::Full(format!("schema changed: {}", schema_changes.join("; ")));
+    }
+
+    let mut reasons: BTreeMap<TableName, String> = BTreeMap::new();
+    for (table, fingerprint) in source {
+        let existing = &target[table];
+        let reason = if existing.row_count != fingerprint.row_count {
+            format!("{} row(s) in source, {} in target", fingerprint.row_count, existing.row_count)
+        } else if existing.checksum != fingerprint.checksum {
+            "row checksum differs".to_string()
+        } else if existing.max_key != fingerprint.max_key {
+            "max primary key differs".to_string()
+        } else if existing.max_updated_at != fingerprint.max_updated_at {
+            "max(updated_at) differs".to_string()
+        } else {
+            continue;
+        };
+        reasons.insert(table.clone(), reason);
+    }
+
+    // Pull in everything that references a re-copied table, transitively.
+    let mut pending: BTreeSet<TableName> = reasons.keys().cloned().collect();
+    while let Some(referenced) = pending.pop_first() {
+        for (referencing, _) in foreign_keys.iter().filter(|(from, to)| *to == referenced && from != to) {
+            if !reasons.contains_key(referencing) {
+                reasons.insert(referencing.clone(), format!("references {}", referenced));
+                pending.insert(referencing.clone());
+            }
+        }
+    }
+
+    IncrementalDecision::Copy(reasons.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(name: &str) -> TableName {
+        TableName { schema: "public".to_string(), name: name.to_string() }
+    }
+
+    fn fingerprint(rows: i64, max_key: &str) -> TableFingerprint {
+        TableFingerprint {
+            columns: "id integer".to_string(),
+            row_count: rows,
+            max_key: Some(max_key.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compare_fingerprints_copies_changed_and_referencing_tables() {
+        let source = BTreeMap::from([
+            (table("customers"), fingerprint(10, "10")),
+            (table("orders"), fingerprint(5, "5")),
+            (table("order_lines"), fingerprint(8, "8")),
+            (table("countries"), fingerprint(3, "3")),
+        ]);
+        let mut target = source.clone();
+        assert_eq!(compare_fingerprints(&source, &target, &[]), IncrementalDecision::Copy(Vec::new()));
+
+        target.insert(table("customers"), fingerprint(10, "9"));
+        let foreign_keys = vec![
+            (table("orders"), table("customers")),
+            (table("order_lines"), table("orders")),
+            (table("customers"), table("countries")),
+        ];
+        let IncrementalDecision::Copy(tables) = compare_fingerprints(&source, &target, &foreign_keys) else {
+            panic!("expected an incremental copy");
+        };
+        let names: Vec<String> = tables.iter().map(|(table, _)| table.to_string()).collect();
+        // countries is referenced by customers, not the other way round, and stays.
+        assert_eq!(names, vec!["public.customers", "public.order_lines", "public.orders"]);
+        assert_eq!(tables[0].1, "max primary key differs");
+        assert_eq!(tables[2].1, "references public.customers");
+    }
+
+    #[test]
+    fn test_compare_fingerprints_falls_back_to_full_on_schema_change() {
+        let source = BTreeMap::from([(table("items"), fingerprint(1, "1"))]);
+        let mut target = source.clone();
+        target.get_mut(&table("items")).unwrap().columns = "id bigint".to_string();
+        assert!(matches!(compare_fingerprints(&source, &target, &[]), IncrementalDecision::Full(reason) if reason.contains("columns of public.items")));
+
+        let decision = compare_fingerprints(&source, &BTreeMap::new(), &[]);
+        assert!(matches!(decision, IncrementalDecision::Full(reason) if reason.contains("missing in the target")));
+    }
+}
@@ -1,5 +1,8 @@
 // databasetool/src/sync/mod.rs
 pub(crate) mod logic;
+pub(crate) mod fingerprint; // Per-table fingerprints for incremental sync
+pub(crate) mod stream;      // COPY streaming between servers, table by table
+pub(crate) mod replication; // sync --continuous: logical replication setup, lag and teardown
 
 use anyhow::Result;
 use crate::config::AppConfig;
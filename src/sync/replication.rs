@@ -0,0 +1,489 @@
+// databasetool/src/sync/replication.rs
+use anyhow::{Context, Result};
+use sqlx::{Connection, Executor, PgConnection};
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use crate::config::{ReplicationAction, SyncConfig};
+use crate::restore::db_restore;
+use crate::restore::plan::{self, PlannedDatabase, TargetAction};
+use crate::restore::safety;
+use crate::sync::logic::{find_pg_dump_executable, get_base_url_without_db, target_management_config};
+use crate::sync::stream;
+use crate::utils::find_psql_executable;
+use crate::utils::sql_script::{quote_ident, quote_literal};
+
+/// How often the initial copy is polled.
+const INITIAL_COPY_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long the initial copy may go on without any worker of the subscription running.
+/// PostgreSQL restarts a failed worker after `wal_retrieve_retry_interval` (5s by default).
+const WORKER_START_GRACE: Duration = Duration::from_secs(30);
+
+/// Name of the publication, subscription and replication slot of a database.
+///
+/// Slot names may only contain lower-case letters, digits and underscores, so everything else
+/// in the database name becomes an underscore.
+pub fn replication_name(db_name: &str) -> String {
+    let sanitized: String = db_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    format!("databasetool_{}", sanitized)
+}
+
+/// Replication state of one database, as reported after setup and on every later run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplicationStatus {
+    pub db_name: String,
+    pub worker_running: bool,           // An apply worker is connected to the source
+    pub tables_ready: i64,              // Tables whose initial copy finished
+    pub tables_total: i64,              // Tables in the subscription
+    pub lag_bytes: Option<i64>,         // WAL the source has written that the target has not confirmed
+    pub last_message_secs: Option<f64>, // Seconds since the target last heard from the source
+}
+
+/// Formats the status of every replicated database for the terminal.
+pub fn render_status(statuses: &[ReplicationStatus]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "\n📡 Replication status");
+    let _ = writeln!(out, "{:<24} {:<10} {:>9} {:>14} {:>14}", "DATABASE", "STATE", "TABLES", "LAG", "LAST MESSAGE");
+    for status in statuses {
+        let state = if !status.worker_running {
+            "stopped"
+        } else if status.tables_ready < status.tables_total {
+            "copying"
+        } else {
+            "streaming"
+        };
+        let lag = status
+            .lag_bytes
+            .map(|bytes| format!("{:.1} KiB", bytes as f64 / 1024.0))
+            .unwrap_or_else(|| "-".to_string());
+        let last_message = status
+            .last_message_secs
+            .map(|secs| format!("{:.1}s ago", secs))
+            .unwrap_or_else(|| "-".to_string());
+        let _ = writeln!(
+            out,
+            "{:<24} {:<10} {:>9} {:>14} {:>14}",
+            status.db_name,
+            state,
+            format!("{}/{}", status.tables_ready, status.tables_total),
+            lag,
+            last_message
+        );
+    }
+    out
+}
+
+/// Sets up, reports on or tears down logical replication for every database of the sync.
+///
+/// # Arguments
+/// * `sync_config` - Source, target and publisher URLs, safety and plan settings.
+/// * `databases` - Databases to replicate; each keeps its name on the target.
+/// * `action` - Start (or check) replication, or remove it.
+pub async fn perform_replication_orchestration(
+    sync_config: &SyncConfig,
+    databases: &[String],
+    action: ReplicationAction,
+) -> Result<()> {
+    let planned = plan_replication(sync_config, databases, action).await?;
+    if sync_config.plan_only {
+        let mut guarded = planned;
+        for db in guarded.iter_mut() {
+            db.action = safety::guard_action(&sync_config.safety, &sync_config.target_db_url, &db.target_name, db.action)?;
+        }
+        let title = match action {
+            ReplicationAction::Start => "Continuous sync plan",
+            ReplicationAction::Teardown => "Replication teardown plan",
+        };
+        println!("{}", plan::render_plan(title, &get_base_url_without_db(&sync_config.source_db_url)?, &guarded));
+        return Ok(());
+    }
+    safety::confirm_destructive_actions(&sync_config.safety, &sync_config.target_db_url, &planned)?;
+
+    match action {
+        ReplicationAction::Start => {
+            for (db_name, planned) in databases.iter().zip(&planned) {
+                if planned.action != TargetAction::Unchanged {
+                    start_replication(sync_config, db_name).await?;
+                }
+            }
+            let mut statuses = Vec::with_capacity(databases.len());
+            for db_name in databases {
+                statuses.push(replication_status(sync_config, db_name).await?);
+            }
+            println!("{}", render_status(&statuses));
+        }
+        ReplicationAction::Teardown => {
+            for db_name in databases {
+                teardown_replication(sync_config, db_name).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One plan entry per database: targets that are already subscribed are left alone, all others
+/// are (re)created empty before subscribing, exactly like a one-shot sync.
+async fn plan_replication(
+    sync_config: &SyncConfig,
+    databases: &[String],
+    action: ReplicationAction,
+) -> Result<Vec<PlannedDatabase>> {
+    let mut planned = Vec::with_capacity(databases.len());
+    for db_name in databases {
+        let name = replication_name(db_name);
+        let target = plan::inspect_database(&sync_config.target_db_url, db_name, sync_config.plan_only)
+            .await
+            .with_context(|| format!("Failed to inspect target database '{}'", db_name))?;
+        let subscribed = subscription_exists(&sync_config.target_db_url, &name).await?;
+
+        let (action, steps) = match action {
+            ReplicationAction::Start if subscribed => (
+                TargetAction::Unchanged,
+                vec![format!("subscription {} exists; report its lag", name)],
+            ),
+            ReplicationAction::Start => (
+                plan::decide_target_action(db_name, target.exists, true, true),
+                vec![
+                    format!("CREATE PUBLICATION {} FOR ALL TABLES on the source (if missing)", name),
+                    "pg_dump --schema-only from source | psql".to_string(),
+                    format!("CREATE SUBSCRIPTION {} (copies every table, then streams changes)", name),
+                ],
+            ),
+            ReplicationAction::Teardown => (
+                TargetAction::Unchanged,
+                vec![
+                    "set sequence values from source".to_string(),
+                    format!("DROP SUBSCRIPTION {} (also drops its slot on the source)", name),
+                    format!("DROP PUBLICATION {} on the source", name),
+                ],
+            ),
+        };
+        planned.push(PlannedDatabase {
+            source_name: db_name.clone(),
+            target_name: db_name.clone(),
+            action,
+            steps,
+            truncated_schema: None,
+            target,
+        });
+    }
+    Ok(planned)
+}
+
+/// Creates the publication, a fresh target with the source schema and the subscription, then
+/// waits until the initial copy of every table has finished.
+async fn start_replication(sync_config: &SyncConfig, db_name: &str) -> Result<()> {
+    let pg_dump_path = find_pg_dump_executable()?;
+    let psql_path = find_psql_executable()?;
+    let name = replication_name(db_name);
+    let source_db_url = database_url(&sync_config.source_db_url, db_name)?;
+    let target_db_url = database_url(&sync_config.target_db_url, db_name)?;
+    println!("\n📡 Setting up replication of {} ({})", db_name, name);
+
+    let mut source = PgConnection::connect(&source_db_url)
+        .await
+        .with_context(|| format!("Failed to connect to source database {}", db_name))?;
+    let wal_level: String = sqlx::query_scalar("SHOW wal_level").fetch_one(&mut source).await?;
+    if wal_level != "logical" {
+        anyhow::bail!(
+            "The source server runs with wal_level = {}. Logical replication needs wal_level = logical (set it in postgresql.conf and restart the server).",
+            wal_level
+        );
+    }
+    let published: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM pg_publication WHERE pubname = $1)")
+        .bind(&name)
+        .fetch_one(&mut source)
+        .await?;
+    if !published {
+        source
+            .execute(format!("CREATE PUBLICATION {} FOR ALL TABLES", quote_ident(&name)).as_str())
+            .await
+            .with_context(|| format!("Failed to create publication {} on the source", name))?;
+        println!("✓ Created publication {} on the source.", name);
+    }
+    source.close().await.ok();
+
+    db_restore::manage_target_database(&target_management_config(sync_config), db_name, None)
+        .await
+        .with_context(|| format!("Failed to manage target database (drop/create): {}", db_name))?;
+    // Logical replication carries rows only; the tables must exist on the target first.
+    stream::pipe_schema_section(&pg_dump_path, &psql_path, &source_db_url, &target_db_url, db_name, "pre-data", &[])?;
+    stream::pipe_schema_section(&pg_dump_path, &psql_path, &source_db_url, &target_db_url, db_name, "post-data", &[])?;
+    println!("✓ Schema of {} copied to the target.", db_name);
+
+    let publisher_db_url = database_url(&sync_config.publisher_url, db_name)?;
+    let mut target = PgConnection::connect(&target_db_url)
+        .await
+        .with_context(|| format!("Failed to connect to target database {}", db_name))?;
+    target
+        .execute(
+            format!(
+                "CREATE SUBSCRIPTION {} CONNECTION {} PUBLICATION {} WITH (copy_data = true, slot_name = {})",
+                quote_ident(&name),
+                quote_literal(&publisher_db_url),
+                quote_ident(&name),
+                quote_literal(&name)
+            )
+            .as_str(),
+        )
+        .await
+        .with_context(|| format!("Failed to create subscription {} on the target (check sync_options.publisher_url)", name))?;
+    println!("✓ Created subscription {}; copying the existing rows...", name);
+    println!("  (Interrupting is safe: the copy continues on the server and the next run reports it.)");
+
+    let timeout = sync_config.initial_copy_timeout;
+    let errors_before = subscription_error_count(&mut target, &name).await?;
+    let started = Instant::now();
+    let mut worker_seen = Instant::now();
+    loop {
+        let progress = initial_copy_progress(&mut target, &name).await?;
+        if progress.tables_ready == progress.tables_total {
+            println!("✓ Initial copy of {} finished ({} table(s)).", db_name, progress.tables_total);
+            break;
+        }
+        let still_copying = progress.pending.join(", ");
+        let errors = subscription_error_count(&mut target, &name).await?;
+        if errors > errors_before {
+            anyhow::bail!(
+                "The initial copy of {} failed on the target ({} error(s); still copying: {}). The error is in the target server's log. \
+                 PostgreSQL retries the copy on its own; fix the cause and run sync --continuous again, or --continuous --teardown to stop.",
+                db_name,
+                errors - errors_before,
+                still_copying
+            );
+        }
+        if progress.worker_running {
+            worker_seen = Instant::now();
+        } else if worker_seen.elapsed() >= WORKER_START_GRACE {
+            anyhow::bail!(
+                "No replication worker of subscription {} has run for {}s (still copying: {}). Check the target server's log, \
+                 e.g. whether it can connect to the source at sync_options.publisher_url.",
+                name,
+                WORKER_START_GRACE.as_secs(),
+                still_copying
+            );
+        }
+        if started.elapsed() >= timeout {
+            anyhow::bail!(
+                "The initial copy of {} did not finish within {}s: {}/{} table(s) copied, still copying: {}. \
+                 It continues on the server; run sync --continuous again to see its progress, or raise sync_options.initial_copy_timeout_secs.",
+                db_name,
+                timeout.as_secs(),
+                progress.tables_ready,
+                progress.tables_total,
+                still_copying
+            );
+        }
+        println!("  {}/{} table(s) of {} copied...", progress.tables_ready, progress.tables_total, db_name);
+        tokio::time::sleep(INITIAL_COPY_POLL_INTERVAL).await;
+    }
+    target.close().await.ok();
+    Ok(())
+}
+
+/// Where the initial copy of a subscription stands, read on the target.
+#[derive(Debug)]
+struct InitialCopyProgress {
+    tables_ready: i64,
+    tables_total: i64,
+    pending: Vec<String>, // Tables still being copied, with their state
+    worker_running: bool, // The apply worker or a table sync worker is running
+}
+
+async fn initial_copy_progress(target: &mut PgConnection, name: &str) -> Result<InitialCopyProgress> {
+    let tables: Vec<(String, String)> = sqlx::query_as(
+        "SELECT r.srrelid::regclass::text, r.srsubstate::text \
+         FROM pg_subscription_rel r JOIN pg_subscription s ON s.oid = r.srsubid WHERE s.subname = $1 ORDER BY 1",
+    )
+    .bind(name)
+    .fetch_all(&mut *target)
+    .await
+    .context("Failed to read the state of the initial copy")?;
+    let worker_running: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM pg_stat_subscription WHERE subname = $1 AND pid IS NOT NULL)")
+        .bind(name)
+        .fetch_one(&mut *target)
+        .await
+        .context("Failed to read pg_stat_subscription")?;
+
+    // srsubstate: i = initialize, d = data copy, f = copy finished, s = synchronized, r = ready.
+    let pending: Vec<String> = tables
+        .iter()
+        .filter(|(_, state)| !matches!(state.as_str(), "r" | "s"))
+        .map(|(table, state)| {
+            let state = match state.as_str() {
+                "i" => "waiting",
+                "d" => "copying data",
+                "f" => "catching up",
+                other => other,
+            };
+            format!("{} ({})", table, state)
+        })
+        .collect();
+    Ok(InitialCopyProgress {
+        tables_ready: (tables.len() - pending.len()) as i64,
+        tables_total: tables.len() as i64,
+        pending,
+        worker_running,
+    })
+}
+
+/// Errors the workers of a subscription have hit so far, from `pg_stat_subscription_stats`.
+/// 0 before PostgreSQL 15, which does not count them.
+async fn subscription_error_count(target: &mut PgConnection, name: &str) -> Result<i64> {
+    let counted: bool = sqlx::query_scalar("SELECT current_setting('server_version_num')::int >= 150000")
+        .fetch_one(&mut *target)
+        .await
+        .context("Failed to read the target server version")?;
+    if !counted {
+        return Ok(0);
+    }
+    let errors: Option<i64> =
+        sqlx::query_scalar("SELECT apply_error_count + sync_error_count FROM pg_stat_subscription_stats WHERE subname = $1")
+            .bind(name)
+            .fetch_optional(&mut *target)
+            .await
+            .context("Failed to read pg_stat_subscription_stats")?;
+    Ok(errors.unwrap_or(0))
+}
+
+/// Reads how far the target is behind the source for one database.
+async fn replication_status(sync_config: &SyncConfig, db_name: &str) -> Result<ReplicationStatus> {
+    let name = replication_name(db_name);
+    let mut target = PgConnection::connect(&database_url(&sync_config.target_db_url, db_name)?)
+        .await
+        .with_context(|| format!("Failed to connect to target database {}", db_name))?;
+    let (tables_ready, tables_total): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*) FILTER (WHERE r.srsubstate IN ('r', 's')), COUNT(*) \
+         FROM pg_subscription_rel r JOIN pg_subscription s ON s.oid = r.srsubid WHERE s.subname = $1",
+    )
+    .bind(&name)
+    .fetch_one(&mut target)
+    .await
+    .context("Failed to read subscription tables")?;
+    // The main apply worker is the row without relid; table sync workers have one.
+    let worker: Option<(Option<f64>,)> = sqlx::query_as(
+        "SELECT EXTRACT(EPOCH FROM now() - last_msg_receipt_time)::float8 \
+         FROM pg_stat_subscription WHERE subname = $1 AND relid IS NULL AND pid IS NOT NULL",
+    )
+    .bind(&name)
+    .fetch_optional(&mut target)
+    .await
+    .context("Failed to read pg_stat_subscription")?;
+    target.close().await.ok();
+
+    let mut source = PgConnection::connect(&database_url(&sync_config.source_db_url, db_name)?)
+        .await
+        .with_context(|| format!("Failed to connect to source database {}", db_name))?;
+    let lag_bytes: Option<i64> = sqlx::query_scalar(
+        "SELECT pg_wal_lsn_diff(pg_current_wal_lsn(), confirmed_flush_lsn)::bigint \
+         FROM pg_replication_slots WHERE slot_name = $1",
+    )
+    .bind(&name)
+    .fetch_optional(&mut source)
+    .await
+    .context("Failed to read the replication slot on the source")?
+    .flatten();
+    source.close().await.ok();
+
+    Ok(ReplicationStatus {
+        db_name: db_name.to_string(),
+        worker_running: worker.is_some(),
+        tables_ready,
+        tables_total,
+        lag_bytes,
+        last_message_secs: worker.and_then(|(secs,)| secs),
+    })
+}
+
+/// Drops the subscription (which also drops its slot on the source) and the publication.
+///
+/// Sequences are not replicated, so their source values are copied first; the target can then
+/// take writes without key collisions.
+async fn teardown_replication(sync_config: &SyncConfig, db_name: &str) -> Result<()> {
+    let name = replication_name(db_name);
+    let source_db_url = database_url(&sync_config.source_db_url, db_name)?;
+    let target_db_url = database_url(&sync_config.target_db_url, db_name)?;
+    println!("\n🧹 Tearing down replication of {} ({})", db_name, name);
+
+    if subscription_exists(&sync_config.target_db_url, &name).await? {
+        let sequences = stream::copy_sequence_values(&source_db_url, &target_db_url)
+            .await
+            .with_context(|| format!("Failed to copy sequence values of {}", db_name))?;
+        println!("✓ Set {} sequence value(s) of {}.", sequences, db_name);
+
+        let mut target = PgConnection::connect(&target_db_url)
+            .await
+            .with_context(|| format!("Failed to connect to target database {}", db_name))?;
+        target
+            .execute(format!("DROP SUBSCRIPTION {}", quote_ident(&name)).as_str())
+            .await
+            .with_context(|| format!("Failed to drop subscription {} on the target", name))?;
+        target.close().await.ok();
+        println!("✓ Dropped subscription {} and its replication slot.", name);
+    } else {
+        println!("Subscription {} does not exist on the target.", name);
+    }
+
+    let mut source = PgConnection::connect(&source_db_url)
+        .await
+        .with_context(|| format!("Failed to connect to source database {}", db_name))?;
+    source
+        .execute(format!("DROP PUBLICATION IF EXISTS {}", quote_ident(&name)).as_str())
+        .await
+        .with_context(|| format!("Failed to drop publication {} on the source", name))?;
+    source.close().await.ok();
+    println!("✓ Dropped publication {} on the source. The target keeps its data.", name);
+    Ok(())
+}
+
+/// `pg_subscription` is shared by all databases of a server, so the `postgres` database is enough.
+async fn subscription_exists(server_url: &str, name: &str) -> Result<bool> {
+    let mut admin = PgConnection::connect(&database_url(server_url, "postgres")?)
+        .await
+        .context("Failed to connect to 'postgres' database on the target server")?;
+    let exists = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM pg_subscription WHERE subname = $1)")
+        .bind(name)
+        .fetch_one(&mut admin)
+        .await
+        .context("Failed to look up subscriptions on the target")?;
+    admin.close().await.ok();
+    Ok(exists)
+}
+
+fn database_url(server_url: &str, db_name: &str) -> Result<String> {
+    Ok(format!("{}/{}", get_base_url_without_db(server_url)?, db_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replication_name_is_a_valid_slot_name() {
+        assert_eq!(replication_name("app"), "databasetool_app");
+        assert_eq!(replication_name("Sales-EU.2"), "databasetool_sales_eu_2");
+    }
+
+    #[test]
+    fn test_render_status() {
+        let statuses = vec![
+            ReplicationStatus {
+                db_name: "app".to_string(),
+                worker_running: true,
+                tables_ready: 2,
+                tables_total: 2,
+                lag_bytes: Some(2048),
+                last_message_secs: Some(0.4),
+            },
+            ReplicationStatus { db_name: "crm".to_string(), tables_ready: 1, tables_total: 3, ..Default::default() },
+        ];
+        let out = render_status(&statuses);
+        assert!(out.contains("app                      streaming        2/2"), "{}", out);
+        assert!(out.contains("2.0 KiB") && out.contains("0.4s ago"));
+        assert!(out.contains("crm                      stopped          1/3"), "{}", out);
+    }
+}
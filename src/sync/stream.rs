@@ -0,0 +1,295 @@
+// databasetool/src/sync/stream.rs
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use sqlx::{Connection, PgConnection, Row};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::backup::db_dump::DumpSnapshot;
+use crate::utils::table_filter::TableName;
+use crate::sync::logic::find_pg_dump_executable;
+use crate::utils::find_psql_executable;
+use crate::utils::masking::{MaskedTable, MaskingPlan};
+use crate::utils::table_filter::{RowFilter, TableSelection};
+
+/// Copies one database from source to target without writing anything to disk.
+///
+/// The schema is piped from `pg_dump` into `psql` in two parts: tables and types first, then
+/// indexes, constraints and triggers once the data is in, so rows can be loaded in any order
+/// and indexes are built once. In between, every table is streamed with `COPY ... TO STDOUT`
+/// on the source into `COPY ... FROM STDIN` on the target, `workers` tables at a time, largest first.
+/// Every step reads one snapshot of the source, exported before the schema is streamed and held
+/// until post-data is in, so the foreign keys hold even while the source keeps taking writes.
+///
+/// # Arguments
+/// * `source_db_url` / `target_db_url` - URLs of the source and the (empty) target database.
+/// * `workers` - Tables copied at the same time; each worker holds one connection per server.
+/// * `selection` - Filters resolved on the source: excluded objects are not created, schema-only
+///   tables are not copied and tables with a `where` condition only get the matching rows.
+/// * `masking` - Masking resolved on the source; masked columns are computed in the `COPY`
+///   query, so the original values never reach the target.
+pub async fn stream_database(
+    source_db_url: &str,
+    target_db_url: &str,
+    db_name: &str,
+    workers: usize,
+    selection: &TableSelection,
+    masking: Option<&MaskingPlan>,
+) -> Result<()> {
+    let snapshot = DumpSnapshot::export(source_db_url)
+        .await
+        .with_context(|| format!("Failed to export a snapshot of source database {}", db_name))?;
+    let result = stream_snapshot(source_db_url, target_db_url, db_name, workers, selection, masking, &snapshot.id).await;
+    snapshot.close().await;
+    result
+}
+
+/// The steps of `stream_database`, each reading the exported snapshot `snapshot_id` of the source.
+async fn stream_snapshot(
+    source_db_url: &str,
+    target_db_url: &str,
+    db_name: &str,
+    workers: usize,
+    selection: &TableSelection,
+    masking: Option<&MaskingPlan>,
+    snapshot_id: &str,
+) -> Result<()> {
+    let pg_dump_path = &find_pg_dump_executable()?;
+    let psql_path = &find_psql_executable()?;
+    let mut dump_args = selection.pg_dump_args();
+    dump_args.push(format!("--snapshot={}", snapshot_id));
+    println!("Streaming schema (pre-data) of {}...", db_name);
+    pipe_schema_section(pg_dump_path, psql_path, source_db_url, target_db_url, db_name, "pre-data", &dump_args)?;
+
+    let tables: Vec<(TableName, Option<RowFilter>, Option<MaskedTable>)> = list_tables_by_size(source_db_url, snapshot_id)
+        .await
+        .with_context(|| format!("Failed to list tables of source database {}", db_name))?
+        .into_iter()
+        .filter(|table| !selection.excludes(table) && !selection.schema_only_tables.contains(table))
+        .map(|table| {
+            let row_filter = selection.row_filter(&table).cloned();
+            let masked = masking.and_then(|masking| masking.masked_table(&table)).cloned();
+            (table, row_filter, masked)
+        })
+        .collect();
+    println!("Copying {} table(s) of {} with up to {} worker(s)...", tables.len(), db_name, workers);
+
+    let started = Instant::now();
+    let semaphore = Arc::new(Semaphore::new(workers));
+    let mut copy_tasks = JoinSet::new();
+    for (table, row_filter, masked) in tables {
+        let semaphore = Arc::clone(&semaphore);
+        let (source_db_url, target_db_url) = (source_db_url.to_string(), target_db_url.to_string());
+        let snapshot_id = snapshot_id.to_string();
+        copy_tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.expect("copy semaphore is never closed");
+            let result = copy_table(&source_db_url, &target_db_url, &snapshot_id, &table, row_filter.as_ref(), masked.as_ref()).await;
+            (table, result)
+        });
+    }
+
+    let mut total_rows = 0;
+    let mut copied_rows = BTreeMap::new();
+    while let Some(joined) = copy_tasks.join_next().await {
+        let (table, result) = joined.context("COPY task panicked")?;
+        match result {
+            Ok(rows) => {
+                println!("  ✓ {} ({} row(s))", table, rows);
+                total_rows += rows;
+                copied_rows.insert(table, rows);
+            }
+            Err(e) => {
+                copy_tasks.abort_all();
+                return Err(e.context(format!("Failed to copy table {} of {}", table, db_name)));
+            }
+        }
+    }
+    println!("✓ Copied {} row(s) of {} in {:.1?}.", total_rows, db_name, started.elapsed());
+    if let Some(masking) = masking {
+        let copied: Vec<&TableName> = copied_rows.keys().collect();
+        println!("{}", masking.restricted_to(&copied).render_report(db_name, &copied_rows));
+    }
+
+    let sequences = copy_sequence_values(source_db_url, target_db_url)
+        .await
+        .with_context(|| format!("Failed to copy sequence values of {}", db_name))?;
+    println!("✓ Set {} sequence value(s) of {}.", sequences, db_name);
+
+    println!("Streaming schema (post-data: indexes, constraints, triggers) of {}...", db_name);
+    pipe_schema_section(pg_dump_path, psql_path, source_db_url, target_db_url, db_name, "post-data", &dump_args)?;
+    Ok(())
+}
+
+/// Runs `pg_dump --section=<section>` on the source with its output piped straight into `psql` on the target.
+///
+/// Publications and subscriptions of the source are left out; they belong to the source server.
+/// `filter_args` are extra pg_dump arguments, see `TableSelection::pg_dump_args`, and `--snapshot`.
+pub(crate) fn pipe_schema_section(
+    pg_dump_path: &Path,
+    psql_path: &Path,
+    source_db_url: &str,
+    target_db_url: &str,
+    db_name: &str,
+    section: &str,
+    filter_args: &[String],
+) -> Result<()> {
+    let mut dump = Command::new(pg_dump_path)
+        .arg(format!("--section={}", section))
+        .args(filter_args)
+        .arg("--no-comments") // Skip comments that might contain unsupported settings
+        .arg("--no-tablespaces") // Skip tablespace settings that might not be compatible
+        .arg("--no-publications")
+        .arg("--no-subscriptions")
+        .arg(source_db_url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute pg_dump ({}) for source database: {}", section, db_name))?;
+    let dump_stdout = dump.stdout.take().context("pg_dump stdout was not captured")?;
+
+    let restore_output = Command::new(psql_path)
+        .arg("-X")
+        .arg("-q")
+        .arg("-v")
+        .arg("ON_ERROR_STOP=1")
+        .arg("-d")
+        .arg(target_db_url)
+        .stdin(Stdio::from(dump_stdout))
+        .output()
+        .with_context(|| format!("Failed to execute psql ({}) for target database: {}", section, db_name))?;
+    let dump_output = dump
+        .wait_with_output()
+        .with_context(|| format!("Failed to wait for pg_dump ({}) of source database: {}", section, db_name))?;
+
+    if !dump_output.status.success() {
+        return Err(anyhow::anyhow!(
+            "pg_dump ({}) for source database {} failed with status: {}\nStderr: {}",
+            section,
+            db_name,
+            dump_output.status,
+            String::from_utf8_lossy(&dump_output.stderr)
+        ));
+    }
+    if !restore_output.status.success() {
+        return Err(anyhow::anyhow!(
+            "psql ({}) for target database {} failed with status: {}\nStderr: {}",
+            section,
+            db_name,
+            restore_output.status,
+            String::from_utf8_lossy(&restore_output.stderr)
+        ));
+    }
+    Ok(())
+}
+
+/// Ordinary tables of the database in the snapshot `snapshot_id`, largest first so the longest copies start early.
+async fn list_tables_by_size(db_url: &str, snapshot_id: &str) -> Result<Vec<TableName>> {
+    let mut conn = PgConnection::connect(db_url).await.context("Failed to connect to list tables")?;
+    DumpSnapshot::import(&mut conn, snapshot_id).await?;
+    let rows = sqlx::query(
+        "SELECT n.nspname AS schema_name, c.relname AS table_name \
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+         WHERE c.relkind = 'r' AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+           AND n.nspname NOT LIKE 'pg_toast%' \
+         ORDER BY pg_total_relation_size(c.oid) DESC, 1, 2",
+    )
+    .fetch_all(&mut conn)
+    .await
+    .context("Failed to list tables")?;
+    conn.close().await.ok();
+
+    rows.into_iter()
+        .map(|row| Ok(TableName { schema: row.try_get("schema_name")?, name: row.try_get("table_name")? }))
+        .collect()
+}
+
+/// Streams one table from source to target, as of the source snapshot `snapshot_id`, and returns
+/// the number of rows copied. With a `row_filter` only the rows matching its condition are copied, and `masked`
+/// columns are replaced by their masked values on the way.
+///
+/// Text format is used because binary COPY embeds type OIDs, which differ between servers
+/// for user-defined types.
+async fn copy_table(
+    source_db_url: &str,
+    target_db_url: &str,
+    snapshot_id: &str,
+    table: &TableName,
+    row_filter: Option<&RowFilter>,
+    masked: Option<&MaskedTable>,
+) -> Result<u64> {
+    let mut source = PgConnection::connect(source_db_url).await.context("Failed to connect to source database")?;
+    DumpSnapshot::import(&mut source, snapshot_id).await?;
+    let mut target = PgConnection::connect(target_db_url).await.context("Failed to connect to target database")?;
+
+    let (copy_in_statement, copy_out_statement) = match (row_filter, masked) {
+        (filter, Some(masked)) => (
+            masked.copy_in_statement(),
+            format!("COPY ({}) TO STDOUT", masked.select_query(filter.map(|filter| filter.condition.as_str()))),
+        ),
+        (Some(filter), None) => (filter.copy_in_statement(), format!("COPY ({}) TO STDOUT", filter.select_query())),
+        (None, None) => (format!("COPY {} FROM STDIN", table.quoted()), format!("COPY {} TO STDOUT", table.quoted())),
+    };
+    let mut copy_in = target
+        .copy_in_raw(&copy_in_statement)
+        .await
+        .context("Failed to start COPY FROM STDIN on the target")?;
+    let mut copy_out = source
+        .copy_out_raw(&copy_out_statement)
+        .await
+        .context("Failed to start COPY TO STDOUT on the source")?;
+
+    while let Some(chunk) = copy_out.next().await {
+        match chunk {
+            Ok(chunk) => {
+                copy_in.send(chunk).await.context("Failed to send rows to the target")?;
+            }
+            Err(e) => {
+                copy_in.abort("source COPY failed").await.ok();
+                return Err(anyhow::Error::new(e).context("Failed to read rows from the source"));
+            }
+        }
+    }
+    drop(copy_out);
+    let rows = copy_in.finish().await.context("Failed to finish COPY on the target")?;
+
+    source.close().await.ok();
+    target.close().await.ok();
+    Ok(rows)
+}
+
+/// Sets every sequence of the target to the current value of the source.
+/// Sequences that were never used on the source, or that the target does not have
+/// (left out by filters), are skipped. Returns the number of sequences set.
+pub(crate) async fn copy_sequence_values(source_db_url: &str, target_db_url: &str) -> Result<usize> {
+    let mut source = PgConnection::connect(source_db_url).await.context("Failed to connect to source database")?;
+    let sequences: Vec<(String, String, i64)> = sqlx::query_as(
+        "SELECT schemaname::text, sequencename::text, last_value FROM pg_sequences WHERE last_value IS NOT NULL",
+    )
+    .fetch_all(&mut source)
+    .await
+    .context("Failed to read sequence values")?;
+    source.close().await.ok();
+
+    let mut target = PgConnection::connect(target_db_url).await.context("Failed to connect to target database")?;
+    let mut set = 0;
+    for (schema, name, value) in &sequences {
+        let result = sqlx::query(
+            "SELECT setval(seq, $3, true) FROM (SELECT to_regclass(format('%I.%I', $1::text, $2::text)) AS seq) s \
+             WHERE seq IS NOT NULL",
+        )
+        .bind(schema)
+        .bind(name)
+        .bind(value)
+        .execute(&mut target)
+        .await
+        .with_context(|| format!("Failed to set sequence {}.{}", schema, name))?;
+        set += result.rows_affected() as usize;
+    }
+    target.close().await.ok();
+    Ok(set)
+}
@@ -0,0 +1,428 @@
+// databasetool/src/utils/masking.rs
+use anyhow::{Context, Result};
+use sqlx::{Connection, PgConnection, Row};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use crate::config::{MaskingConfig, MaskingRule, MaskingStrategy};
+use crate::utils::sql_script::{quote_ident, quote_literal};
+use crate::utils::table_filter::{matches_pattern, table_matches, TableName};
+
+const FIRST_NAMES: [&str; 16] = [
+    "Alex", "Sam", "Jordan", "Taylor", "Morgan", "Casey", "Robin", "Jamie",
+    "Charlie", "Avery", "Riley", "Quinn", "Dana", "Kim", "Lee", "Noa",
+];
+const LAST_NAMES: [&str; 16] = [
+    "Smith", "Jansen", "Garcia", "Kowalski", "Novak", "Silva", "Berg", "Moreau",
+    "Rossi", "Tanaka", "Okafor", "Larsen", "Murphy", "Weber", "Costa", "Ivanova",
+];
+
+/// A column of the catalog, as far as masking is concerned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub sql_type: String, // format_type(), e.g. `character varying(255)`
+    pub category: String, // pg_type.typcategory: S for strings, N for numbers
+    pub not_null: bool,
+    pub generated: bool,
+}
+
+/// A column that is masked, with the SQL that computes its masked value from `t."column"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskedColumn {
+    pub name: String,
+    pub strategy: MaskingStrategy,
+    pub expression: String,
+}
+
+/// A table with at least one masked column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaskedTable {
+    pub table: TableName,
+    pub columns: Vec<String>, // Columns that can be written, in order; generated columns are left out
+    pub masked: Vec<MaskedColumn>,
+}
+
+/// `MaskingConfig` resolved against the catalog of one database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MaskingPlan {
+    pub tables: Vec<MaskedTable>,
+    pub unmatched_rules: Vec<String>, // Rules that match no column of this database
+    pub fixed_seed: bool,
+}
+
+/// SQL for the masked value of `column` (a column reference), cast back to `sql_type`.
+///
+/// The seed goes into every hash, so the output cannot be recomputed from a guessed input
+/// without it, and equal inputs give equal outputs: a masked key and the columns that
+/// reference it still match. NULL stays NULL.
+pub fn mask_expression(strategy: &MaskingStrategy, column: &str, sql_type: &str, seed: &str) -> String {
+    let value = format!("{}::text", column);
+    let salted = |extra: &str| format!("md5({} || {}{})", quote_literal(&format!("{}:", seed)), extra, value);
+    let pick = |names: &[&str], offset: usize| {
+        format!(
+            "(ARRAY[{}])[1 + abs(('x' || substr({}, {}, 8))::bit(32)::int % {})]",
+            names.iter().map(|name| quote_literal(name)).collect::<Vec<_>>().join(", "),
+            salted(""),
+            offset,
+            names.len()
+        )
+    };
+    let masked = match strategy {
+        MaskingStrategy::Null => return format!("NULL::{}", sql_type),
+        MaskingStrategy::Fixed(fixed) => quote_literal(fixed),
+        MaskingStrategy::FakeEmail => format!("'user_' || left({}, 12) || '@example.com'", salted("")),
+        MaskingStrategy::FakeName => format!("{} || ' ' || {}", pick(&FIRST_NAMES, 1), pick(&LAST_NAMES, 9)),
+        MaskingStrategy::Hash => format!(
+            "encode(sha256(convert_to({} || {}, 'UTF8')), 'hex')",
+            quote_literal(&format!("{}:", seed)),
+            value
+        ),
+        // One random byte per position, drawn from the seed, the position and the whole value.
+        MaskingStrategy::PreserveFormat => format!(
+            "COALESCE((SELECT string_agg(CASE \
+                WHEN ascii(chars.c) BETWEEN 48 AND 57 THEN chr(48 + chars.b % 10) \
+                WHEN ascii(chars.c) BETWEEN 97 AND 122 THEN chr(97 + chars.b % 26) \
+                WHEN ascii(chars.c) BETWEEN 65 AND 90 THEN chr(65 + chars.b % 26) \
+                ELSE chars.c END, '' ORDER BY chars.i) \
+             FROM (SELECT c, i, get_byte(decode({}, 'hex'), 0) AS b \
+                   FROM regexp_split_to_table({}, '') WITH ORDINALITY AS split(c, i)) chars), '')",
+            salted("split.i || ':' || "),
+            value
+        ),
+    };
+    format!("(CASE WHEN {} IS NULL THEN NULL ELSE {} END)::{}", column, masked, sql_type)
+}
+
+/// Finds the rule for one column and checks that its strategy fits the column.
+///
+/// # Arguments
+/// * `table` / `root` - The table, and the root of its partition tree if it is a partition;
+///   a rule for a partitioned table applies to all of its partitions.
+/// * `column` - The column as read from the catalog.
+/// * `rules` - Rules of the masking rules file; at most one may match.
+///
+/// # Returns
+/// The matching rule, or `None` when the column is not masked.
+pub fn rule_for_column<'a>(
+    table: &TableName,
+    root: Option<&TableName>,
+    column: &ColumnInfo,
+    rules: &'a [MaskingRule],
+) -> Result<Option<&'a MaskingRule>> {
+    let mut matching = rules.iter().filter(|rule| {
+        matches_pattern(&rule.column, &column.name)
+            && (table_matches(&rule.table, table) || root.is_some_and(|root| table_matches(&rule.table, root)))
+    });
+    let Some(rule) = matching.next() else {
+        return Ok(None);
+    };
+    if let Some(other) = matching.next() {
+        anyhow::bail!("Column {}.{} matches more than one masking rule ('{}' and '{}').", table, column.name, rule.key(), other.key());
+    }
+
+    if column.generated {
+        anyhow::bail!(
+            "Masking rule '{}' matches {}.{}, a generated column; mask the columns it is computed from instead.",
+            rule.key(),
+            table,
+            column.name
+        );
+    }
+    let fits = match rule.strategy {
+        MaskingStrategy::Null => !column.not_null,
+        MaskingStrategy::Fixed(_) => true,
+        MaskingStrategy::PreserveFormat => column.category == "S" || column.category == "N",
+        MaskingStrategy::FakeEmail | MaskingStrategy::FakeName | MaskingStrategy::Hash => column.category == "S",
+    };
+    if !fits {
+        anyhow::bail!(
+            "Masking rule '{}' cannot apply {} to {}.{} ({}{}).",
+            rule.key(),
+            rule.strategy.as_str(),
+            table,
+            column.name,
+            column.sql_type,
+            if column.not_null { " NOT NULL" } else { "" }
+        );
+    }
+    Ok(Some(rule))
+}
+
+/// Applies the masking rules to the ordinary tables of the database behind `conn`.
+/// Fails when a column matches several rules or a strategy does not fit its column type.
+pub async fn resolve_masking(conn: &mut PgConnection, config: &MaskingConfig) -> Result<MaskingPlan> {
+    let rows = sqlx::query(
+        "SELECT n.nspname::text AS schema_name, c.relname::text AS table_name, \
+                rn.nspname::text AS root_schema, rc.relname::text AS root_table, \
+                a.attname::text AS column_name, format_type(a.atttypid, a.atttypmod) AS column_type, \
+                ty.typcategory::text AS category, a.attnotnull AS not_null, a.attgenerated <> '' AS generated \
+         FROM pg_attribute a \
+         JOIN pg_class c ON c.oid = a.attrelid JOIN pg_namespace n ON n.oid = c.relnamespace \
+         JOIN pg_type ty ON ty.oid = a.atttypid \
+         LEFT JOIN pg_class rc ON c.relispartition AND rc.oid = pg_partition_root(c.oid) \
+         LEFT JOIN pg_namespace rn ON rn.oid = rc.relnamespace \
+         WHERE c.relkind = 'r' AND a.attnum > 0 AND NOT a.attisdropped \
+           AND n.nspname NOT IN ('pg_catalog', 'information_schema') AND n.nspname NOT LIKE 'pg_toast%' \
+         ORDER BY 1, 2, a.attnum",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to list columns for masking")?;
+
+    let mut plan = MaskingPlan { fixed_seed: config.fixed_seed, ..Default::default() };
+    let mut used = vec![false; config.rules.len()];
+    let mut current: Option<MaskedTable> = None;
+    for row in rows {
+        let table = TableName { schema: row.try_get("schema_name")?, name: row.try_get("table_name")? };
+        let root = match (row.try_get::<Option<String>, _>("root_schema")?, row.try_get::<Option<String>, _>("root_table")?) {
+            (Some(schema), Some(name)) => Some(TableName { schema, name }),
+            _ => None,
+        };
+        let column = ColumnInfo {
+            name: row.try_get("column_name")?,
+            sql_type: row.try_get("column_type")?,
+            category: row.try_get("category")?,
+            not_null: row.try_get("not_null")?,
+            generated: row.try_get("generated")?,
+        };
+
+        if current.as_ref().is_none_or(|masked| masked.table != table) {
+            plan.tables.extend(current.take().filter(|masked| !masked.masked.is_empty()));
+            current = Some(MaskedTable { table: table.clone(), columns: Vec::new(), masked: Vec::new() });
+        }
+        let masked_table = current.as_mut().expect("set above");
+        if let Some(rule) = rule_for_column(&table, root.as_ref(), &column, &config.rules)? {
+            used[config.rules.iter().position(|candidate| std::ptr::eq(candidate, rule)).expect("rule comes from the list")] = true;
+            masked_table.masked.push(MaskedColumn {
+                expression: mask_expression(
+                    &rule.strategy,
+                    &format!("t.{}", quote_ident(&column.name)),
+                    &column.sql_type,
+                    &config.seed,
+                ),
+                name: column.name.clone(),
+                strategy: rule.strategy.clone(),
+            });
+        }
+        if !column.generated {
+            masked_table.columns.push(column.name);
+        }
+    }
+    plan.tables.extend(current.filter(|masked| !masked.masked.is_empty()));
+    plan.unmatched_rules =
+        config.rules.iter().zip(used).filter(|(_, used)| !used).map(|(rule, _)| rule.key()).collect();
+    Ok(plan)
+}
+
+/// `resolve_masking` for the database at `db_url`.
+pub async fn resolve_database_masking(db_url: &str, config: &MaskingConfig) -> Result<MaskingPlan> {
+    let mut conn = PgConnection::connect(db_url).await.context("Failed to connect to resolve masking rules")?;
+    let plan = resolve_masking(&mut conn, config).await?;
+    conn.close().await.ok();
+    Ok(plan)
+}
+
+impl MaskedTable {
+    fn column_list(&self) -> String {
+        self.columns.iter().map(|column| quote_ident(column)).collect::<Vec<_>>().join(", ")
+    }
+
+    /// The writable columns of the table as `t.column`, with masked columns replaced by their expression.
+    fn select_list(&self, apply_masks: bool) -> String {
+        self.columns
+            .iter()
+            .map(|column| match self.masked.iter().find(|masked| masked.name == *column) {
+                Some(masked) if apply_masks => masked.expression.clone(),
+                _ => format!("t.{}", quote_ident(column)),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The masked rows for `COPY (...) TO STDOUT`, limited by `condition` when given.
+    pub fn select_query(&self, condition: Option<&str>) -> String {
+        let mut query = format!("SELECT {} FROM {} t", self.select_list(true), self.table.quoted());
+        if let Some(condition) = condition {
+            let _ = write!(query, " WHERE ({})", condition);
+        }
+        query
+    }
+
+    /// `COPY ... FROM STDIN` matching the columns of `select_query`.
+    pub fn copy_in_statement(&self) -> String {
+        format!("COPY {} ({}) FROM STDIN", self.table.quoted(), self.column_list())
+    }
+
+    /// Rewrites every row of the table in place.
+    pub fn update_statement(&self) -> String {
+        let assignments: Vec<String> =
+            self.masked.iter().map(|masked| format!("{} = {}", quote_ident(&masked.name), masked.expression)).collect();
+        format!("UPDATE {} AS t SET {}", self.table.quoted(), assignments.join(", "))
+    }
+
+    /// `ROW(...)::text` over the writable columns, for fingerprints. With `apply_masks` the masked
+    /// columns are computed, so the source fingerprint equals that of a masked target.
+    pub fn row_text(&self, apply_masks: bool) -> String {
+        format!("ROW({})::text", self.select_list(apply_masks))
+    }
+
+    /// e.g. `email (fake_email), name (fake_name)`.
+    pub fn describe_columns(&self) -> String {
+        self.masked.iter().map(|masked| format!("{} ({})", masked.name, masked.strategy.as_str())).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl MaskingPlan {
+    pub fn masked_table(&self, table: &TableName) -> Option<&MaskedTable> {
+        self.tables.iter().find(|masked| masked.table == *table)
+    }
+
+    /// The same plan, limited to `tables`.
+    pub fn restricted_to(&self, tables: &[&TableName]) -> MaskingPlan {
+        MaskingPlan {
+            tables: self.tables.iter().filter(|masked| tables.contains(&&masked.table)).cloned().collect(),
+            unmatched_rules: self.unmatched_rules.clone(),
+            fixed_seed: self.fixed_seed,
+        }
+    }
+
+    /// Row text per masked table for `fingerprint::collect_fingerprints`, masked on the source side only.
+    pub fn row_texts(&self, apply_masks: bool) -> BTreeMap<TableName, String> {
+        self.tables.iter().map(|masked| (masked.table.clone(), masked.row_text(apply_masks))).collect()
+    }
+
+    /// What was masked in `db_name`, with the rows of each table, and the rules that matched nothing.
+    pub fn render_report(&self, db_name: &str, rows: &BTreeMap<TableName, u64>) -> String {
+        let mut out = String::new();
+        let columns: usize = self.tables.iter().map(|masked| masked.masked.len()).sum();
+        let _ = writeln!(
+            out,
+            "🎭 Masked {} column(s) in {} table(s) of {} ({}):",
+            columns,
+            self.tables.len(),
+            db_name,
+            if self.fixed_seed { "fixed seed" } else { "random seed for this run" }
+        );
+        for masked in &self.tables {
+            let _ = writeln!(
+                out,
+                "  {}: {}, {} row(s)",
+                masked.table,
+                masked.describe_columns(),
+                rows.get(&masked.table).copied().unwrap_or(0)
+            );
+        }
+        for rule in &self.unmatched_rules {
+            let _ = writeln!(out, "⚠️  Masking rule '{}' matches no column of {}.", rule, db_name);
+        }
+        out.trim_end().to_string()
+    }
+}
+
+/// Masks every table of `plan` in place and returns the rows rewritten per table.
+///
+/// Runs in one transaction with triggers and foreign keys off: a masked key and the columns
+/// referencing it are rewritten one after the other, and a failure leaves the data as loaded.
+pub async fn mask_database(conn: &mut PgConnection, plan: &MaskingPlan) -> Result<BTreeMap<TableName, u64>> {
+    let mut transaction = conn.begin().await.context("Failed to start the masking transaction")?;
+    sqlx::query("SET LOCAL session_replication_role = replica")
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to disable triggers for masking")?;
+    let mut rows = BTreeMap::new();
+    for masked in &plan.tables {
+        let result = sqlx::query(&masked.update_statement())
+            .execute(&mut *transaction)
+            .await
+            .with_context(|| format!("Failed to mask {} ({})", masked.table, masked.describe_columns()))?;
+        rows.insert(masked.table.clone(), result.rows_affected());
+    }
+    transaction.commit().await.context("Failed to commit the masking transaction")?;
+    Ok(rows)
+}
+
+/// Resolves `config` on the database at `db_url`, masks it and prints the report.
+/// With `only`, tables outside that list are left alone.
+pub async fn mask_target_database(db_url: &str, db_name: &str, config: &MaskingConfig, only: Option<&[&TableName]>) -> Result<()> {
+    let mut conn = PgConnection::connect(db_url).await.context("Failed to connect to mask the database")?;
+    let mut plan = resolve_masking(&mut conn, config).await?;
+    if let Some(tables) = only {
+        plan = plan.restricted_to(tables);
+    }
+    let rows = mask_database(&mut conn, &plan).await?;
+    conn.close().await.ok();
+    println!("{}", plan.render_report(db_name, &rows));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, sql_type: &str, category: &str) -> ColumnInfo {
+        ColumnInfo { name: name.to_string(), sql_type: sql_type.to_string(), category: category.to_string(), not_null: false, generated: false }
+    }
+
+    fn rule(table: &str, column: &str, strategy: MaskingStrategy) -> MaskingRule {
+        MaskingRule { table: table.to_string(), column: column.to_string(), strategy }
+    }
+
+    #[test]
+    fn test_rule_for_column_matches_and_checks_types() -> Result<()> {
+        let users = TableName { schema: "public".to_string(), name: "users".to_string() };
+        let partition = TableName { schema: "public".to_string(), name: "events_2024".to_string() };
+        let events = TableName { schema: "public".to_string(), name: "events".to_string() };
+        let rules = vec![
+            rule("public.users", "email", MaskingStrategy::FakeEmail),
+            rule("*", "phone*", MaskingStrategy::PreserveFormat),
+            rule("events", "ip", MaskingStrategy::Null),
+        ];
+
+        let email = column("email", "text", "S");
+        assert_eq!(rule_for_column(&users, None, &email, &rules)?.map(MaskingRule::key), Some("public.users.email".to_string()));
+        assert!(rule_for_column(&users, None, &column("name", "text", "S"), &rules)?.is_none());
+        assert!(rule_for_column(&users, None, &column("phone", "bigint", "N"), &rules)?.is_some());
+        assert!(rule_for_column(&partition, Some(&events), &column("ip", "inet", "I"), &rules)?.is_some());
+        assert!(rule_for_column(&partition, None, &column("ip", "inet", "I"), &rules)?.is_none());
+
+        // Type, NOT NULL and generated columns are checked; overlapping rules are refused.
+        let strict = vec![rule("users", "email", MaskingStrategy::Hash)];
+        assert!(rule_for_column(&users, None, &column("email", "integer", "N"), &strict).is_err());
+        let not_null = ColumnInfo { not_null: true, ..column("ip", "inet", "I") };
+        assert!(rule_for_column(&events, None, &not_null, &rules).is_err());
+        let generated = ColumnInfo { generated: true, ..column("email", "text", "S") };
+        assert!(rule_for_column(&users, None, &generated, &rules).is_err());
+        let overlapping = vec![rule("users", "email", MaskingStrategy::Hash), rule("*", "e*", MaskingStrategy::Null)];
+        assert!(rule_for_column(&users, None, &email, &overlapping).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_masked_table_statements_and_report() {
+        let expression = mask_expression(&MaskingStrategy::FakeEmail, "t.\"email\"", "character varying(255)", "it's");
+        assert_eq!(
+            expression,
+            "(CASE WHEN t.\"email\" IS NULL THEN NULL ELSE 'user_' || left(md5('it''s:' || t.\"email\"::text), 12) || '@example.com' END)::character varying(255)"
+        );
+        assert_eq!(mask_expression(&MaskingStrategy::Null, "t.\"ip\"", "inet", "it's"), "NULL::inet");
+        assert!(mask_expression(&MaskingStrategy::Fixed("O'Hara".to_string()), "t.\"n\"", "text", "s").contains("ELSE 'O''Hara' END"));
+
+        let table = MaskedTable {
+            table: TableName { schema: "public".to_string(), name: "users".to_string() },
+            columns: vec!["id".to_string(), "email".to_string()],
+            masked: vec![MaskedColumn { name: "email".to_string(), strategy: MaskingStrategy::Null, expression: "NULL::text".to_string() }],
+        };
+        assert_eq!(table.update_statement(), "UPDATE \"public\".\"users\" AS t SET \"email\" = NULL::text");
+        assert_eq!(table.copy_in_statement(), "COPY \"public\".\"users\" (\"id\", \"email\") FROM STDIN");
+        assert_eq!(table.select_query(Some("id > 1")), "SELECT t.\"id\", NULL::text FROM \"public\".\"users\" t WHERE (id > 1)");
+        assert_eq!(table.row_text(false), "ROW(t.\"id\", t.\"email\")::text");
+
+        let plan = MaskingPlan { tables: vec![table.clone()], unmatched_rules: vec!["users.ssn".to_string()], fixed_seed: false };
+        let report = plan.render_report("app", &BTreeMap::from([(table.table.clone(), 42)]));
+        assert!(report.contains("Masked 1 column(s) in 1 table(s) of app (random seed for this run)"));
+        assert!(report.contains("public.users: email (null), 42 row(s)"));
+        assert!(report.contains("'users.ssn' matches no column of app"));
+        assert!(plan.restricted_to(&[]).tables.is_empty());
+    }
+}
@@ -1,5 +1,8 @@
+pub mod masking;
 pub mod setting;
 pub mod sequence_reset;
+pub mod sql_script;
+pub mod table_filter;
 
 use anyhow::{Context, Result};
 use std::path::PathBuf;
@@ -1,20 +1,27 @@
 // databasetool/src/utils/sequence_reset.rs
 use anyhow::{Context, Result};
 use sqlx::{Pool, Postgres, Row};
+use std::collections::BTreeSet;
 use std::time::Duration;
 use tokio::time::timeout;
 
+use crate::utils::sql_script::{format_ident, quote_literal};
+
 /// Resets all PostgreSQL sequences to match the maximum values of their corresponding tables
 /// This prevents migration failures due to sequence desynchronization
-pub async fn reset_all_sequences(db_pool: &Pool<Postgres>, db_name: &str) -> Result<()> {
+///
+/// With `schemas`, only the sequences in those schemas are reset: a restore into renamed
+/// schemas leaves the rest of the database alone.
+pub async fn reset_all_sequences(db_pool: &Pool<Postgres>, db_name: &str, schemas: Option<&BTreeSet<String>>) -> Result<()> {
     println!("🔄 Resetting all sequences for database: {}", db_name);
     
     // Query to get all sequences and their corresponding tables/columns
+    // Every user schema; names are returned quoted and schema-qualified so they can be used as-is.
     let sequences_query = r#"
         SELECT 
-            seq.relname as sequence_name,
-            tab.relname as table_name,
-            attr.attname as column_name
+            format('%I.%I', seq_nsp.nspname, seq.relname) as sequence_name,
+            format('%I.%I', tab_nsp.nspname, tab.relname) as table_name,
+            quote_ident(attr.attname) as column_name
         FROM 
             pg_class seq
         JOIN 
@@ -24,22 +31,26 @@ pub async fn reset_all_sequences(db_pool: &Pool<Postgres>, db_name: &str) -> Res
         JOIN 
             pg_attribute attr ON dep.refobjid = attr.attrelid AND dep.refobjsubid = attr.attnum
         JOIN
-            pg_namespace nsp ON seq.relnamespace = nsp.oid
+            pg_namespace seq_nsp ON seq.relnamespace = seq_nsp.oid
+        JOIN
+            pg_namespace tab_nsp ON tab.relnamespace = tab_nsp.oid
         WHERE 
             seq.relkind = 'S'
             AND tab.relkind = 'r'
-            AND nsp.nspname = 'public'
+            AND seq_nsp.nspname NOT IN ('pg_catalog', 'information_schema')
+            AND ($1::text[] IS NULL OR seq_nsp.nspname = ANY($1))
         ORDER BY 
-            tab.relname, attr.attname
+            tab_nsp.nspname, tab.relname, attr.attname
     "#;
     
     let sequences = sqlx::query_as::<_, (String, String, String)>(sequences_query)
+        .bind(schemas.map(|schemas| schemas.iter().cloned().collect::<Vec<String>>()))
         .fetch_all(db_pool)
         .await
         .context("Failed to fetch sequence information")?;
     
     if sequences.is_empty() {
-        println!("ℹ️  No sequences found in public schema for database: {}", db_name);
+        println!("ℹ️  No sequences found for database: {}", db_name);
         return Ok(());
     }
     
@@ -79,8 +90,8 @@ pub async fn reset_all_sequences(db_pool: &Pool<Postgres>, db_name: &str) -> Res
                 
                 // Reset the sequence
                 let reset_query = format!(
-                    "SELECT setval('{}', {}, false)",
-                    sequence_name, next_val
+                    "SELECT setval({}, {}, false)",
+                    quote_literal(&sequence_name), next_val
                 );
                 
                 match sqlx::query(&reset_query)
@@ -106,15 +117,17 @@ pub async fn reset_all_sequences(db_pool: &Pool<Postgres>, db_name: &str) -> Res
     }
     
     // Handle common system tables that might not be caught by the above query
-    reset_common_system_sequences(db_pool).await?;
+    reset_common_system_sequences(db_pool, schemas).await?;
     
     println!("✅ Sequence reset completed: {} successful, {} errors", reset_count, error_count);
     Ok(())
 }
 
 /// Special handling for common system tables that often have sequence issues
-async fn reset_common_system_sequences(db_pool: &Pool<Postgres>) -> Result<()> {
-    let common_tables = vec![
+///
+/// The tables are looked up on the search path, or in each of `schemas` when it is given.
+async fn reset_common_system_sequences(db_pool: &Pool<Postgres>, schemas: Option<&BTreeSet<String>>) -> Result<()> {
+    let common_tables = [
         ("migrations", "id"),
         ("schema_migrations", "id"), 
         ("users", "id"),
@@ -125,6 +138,14 @@ async fn reset_common_system_sequences(db_pool: &Pool<Postgres>) -> Result<()> {
     
     println!("   Processing common system tables...");
     
+    let qualifiers: Vec<String> = match schemas {
+        Some(schemas) => schemas.iter().map(|schema| format!("{}.", format_ident(schema))).collect(),
+        None => vec![String::new()],
+    };
+    let common_tables: Vec<(String, &str)> = qualifiers
+        .iter()
+        .flat_map(|qualifier| common_tables.iter().map(move |(table_name, column_name)| (format!("{}{}", qualifier, table_name), *column_name)))
+        .collect();
     for (table_name, column_name) in common_tables {
         let sequence_name = format!("{}_{}_seq", table_name, column_name);
         let max_value_query = format!("SELECT COALESCE(MAX({}), 0) as max_val FROM {}", column_name, table_name);
@@ -145,7 +166,7 @@ async fn reset_common_system_sequences(db_pool: &Pool<Postgres>) -> Result<()> {
                 };
                 
                 let next_val = max_val + 1;
-                let reset_query = format!("SELECT setval('{}', {}, false)", sequence_name, next_val);
+                let reset_query = format!("SELECT setval({}, {}, false)", quote_literal(&sequence_name), next_val);
                 
                 match sqlx::query(&reset_query)
                     .execute(db_pool)
@@ -175,10 +196,10 @@ async fn reset_common_system_sequences(db_pool: &Pool<Postgres>) -> Result<()> {
 }
 
 /// Ensures sequences are properly reset with a timeout
-pub async fn reset_sequences_with_timeout(db_pool: &Pool<Postgres>, db_name: &str) -> Result<()> {
+pub async fn reset_sequences_with_timeout(db_pool: &Pool<Postgres>, db_name: &str, schemas: Option<&BTreeSet<String>>) -> Result<()> {
     let timeout_duration = Duration::from_secs(300); // 5 minutes timeout
     
-    match timeout(timeout_duration, reset_all_sequences(db_pool, db_name)).await {
+    match timeout(timeout_duration, reset_all_sequences(db_pool, db_name, schemas)).await {
         Ok(result) => result,
         Err(_) => {
             Err(anyhow::anyhow!(
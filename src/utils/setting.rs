@@ -3,6 +3,8 @@ use std::path::Path; // Keep Path, PathBuf might be unused if TempDir handles it
 use hex;
 use anyhow::{anyhow, Context, Result};
 use tempfile::{Builder as TempFileBuilder, TempDir};
+use crate::config::EncryptionConfig;
+use crate::utils::sql_script::quote_literal;
 use sqlx::{
     postgres::{PgPoolOptions, PgRow},
     PgPool, Row, ValueRef, TypeInfo,
@@ -153,7 +155,7 @@ pub fn serialize_value(row: &PgRow, column: &str) -> Result<String> {
     // 10. Handle JSON/JSONB
     if let Ok(val) = row.try_get::<Option<serde_json::Value>, _>(column) {
         return Ok(val
-            .map(|v| format!("'{}'", v.to_string().replace('\'', "''")))
+            .map(|v| quote_literal(&v.to_string()))
             .unwrap_or_else(|| "NULL".to_string()));
     }
 
@@ -216,7 +218,7 @@ pub fn serialize_value(row: &PgRow, column: &str) -> Result<String> {
     match row.try_get_raw(column) {
         Ok(raw_value) if !raw_value.is_null() => {
             if let Ok(str_val) = raw_value.as_str() {
-                Ok(format!("'{}'", str_val.replace('\'', "''")))
+                Ok(quote_literal(str_val))
             } else {
                 eprintln!(
                     "Warning: Column '{}' has an unsupported type ('{}') for direct SQL serialization. Raw value could not be displayed as string.",
@@ -238,15 +240,17 @@ pub fn serialize_value(row: &PgRow, column: &str) -> Result<String> {
 
 /// Prepares a backup archive for restore by extracting it to a new temporary directory.
 ///
-/// This function is specifically for `.tar.gz` archives.
+/// The compression codec (gzip, zstd, lz4 or none) is detected from the file contents, not its
+/// name; encrypted archives (`.age`) are decrypted transparently with the configured keys.
 /// The caller is responsible for the lifetime of the returned `TempDir`.
 ///
 /// # Arguments
-/// * `archive_path` - Path to the `.tar.gz` archive file.
+/// * `archive_path` - Path to the archive file, e.g. `.tar.gz`, `.tar.zst` or `.tar.gz.age`.
+/// * `encryption` - Keys for encrypted archives, if configured.
 ///
 /// # Returns
 /// A `Result` containing a `TempDir` where the archive has been extracted.
-pub fn prepare_archive_for_restore(archive_path: &Path) -> Result<TempDir> {
+pub fn prepare_archive_for_restore(archive_path: &Path, encryption: Option<&EncryptionConfig>) -> Result<TempDir> {
     println!(
         "\n📦 Preparing archive for restore: {}",
         archive_path.display()
@@ -259,13 +263,6 @@ pub fn prepare_archive_for_restore(archive_path: &Path) -> Result<TempDir> {
         ));
     }
 
-    if !is_tar_gz(archive_path) {
-        return Err(anyhow!(
-            "Archive for restore is not a .tar.gz file: {}. Supported format is .tar.gz.",
-            archive_path.display()
-        ));
-    }
-
     // Create a new temporary directory for extraction.
     let temp_dir = TempFileBuilder::new()
         .prefix("restore_extract_")
@@ -279,7 +276,7 @@ pub fn prepare_archive_for_restore(archive_path: &Path) -> Result<TempDir> {
     );
 
     // Use the robust archive extraction function.
-    crate::backup::archive::extract_tar_gz_archive(archive_path, temp_dir.path())
+    crate::backup::archive::extract_archive(archive_path, temp_dir.path(), encryption)
         .with_context(|| {
             format!(
                 "Failed to extract archive {} into temporary directory {}",
@@ -288,19 +285,13 @@ pub fn prepare_archive_for_restore(archive_path: &Path) -> Result<TempDir> {
             )
         })?;
 
+    // Streamed backups split large dumps into segments; put them back together.
+    crate::backup::archive::join_segmented_files(temp_dir.path())
+        .context("Failed to reassemble segmented files from streamed archive")?;
+
     println!(
         "✓ Archive successfully extracted to: {}",
         temp_dir.path().display()
     );
     Ok(temp_dir)
 }
-
-/// Checks if the given path likely points to a `.tar.gz` file based on its extension.
-fn is_tar_gz(path: &Path) -> bool {
-    path.extension()
-        .map_or(false, |ext| ext.eq_ignore_ascii_case("gz"))
-        && path
-            .file_stem()
-            .and_then(|stem| Path::new(stem).extension())
-            .map_or(false, |ext| ext.eq_ignore_ascii_case("tar"))
-}
\ No newline at end of file
@@ -0,0 +1,517 @@
+// databasetool/src/utils/sql_script.rs
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+use std::ops::Range;
+
+/// Where the reader is inside a statement; kept from one line to the next.
+#[derive(Debug, Clone, PartialEq)]
+enum Lexical {
+    Code,
+    Literal { escapes: bool }, // '...', or E'...' where backslash escapes
+    QuotedIdent,
+    Dollar(Vec<u8>), // Inside $tag$ ... $tag$, holding the tag
+    LineComment,
+    BlockComment(u32), // Nesting depth
+}
+
+/// Finds where the statements of a psql script end, the way psql does: at a `;` outside
+/// literals, quoted identifiers, comments and parentheses, and outside the `BEGIN ... END`
+/// body of a `CREATE FUNCTION` or `CREATE PROCEDURE` written in SQL-standard syntax.
+///
+/// The statement is fed line by line; the state carries over, so every byte is read once.
+#[derive(Debug)]
+struct StatementSplitter {
+    lexical: Lexical,
+    paren_depth: u32,
+    begin_depth: u32,
+    head: Vec<String>, // The first words of the statement, lower case
+    previous_word: String,
+    from_stdin: bool, // Saw FROM STDIN, so a COPY is followed by data lines
+    has_code: bool,   // Saw anything besides whitespace and comments
+}
+
+impl Default for StatementSplitter {
+    fn default() -> Self {
+        StatementSplitter {
+            lexical: Lexical::Code,
+            paren_depth: 0,
+            begin_depth: 0,
+            head: Vec::new(),
+            previous_word: String::new(),
+            from_stdin: false,
+            has_code: false,
+        }
+    }
+}
+
+impl StatementSplitter {
+    /// Whether a statement has started: the next line belongs to it.
+    fn in_statement(&self) -> bool {
+        self.has_code || !matches!(self.lexical, Lexical::Code | Lexical::LineComment)
+    }
+
+    /// Whether the statement just ended is a `COPY ... FROM STDIN` with data lines after it.
+    fn is_copy_from_stdin(&self) -> bool {
+        self.head.first().is_some_and(|word| word == "copy") && self.from_stdin
+    }
+
+    /// CREATE [OR REPLACE] FUNCTION|PROCEDURE, whose SQL-standard body may contain `;`.
+    fn is_routine(&self) -> bool {
+        let head: Vec<&str> = self.head.iter().map(String::as_str).collect();
+        matches!(head.as_slice(), ["create", "function" | "procedure", ..] | ["create", "or", "replace", "function" | "procedure", ..])
+    }
+
+    fn word(&mut self, word: &[u8]) {
+        let word = String::from_utf8_lossy(word).to_lowercase();
+        if self.head.len() < 4 {
+            self.head.push(word.clone());
+        }
+        if self.is_routine() && self.paren_depth == 0 {
+            match word.as_str() {
+                "begin" => self.begin_depth += 1,
+                "case" if self.begin_depth > 0 => self.begin_depth += 1, // CASE also ends with END
+                "end" if self.begin_depth > 0 => self.begin_depth -= 1,
+                _ => {}
+            }
+        }
+        if word == "stdin" && self.previous_word == "from" {
+            self.from_stdin = true;
+        }
+        self.previous_word = word;
+    }
+
+    /// Reads `buf[from..]` and returns the position just after the `;` that ends the
+    /// statement, if it ends there.
+    fn scan(&mut self, buf: &[u8], from: usize) -> Option<usize> {
+        let mut i = from;
+        while i < buf.len() {
+            let c = buf[i];
+            match &self.lexical {
+                Lexical::Code => {
+                    if is_ident_byte(c) && c != b'$' && (i == 0 || !is_ident_byte(buf[i - 1])) {
+                        let end = buf[i..].iter().position(|&b| !is_ident_byte(b)).map_or(buf.len(), |n| i + n);
+                        self.has_code = true;
+                        if !buf[i].is_ascii_digit() {
+                            self.word(&buf[i..end]);
+                        }
+                        i = end;
+                        continue;
+                    }
+                    let starts_comment = (c == b'-' && buf.get(i + 1) == Some(&b'-')) || (c == b'/' && buf.get(i + 1) == Some(&b'*'));
+                    if !c.is_ascii_whitespace() && !starts_comment {
+                        self.has_code = true;
+                    }
+                    match c {
+                        b'\'' => {
+                            let escapes = i > 0 && matches!(buf[i - 1], b'E' | b'e') && (i < 2 || !is_ident_byte(buf[i - 2]));
+                            self.lexical = Lexical::Literal { escapes };
+                        }
+                        b'"' => self.lexical = Lexical::QuotedIdent,
+                        b'-' if buf.get(i + 1) == Some(&b'-') => {
+                            self.lexical = Lexical::LineComment;
+                            i += 1;
+                        }
+                        b'/' if buf.get(i + 1) == Some(&b'*') => {
+                            self.lexical = Lexical::BlockComment(1);
+                            i += 1;
+                        }
+                        b'$' => {
+                            if let Some(tag) = dollar_tag(buf, i) {
+                                i += tag.len() + 1;
+                                self.lexical = Lexical::Dollar(tag.to_vec());
+                            }
+                        }
+                        b'(' => self.paren_depth += 1,
+                        b')' => self.paren_depth = self.paren_depth.saturating_sub(1),
+                        b';' if self.paren_depth == 0 && self.begin_depth == 0 => return Some(i + 1),
+                        _ => {}
+                    }
+                }
+                Lexical::Literal { escapes } => {
+                    if *escapes && c == b'\\' {
+                        i += 1;
+                    } else if c == b'\'' {
+                        if buf.get(i + 1) == Some(&b'\'') {
+                            i += 1;
+                        } else {
+                            self.lexical = Lexical::Code;
+                        }
+                    }
+                }
+                Lexical::QuotedIdent => {
+                    if c == b'"' {
+                        if buf.get(i + 1) == Some(&b'"') {
+                            i += 1;
+                        } else {
+                            self.lexical = Lexical::Code;
+                        }
+                    }
+                }
+                Lexical::Dollar(tag) => {
+                    if c == b'$' && buf[i + 1..].starts_with(tag) && buf.get(i + 1 + tag.len()) == Some(&b'$') {
+                        i += tag.len() + 1;
+                        self.lexical = Lexical::Code;
+                    }
+                }
+                Lexical::LineComment => {
+                    if c == b'\n' {
+                        self.lexical = Lexical::Code;
+                    }
+                }
+                Lexical::BlockComment(depth) => {
+                    let depth = *depth;
+                    if c == b'/' && buf.get(i + 1) == Some(&b'*') {
+                        self.lexical = Lexical::BlockComment(depth + 1);
+                        i += 1;
+                    } else if c == b'*' && buf.get(i + 1) == Some(&b'/') {
+                        self.lexical = if depth > 1 { Lexical::BlockComment(depth - 1) } else { Lexical::Code };
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+        }
+        None
+    }
+}
+
+/// Bytes of an unquoted identifier or keyword. Bytes of multibyte characters count as letters.
+fn is_ident_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80
+}
+
+/// The tag of a dollar quote opening at `buf[at]` (`$$` or `$tag$`), if one opens there.
+fn dollar_tag(buf: &[u8], at: usize) -> Option<&[u8]> {
+    if at > 0 && is_ident_byte(buf[at - 1]) {
+        return None; // Part of an identifier such as a$b
+    }
+    let rest = &buf[at + 1..];
+    let len = rest.iter().position(|&b| b == b'$')?;
+    let tag = &rest[..len];
+    let valid = tag.iter().all(|&b| is_ident_byte(b) && b != b'$') && tag.first().is_none_or(|b| !b.is_ascii_digit());
+    valid.then_some(tag)
+}
+
+/// The `\.` line that ends the data of `COPY ... FROM stdin`.
+fn is_end_of_copy_data(line: &[u8]) -> bool {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line) == b"\\."
+}
+
+/// Copies a psql script, such as a plain pg_dump, from `reader` to `writer` and lets the
+/// callbacks rewrite it: `rewrite_statement` gets every complete SQL statement with the
+/// comments before it, `rewrite_meta_command` every meta-command line (`\connect`, ...).
+/// A callback returns `None` to keep its input unchanged.
+///
+/// The script is read line by line and only one statement is held in memory. Comments
+/// between statements and the data lines that follow `COPY ... FROM stdin` are copied as they are.
+pub fn rewrite_script<R, W, S, M>(mut reader: R, mut writer: W, mut rewrite_statement: S, mut rewrite_meta_command: M) -> Result<()>
+where
+    R: BufRead,
+    W: Write,
+    S: FnMut(&[u8]) -> Option<Vec<u8>>,
+    M: FnMut(&[u8]) -> Option<Vec<u8>>,
+{
+    let mut splitter = StatementSplitter::default();
+    let mut statement: Vec<u8> = Vec::new();
+    let mut line = Vec::new();
+    let mut copy_data = false;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).context("Failed to read SQL script")? == 0 {
+            break;
+        }
+        if copy_data {
+            writer.write_all(&line).context("Failed to write SQL script")?;
+            copy_data = !is_end_of_copy_data(&line);
+            continue;
+        }
+        if !splitter.in_statement() {
+            // Between statements: what was read so far is whitespace and comments.
+            writer.write_all(&statement).context("Failed to write SQL script")?;
+            statement.clear();
+            splitter = StatementSplitter::default();
+            if line.iter().find(|b| !matches!(b, b' ' | b'\t')) == Some(&b'\\') {
+                let rewritten = rewrite_meta_command(&line);
+                writer.write_all(rewritten.as_deref().unwrap_or(&line)).context("Failed to write SQL script")?;
+                continue;
+            }
+        }
+        let mut from = statement.len();
+        statement.extend_from_slice(&line);
+        while let Some(end) = splitter.scan(&statement, from) {
+            let rewritten = rewrite_statement(&statement[..end]);
+            writer.write_all(rewritten.as_deref().unwrap_or(&statement[..end])).context("Failed to write SQL script")?;
+            copy_data = splitter.is_copy_from_stdin();
+            statement.drain(..end);
+            if copy_data {
+                // The data lines start after the rest of the COPY line.
+                writer.write_all(&statement).context("Failed to write SQL script")?;
+                statement.clear();
+            }
+            splitter = StatementSplitter::default();
+            from = 0;
+        }
+    }
+    // A statement without a final `;` is still run by psql at the end of the script.
+    if splitter.has_code {
+        let rewritten = rewrite_statement(&statement);
+        writer.write_all(rewritten.as_deref().unwrap_or(&statement)).context("Failed to write SQL script")?;
+    } else {
+        writer.write_all(&statement).context("Failed to write SQL script")?;
+    }
+    writer.flush().context("Failed to write SQL script")?;
+    Ok(())
+}
+
+/// What a token of a complete statement is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Word,        // Keyword, unquoted identifier or number
+    QuotedIdent, // "..."
+    Literal,     // '...', E'...' or $tag$...$tag$
+    Punct,
+    Space, // Whitespace and comments
+}
+
+/// A token of a statement as a byte range, so unchanged parts are copied exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub range: Range<usize>,
+}
+
+/// Splits a statement into tokens, with the same quoting rules psql uses to find its end.
+pub fn tokenize(sql: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < sql.len() {
+        let start = i;
+        let c = sql[i];
+        let kind = if c.is_ascii_whitespace() {
+            i += sql[i..].iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(sql.len() - i);
+            TokenKind::Space
+        } else if c == b'-' && sql.get(i + 1) == Some(&b'-') {
+            i += sql[i..].iter().position(|&b| b == b'\n').unwrap_or(sql.len() - i);
+            TokenKind::Space
+        } else if c == b'/' && sql.get(i + 1) == Some(&b'*') {
+            let mut depth = 0;
+            while i < sql.len() {
+                if sql[i..].starts_with(b"/*") {
+                    depth += 1;
+                    i += 2;
+                } else if sql[i..].starts_with(b"*/") {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+            TokenKind::Space
+        } else if c == b'"' {
+            i += 1;
+            while i < sql.len() {
+                if sql[i] == b'"' {
+                    if sql.get(i + 1) == Some(&b'"') {
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                i += 1;
+            }
+            TokenKind::QuotedIdent
+        } else if c == b'\'' || (matches!(c, b'E' | b'e') && sql.get(i + 1) == Some(&b'\'') && (i == 0 || !is_ident_byte(sql[i - 1]))) {
+            let escapes = c != b'\'';
+            i += if escapes { 2 } else { 1 };
+            while i < sql.len() {
+                if escapes && sql[i] == b'\\' {
+                    i += 2;
+                    continue;
+                }
+                if sql[i] == b'\'' {
+                    if sql.get(i + 1) == Some(&b'\'') {
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                i += 1;
+            }
+            TokenKind::Literal
+        } else if let Some(tag) = (c == b'$').then(|| dollar_tag(sql, i)).flatten() {
+            let delimiter_len = tag.len() + 2;
+            let body_start = i + delimiter_len;
+            let delimiter = &sql[i..body_start];
+            i = sql[body_start..]
+                .windows(delimiter_len)
+                .position(|window| window == delimiter)
+                .map_or(sql.len(), |n| body_start + n + delimiter_len);
+            TokenKind::Literal
+        } else if is_ident_byte(c) {
+            i += sql[i..].iter().position(|&b| !is_ident_byte(b)).unwrap_or(sql.len() - i);
+            TokenKind::Word
+        } else {
+            i += 1;
+            TokenKind::Punct
+        };
+        tokens.push(Token { kind, range: start..i.min(sql.len()) });
+    }
+    tokens
+}
+
+/// Where `sql` starts after the whitespace and comments before it, such as the comments
+/// `rewrite_script` passes along with a statement.
+pub fn statement_start(sql: &[u8]) -> usize {
+    tokenize(sql).into_iter().find(|token| token.kind != TokenKind::Space).map_or(sql.len(), |token| token.range.start)
+}
+
+/// The name an identifier token refers to: unquoted words fold to lower case, quoted
+/// identifiers are taken as written. `None` for other tokens and numbers.
+pub fn ident_name(sql: &[u8], token: &Token) -> Option<String> {
+    let text = &sql[token.range.clone()];
+    match token.kind {
+        TokenKind::Word if !text[0].is_ascii_digit() => Some(String::from_utf8_lossy(text).to_lowercase()),
+        TokenKind::QuotedIdent => {
+            let inner = text.strip_prefix(b"\"")?.strip_suffix(b"\"")?;
+            Some(String::from_utf8_lossy(inner).replace("\"\"", "\""))
+        }
+        _ => None,
+    }
+}
+
+/// Whether `token` is the keyword `keyword` (compared without case).
+pub fn is_keyword(sql: &[u8], token: &Token, keyword: &str) -> bool {
+    token.kind == TokenKind::Word && sql[token.range.clone()].eq_ignore_ascii_case(keyword.as_bytes())
+}
+
+/// Keywords that cannot be used as an unquoted identifier everywhere: every category of
+/// `pg_get_keywords()` but the unreserved one, up to PostgreSQL 17. Sorted for binary search.
+const NON_UNRESERVED_KEYWORDS: &[&str] = &[
+    "all", "analyse", "analyze", "and", "any", "array", "as", "asc", "asymmetric", "authorization", "between",
+    "bigint", "binary", "bit", "boolean", "both", "case", "cast", "char", "character", "check", "coalesce", "collate",
+    "collation", "column", "concurrently", "constraint", "create", "cross", "current_catalog", "current_date",
+    "current_role", "current_schema", "current_time", "current_timestamp", "current_user", "dec", "decimal", "default",
+    "deferrable", "desc", "distinct", "do", "else", "end", "except", "exists", "extract", "false", "fetch", "float",
+    "for", "foreign", "freeze", "from", "full", "grant", "greatest", "group", "grouping", "having", "ilike", "in",
+    "initially", "inner", "inout", "int", "integer", "intersect", "interval", "into", "is", "isnull", "join", "json",
+    "json_array", "json_arrayagg", "json_exists", "json_object", "json_objectagg", "json_query", "json_scalar",
+    "json_serialize", "json_table", "json_value", "lateral", "leading", "least", "left", "like", "limit", "localtime",
+    "localtimestamp", "merge_action", "national", "natural", "nchar", "none", "normalize", "not", "notnull", "null",
+    "nullif", "numeric", "offset", "on", "only", "or", "order", "out", "outer", "overlaps", "overlay", "placing",
+    "position", "precision", "primary", "real", "references", "returning", "right", "row", "select", "session_user",
+    "setof", "similar", "smallint", "some", "substring", "symmetric", "system_user", "table", "tablesample", "then",
+    "time", "timestamp", "to", "trailing", "treat", "trim", "true", "union", "unique", "user", "using", "values",
+    "varchar", "variadic", "verbose", "when", "where", "window", "with", "xmlattributes", "xmlconcat", "xmlelement",
+    "xmlexists", "xmlforest", "xmlnamespaces", "xmlparse", "xmlpi", "xmlroot", "xmlserialize", "xmltable",
+];
+
+/// An identifier for SQL: unquoted if it is lower case letters, digits and underscores and not a
+/// keyword, else quoted, like PostgreSQL's `quote_ident`.
+pub fn format_ident(name: &str) -> String {
+    let simple = name.chars().next().is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && NON_UNRESERVED_KEYWORDS.binary_search(&name).is_err();
+    if simple { name.to_string() } else { quote_ident(name) }
+}
+
+/// An identifier for SQL, always in double quotes so its case and characters are kept.
+pub(crate) fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// A string literal for SQL (standard_conforming_strings on, so only quotes are doubled).
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Copies `sql` with the given byte ranges replaced. The ranges must not overlap.
+pub fn splice(sql: &[u8], mut replacements: Vec<(Range<usize>, String)>) -> Vec<u8> {
+    replacements.sort_by_key(|(range, _)| range.start);
+    let mut output = Vec::with_capacity(sql.len());
+    let mut position = 0;
+    for (range, text) in replacements {
+        output.extend_from_slice(&sql[position..range.start]);
+        output.extend_from_slice(text.as_bytes());
+        position = range.end;
+    }
+    output.extend_from_slice(&sql[position..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"--
+-- PostgreSQL database dump
+--
+
+\restrict abc123
+SET standard_conforming_strings = on;
+CREATE FUNCTION public.f() RETURNS text
+    LANGUAGE plpgsql
+    AS $_$BEGIN RETURN 'a;b'; END$_$;
+CREATE FUNCTION public.g() RETURNS integer
+    LANGUAGE sql
+    BEGIN ATOMIC
+ SELECT CASE WHEN true THEN 1 ELSE 2 END;
+END;
+CREATE RULE r AS ON INSERT TO public.t DO INSTEAD (INSERT INTO public.u VALUES (1); NOTIFY t);
+INSERT INTO public.t VALUES (1, 'it''s; -- not a comment', E'\'; x');
+COPY public.t (id, note) FROM stdin;
+1	a; b
+\.
+/* block; comment */ SELECT 1; SELECT "a;b"
+"#;
+
+    #[test]
+    fn test_rewrite_script_splits_statements_like_psql() -> Result<()> {
+        let mut statements = Vec::new();
+        let mut meta_commands = Vec::new();
+        let mut output = Vec::new();
+        rewrite_script(
+            SCRIPT.as_bytes(),
+            &mut output,
+            |sql| {
+                statements.push(String::from_utf8_lossy(sql).trim().to_string());
+                None
+            },
+            |line| {
+                meta_commands.push(String::from_utf8_lossy(line).to_string());
+                None
+            },
+        )?;
+        assert_eq!(String::from_utf8(output)?, SCRIPT);
+        assert_eq!(meta_commands, vec!["\\restrict abc123\n"]);
+        assert_eq!(statements.len(), 8, "{:#?}", statements);
+        assert!(statements[1].ends_with("END$_$;"));
+        assert!(statements[2].ends_with("END;\nEND;"));
+        assert!(statements[3].ends_with("NOTIFY t);"));
+        assert!(statements[5].starts_with("COPY") && statements[5].ends_with("FROM stdin;"));
+        assert_eq!(statements[6], "/* block; comment */ SELECT 1;");
+        assert_eq!(statements[7], "SELECT \"a;b\"");
+        Ok(())
+    }
+
+    #[test]
+    fn test_tokenize_and_splice() {
+        let sql = b"ALTER TABLE \"My\"\"T\" SET (fillfactor = 70) -- note\n;";
+        let tokens: Vec<Token> = tokenize(sql).into_iter().filter(|token| token.kind != TokenKind::Space).collect();
+        assert!(is_keyword(sql, &tokens[0], "alter"));
+        assert_eq!(ident_name(sql, &tokens[2]).as_deref(), Some("My\"T"));
+        assert_eq!(ident_name(sql, &tokens[7]), None); // 70
+        let renamed = splice(sql, vec![(tokens[2].range.clone(), format_ident("Other"))]);
+        assert_eq!(renamed, b"ALTER TABLE \"Other\" SET (fillfactor = 70) -- note\n;");
+        assert_eq!(format_ident("app_dev"), "app_dev");
+        assert_eq!(format_ident("user"), "\"user\"");
+        assert_eq!(format_ident("order"), "\"order\"");
+        assert_eq!(format_ident("name"), "name"); // Unreserved
+        assert!(NON_UNRESERVED_KEYWORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
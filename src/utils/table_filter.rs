@@ -0,0 +1,469 @@
+// databasetool/src/utils/table_filter.rs
+use anyhow::{Context, Result};
+use sqlx::{Connection, PgConnection, Row};
+use std::collections::BTreeSet;
+
+use crate::config::TableFilters;
+use crate::utils::sql_script::{quote_ident, quote_literal};
+
+/// A user table, identified by schema and name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TableName {
+    pub schema: String,
+    pub name: String,
+}
+
+impl TableName {
+    /// `"schema"."table"`, safe to use in SQL and as a `pg_dump -t` pattern.
+    pub fn quoted(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.name))
+    }
+}
+
+impl std::fmt::Display for TableName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.schema, self.name)
+    }
+}
+
+/// A table whose rows are limited by a `filters.where` condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowFilter {
+    pub table: TableName,
+    pub columns: Vec<String>, // Columns that can be written, in order; generated columns are left out
+    pub condition: String,
+}
+
+impl RowFilter {
+    fn column_list(&self) -> String {
+        self.columns.iter().map(|column| quote_ident(column)).collect::<Vec<_>>().join(", ")
+    }
+
+    /// The rows to keep, for `COPY (...) TO STDOUT`.
+    pub fn select_query(&self) -> String {
+        format!("SELECT {} FROM {} WHERE ({})", self.column_list(), self.table.quoted(), self.condition)
+    }
+
+    /// `COPY ... FROM STDIN` matching the columns of `select_query`.
+    pub fn copy_in_statement(&self) -> String {
+        format!("COPY {} ({}) FROM STDIN", self.table.quoted(), self.column_list())
+    }
+}
+
+/// `TableFilters` resolved against the catalog of one database: what to leave out, by exact name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableSelection {
+    pub excluded_schemas: Vec<String>,
+    pub excluded_tables: Vec<TableName>, // Tables of the remaining schemas that are left out
+    pub schema_only_tables: Vec<TableName>,
+    pub row_filters: Vec<RowFilter>,
+}
+
+impl TableFilters {
+    pub fn includes_schema(&self, schema: &str) -> bool {
+        (self.include_schemas.is_empty() || self.include_schemas.iter().any(|pattern| matches_pattern(pattern, schema)))
+            && !self.exclude_schemas.iter().any(|pattern| matches_pattern(pattern, schema))
+    }
+
+    /// Whether `table` is part of the copy at all (with or without its rows).
+    pub fn includes_table(&self, table: &TableName) -> bool {
+        self.includes_schema(&table.schema)
+            && (self.include_tables.is_empty() || self.include_tables.iter().any(|pattern| table_matches(pattern, table)))
+            && !self.exclude_tables.iter().any(|pattern| table_matches(pattern, table))
+    }
+
+    /// Whether the rows of `table` are copied, possibly limited by a `where` condition.
+    pub fn includes_data(&self, table: &TableName) -> bool {
+        self.includes_table(table) && !self.schema_only_tables.iter().any(|pattern| table_matches(pattern, table))
+    }
+
+    /// The `where` condition for `table`. Fails when several entries match it.
+    pub fn row_condition(&self, table: &TableName) -> Result<Option<&str>> {
+        let mut matching = self.row_filters.iter().filter(|(pattern, _)| table_matches(pattern, table));
+        let first = matching.next();
+        if let Some((other, _)) = matching.next() {
+            anyhow::bail!(
+                "Table {} matches more than one filters.where entry ('{}' and '{}').",
+                table,
+                first.map(|(pattern, _)| pattern.as_str()).unwrap_or_default(),
+                other
+            );
+        }
+        Ok(first.map(|(_, condition)| condition.as_str()))
+    }
+}
+
+/// Matches `value` against a pattern where `*` stands for any run of characters, ignoring case.
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let value = value.to_ascii_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == value;
+    }
+
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if !value.starts_with(first) || value.len() < first.len() + last.len() || !value.ends_with(last) {
+        return false;
+    }
+    let mut rest = &value[first.len()..value.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Matches `schema.table` patterns against both parts, and unqualified patterns against the table name only.
+pub(crate) fn table_matches(pattern: &str, table: &TableName) -> bool {
+    match pattern.split_once('.') {
+        Some((schema, name)) => matches_pattern(schema, &table.schema) && matches_pattern(name, &table.name),
+        None => matches_pattern(pattern, &table.name),
+    }
+}
+
+/// Applies `filters` to the tables that currently exist in the database behind `conn`.
+///
+/// Fails when a table that stays references an excluded one: pg_dump keeps the foreign key,
+/// which could then not be created. Tables whose rows are limited or left out while others
+/// still reference them only get a warning, because the constraint holds as long as no
+/// remaining row points at a missing one.
+pub async fn resolve_filters(conn: &mut PgConnection, filters: &TableFilters) -> Result<TableSelection> {
+    if filters.is_empty() {
+        return Ok(TableSelection::default());
+    }
+
+    let schemas: Vec<String> = sqlx::query_scalar(
+        "SELECT nspname::text FROM pg_namespace \
+         WHERE nspname NOT IN ('pg_catalog', 'information_schema') AND nspname NOT LIKE 'pg_toast%' \
+           AND nspname NOT LIKE 'pg_temp_%' \
+         ORDER BY 1",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to list schemas")?;
+
+    let tables = sqlx::query(
+        "SELECT n.nspname::text AS schema_name, c.relname::text AS table_name, c.relkind::text AS kind, \
+                ARRAY(SELECT a.attname::text FROM pg_attribute a WHERE a.attrelid = c.oid AND a.attnum > 0 \
+                      AND NOT a.attisdropped AND a.attgenerated = '' ORDER BY a.attnum) AS columns \
+         FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace \
+         WHERE c.relkind IN ('r', 'p') AND n.nspname NOT IN ('pg_catalog', 'information_schema') \
+           AND n.nspname NOT LIKE 'pg_toast%' \
+         ORDER BY 1, 2",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to list tables")?;
+
+    let mut selection = TableSelection {
+        excluded_schemas: schemas.into_iter().filter(|schema| !filters.includes_schema(schema)).collect(),
+        ..Default::default()
+    };
+    for row in tables {
+        let table = TableName { schema: row.try_get("schema_name")?, name: row.try_get("table_name")? };
+        let partitioned = row.try_get::<String, _>("kind")? == "p";
+        if !filters.includes_schema(&table.schema) {
+            continue;
+        }
+        if !filters.includes_table(&table) {
+            selection.excluded_tables.push(table);
+            continue;
+        }
+        let condition = filters.row_condition(&table)?;
+        let limited = !filters.includes_data(&table) || condition.is_some();
+        if partitioned {
+            if limited {
+                anyhow::bail!(
+                    "{} is partitioned; put schema_only_tables and where filters on its partitions instead (e.g. '{}_*').",
+                    table,
+                    table
+                );
+            }
+            continue;
+        }
+        if !filters.includes_data(&table) {
+            selection.schema_only_tables.push(table);
+        } else if let Some(condition) = condition {
+            selection.row_filters.push(RowFilter { columns: row.try_get("columns")?, condition: condition.to_string(), table });
+        }
+    }
+
+    let foreign_keys: Vec<(String, String, String, String, String)> = sqlx::query_as(
+        "SELECT k.conname::text, fn.nspname::text, fc.relname::text, tn.nspname::text, tc.relname::text \
+         FROM pg_constraint k \
+         JOIN pg_class fc ON fc.oid = k.conrelid JOIN pg_namespace fn ON fn.oid = fc.relnamespace \
+         JOIN pg_class tc ON tc.oid = k.confrelid JOIN pg_namespace tn ON tn.oid = tc.relnamespace \
+         WHERE k.contype = 'f' AND k.conrelid <> k.confrelid",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .context("Failed to read foreign keys")?;
+
+    let mut broken = Vec::new();
+    for (constraint, from_schema, from_table, to_schema, to_table) in foreign_keys {
+        let from = TableName { schema: from_schema, name: from_table };
+        let to = TableName { schema: to_schema, name: to_table };
+        if !filters.includes_table(&from) {
+            continue;
+        }
+        if !filters.includes_table(&to) {
+            broken.push(format!("{} references {} ({})", from, to, constraint));
+        } else if filters.includes_data(&from) && selection.limits_rows(&to) {
+            println!(
+                "⚠️  {} references {}, whose rows are filtered; rows of {} pointing at rows left out will fail {}.",
+                from, to, from, constraint
+            );
+        }
+    }
+    if !broken.is_empty() {
+        anyhow::bail!(
+            "Excluded tables are still referenced by tables that are kept:\n  {}\nExclude the referencing tables too, or keep the referenced ones (schema_only_tables keeps a table without its rows).",
+            broken.join("\n  ")
+        );
+    }
+    Ok(selection)
+}
+
+/// `resolve_filters` for the database at `db_url`, without connecting when there are no filters.
+pub async fn resolve_database_filters(db_url: &str, filters: &TableFilters) -> Result<TableSelection> {
+    if filters.is_empty() {
+        return Ok(TableSelection::default());
+    }
+    let mut conn = PgConnection::connect(db_url).await.context("Failed to connect to resolve filters")?;
+    let selection = resolve_filters(&mut conn, filters).await?;
+    conn.close().await.ok();
+    Ok(selection)
+}
+
+impl TableSelection {
+    /// Whether `table` is left out entirely, on its own or with its schema.
+    pub fn excludes(&self, table: &TableName) -> bool {
+        self.excluded_schemas.contains(&table.schema) || self.excluded_tables.contains(table)
+    }
+
+    /// Whether only part (or none) of the rows of `table` are copied.
+    pub fn limits_rows(&self, table: &TableName) -> bool {
+        self.schema_only_tables.contains(table) || self.row_filter(table).is_some()
+    }
+
+    pub fn row_filter(&self, table: &TableName) -> Option<&RowFilter> {
+        self.row_filters.iter().find(|filter| filter.table == *table)
+    }
+
+    /// pg_dump arguments that leave out the excluded schemas and tables, and the data of
+    /// schema-only and row-filtered tables. The filtered rows are dumped with `row_dump_args`.
+    pub fn pg_dump_args(&self) -> Vec<String> {
+        let mut args: Vec<String> =
+            self.excluded_schemas.iter().map(|schema| format!("--exclude-schema={}", quote_ident(schema))).collect();
+        args.extend(self.excluded_tables.iter().map(|table| format!("--exclude-table={}", table.quoted())));
+        args.extend(
+            self.schema_only_tables
+                .iter()
+                .chain(self.row_filters.iter().map(|filter| &filter.table))
+                .map(|table| format!("--exclude-table-data={}", table.quoted())),
+        );
+        args
+    }
+
+    /// psql arguments that print the rows kept by `filters.where` as a script psql can replay:
+    /// one `COPY ... FROM stdin` block per table, loaded with triggers and foreign keys off
+    /// like pg_dump's data. Empty when no table has a `where` filter.
+    ///
+    /// With a `snapshot` (from `pg_export_snapshot()`) the rows are read in it, so they match
+    /// a pg_dump run with `--snapshot`.
+    pub fn row_dump_args(&self, snapshot: Option<&str>) -> Vec<String> {
+        if self.row_filters.is_empty() {
+            return Vec::new();
+        }
+        let mut args = vec!["-X".to_string(), "-q".to_string(), "-v".to_string(), "ON_ERROR_STOP=1".to_string()];
+        if let Some(snapshot) = snapshot {
+            args.push("-c".to_string());
+            args.push("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY".to_string());
+            args.push("-c".to_string());
+            args.push(format!("SET TRANSACTION SNAPSHOT {}", quote_literal(snapshot)));
+        }
+        let echo = |args: &mut Vec<String>, line: &str| {
+            args.push("-c".to_string());
+            args.push(format!("\\qecho '{}'", line.replace('\\', "\\\\").replace('\'', "''")));
+        };
+        echo(&mut args, "SET client_encoding = 'UTF8';");
+        echo(&mut args, "SET session_replication_role = replica;");
+        args.push("-c".to_string());
+        args.push("SET client_encoding = 'UTF8'".to_string());
+        for filter in &self.row_filters {
+            echo(&mut args, &format!("{};", filter.copy_in_statement()));
+            args.push("-c".to_string());
+            args.push(format!("COPY ({}) TO STDOUT", filter.select_query()));
+            echo(&mut args, "\\.");
+        }
+        args
+    }
+}
+
+impl TableSelection {
+    /// Narrows the selection to the `restored` tables (keyed `schema.table`, as in the manifest), for a
+    /// restore into a database that had objects of its own: those are left alone, and an excluded
+    /// schema loses only its restored tables instead of being dropped.
+    pub fn restricted_to(self, restored: &BTreeSet<String>) -> TableSelection {
+        let is_restored = |table: &TableName| restored.contains(&table.to_string());
+        let mut excluded_tables: Vec<TableName> = self.excluded_tables.into_iter().filter(|table| is_restored(table)).collect();
+        for schema in &self.excluded_schemas {
+            let prefix = format!("{}.", schema);
+            excluded_tables.extend(
+                restored
+                    .iter()
+                    .filter_map(|key| key.strip_prefix(&prefix))
+                    .map(|name| TableName { schema: schema.clone(), name: name.to_string() }),
+            );
+        }
+        TableSelection {
+            excluded_schemas: Vec::new(),
+            excluded_tables,
+            schema_only_tables: self.schema_only_tables.into_iter().filter(|table| is_restored(table)).collect(),
+            row_filters: self.row_filters.into_iter().filter(|filter| is_restored(&filter.table)).collect(),
+        }
+    }
+}
+
+/// Brings a restored database in line with `selection`: excluded schemas and tables are
+/// dropped, schema-only tables emptied and rows outside the `where` conditions deleted.
+///
+/// Runs in one transaction with triggers and foreign keys off, so the order does not matter
+/// and a failure leaves the restored database as it was.
+pub async fn prune_database(conn: &mut PgConnection, selection: &TableSelection) -> Result<()> {
+    let mut statements: Vec<String> = selection
+        .excluded_schemas
+        .iter()
+        .map(|schema| format!("DROP SCHEMA IF EXISTS {} CASCADE", quote_ident(schema)))
+        .collect();
+    statements.extend(selection.excluded_tables.iter().map(|table| format!("DROP TABLE IF EXISTS {} CASCADE", table.quoted())));
+    // DELETE rather than TRUNCATE: TRUNCATE refuses tables that other (kept) tables reference.
+    statements.extend(selection.schema_only_tables.iter().map(|table| format!("DELETE FROM {}", table.quoted())));
+    statements.extend(selection.row_filters.iter().map(|filter| {
+        format!("DELETE FROM {} WHERE NOT COALESCE(({}), false)", filter.table.quoted(), filter.condition)
+    }));
+
+    let mut transaction = conn.begin().await.context("Failed to start the filter transaction")?;
+    sqlx::query("SET LOCAL session_replication_role = replica")
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to disable triggers for filtering")?;
+    for statement in &statements {
+        let result = sqlx::query(statement)
+            .execute(&mut *transaction)
+            .await
+            .with_context(|| format!("Failed to apply filter: {}", statement))?;
+        println!("  ✓ {} ({} row(s))", statement, result.rows_affected());
+    }
+    transaction.commit().await.context("Failed to commit the filter transaction")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(schema: &str, name: &str) -> TableName {
+        TableName { schema: schema.to_string(), name: name.to_string() }
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("prod", "PROD"));
+        assert!(!matches_pattern("prod", "prod_copy"));
+        assert!(matches_pattern("prod_*", "prod_billing"));
+        assert!(matches_pattern("*.prod.example.com", "db1.prod.example.com"));
+        assert!(matches_pattern("db*prod*", "db-eu-prod-1"));
+        assert!(!matches_pattern("*.prod.example.com", "prod.example.com"));
+        assert!(!matches_pattern("a*a", "a"));
+    }
+
+    #[test]
+    fn test_table_filters_match_schemas_tables_and_rows() -> Result<()> {
+        let filters = TableFilters {
+            exclude_schemas: vec!["audit".to_string()],
+            exclude_tables: vec!["sessions".to_string(), "public.*_log".to_string()],
+            schema_only_tables: vec!["public.events_archive".to_string()],
+            row_filters: vec![("public.events".to_string(), "created_at > now() - interval '90 days'".to_string())],
+            ..Default::default()
+        };
+        assert!(!filters.includes_table(&table("audit", "changes")));
+        assert!(!filters.includes_table(&table("auth", "sessions")));
+        assert!(!filters.includes_table(&table("public", "AUDIT_LOG")));
+        assert!(filters.includes_table(&table("sales", "audit_log")));
+        assert!(filters.includes_table(&table("public", "events_archive")));
+        assert!(!filters.includes_data(&table("public", "events_archive")));
+        assert_eq!(filters.row_condition(&table("public", "events"))?, Some("created_at > now() - interval '90 days'"));
+        assert_eq!(filters.row_condition(&table("sales", "events"))?, None);
+
+        let only = TableFilters { include_schemas: vec!["sales".to_string()], ..Default::default() };
+        assert!(only.includes_table(&table("sales", "orders")));
+        assert!(!only.includes_table(&table("public", "items")));
+
+        let ambiguous = TableFilters {
+            row_filters: vec![("events".to_string(), "true".to_string()), ("public.ev*".to_string(), "false".to_string())],
+            ..Default::default()
+        };
+        assert!(ambiguous.row_condition(&table("public", "events")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_selection_restricted_to_restored_tables_keeps_other_objects() {
+        let selection = TableSelection {
+            excluded_schemas: vec!["audit".to_string(), "legacy".to_string()],
+            excluded_tables: vec![table("public", "sessions"), table("public", "local_notes")],
+            schema_only_tables: vec![table("public", "events_archive"), table("public", "local_cache")],
+            row_filters: vec![RowFilter {
+                table: table("public", "local_log"),
+                columns: vec!["id".to_string()],
+                condition: "false".to_string(),
+            }],
+        };
+        // local_* and the legacy schema were in the target before the restore; the archive never had them
+        let restored: BTreeSet<String> =
+            ["audit.changes", "public.events_archive", "public.items", "public.sessions"].into_iter().map(str::to_string).collect();
+
+        let restricted = selection.restricted_to(&restored);
+        assert!(restricted.excluded_schemas.is_empty());
+        assert_eq!(restricted.excluded_tables, vec![table("public", "sessions"), table("audit", "changes")]);
+        assert_eq!(restricted.schema_only_tables, vec![table("public", "events_archive")]);
+        assert!(restricted.row_filters.is_empty());
+    }
+
+    #[test]
+    fn test_selection_pg_dump_and_row_dump_args() {
+        let selection = TableSelection {
+            excluded_schemas: vec!["audit".to_string()],
+            excluded_tables: vec![table("public", "sessions")],
+            schema_only_tables: vec![table("public", "events_archive")],
+            row_filters: vec![RowFilter {
+                table: table("public", "events"),
+                columns: vec!["id".to_string(), "kind".to_string()],
+                condition: "kind <> 'debug'".to_string(),
+            }],
+        };
+        assert_eq!(
+            selection.pg_dump_args(),
+            vec![
+                "--exclude-schema=\"audit\"",
+                "--exclude-table=\"public\".\"sessions\"",
+                "--exclude-table-data=\"public\".\"events_archive\"",
+                "--exclude-table-data=\"public\".\"events\"",
+            ]
+        );
+
+        let args = selection.row_dump_args(None);
+        assert!(args.contains(&"\\qecho 'COPY \"public\".\"events\" (\"id\", \"kind\") FROM STDIN;'".to_string()));
+        assert!(args.contains(&"COPY (SELECT \"id\", \"kind\" FROM \"public\".\"events\" WHERE (kind <> 'debug')) TO STDOUT".to_string()));
+        assert_eq!(args.last().map(String::as_str), Some("\\qecho '\\\\.'"));
+        assert!(TableSelection::default().row_dump_args(None).is_empty());
+
+        let args = selection.row_dump_args(Some("00000003-0000001B-1"));
+        assert_eq!(args[5], "BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY");
+        assert_eq!(args[7], "SET TRANSACTION SNAPSHOT '00000003-0000001B-1'");
+    }
+}